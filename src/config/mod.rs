@@ -0,0 +1,181 @@
+use std::env;
+use std::str::FromStr;
+
+use lazy_static::lazy_static;
+
+use super::middlewares::rate_limit::RateLimit;
+use super::utils::random_token;
+
+const DEVELOPMENT_JWT_SECRET: &str = "random123";
+
+lazy_static! {
+   pub static ref CONFIG: Config = Config::from_env();
+}
+
+pub struct Config {
+   pub trash_retention_days: i64,
+   pub trash_purge_interval_seconds: u64,
+   pub require_if_match: bool,
+   pub body_max_bytes: usize,
+   pub password_min_length: usize,
+   pub password_min_score: u8,
+   pub argon2_memory_kib: u32,
+   pub argon2_iterations: u32,
+   pub argon2_parallelism: u32,
+   pub login_delay_after: i64,
+   pub login_ip_delay_after: i64,
+   pub login_delay_max_seconds: i64,
+   pub login_lockout_after: i64,
+   pub login_ip_lockout_after: i64,
+   pub login_lockout_seconds: i64,
+   pub login_attempts_window_seconds: i64,
+   pub trust_proxy: bool,
+   pub trusted_proxy_hops: usize,
+   pub admin_ids: Vec<String>,
+   pub rate_limit_auth: RateLimit,
+   pub rate_limit_read: RateLimit,
+   pub rate_limit_write: RateLimit,
+   pub rate_limit_ip_factor: u32,
+   pub mailer: String,
+   pub smtp_url: String,
+   pub mail_dir: String,
+   pub mail_from: String,
+   pub app_url: String,
+   pub email_token_secret: String,
+   pub email_verification_hours: i64,
+   pub verification_resend_seconds: i64,
+   pub require_verified_email: bool,
+   pub jwt_secret: String,
+   pub password_reset_minutes: i64,
+   pub password_reset_resend_seconds: i64,
+   pub totp_issuer: String,
+   pub mfa_token_minutes: i64,
+   pub recovery_codes: usize,
+   pub attachments_dir: String,
+   pub attachment_max_bytes: u64,
+   pub attachment_types: Vec<String>,
+   pub webhook_poll_interval_seconds: u64,
+   pub webhook_timeout_seconds: u64,
+   pub webhook_max_attempts: i64,
+   pub webhook_retry_base_seconds: i64,
+   pub webhook_allow_private: bool,
+   pub events_heartbeat_seconds: u64,
+}
+
+impl Config {
+   fn from_env() -> Config {
+      let app_env = get_env("APP_ENV", String::from("production"));
+
+      Config {
+         trash_retention_days: get_env("TRASH_RETENTION_DAYS", 30),
+         trash_purge_interval_seconds: get_env("TRASH_PURGE_INTERVAL_SECONDS", 3600),
+         require_if_match: get_env("REQUIRE_IF_MATCH", false),
+         body_max_bytes: get_env("BODY_MAX_BYTES", 1024 * 1024),
+         password_min_length: get_env("PASSWORD_MIN_LENGTH", 8),
+         password_min_score: get_env("PASSWORD_MIN_SCORE", 3),
+         argon2_memory_kib: get_env("ARGON2_MEMORY_KIB", 19 * 1024),
+         argon2_iterations: get_env("ARGON2_ITERATIONS", 2),
+         argon2_parallelism: get_env("ARGON2_PARALLELISM", 1),
+         login_delay_after: get_env("LOGIN_DELAY_AFTER", 3),
+         login_ip_delay_after: get_env("LOGIN_IP_DELAY_AFTER", 20),
+         login_delay_max_seconds: get_env("LOGIN_DELAY_MAX_SECONDS", 60),
+         login_lockout_after: get_env("LOGIN_LOCKOUT_AFTER", 10),
+         login_ip_lockout_after: get_env("LOGIN_IP_LOCKOUT_AFTER", 100),
+         login_lockout_seconds: get_env("LOGIN_LOCKOUT_SECONDS", 900),
+         login_attempts_window_seconds: get_env("LOGIN_ATTEMPTS_WINDOW_SECONDS", 3600),
+         trust_proxy: get_env("TRUST_PROXY", false),
+         trusted_proxy_hops: get_env("TRUSTED_PROXY_HOPS", 1),
+         admin_ids: get_env("ADMIN_IDS", String::new())
+            .split(',')
+            .map(|id| id.trim().to_string())
+            .filter(|id| !id.is_empty())
+            .collect(),
+         rate_limit_auth: get_env(
+            "RATE_LIMIT_AUTH",
+            RateLimit {
+               requests: 10,
+               seconds: 60,
+            },
+         ),
+         rate_limit_read: get_env(
+            "RATE_LIMIT_READ",
+            RateLimit {
+               requests: 300,
+               seconds: 60,
+            },
+         ),
+         rate_limit_write: get_env(
+            "RATE_LIMIT_WRITE",
+            RateLimit {
+               requests: 60,
+               seconds: 60,
+            },
+         ),
+         rate_limit_ip_factor: get_env("RATE_LIMIT_IP_FACTOR", 4),
+         mailer: get_env("MAILER", String::from("log")),
+         smtp_url: get_env("SMTP_URL", String::from("smtp://localhost:25")),
+         mail_dir: get_env("MAIL_DIR", String::from("./src/database/mail")),
+         mail_from: get_env("MAIL_FROM", String::from("todo-api <no-reply@localhost>")),
+         app_url: get_env("APP_URL", String::new()),
+         // Without a secret, tokens sent before a restart stop working.
+         email_token_secret: get_env("EMAIL_TOKEN_SECRET", random_token()),
+         email_verification_hours: get_env("EMAIL_VERIFICATION_HOURS", 48),
+         verification_resend_seconds: get_env("VERIFICATION_RESEND_SECONDS", 60),
+         require_verified_email: get_env("REQUIRE_VERIFIED_EMAIL", false),
+         jwt_secret: jwt_secret(&app_env),
+         password_reset_minutes: get_env("PASSWORD_RESET_MINUTES", 60),
+         password_reset_resend_seconds: get_env("PASSWORD_RESET_RESEND_SECONDS", 60),
+         totp_issuer: get_env("TOTP_ISSUER", String::from("todo-api")),
+         mfa_token_minutes: get_env("MFA_TOKEN_MINUTES", 5),
+         recovery_codes: get_env("RECOVERY_CODES", 10),
+         attachments_dir: get_env(
+            "ATTACHMENTS_DIR",
+            String::from("./src/database/attachments"),
+         ),
+         attachment_max_bytes: get_env("ATTACHMENT_MAX_BYTES", 10 * 1024 * 1024),
+         attachment_types: get_env(
+            "ATTACHMENT_TYPES",
+            String::from("image/png,image/jpeg,image/gif,application/pdf,text/plain"),
+         )
+         .split(',')
+         .map(|content_type| content_type.trim().to_lowercase())
+         .filter(|content_type| !content_type.is_empty())
+         .collect(),
+         webhook_poll_interval_seconds: get_env("WEBHOOK_POLL_INTERVAL_SECONDS", 5),
+         webhook_timeout_seconds: get_env("WEBHOOK_TIMEOUT_SECONDS", 10),
+         webhook_max_attempts: get_env("WEBHOOK_MAX_ATTEMPTS", 8),
+         webhook_retry_base_seconds: get_env("WEBHOOK_RETRY_BASE_SECONDS", 30),
+         webhook_allow_private: get_env("WEBHOOK_ALLOW_PRIVATE", false),
+         events_heartbeat_seconds: get_env("EVENTS_HEARTBEAT_SECONDS", 15),
+      }
+   }
+}
+
+// Tokens signed with a secret anyone can read are only good enough for
+// development and tests, where the fixed one keeps them working across
+// restarts.
+fn jwt_secret(app_env: &str) -> String {
+   match env::var("JWT_SECRET") {
+      Ok(secret) if !secret.trim().is_empty() => secret,
+      _ if app_env == "development" || cfg!(test) => {
+         eprintln!("Config Error: JWT_SECRET is not set, using the development secret");
+
+         String::from(DEVELOPMENT_JWT_SECRET)
+      }
+      _ => panic!("JWT_SECRET must be set unless APP_ENV is development"),
+   }
+}
+
+fn get_env<T: FromStr>(name: &'static str, default: T) -> T {
+   match env::var(name) {
+      Ok(value) => match value.parse::<T>() {
+         Ok(value) => value,
+         _ => {
+            eprintln!("Config Error: {} is invalid, using the default", name);
+
+            default
+         }
+      },
+      _ => default,
+   }
+}
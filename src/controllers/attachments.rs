@@ -0,0 +1,589 @@
+use super::super::config::CONFIG;
+use super::super::middlewares::users::{valid_user, ValidResponse};
+use super::super::utils::attachments::{
+   blob_body, discard_blobs, place_blob, remove_unreferenced_blobs, store_field, StoreError,
+   StoredBlob,
+};
+use super::super::utils::etag::etag_matches;
+use super::super::utils::events::Wakeups;
+use super::super::utils::history::record_task_event;
+use super::super::utils::permissions::{find_permitted_task, Role};
+use super::super::utils::{create_error, get_users, now, valid_json, ErrorFormated};
+use super::super::views::attachments::{
+   Attachment, AttachmentFormated, ATTACHMENT_COLUMNS, ATTACHMENT_COLUMNS_COUNT,
+};
+use super::super::views::users::CreatedUser;
+
+use std::collections::HashSet;
+use std::convert::{From, Infallible};
+use std::sync::Arc;
+
+use futures::lock::Mutex;
+
+use rusqlite::{params, Connection, OptionalExtension, Row};
+
+use uuid::Uuid;
+
+use hyper::{Body, Request, Response};
+
+use multer::Multipart;
+
+use serde_json::json;
+
+const ATTACHMENT_MAX_FILES: usize = 10;
+const FILENAME_MAX_LENGTH: usize = 255;
+
+fn attachment_from_row(row: &Row) -> rusqlite::Result<Attachment> {
+   let mut attachment = Attachment::from_row(row, 0)?;
+
+   attachment.user = Option::from(CreatedUser {
+      id: row.get(ATTACHMENT_COLUMNS_COUNT)?,
+      firstname: row.get(ATTACHMENT_COLUMNS_COUNT + 1)?,
+      lastname: row.get(ATTACHMENT_COLUMNS_COUNT + 2)?,
+      email: row.get(ATTACHMENT_COLUMNS_COUNT + 3)?,
+      password: row.get(ATTACHMENT_COLUMNS_COUNT + 4)?,
+   });
+
+   Ok(attachment)
+}
+
+fn find_attachment(conn: &Connection, attachment_id: &str) -> Option<Attachment> {
+   conn
+      .query_row(
+         &format!(
+            "SELECT {}, users.id, users.firstname, users.lastname, users.email, users.password FROM attachments INNER JOIN users ON users.id = attachments.user_id WHERE attachments.id = ?",
+            ATTACHMENT_COLUMNS
+         ),
+         [attachment_id],
+         attachment_from_row,
+      )
+      .optional()
+      .unwrap()
+}
+
+// Keeps the last path segment of the client's filename, without control
+// characters or quotes.
+fn sanitize_filename(filename: &str) -> String {
+   let filename: String = filename
+      .rsplit(['/', '\\'])
+      .next()
+      .unwrap_or("")
+      .chars()
+      .filter(|c| !c.is_control() && *c != '"')
+      .take(FILENAME_MAX_LENGTH)
+      .collect();
+
+   match filename.trim() {
+      "" | "." | ".." => String::from("file"),
+      filename => String::from(filename),
+   }
+}
+
+// RFC 6266: an ASCII `filename` for old clients plus the exact name as a
+// percent-encoded UTF-8 `filename*`.
+fn content_disposition(filename: &str) -> String {
+   let fallback: String = filename
+      .chars()
+      .map(|c| if c.is_ascii() && c != '\\' { c } else { '_' })
+      .collect();
+
+   let mut encoded = String::new();
+   for byte in filename.bytes() {
+      if byte.is_ascii_alphanumeric() || b"!#$&+-.^_`|~".contains(&byte) {
+         encoded.push(byte as char);
+      } else {
+         encoded.push_str(&format!("%{:02X}", byte));
+      }
+   }
+
+   format!(
+      "attachment; filename=\"{}\"; filename*=UTF-8''{}",
+      fallback, encoded
+   )
+}
+
+#[utoipa::path(
+   get,
+   path = "/tasks/{id}/attachments",
+   tag = "attachments",
+   params(
+      ("id" = String, Path, description = "Task id")
+   ),
+   security(("token" = [])),
+   responses(
+      (status = 200, description = "The files attached to the task", body = [AttachmentFormated]),
+      (status = 404, description = "The task does not exist or is not shared with the user", body = ErrorFormated),
+      (status = 400, description = "Invalid request", body = ErrorFormated)
+   )
+)]
+pub async fn list_attachments(
+   req: Request<Body>,
+   conn: Arc<Mutex<Connection>>,
+   task_id: String,
+) -> Result<Response<Body>, Infallible> {
+   let headers = req.headers();
+   let mut _user_id = String::from("");
+
+   let result = valid_user(headers, &conn).await;
+
+   match result {
+      Ok(data) => match data {
+         ValidResponse::Id(id) => {
+            let users = get_users(conn.clone(), id.clone(), String::from("id")).await;
+
+            if users.is_empty() {
+               return create_error("this user not exists", None);
+            }
+
+            _user_id = id;
+         }
+         ValidResponse::Respo(data) => return data,
+      },
+      _ => return create_error("", None),
+   }
+
+   let conn = conn.lock().await;
+
+   if let Err((status, error)) =
+      find_permitted_task(&conn, &task_id, &_user_id, Role::Viewer, false)
+   {
+      return create_error(error, Option::from(status));
+   }
+
+   let mut query = conn.prepare(&format!("SELECT {}, users.id, users.firstname, users.lastname, users.email, users.password FROM attachments INNER JOIN users ON users.id = attachments.user_id WHERE attachments.task_id = ? ORDER BY attachments.created_at ASC, attachments.rowid ASC", ATTACHMENT_COLUMNS)).unwrap();
+   let data = query.query_map([&task_id], attachment_from_row).unwrap();
+
+   let mut attachments: Vec<AttachmentFormated> = vec![];
+   for attachment in data {
+      attachments.push(attachment.unwrap().format());
+   }
+
+   let json = serde_json::to_string(&attachments);
+
+   valid_json(json)
+}
+
+/// Accepts a `multipart/form-data` body and attaches every file part in it.
+#[utoipa::path(
+   post,
+   path = "/tasks/{id}/attachments",
+   tag = "attachments",
+   params(
+      ("id" = String, Path, description = "Task id")
+   ),
+   request_body(content_type = "multipart/form-data", description = "One or more files"),
+   security(("token" = [])),
+   responses(
+      (status = 201, description = "The attached files", body = [AttachmentFormated]),
+      (status = 403, description = "The user's role does not allow it", body = ErrorFormated),
+      (status = 404, description = "The task does not exist or is not shared with the user", body = ErrorFormated),
+      (status = 413, description = "A file is too large", body = ErrorFormated),
+      (status = 400, description = "Invalid request", body = ErrorFormated)
+   )
+)]
+pub async fn upload_attachments(
+   req: Request<Body>,
+   conn: Arc<Mutex<Connection>>,
+   task_id: String,
+) -> Result<Response<Body>, Infallible> {
+   let (head, body) = req.into_parts();
+   let mut _user = None;
+
+   let result = valid_user(&head.headers, &conn).await;
+
+   match result {
+      Ok(data) => match data {
+         ValidResponse::Id(id) => {
+            let users = get_users(conn.clone(), id.clone(), String::from("id")).await;
+
+            if users.is_empty() {
+               return create_error("this user not exists", None);
+            }
+
+            _user = users.into_iter().next();
+         }
+         ValidResponse::Respo(data) => return data,
+      },
+      _ => return create_error("", None),
+   }
+
+   let user = _user.unwrap();
+
+   let boundary = head
+      .headers
+      .get("content-type")
+      .and_then(|content_type| content_type.to_str().ok())
+      .and_then(|content_type| multer::parse_boundary(content_type).ok());
+
+   let boundary = match boundary {
+      Some(boundary) => boundary,
+      None => {
+         return create_error(
+            "content type must be multipart/form-data",
+            Option::from(415),
+         )
+      }
+   };
+
+   // Checked up front so that a forbidden upload is not streamed to disk;
+   // checked again before the rows are written.
+   {
+      let conn = conn.lock().await;
+
+      if let Err((status, error)) =
+         find_permitted_task(&conn, &task_id, &user.id, Role::Editor, false)
+      {
+         return create_error(error, Option::from(status));
+      }
+   }
+
+   let mut multipart = Multipart::new(body, boundary);
+   let mut attachments: Vec<Attachment> = vec![];
+   let mut stored: Vec<StoredBlob> = vec![];
+   let mut failure: Option<(u16, &'static str)> = None;
+
+   loop {
+      let mut field = match multipart.next_field().await {
+         Ok(Some(field)) => field,
+         Ok(None) => break,
+         Err(_) => {
+            failure = Option::from((400, "upload invalid"));
+            break;
+         }
+      };
+
+      let filename = match field.file_name() {
+         Some(filename) => sanitize_filename(filename),
+         None => continue,
+      };
+
+      if attachments.len() == ATTACHMENT_MAX_FILES {
+         failure = Option::from((413, "too many files"));
+         break;
+      }
+
+      let content_type = field
+         .content_type()
+         .map(|content_type| content_type.essence_str().to_lowercase())
+         .unwrap_or_default();
+
+      if !CONFIG.attachment_types.contains(&content_type) {
+         failure = Option::from((415, "file type not allowed"));
+         break;
+      }
+
+      match store_field(&mut field).await {
+         Ok(blob) => {
+            attachments.push(Attachment {
+               id: Uuid::new_v4().to_string(),
+               task_id: task_id.clone(),
+               user_id: user.id.clone(),
+               filename,
+               content_type,
+               size: blob.size as i64,
+               sha256: blob.sha256.clone(),
+               created_at: now(),
+               user: Option::from(user.clone()),
+            });
+            stored.push(blob);
+         }
+         Err(StoreError::TooLarge) => failure = Option::from((413, "file too large")),
+         Err(StoreError::Upload) => failure = Option::from((400, "upload invalid")),
+         Err(StoreError::Io(e)) => {
+            eprintln!("Attachment Error: {}", e);
+
+            failure = Option::from((500, "error on store this file"));
+         }
+      }
+
+      if failure.is_some() {
+         break;
+      }
+   }
+
+   if failure.is_none() && attachments.is_empty() {
+      failure = Option::from((400, "file is necessary"));
+   }
+
+   let blobs: HashSet<String> = attachments
+      .iter()
+      .map(|attachment| attachment.sha256.clone())
+      .collect();
+
+   if let Some((status, error)) = failure {
+      discard_blobs(stored).await;
+
+      return create_error(error, Option::from(status));
+   }
+
+   let mut conn = conn.lock().await;
+
+   let transaction = match conn.transaction() {
+      Ok(transaction) => transaction,
+      _ => {
+         tokio::spawn(discard_blobs(stored));
+
+         return create_error("", None);
+      }
+   };
+
+   let task = match find_permitted_task(&transaction, &task_id, &user.id, Role::Editor, false) {
+      Ok((task, _)) => task,
+      Err((status, error)) => {
+         tokio::spawn(discard_blobs(stored));
+
+         return create_error(error, Option::from(status));
+      }
+   };
+
+   // Placed while the connection is locked, until the rows pointing to the
+   // blobs are written.
+   if let Err(e) = stored.iter().try_for_each(place_blob) {
+      eprintln!("Attachment Error: {}", e);
+
+      drop(transaction);
+      remove_unreferenced_blobs(&conn, &blobs);
+      tokio::spawn(discard_blobs(stored));
+
+      return create_error("error on store this file", Option::from(500));
+   }
+
+   let mut query = Ok(());
+   let mut wakeups = Wakeups::default();
+
+   for attachment in attachments.iter() {
+      query = transaction
+         .execute(
+            "INSERT INTO attachments (id, task_id, user_id, filename, content_type, size, sha256, created_at) VALUES (?, ?, ?, ?, ?, ?, ?, ?)",
+            params![
+               attachment.id,
+               attachment.task_id,
+               attachment.user_id,
+               attachment.filename,
+               attachment.content_type,
+               attachment.size,
+               attachment.sha256,
+               attachment.created_at
+            ],
+         )
+         .and_then(|_| {
+            record_task_event(
+               &transaction,
+               &task,
+               &user.id,
+               "attachment_added",
+               json!({ "attachment_id": attachment.id, "filename": attachment.filename }),
+            )
+         })
+         .map(|added| wakeups.add(added));
+
+      if query.is_err() {
+         break;
+      }
+   }
+
+   let query = query.and_then(|_| transaction.commit());
+
+   if query.is_err() {
+      remove_unreferenced_blobs(&conn, &blobs);
+
+      return create_error("not is possible attach this file", None);
+   }
+
+   wakeups.send();
+
+   let attachments: Vec<AttachmentFormated> = attachments
+      .into_iter()
+      .map(|attachment| attachment.format())
+      .collect();
+
+   match serde_json::to_string(&attachments) {
+      Ok(json) => Ok(Response::builder()
+         .status(201)
+         .body(Body::from(json))
+         .unwrap()),
+      _ => create_error("", None),
+   }
+}
+
+#[utoipa::path(
+   get,
+   path = "/attachments/{id}",
+   tag = "attachments",
+   params(
+      ("id" = String, Path, description = "Attachment id")
+   ),
+   security(("token" = [])),
+   responses(
+      (status = 200, description = "The file", body = Vec<u8>, content_type = "application/octet-stream"),
+      (status = 304, description = "Matches `If-None-Match`"),
+      (status = 404, description = "The attachment does not exist or is not shared with the user", body = ErrorFormated),
+      (status = 400, description = "Invalid request", body = ErrorFormated)
+   )
+)]
+pub async fn download_attachment(
+   req: Request<Body>,
+   conn: Arc<Mutex<Connection>>,
+   attachment_id: String,
+) -> Result<Response<Body>, Infallible> {
+   let headers = req.headers();
+   let mut _user_id = String::from("");
+
+   let result = valid_user(headers, &conn).await;
+
+   match result {
+      Ok(data) => match data {
+         ValidResponse::Id(id) => {
+            let users = get_users(conn.clone(), id.clone(), String::from("id")).await;
+
+            if users.is_empty() {
+               return create_error("this user not exists", None);
+            }
+
+            _user_id = id;
+         }
+         ValidResponse::Respo(data) => return data,
+      },
+      _ => return create_error("", None),
+   }
+
+   let attachment = {
+      let conn = conn.lock().await;
+
+      let attachment = match find_attachment(&conn, &attachment_id) {
+         Some(attachment) => attachment,
+         None => return create_error("this attachment not exists", Option::from(404)),
+      };
+
+      if find_permitted_task(&conn, &attachment.task_id, &_user_id, Role::Viewer, false).is_err() {
+         return create_error("this attachment not exists", Option::from(404));
+      }
+
+      attachment
+   };
+
+   // The content never changes for a given hash, so it is a strong validator.
+   let etag = format!("\"{}\"", attachment.sha256);
+
+   if etag_matches(headers, "if-none-match", &etag, true) == Some(true) {
+      return Ok(Response::builder()
+         .status(304)
+         .header("ETag", etag)
+         .body(Body::empty())
+         .unwrap());
+   }
+
+   let body = match blob_body(&attachment.sha256).await {
+      Ok(body) => body,
+      Err(e) => {
+         eprintln!("Attachment Error: {}", e);
+
+         return create_error("this attachment not exists", Option::from(404));
+      }
+   };
+
+   Ok(Response::builder()
+      .status(200)
+      .header("Content-Type", attachment.content_type.as_str())
+      .header("Content-Length", attachment.size)
+      .header(
+         "Content-Disposition",
+         content_disposition(&attachment.filename),
+      )
+      .header("X-Content-Type-Options", "nosniff")
+      .header("ETag", etag)
+      .body(body)
+      .unwrap())
+}
+
+#[utoipa::path(
+   delete,
+   path = "/attachments/{id}",
+   tag = "attachments",
+   params(
+      ("id" = String, Path, description = "Attachment id")
+   ),
+   security(("token" = [])),
+   responses(
+      (status = 200, description = "The attachment was deleted"),
+      (status = 403, description = "The user's role does not allow it", body = ErrorFormated),
+      (status = 404, description = "The attachment does not exist or is not shared with the user", body = ErrorFormated),
+      (status = 400, description = "Invalid request", body = ErrorFormated)
+   )
+)]
+pub async fn delete_attachment(
+   req: Request<Body>,
+   conn: Arc<Mutex<Connection>>,
+   attachment_id: String,
+) -> Result<Response<Body>, Infallible> {
+   let headers = req.headers();
+   let mut _user_id = String::from("");
+
+   let result = valid_user(headers, &conn).await;
+
+   match result {
+      Ok(data) => match data {
+         ValidResponse::Id(id) => {
+            let users = get_users(conn.clone(), id.clone(), String::from("id")).await;
+
+            if users.is_empty() {
+               return create_error("this user not exists", None);
+            }
+
+            _user_id = id;
+         }
+         ValidResponse::Respo(data) => return data,
+      },
+      _ => return create_error("", None),
+   }
+
+   let mut conn = conn.lock().await;
+
+   let transaction = match conn.transaction() {
+      Ok(transaction) => transaction,
+      _ => return create_error("", None),
+   };
+
+   let attachment = match find_attachment(&transaction, &attachment_id) {
+      Some(attachment) => attachment,
+      None => return create_error("this attachment not exists", Option::from(404)),
+   };
+
+   let task = match find_permitted_task(
+      &transaction,
+      &attachment.task_id,
+      &_user_id,
+      Role::Editor,
+      false,
+   ) {
+      Ok((task, _)) => task,
+      Err((403, error)) => return create_error(error, Option::from(403)),
+      Err(_) => return create_error("this attachment not exists", Option::from(404)),
+   };
+
+   let query = transaction
+      .execute("DELETE FROM attachments WHERE id = ?", [&attachment.id])
+      .and_then(|_| {
+         record_task_event(
+            &transaction,
+            &task,
+            &_user_id,
+            "attachment_deleted",
+            json!({ "attachment_id": attachment.id, "filename": attachment.filename }),
+         )
+      })
+      .and_then(|wakeups| transaction.commit().map(|_| wakeups));
+
+   match query {
+      Ok(wakeups) => {
+         wakeups.send();
+         remove_unreferenced_blobs(&conn, &std::iter::once(attachment.sha256).collect());
+
+         Ok(Response::builder()
+            .status(200)
+            .body(Body::from(""))
+            .unwrap())
+      }
+      _ => create_error("error on delete this attachment", None),
+   }
+}
@@ -0,0 +1,358 @@
+use super::super::middlewares::users::{valid_user, ValidResponse};
+use super::super::utils::events::Wakeups;
+use super::super::utils::ical::{parse_calendar, write_calendar, Component};
+use super::super::utils::permissions::{find_visible_task, VISIBLE_TASKS};
+use super::super::utils::{
+   create_error, get_query_params, get_users, random_token, read_body, ErrorFormated,
+};
+use super::super::views::calendar::{CalendarTokenFormated, ImportReport, ImportSkipped};
+use super::super::views::tasks::{TaskCreated, TASK_COLUMNS};
+use super::tasks::{insert_task, validate_new_task};
+
+use std::convert::{From, Infallible};
+use std::sync::Arc;
+
+use futures::lock::Mutex;
+
+use rusqlite::{Connection, OptionalExtension};
+
+use hyper::{Body, Request, Response};
+
+const ICS_IMPORT_MAX_BYTES: usize = 1024 * 1024;
+const ICS_IMPORT_MAX_TASKS: usize = 1000;
+
+// UIDs written by the feed, so a calendar exported from here and imported
+// back does not duplicate the tasks.
+const UID_SUFFIX: &str = "@todo-api";
+
+#[utoipa::path(
+   post,
+   path = "/calendar/token",
+   tag = "calendar",
+   security(("token" = [])),
+   responses(
+      (status = 201, description = "The feed token, replacing any previous one", body = CalendarTokenFormated),
+      (status = 400, description = "Invalid request", body = ErrorFormated)
+   )
+)]
+pub async fn create_calendar_token(
+   req: Request<Body>,
+   conn: Arc<Mutex<Connection>>,
+) -> Result<Response<Body>, Infallible> {
+   let headers = req.headers();
+   let mut _user_id = String::from("");
+
+   let result = valid_user(headers, &conn).await;
+
+   match result {
+      Ok(data) => match data {
+         ValidResponse::Id(id) => {
+            let users = get_users(conn.clone(), id.clone(), String::from("id")).await;
+
+            if users.is_empty() {
+               return create_error("this user not exists", None);
+            }
+
+            _user_id = id;
+         }
+         ValidResponse::Respo(data) => return data,
+      },
+      _ => return create_error("", None),
+   }
+
+   let conn = conn.lock().await;
+
+   // Creating a token again replaces the old one, so a leaked feed URL can be
+   // cut off without deleting the feed.
+   let token = random_token();
+
+   let query = conn.execute(
+      "UPDATE users SET calendar_token = ? WHERE id = ?",
+      [&token, &_user_id],
+   );
+
+   match query {
+      Ok(_) => {
+         let json = serde_json::to_string(&CalendarTokenFormated {
+            path: format!("/calendar/{}.ics", token),
+            token,
+         });
+
+         match json {
+            Ok(json) => Ok(Response::builder()
+               .status(201)
+               .body(Body::from(json))
+               .unwrap()),
+            _ => create_error("", None),
+         }
+      }
+      _ => create_error("not is possible create this calendar token", None),
+   }
+}
+
+#[utoipa::path(
+   delete,
+   path = "/calendar/token",
+   tag = "calendar",
+   security(("token" = [])),
+   responses(
+      (status = 200, description = "The feed was revoked"),
+      (status = 400, description = "Invalid request", body = ErrorFormated)
+   )
+)]
+pub async fn delete_calendar_token(
+   req: Request<Body>,
+   conn: Arc<Mutex<Connection>>,
+) -> Result<Response<Body>, Infallible> {
+   let headers = req.headers();
+   let mut _user_id = String::from("");
+
+   let result = valid_user(headers, &conn).await;
+
+   match result {
+      Ok(data) => match data {
+         ValidResponse::Id(id) => {
+            let users = get_users(conn.clone(), id.clone(), String::from("id")).await;
+
+            if users.is_empty() {
+               return create_error("this user not exists", None);
+            }
+
+            _user_id = id;
+         }
+         ValidResponse::Respo(data) => return data,
+      },
+      _ => return create_error("", None),
+   }
+
+   let conn = conn.lock().await;
+
+   let query = conn.execute(
+      "UPDATE users SET calendar_token = NULL WHERE id = ?",
+      [&_user_id],
+   );
+
+   match query {
+      Ok(_) => Ok(Response::builder()
+         .status(200)
+         .body(Body::from(""))
+         .unwrap()),
+      _ => create_error("not is possible delete this calendar token", None),
+   }
+}
+
+/// Serves the calendar feed of the user owning `token`. The token in the URL
+/// is the only credential: calendar apps cannot send an Authorization
+/// header. `?component=vevent` switches from todos to events for calendars
+/// that do not show todos.
+#[utoipa::path(
+   get,
+   path = "/calendar/{token}.ics",
+   tag = "calendar",
+   params(
+      ("token" = String, Path, description = "Feed token"),
+      ("component" = Option<String>, Query, description = "`vtodo` (default) or `vevent`")
+   ),
+   responses(
+      (status = 200, description = "The iCalendar feed", body = String, content_type = "text/calendar"),
+      (status = 404, description = "Unknown token", body = ErrorFormated),
+      (status = 400, description = "Invalid request", body = ErrorFormated)
+   )
+)]
+pub async fn calendar_feed(
+   req: Request<Body>,
+   conn: Arc<Mutex<Connection>>,
+   token: String,
+) -> Result<Response<Body>, Infallible> {
+   let token = token.strip_suffix(".ics").unwrap_or(&token).to_string();
+
+   let component = match get_query_params(&req).get("component") {
+      Some(component) => match Component::parse(component) {
+         Some(component) => component,
+         None => return create_error("component invalid", None),
+      },
+      None => Component::Todo,
+   };
+
+   let conn = conn.lock().await;
+
+   let user: Option<(String, String)> = conn
+      .query_row(
+         "SELECT id, firstname FROM users WHERE calendar_token = ? AND deleted_at IS NULL",
+         [&token],
+         |row| Ok((row.get(0)?, row.get(1)?)),
+      )
+      .optional()
+      .unwrap();
+
+   let (user_id, firstname) = match user {
+      Some(user) => user,
+      None => return create_error("this calendar not exists", Option::from(404)),
+   };
+
+   let mut query = conn
+      .prepare(&format!(
+         "SELECT {} FROM tasks WHERE tasks.deleted_at IS NULL AND tasks.due_date IS NOT NULL AND {} ORDER BY tasks.due_date",
+         TASK_COLUMNS, VISIBLE_TASKS
+      ))
+      .unwrap();
+
+   let tasks: Vec<TaskCreated> = query
+      .query_map([&user_id], |row| TaskCreated::from_row(row, 0))
+      .unwrap()
+      .filter_map(|task| task.ok())
+      .collect();
+
+   let calendar = write_calendar(&format!("Tasks of {}", firstname), &tasks, component);
+
+   Ok(Response::builder()
+      .status(200)
+      .header("Content-Type", "text/calendar; charset=utf-8")
+      .header("Cache-Control", "private, max-age=300")
+      .body(Body::from(calendar))
+      .unwrap())
+}
+
+/// Creates a task for every `VTODO` and `VEVENT` of the uploaded calendar,
+/// in a single transaction. Components that cannot become a task are
+/// reported back instead of failing the whole import.
+#[utoipa::path(
+   post,
+   path = "/import/ics",
+   tag = "calendar",
+   request_body(content = String, content_type = "text/calendar"),
+   security(("token" = [])),
+   responses(
+      (status = 201, description = "The imported and skipped tasks", body = ImportReport),
+      (status = 413, description = "The calendar is too large", body = ErrorFormated),
+      (status = 415, description = "The body is not text/calendar", body = ErrorFormated),
+      (status = 400, description = "Invalid request", body = ErrorFormated)
+   )
+)]
+pub async fn import_calendar(
+   req: Request<Body>,
+   conn: Arc<Mutex<Connection>>,
+) -> Result<Response<Body>, Infallible> {
+   let (head, body) = req.into_parts();
+   let mut _user_id = String::from("");
+
+   let result = valid_user(&head.headers, &conn).await;
+
+   match result {
+      Ok(data) => match data {
+         ValidResponse::Id(id) => {
+            let users = get_users(conn.clone(), id.clone(), String::from("id")).await;
+
+            if users.is_empty() {
+               return create_error("this user not exists", None);
+            }
+
+            _user_id = id;
+         }
+         ValidResponse::Respo(data) => return data,
+      },
+      _ => return create_error("", None),
+   }
+
+   let content_type = head
+      .headers
+      .get("content-type")
+      .and_then(|content_type| content_type.to_str().ok())
+      .map(|content_type| content_type.to_lowercase())
+      .unwrap_or_default();
+
+   if !content_type.starts_with("text/calendar") {
+      return create_error("content type must be text/calendar", Option::from(415));
+   }
+
+   let data = match read_body(body, ICS_IMPORT_MAX_BYTES).await {
+      Ok(data) => data,
+      Err((status, error)) => return create_error(error, Option::from(status)),
+   };
+
+   let text = match String::from_utf8(data) {
+      Ok(text) => text,
+      _ => return create_error("calendar must be utf-8", None),
+   };
+
+   let imported = match parse_calendar(&text) {
+      Ok(imported) => imported,
+      Err(error) => return create_error(error, None),
+   };
+
+   if imported.len() > ICS_IMPORT_MAX_TASKS {
+      return create_error("too many tasks in this calendar", Option::from(413));
+   }
+
+   let mut conn = conn.lock().await;
+
+   let transaction = match conn.transaction() {
+      Ok(transaction) => transaction,
+      _ => return create_error("", None),
+   };
+
+   let mut report = ImportReport {
+      created: vec![],
+      skipped: vec![],
+   };
+   let mut wakeups = Wakeups::default();
+
+   for (index, item) in imported.into_iter().enumerate() {
+      let uid = item.uid.clone();
+
+      let mut skip = |error: &'static str| {
+         report.skipped.push(ImportSkipped {
+            index,
+            uid: uid.clone(),
+            error,
+         })
+      };
+
+      let exported_id = uid.as_deref().and_then(|uid| uid.strip_suffix(UID_SUFFIX));
+
+      if let Some(task_id) = exported_id {
+         if find_visible_task(&transaction, task_id, &_user_id).is_some() {
+            skip("task already exists");
+            continue;
+         }
+      }
+
+      let name = item.name.unwrap_or_default();
+
+      let (name, due_date, recurrence) =
+         match validate_new_task(&name, item.due_date, item.recurrence) {
+            Ok(task) => task,
+            Err(error) => {
+               skip(error);
+               continue;
+            }
+         };
+
+      let mut task = TaskCreated::new(&_user_id, &name);
+      task.due_date = due_date;
+      task.recurrence = recurrence;
+      task.completed = if item.completed { 1 } else { 0 };
+
+      match insert_task(&transaction, task, &_user_id) {
+         Ok((task_id, created)) => {
+            report.created.push(task_id);
+            wakeups.add(created);
+         }
+         _ => return create_error("not is possible import this calendar", None),
+      }
+   }
+
+   if transaction.commit().is_err() {
+      return create_error("not is possible import this calendar", None);
+   }
+
+   wakeups.send();
+
+   match serde_json::to_string(&report) {
+      Ok(json) => Ok(Response::builder()
+         .status(201)
+         .body(Body::from(json))
+         .unwrap()),
+      _ => create_error("", None),
+   }
+}
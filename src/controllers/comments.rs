@@ -0,0 +1,442 @@
+use super::super::middlewares::users::{valid_user, ValidResponse};
+use super::super::utils::history::record_task_event;
+use super::super::utils::permissions::{find_permitted_task, Role};
+use super::super::utils::validation::{not_blank, ValidationErrorFormated};
+use super::super::utils::{
+   body_error, create_error, get_users, now, parse_body, valid_json, ErrorFormated,
+};
+use super::super::views::comments::{
+   Comment, CommentFormated, COMMENT_COLUMNS, COMMENT_COLUMNS_COUNT,
+};
+use super::super::views::users::CreatedUser;
+
+use std::convert::{From, Infallible};
+use std::sync::Arc;
+
+use futures::lock::Mutex;
+
+use rusqlite::{params, Connection, OptionalExtension};
+
+use uuid::Uuid;
+
+use hyper::{Body, Request, Response};
+
+use utoipa::ToSchema;
+
+use validator::Validate;
+
+use serde_json::json;
+
+#[derive(Serialize, Deserialize, Debug, ToSchema, Validate)]
+#[schema(as = CommentBody)]
+struct RequestBodyComment {
+   #[validate(length(max = COMMENT_MAX_LENGTH), custom(function = "not_blank"))]
+   body: String,
+}
+
+const COMMENT_MAX_LENGTH: u64 = 10000;
+
+fn find_comment(conn: &Connection, comment_id: &str) -> Option<Comment> {
+   conn
+      .query_row(
+         &format!(
+            "SELECT {}, users.id, users.firstname, users.lastname, users.email, users.password FROM comments INNER JOIN users ON users.id = comments.user_id WHERE comments.id = ?",
+            COMMENT_COLUMNS
+         ),
+         [comment_id],
+         comment_from_row,
+      )
+      .optional()
+      .unwrap()
+}
+
+fn comment_from_row(row: &rusqlite::Row) -> rusqlite::Result<Comment> {
+   let mut comment = Comment::from_row(row, 0)?;
+
+   comment.user = Option::from(CreatedUser {
+      id: row.get(COMMENT_COLUMNS_COUNT)?,
+      firstname: row.get(COMMENT_COLUMNS_COUNT + 1)?,
+      lastname: row.get(COMMENT_COLUMNS_COUNT + 2)?,
+      email: row.get(COMMENT_COLUMNS_COUNT + 3)?,
+      password: row.get(COMMENT_COLUMNS_COUNT + 4)?,
+   });
+
+   Ok(comment)
+}
+
+#[utoipa::path(
+   get,
+   path = "/tasks/{id}/comments",
+   tag = "comments",
+   params(
+      ("id" = String, Path, description = "Task id")
+   ),
+   security(("token" = [])),
+   responses(
+      (status = 200, description = "The comments of the task, oldest first", body = [CommentFormated]),
+      (status = 404, description = "The task does not exist or is not shared with the user", body = ErrorFormated),
+      (status = 400, description = "Invalid request", body = ErrorFormated)
+   )
+)]
+pub async fn list_comments(
+   req: Request<Body>,
+   conn: Arc<Mutex<Connection>>,
+   task_id: String,
+) -> Result<Response<Body>, Infallible> {
+   let headers = req.headers();
+   let mut _user_id = String::from("");
+
+   let result = valid_user(headers, &conn).await;
+
+   match result {
+      Ok(data) => match data {
+         ValidResponse::Id(id) => {
+            let users = get_users(conn.clone(), id.clone(), String::from("id")).await;
+
+            if users.is_empty() {
+               return create_error("this user not exists", None);
+            }
+
+            _user_id = id;
+         }
+         ValidResponse::Respo(data) => return data,
+      },
+      _ => return create_error("", None),
+   }
+
+   let conn = conn.lock().await;
+
+   if let Err((status, error)) =
+      find_permitted_task(&conn, &task_id, &_user_id, Role::Viewer, false)
+   {
+      return create_error(error, Option::from(status));
+   }
+
+   let mut query = conn.prepare(&format!("SELECT {}, users.id, users.firstname, users.lastname, users.email, users.password FROM comments INNER JOIN users ON users.id = comments.user_id WHERE comments.task_id = ? AND users.deleted_at IS NULL ORDER BY comments.created_at ASC, comments.rowid ASC", COMMENT_COLUMNS)).unwrap();
+   let data = query.query_map([&task_id], comment_from_row).unwrap();
+
+   let mut comments: Vec<CommentFormated> = vec![];
+   for comment in data {
+      comments.push(comment.unwrap().format());
+   }
+
+   let json = serde_json::to_string(&comments);
+
+   valid_json(json)
+}
+
+#[utoipa::path(
+   post,
+   path = "/tasks/{id}/comments",
+   tag = "comments",
+   params(
+      ("id" = String, Path, description = "Task id")
+   ),
+   request_body = RequestBodyComment,
+   security(("token" = [])),
+   responses(
+      (status = 201, description = "The comment", body = CommentFormated),
+      (status = 403, description = "The user's role does not allow it", body = ErrorFormated),
+      (status = 404, description = "The task does not exist or is not shared with the user", body = ErrorFormated),
+      (status = 400, description = "Invalid request", body = ErrorFormated),
+      (status = 422, description = "Fields that break their rules", body = ValidationErrorFormated),
+      (status = 413, description = "Body larger than `BODY_MAX_BYTES`", body = ErrorFormated),
+      (status = 415, description = "Body not sent as JSON", body = ErrorFormated)
+   )
+)]
+pub async fn create_comment(
+   req: Request<Body>,
+   conn: Arc<Mutex<Connection>>,
+   task_id: String,
+) -> Result<Response<Body>, Infallible> {
+   let (head, body) = req.into_parts();
+   let mut _user = None;
+
+   let result = valid_user(&head.headers, &conn).await;
+
+   match result {
+      Ok(data) => match data {
+         ValidResponse::Id(id) => {
+            let users = get_users(conn.clone(), id.clone(), String::from("id")).await;
+
+            if users.is_empty() {
+               return create_error("this user not exists", None);
+            }
+
+            _user = users.into_iter().next();
+         }
+         ValidResponse::Respo(data) => return data,
+      },
+      _ => return create_error("", None),
+   }
+
+   let body = parse_body::<RequestBodyComment>(&head.headers, body).await;
+
+   let user = _user.unwrap();
+
+   let body = match body {
+      Ok(RequestBodyComment { body }) => body,
+      Err(error) => return body_error(error),
+   };
+
+   let mut conn = conn.lock().await;
+
+   let transaction = match conn.transaction() {
+      Ok(transaction) => transaction,
+      _ => return create_error("", None),
+   };
+
+   let task = match find_permitted_task(&transaction, &task_id, &user.id, Role::Viewer, false) {
+      Ok((task, _)) => task,
+      Err((status, error)) => return create_error(error, Option::from(status)),
+   };
+
+   let comment = Comment {
+      id: Uuid::new_v4().to_string(),
+      task_id: task.id.clone(),
+      user_id: user.id.clone(),
+      body,
+      created_at: now(),
+      updated_at: None,
+      user: Option::from(user),
+   };
+
+   let query = transaction
+      .execute(
+         "INSERT INTO comments (id, task_id, user_id, body, created_at) VALUES (?, ?, ?, ?, ?)",
+         params![
+            comment.id,
+            comment.task_id,
+            comment.user_id,
+            comment.body,
+            comment.created_at
+         ],
+      )
+      .and_then(|_| {
+         record_task_event(
+            &transaction,
+            &task,
+            &comment.user_id,
+            "commented",
+            json!({ "comment_id": comment.id }),
+         )
+      })
+      .and_then(|wakeups| transaction.commit().map(|_| wakeups));
+
+   match query {
+      Ok(wakeups) => {
+         wakeups.send();
+
+         match serde_json::to_string(&comment.format()) {
+            Ok(json) => Ok(Response::builder()
+               .status(201)
+               .body(Body::from(json))
+               .unwrap()),
+            _ => create_error("", None),
+         }
+      }
+      _ => create_error("not is possible create this comment", None),
+   }
+}
+
+#[utoipa::path(
+   put,
+   path = "/comments/{id}",
+   tag = "comments",
+   params(
+      ("id" = String, Path, description = "Comment id")
+   ),
+   request_body = RequestBodyComment,
+   security(("token" = [])),
+   responses(
+      (status = 200, description = "The comment", body = CommentFormated),
+      (status = 403, description = "Only the author may edit a comment", body = ErrorFormated),
+      (status = 404, description = "The comment does not exist or is not shared with the user", body = ErrorFormated),
+      (status = 400, description = "Invalid request", body = ErrorFormated),
+      (status = 422, description = "Fields that break their rules", body = ValidationErrorFormated),
+      (status = 413, description = "Body larger than `BODY_MAX_BYTES`", body = ErrorFormated),
+      (status = 415, description = "Body not sent as JSON", body = ErrorFormated)
+   )
+)]
+pub async fn update_comment(
+   req: Request<Body>,
+   conn: Arc<Mutex<Connection>>,
+   comment_id: String,
+) -> Result<Response<Body>, Infallible> {
+   let (head, body) = req.into_parts();
+   let mut _user_id = String::from("");
+
+   let result = valid_user(&head.headers, &conn).await;
+
+   match result {
+      Ok(data) => match data {
+         ValidResponse::Id(id) => {
+            let users = get_users(conn.clone(), id.clone(), String::from("id")).await;
+
+            if users.is_empty() {
+               return create_error("this user not exists", None);
+            }
+
+            _user_id = id;
+         }
+         ValidResponse::Respo(data) => return data,
+      },
+      _ => return create_error("", None),
+   }
+
+   let body = parse_body::<RequestBodyComment>(&head.headers, body).await;
+   let body = match body {
+      Ok(RequestBodyComment { body }) => body,
+      Err(error) => return body_error(error),
+   };
+
+   let mut conn = conn.lock().await;
+
+   let transaction = match conn.transaction() {
+      Ok(transaction) => transaction,
+      _ => return create_error("", None),
+   };
+
+   let mut comment = match find_comment(&transaction, &comment_id) {
+      Some(comment) => comment,
+      None => return create_error("this comment not exists", Option::from(404)),
+   };
+
+   // Losing access to the task also hides its comments from their authors.
+   let task = match find_permitted_task(
+      &transaction,
+      &comment.task_id,
+      &_user_id,
+      Role::Viewer,
+      false,
+   ) {
+      Ok((task, _)) => task,
+      Err(_) => return create_error("this comment not exists", Option::from(404)),
+   };
+
+   if comment.user_id != _user_id {
+      return create_error("you not have permission for to follow", Option::from(403));
+   }
+
+   let changes = json!({ "comment_id": comment.id, "body": { "from": comment.body, "to": body } });
+
+   comment.body = body;
+   comment.updated_at = Option::from(now());
+
+   let query = transaction
+      .execute(
+         "UPDATE comments SET body = ?, updated_at = ? WHERE id = ?",
+         params![comment.body, comment.updated_at, comment.id],
+      )
+      .and_then(|_| record_task_event(&transaction, &task, &_user_id, "comment_edited", changes))
+      .and_then(|wakeups| transaction.commit().map(|_| wakeups));
+
+   match query {
+      Ok(wakeups) => {
+         wakeups.send();
+
+         match serde_json::to_string(&comment.format()) {
+            Ok(json) => Ok(Response::builder()
+               .status(200)
+               .body(Body::from(json))
+               .unwrap()),
+            _ => create_error("", None),
+         }
+      }
+      _ => create_error("error on update this comment", None),
+   }
+}
+
+#[utoipa::path(
+   delete,
+   path = "/comments/{id}",
+   tag = "comments",
+   params(
+      ("id" = String, Path, description = "Comment id")
+   ),
+   security(("token" = [])),
+   responses(
+      (status = 200, description = "The comment was deleted"),
+      (status = 403, description = "The user's role does not allow it", body = ErrorFormated),
+      (status = 404, description = "The comment does not exist or is not shared with the user", body = ErrorFormated),
+      (status = 400, description = "Invalid request", body = ErrorFormated)
+   )
+)]
+pub async fn delete_comment(
+   req: Request<Body>,
+   conn: Arc<Mutex<Connection>>,
+   comment_id: String,
+) -> Result<Response<Body>, Infallible> {
+   let headers = req.headers();
+   let mut _user_id = String::from("");
+
+   let result = valid_user(headers, &conn).await;
+
+   match result {
+      Ok(data) => match data {
+         ValidResponse::Id(id) => {
+            let users = get_users(conn.clone(), id.clone(), String::from("id")).await;
+
+            if users.is_empty() {
+               return create_error("this user not exists", None);
+            }
+
+            _user_id = id;
+         }
+         ValidResponse::Respo(data) => return data,
+      },
+      _ => return create_error("", None),
+   }
+
+   let mut conn = conn.lock().await;
+
+   let transaction = match conn.transaction() {
+      Ok(transaction) => transaction,
+      _ => return create_error("", None),
+   };
+
+   let comment = match find_comment(&transaction, &comment_id) {
+      Some(comment) => comment,
+      None => return create_error("this comment not exists", Option::from(404)),
+   };
+
+   let task = match find_permitted_task(
+      &transaction,
+      &comment.task_id,
+      &_user_id,
+      Role::Viewer,
+      false,
+   ) {
+      Ok((task, _)) => task,
+      Err(_) => return create_error("this comment not exists", Option::from(404)),
+   };
+
+   if comment.user_id != _user_id {
+      return create_error("you not have permission for to follow", Option::from(403));
+   }
+
+   let query = transaction
+      .execute("DELETE FROM comments WHERE id = ?", [&comment.id])
+      .and_then(|_| {
+         record_task_event(
+            &transaction,
+            &task,
+            &_user_id,
+            "comment_deleted",
+            json!({ "comment_id": comment.id }),
+         )
+      })
+      .and_then(|wakeups| transaction.commit().map(|_| wakeups));
+
+   match query {
+      Ok(wakeups) => {
+         wakeups.send();
+
+         Ok(Response::builder()
+            .status(200)
+            .body(Body::from(""))
+            .unwrap())
+      }
+      _ => create_error("error on delete this comment", None),
+   }
+}
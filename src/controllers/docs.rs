@@ -0,0 +1,40 @@
+use super::super::openapi::OPENAPI_JSON;
+
+use std::convert::Infallible;
+
+use hyper::{Body, Request, Response};
+
+// Loads Swagger UI from its CDN and points it at `/openapi.json`.
+const SWAGGER_UI: &str = include_str!("swagger_ui.html");
+
+#[utoipa::path(
+   get,
+   path = "/openapi.json",
+   tag = "docs",
+   responses(
+      (status = 200, description = "This document", body = Object)
+   )
+)]
+pub async fn openapi_json(_req: Request<Body>) -> Result<Response<Body>, Infallible> {
+   Ok(Response::builder()
+      .status(200)
+      .header("Content-Type", "application/json")
+      .body(Body::from(OPENAPI_JSON.as_str()))
+      .unwrap())
+}
+
+#[utoipa::path(
+   get,
+   path = "/docs",
+   tag = "docs",
+   responses(
+      (status = 200, description = "Swagger UI for this document", body = String, content_type = "text/html")
+   )
+)]
+pub async fn swagger_ui(_req: Request<Body>) -> Result<Response<Body>, Infallible> {
+   Ok(Response::builder()
+      .status(200)
+      .header("Content-Type", "text/html; charset=utf-8")
+      .body(Body::from(SWAGGER_UI))
+      .unwrap())
+}
@@ -0,0 +1,375 @@
+use super::super::config::CONFIG;
+use super::super::middlewares::users::{valid_user, ValidResponse};
+use super::super::utils::events::{latest_event_id, subscribe, visible_task_events, Published};
+use super::super::utils::{create_error, get_query_params, get_users, ErrorFormated};
+use super::super::views::history::TaskEvent;
+
+use std::convert::{From, Infallible};
+use std::sync::Arc;
+use std::time::Duration as StdDuration;
+
+use futures::lock::Mutex;
+use futures::{SinkExt, StreamExt};
+
+use rusqlite::Connection;
+
+use hyper::body::Bytes;
+use hyper::header::HeaderValue;
+use hyper::{Body, HeaderMap, Request, Response};
+
+use tokio::sync::broadcast;
+use tokio::time::{interval, Interval};
+
+use tokio_tungstenite::tungstenite::handshake::derive_accept_key;
+use tokio_tungstenite::tungstenite::protocol::Role;
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::WebSocketStream;
+
+use serde_json::json;
+
+const EVENTS_BATCH_SIZE: i64 = 200;
+
+enum FeedItem {
+   Events(Vec<TaskEvent>),
+   Heartbeat,
+}
+
+// Follows `task_events` for one user, waking up on new events and yielding
+// a heartbeat when nothing happened for a while. Only the events published
+// for the user make it read the table.
+struct EventFeed {
+   conn: Arc<Mutex<Connection>>,
+   user_id: String,
+   last_id: i64,
+   behind: bool,
+   wake: broadcast::Receiver<Published>,
+   heartbeat: Interval,
+}
+
+impl EventFeed {
+   async fn next(&mut self) -> FeedItem {
+      loop {
+         if self.behind {
+            let events = {
+               let conn = self.conn.lock().await;
+
+               visible_task_events(&conn, &self.user_id, self.last_id, EVENTS_BATCH_SIZE)
+            };
+
+            self.behind = events.len() as i64 == EVENTS_BATCH_SIZE;
+
+            if let Some(last) = events.last() {
+               self.last_id = last.id;
+
+               return FeedItem::Events(events);
+            }
+         }
+
+         tokio::select! {
+            published = self.wake.recv() => match published {
+               Ok(published) => {
+                  if published.id > self.last_id && published.audience.contains(&self.user_id) {
+                     self.behind = true;
+                  }
+               }
+               // A lagged receiver only missed wake ups; the table has the
+               // events.
+               Err(_) => self.behind = true,
+            },
+            _ = self.heartbeat.tick() => return FeedItem::Heartbeat,
+         }
+      }
+   }
+}
+
+fn event_name(event: &TaskEvent) -> String {
+   format!("{}.{}", event.entity, event.action)
+}
+
+fn sse_frame(event: &TaskEvent) -> String {
+   format!(
+      "id: {}\nevent: {}\ndata: {}\n\n",
+      event.id,
+      event_name(event),
+      serde_json::to_string(event).unwrap_or_default()
+   )
+}
+
+fn is_websocket_upgrade(headers: &HeaderMap) -> bool {
+   let header_has = |name: &str, value: &str| {
+      headers
+         .get(name)
+         .and_then(|header| header.to_str().ok())
+         .map(|header| {
+            header
+               .split(',')
+               .any(|part| part.trim().eq_ignore_ascii_case(value))
+         })
+         .unwrap_or(false)
+   };
+
+   header_has("upgrade", "websocket") && header_has("connection", "upgrade")
+}
+
+/// `GET /events`: task events for the user's own and shared tasks, as
+/// Server-Sent Events or, when the request asks for an upgrade, over a
+/// WebSocket. `Last-Event-ID` (or `?last_event_id=`) replays what was missed
+/// since that event; `?token=` stands in for the `Authorization` header,
+/// which browsers cannot set on either kind of connection.
+#[utoipa::path(
+   get,
+   path = "/events",
+   tag = "events",
+   params(
+      ("Last-Event-ID" = Option<i64>, Header, description = "Resume after this event"),
+      ("token" = Option<String>, Query, description = "The token, for clients that cannot send headers")
+   ),
+   security(("token" = [])),
+   responses(
+      (status = 101, description = "Switched to a WebSocket"),
+      (status = 200, description = "A stream of task events", body = String, content_type = "text/event-stream"),
+      (status = 400, description = "Invalid request", body = ErrorFormated)
+   )
+)]
+pub async fn stream_events(
+   req: Request<Body>,
+   conn: Arc<Mutex<Connection>>,
+) -> Result<Response<Body>, Infallible> {
+   let params = get_query_params(&req);
+   let mut _user_id = String::from("");
+
+   let mut headers = req.headers().clone();
+
+   if !headers.contains_key("authorization") {
+      if let Some(token) = params.get("token") {
+         if let Ok(bearer) = HeaderValue::from_str(&format!("Bearer {}", token)) {
+            headers.insert("authorization", bearer);
+         }
+      }
+   }
+
+   let result = valid_user(&headers, &conn).await;
+
+   match result {
+      Ok(data) => match data {
+         ValidResponse::Id(id) => {
+            let users = get_users(conn.clone(), id.clone(), String::from("id")).await;
+
+            if users.is_empty() {
+               return create_error("this user not exists", None);
+            }
+
+            _user_id = id;
+         }
+         ValidResponse::Respo(data) => return data,
+      },
+      _ => return create_error("", None),
+   }
+
+   let last_event_id = headers
+      .get("last-event-id")
+      .and_then(|header| header.to_str().ok())
+      .map(String::from)
+      .or_else(|| params.get("last_event_id").cloned());
+
+   let last_id = match last_event_id {
+      Some(last_event_id) => match last_event_id.trim().parse::<i64>() {
+         Ok(last_id) if last_id >= 0 => last_id,
+         _ => return create_error("last event id invalid", None),
+      },
+      None => latest_event_id(&*conn.lock().await),
+   };
+
+   let heartbeat_period = StdDuration::from_secs(CONFIG.events_heartbeat_seconds.max(1));
+   let mut heartbeat = interval(heartbeat_period);
+   // The first tick of an interval completes immediately.
+   heartbeat.tick().await;
+
+   let feed = EventFeed {
+      conn,
+      user_id: _user_id,
+      last_id,
+      behind: true,
+      wake: subscribe(),
+      heartbeat,
+   };
+
+   if is_websocket_upgrade(&headers) {
+      websocket(req, feed)
+   } else {
+      server_sent_events(feed)
+   }
+}
+
+fn server_sent_events(mut feed: EventFeed) -> Result<Response<Body>, Infallible> {
+   let (mut sender, body) = Body::channel();
+
+   tokio::spawn(async move {
+      let retry = format!("retry: {}\n\n", CONFIG.events_heartbeat_seconds * 1000);
+
+      if sender.send_data(Bytes::from(retry)).await.is_err() {
+         return;
+      }
+
+      loop {
+         let frames = match feed.next().await {
+            FeedItem::Events(events) => events.iter().map(sse_frame).collect::<String>(),
+            FeedItem::Heartbeat => String::from(": heartbeat\n\n"),
+         };
+
+         // Fails once the client went away.
+         if sender.send_data(Bytes::from(frames)).await.is_err() {
+            break;
+         }
+      }
+   });
+
+   Ok(Response::builder()
+      .status(200)
+      .header("Content-Type", "text/event-stream")
+      .header("Cache-Control", "no-cache")
+      .header("X-Accel-Buffering", "no")
+      .body(body)
+      .unwrap())
+}
+
+fn websocket(req: Request<Body>, mut feed: EventFeed) -> Result<Response<Body>, Infallible> {
+   let key = req
+      .headers()
+      .get("sec-websocket-key")
+      .map(|key| derive_accept_key(key.as_bytes()));
+
+   let version = req
+      .headers()
+      .get("sec-websocket-version")
+      .and_then(|version| version.to_str().ok());
+
+   let accept = match (key, version) {
+      (Some(accept), Some("13")) => accept,
+      _ => return create_error("websocket handshake invalid", None),
+   };
+
+   tokio::spawn(async move {
+      let upgraded = match hyper::upgrade::on(req).await {
+         Ok(upgraded) => upgraded,
+         Err(e) => {
+            eprintln!("Events Error: {}", e);
+            return;
+         }
+      };
+
+      let mut socket = WebSocketStream::from_raw_socket(upgraded, Role::Server, None).await;
+
+      loop {
+         tokio::select! {
+            item = feed.next() => {
+               let messages = match item {
+                  FeedItem::Events(events) => events
+                     .into_iter()
+                     .map(|event| {
+                        let message = json!({
+                           "id": event.id,
+                           "event": event_name(&event),
+                           "data": event,
+                        });
+
+                        Message::Text(message.to_string())
+                     })
+                     .collect(),
+                  FeedItem::Heartbeat => vec![Message::Ping(vec![])],
+               };
+
+               let mut sent = Ok(());
+               for message in messages {
+                  sent = socket.feed(message).await;
+
+                  if sent.is_err() {
+                     break;
+                  }
+               }
+
+               if sent.and(socket.flush().await).is_err() {
+                  break;
+               }
+            }
+            message = socket.next() => match message {
+               Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+               // Pings are answered by tungstenite itself.
+               Some(Ok(_)) => {}
+            },
+         }
+      }
+   });
+
+   Ok(Response::builder()
+      .status(101)
+      .header("Upgrade", "websocket")
+      .header("Connection", "Upgrade")
+      .header("Sec-WebSocket-Accept", accept)
+      .body(Body::empty())
+      .unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+   use super::super::super::database::create_memory_connection;
+   use super::super::super::utils::events::Wakeups;
+   use super::super::super::views::tasks::TaskCreated;
+   use super::super::tasks::insert_task;
+   use super::*;
+
+   use tokio::time::timeout;
+
+   fn create_task(conn: &Connection, user_id: &str, name: &str) -> Wakeups {
+      insert_task(conn, TaskCreated::new(user_id, name), user_id)
+         .unwrap()
+         .1
+   }
+
+   async fn next_events(feed: &mut EventFeed) -> Vec<String> {
+      match timeout(StdDuration::from_secs(5), feed.next()).await {
+         Ok(FeedItem::Events(events)) => events
+            .into_iter()
+            .map(|event| event.changes["name"]["to"].as_str().unwrap().to_string())
+            .collect(),
+         Ok(FeedItem::Heartbeat) => panic!("heartbeat instead of events"),
+         Err(_) => panic!("no events"),
+      }
+   }
+
+   #[tokio::test]
+   async fn resumes_after_the_last_event_id() {
+      let conn = create_memory_connection();
+      conn
+         .execute_batch(
+            "INSERT INTO users (id, firstname, lastname, email, password) VALUES ('alice', 'Alice', 'A', 'alice@x.io', '');
+            INSERT INTO users (id, firstname, lastname, email, password) VALUES ('bob', 'Bob', 'B', 'bob@x.io', '');",
+         )
+         .unwrap();
+
+      let _ = create_task(&conn, "alice", "Milk");
+      let last_id = latest_event_id(&conn);
+      let _ = create_task(&conn, "bob", "Tea");
+      let _ = create_task(&conn, "alice", "Eggs");
+
+      let conn = Arc::new(Mutex::new(conn));
+      let mut heartbeat = interval(StdDuration::from_secs(60));
+      heartbeat.tick().await;
+
+      let mut feed = EventFeed {
+         conn: conn.clone(),
+         user_id: String::from("alice"),
+         last_id,
+         behind: true,
+         wake: subscribe(),
+         heartbeat,
+      };
+
+      assert_eq!(next_events(&mut feed).await, vec!["Eggs"]);
+
+      let wakeups = create_task(&*conn.lock().await, "alice", "Bread");
+      wakeups.send();
+
+      assert_eq!(next_events(&mut feed).await, vec!["Bread"]);
+   }
+}
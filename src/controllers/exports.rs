@@ -0,0 +1,853 @@
+use super::super::middlewares::users::{valid_user, ValidResponse};
+use super::super::utils::events::Wakeups;
+use super::super::utils::labels::{add_task_labels, task_labels};
+use super::super::utils::permissions::{
+   find_any_task, find_permitted_task, find_visible_task, project_role, Role,
+};
+use super::super::utils::validation::NAME_MAX_LENGTH;
+use super::super::utils::{
+   create_error, get_query_params, get_users, now, read_body, ErrorFormated,
+};
+use super::super::views::exports::{
+   CsvRecord, DataImportInvalid, DataImportReport, ExportDocument, ExportedProfile,
+   ExportedProject, ExportedTask, ImportItemError, EXPORT_VERSION,
+};
+use super::super::views::projects::{Project, PROJECT_COLUMNS};
+use super::super::views::tasks::{TaskCreated, TASK_COLUMNS};
+use super::super::views::users::CreatedUser;
+use super::projects::insert_project;
+use super::tasks::{
+   apply_task_changes, check_project, insert_task, validate_new_task, RequestBodyUpdate,
+};
+
+use std::collections::HashMap;
+use std::convert::{From, Infallible};
+use std::sync::Arc;
+
+use futures::lock::Mutex;
+use futures::stream;
+
+use hyper::body::Bytes;
+use hyper::{Body, Request, Response};
+
+use rusqlite::{Connection, OptionalExtension};
+
+use uuid::Uuid;
+
+const EXPORT_PAGE_SIZE: usize = 500;
+const IMPORT_MAX_BYTES: usize = 10 * 1024 * 1024;
+const IMPORT_MAX_TASKS: usize = 10_000;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Format {
+   Json,
+   Csv,
+}
+
+/// What `POST /import` does with a task whose id already exists.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Conflict {
+   Skip,
+   Overwrite,
+   Duplicate,
+}
+
+impl Conflict {
+   fn parse(conflict: &str) -> Option<Conflict> {
+      match conflict {
+         "skip" => Option::from(Conflict::Skip),
+         "overwrite" => Option::from(Conflict::Overwrite),
+         "duplicate" => Option::from(Conflict::Duplicate),
+         _ => None,
+      }
+   }
+}
+
+enum ExportState {
+   Start,
+   Tasks { after: String, first: bool },
+   Done,
+}
+
+fn export_page(conn: &Connection, user_id: &str, after: &str) -> Vec<TaskCreated> {
+   let mut query = conn
+      .prepare(&format!(
+         "SELECT {} FROM tasks WHERE tasks.user_id = ? AND tasks.id > ? ORDER BY tasks.id LIMIT {}",
+         TASK_COLUMNS, EXPORT_PAGE_SIZE
+      ))
+      .unwrap();
+
+   let mut tasks: Vec<TaskCreated> = query
+      .query_map([user_id, after], |row| TaskCreated::from_row(row, 0))
+      .unwrap()
+      .filter_map(|task| task.ok())
+      .collect();
+
+   for task in tasks.iter_mut() {
+      task.labels = task_labels(conn, &task.id);
+   }
+
+   tasks
+}
+
+fn export_projects(conn: &Connection, user_id: &str) -> Vec<Project> {
+   let mut query = conn
+      .prepare(&format!(
+         "SELECT {} FROM projects WHERE projects.user_id = ? ORDER BY projects.created_at, projects.id",
+         PROJECT_COLUMNS
+      ))
+      .unwrap();
+
+   query
+      .query_map([user_id], |row| Project::from_row(row, 0))
+      .unwrap()
+      .filter_map(|project| project.ok())
+      .collect()
+}
+
+fn csv_chunk(records: Vec<CsvRecord>, headers: bool) -> Vec<u8> {
+   let mut writer = csv::WriterBuilder::new()
+      .has_headers(headers)
+      .from_writer(vec![]);
+
+   for record in records {
+      writer.serialize(record).unwrap();
+   }
+
+   writer.into_inner().unwrap_or_default()
+}
+
+fn export_start(format: Format, profile: ExportedProfile, projects: Vec<Project>) -> Vec<u8> {
+   let projects = projects.into_iter().map(ExportedProject::new);
+
+   match format {
+      Format::Json => {
+         let user = serde_json::to_string(&profile).unwrap();
+         let projects = serde_json::to_string(&projects.collect::<Vec<_>>()).unwrap();
+
+         format!(
+            "{{\"version\":{},\"exported_at\":\"{}\",\"user\":{},\"projects\":{},\"tasks\":[",
+            EXPORT_VERSION,
+            now(),
+            user,
+            projects
+         )
+         .into_bytes()
+      }
+      Format::Csv => csv_chunk(
+         std::iter::once(CsvRecord::from_profile(profile))
+            .chain(projects.map(CsvRecord::from_project))
+            .collect(),
+         true,
+      ),
+   }
+}
+
+fn export_tasks(format: Format, tasks: Vec<TaskCreated>, first: bool) -> Vec<u8> {
+   match format {
+      Format::Json => {
+         let mut chunk = vec![];
+
+         for (index, task) in tasks.into_iter().enumerate() {
+            if !first || index > 0 {
+               chunk.push(b',');
+            }
+
+            chunk.extend(serde_json::to_vec(&ExportedTask::new(task)).unwrap());
+         }
+
+         chunk
+      }
+      Format::Csv => csv_chunk(
+         tasks
+            .into_iter()
+            .map(|task| CsvRecord::from_task(ExportedTask::new(task)))
+            .collect(),
+         false,
+      ),
+   }
+}
+
+/// Streams the profile, the projects and every task of the user, trashed
+/// ones included, as `?format=json` (the default) or `?format=csv`. Tasks
+/// are read a page at a time so large accounts do not hold the database for
+/// the whole download.
+#[utoipa::path(
+   get,
+   path = "/export",
+   tag = "data",
+   params(
+      ("format" = Option<String>, Query, description = "`json` (default) or `csv`")
+   ),
+   security(("token" = [])),
+   responses(
+      (status = 200, description = "The profile, projects and tasks of the user", content((ExportDocument = "application/json"), (String = "text/csv"))),
+      (status = 400, description = "Invalid request", body = ErrorFormated)
+   )
+)]
+pub async fn export_data(
+   req: Request<Body>,
+   conn: Arc<Mutex<Connection>>,
+) -> Result<Response<Body>, Infallible> {
+   let headers = req.headers();
+   let mut _user: Option<CreatedUser> = None;
+
+   let result = valid_user(headers, &conn).await;
+
+   match result {
+      Ok(data) => match data {
+         ValidResponse::Id(id) => {
+            let users = get_users(conn.clone(), id.clone(), String::from("id")).await;
+
+            if users.is_empty() {
+               return create_error("this user not exists", None);
+            }
+
+            _user = users.into_iter().next();
+         }
+         ValidResponse::Respo(data) => return data,
+      },
+      _ => return create_error("", None),
+   }
+
+   let user = _user.unwrap();
+
+   let format = match get_query_params(&req).get("format").map(String::as_str) {
+      None | Some("json") => Format::Json,
+      Some("csv") => Format::Csv,
+      _ => return create_error("format invalid", None),
+   };
+
+   let (content_type, extension) = match format {
+      Format::Json => ("application/json", "json"),
+      Format::Csv => ("text/csv; charset=utf-8", "csv"),
+   };
+
+   let user_id = user.id.clone();
+   let mut profile = Option::from(ExportedProfile::new(user));
+
+   let chunks = stream::unfold(ExportState::Start, move |state| {
+      let conn = conn.clone();
+      let user_id = user_id.clone();
+      let profile = profile.take();
+
+      async move {
+         match state {
+            ExportState::Start => {
+               let projects = {
+                  let conn = conn.lock().await;
+
+                  export_projects(&conn, &user_id)
+               };
+               let chunk = export_start(format, profile?, projects);
+               let next = ExportState::Tasks {
+                  after: String::from(""),
+                  first: true,
+               };
+
+               Some((Ok::<_, Infallible>(Bytes::from(chunk)), next))
+            }
+            ExportState::Tasks { after, first } => {
+               let tasks = {
+                  let conn = conn.lock().await;
+
+                  export_page(&conn, &user_id, &after)
+               };
+
+               if tasks.is_empty() {
+                  let end = match format {
+                     Format::Json => Bytes::from("]}"),
+                     Format::Csv => Bytes::new(),
+                  };
+
+                  return Some((Ok(end), ExportState::Done));
+               }
+
+               let after = tasks.last().unwrap().id.clone();
+               let chunk = export_tasks(format, tasks, first);
+
+               Some((
+                  Ok(Bytes::from(chunk)),
+                  ExportState::Tasks {
+                     after,
+                     first: false,
+                  },
+               ))
+            }
+            ExportState::Done => None,
+         }
+      }
+   });
+
+   Ok(Response::builder()
+      .status(200)
+      .header("Content-Type", content_type)
+      .header(
+         "Content-Disposition",
+         format!(
+            "attachment; filename=\"export-{}.{}\"",
+            now().get(0..10).unwrap_or("data"),
+            extension
+         ),
+      )
+      .body(Body::wrap_stream(chunks))
+      .unwrap())
+}
+
+fn parse_import(format: Format, data: &[u8]) -> Result<ExportDocument, &'static str> {
+   match format {
+      Format::Json => match serde_json::from_slice::<ExportDocument>(data) {
+         Ok(document) if document.version > EXPORT_VERSION => Err("export version not supported"),
+         Ok(document) => Ok(document),
+         _ => Err("data invalid"),
+      },
+      Format::Csv => {
+         let mut reader = csv::Reader::from_reader(data);
+         let mut document = ExportDocument {
+            version: EXPORT_VERSION,
+            exported_at: None,
+            user: None,
+            projects: vec![],
+            tasks: vec![],
+         };
+
+         for record in reader.deserialize::<CsvRecord>() {
+            match record {
+               Ok(record) if record.record == "task" => document.tasks.push(record.into_task()),
+               Ok(record) if record.record == "project" => {
+                  document.projects.push(record.into_project())
+               }
+               Ok(record) if record.record == "profile" => {}
+               _ => return Err("csv invalid"),
+            }
+         }
+
+         Ok(document)
+      }
+   }
+}
+
+// Gives every exported project a project of the user to import its tasks
+// into, by exported id. A project the user can already write to is reused,
+// so importing an export back into the same account does not copy its
+// projects; the others are created, keeping their id when it is free.
+fn import_projects(
+   conn: &Connection,
+   projects: Vec<ExportedProject>,
+   user_id: &str,
+   report: &mut DataImportReport,
+) -> Result<(HashMap<String, String>, Wakeups), rusqlite::Error> {
+   let mut ids = HashMap::new();
+   let mut wakeups = Wakeups::default();
+
+   for exported in projects {
+      let name = exported.name.trim();
+
+      if exported.id.is_empty()
+         || name.is_empty()
+         || name.chars().count() > NAME_MAX_LENGTH as usize
+      {
+         continue;
+      }
+
+      match project_role(conn, &exported.id, user_id) {
+         Some(role) if role >= Role::Editor => {
+            ids.insert(exported.id.clone(), exported.id);
+            continue;
+         }
+         _ => {}
+      }
+
+      let taken = conn
+         .query_row(
+            "SELECT 1 FROM projects WHERE id = ?",
+            [&exported.id],
+            |_| Ok(()),
+         )
+         .optional()?
+         .is_some();
+
+      let project = Project {
+         id: if taken {
+            Uuid::new_v4().to_string()
+         } else {
+            exported.id.clone()
+         },
+         name: String::from(name),
+         user_id: String::from(user_id),
+         created_at: now(),
+      };
+
+      wakeups.add(insert_project(conn, &project, user_id)?);
+      report.created_projects.push(project.id.clone());
+      ids.insert(exported.id, project.id);
+   }
+
+   Ok((ids, wakeups))
+}
+
+// Checks an imported task against the same rules as `POST /tasks`. Its
+// project is moved to the one imported for it; a project that exists
+// neither in the export nor here is dropped and the task reported as
+// unlinked.
+fn validate_task(
+   conn: &Connection,
+   task: ExportedTask,
+   projects: &HashMap<String, String>,
+   user_id: &str,
+) -> Result<(ExportedTask, bool), &'static str> {
+   let (name, due_date, recurrence) =
+      validate_new_task(&task.name, task.due_date, task.recurrence)?;
+
+   let mut project_id = task
+      .project_id
+      .map(|project_id| projects.get(&project_id).cloned().unwrap_or(project_id));
+   let mut unlinked = false;
+
+   match check_project(conn, project_id.as_deref(), user_id) {
+      Ok(()) => {}
+      Err((404, _)) => {
+         project_id = None;
+         unlinked = true;
+      }
+      Err((_, error)) => return Err(error),
+   }
+
+   Ok((
+      ExportedTask {
+         name,
+         due_date,
+         recurrence,
+         project_id,
+         ..task
+      },
+      unlinked,
+   ))
+}
+
+// Replaces the fields of an existing task the user may edit with the
+// imported ones, through the same path as `PUT /task/{id}`. A task imported
+// without a project stays in the one it is in, and its labels are added to
+// the ones it has.
+fn overwrite_task(
+   conn: &Connection,
+   task_id: &str,
+   task: ExportedTask,
+   user_id: &str,
+) -> Result<Wakeups, &'static str> {
+   let (current, _) = find_permitted_task(conn, task_id, user_id, Role::Editor, false)
+      .map_err(|(_, error)| error)?;
+
+   let changes = RequestBodyUpdate {
+      name: Option::from(task.name),
+      completed: Option::from(task.completed),
+      due_date: Option::from(task.due_date.unwrap_or_default()),
+      recurrence: Option::from(task.recurrence.unwrap_or_default()),
+      project_id: task.project_id,
+      assignee_id: None,
+   };
+
+   let (_, wakeups) =
+      apply_task_changes(conn, current, changes, user_id).map_err(|(_, error)| error)?;
+
+   add_task_labels(conn, task_id, user_id, &task.labels)
+      .map(|_| wakeups)
+      .map_err(|_| "not is possible update this task")
+}
+
+// Writes the projects and tasks of an export, returning the wake-ups to send
+// once committed. Every task that cannot be imported is returned instead,
+// so that the caller can roll the whole import back.
+fn import_document(
+   conn: &Connection,
+   document: ExportDocument,
+   user_id: &str,
+   conflict: Conflict,
+   dry_run: bool,
+) -> Result<(DataImportReport, Wakeups), Vec<ImportItemError>> {
+   let mut report = DataImportReport {
+      dry_run,
+      created_projects: vec![],
+      created: vec![],
+      updated: vec![],
+      skipped: vec![],
+      unlinked: vec![],
+   };
+   let mut errors: Vec<ImportItemError> = vec![];
+
+   let (projects, mut wakeups) =
+      match import_projects(conn, document.projects, user_id, &mut report) {
+         Ok(imported) => imported,
+         Err(_) => {
+            return Err(vec![ImportItemError {
+               index: 0,
+               id: None,
+               error: "not is possible create the projects",
+            }])
+         }
+      };
+
+   for (index, task) in document.tasks.into_iter().enumerate() {
+      let id = task.id.clone();
+
+      if task.deleted_at.is_some() {
+         report.skipped.push(ImportItemError {
+            index,
+            id,
+            error: "task is in the trash",
+         });
+         continue;
+      }
+
+      let task = match validate_task(conn, task, &projects, user_id) {
+         Ok((task, unlinked)) => {
+            if unlinked {
+               report.unlinked.push(ImportItemError {
+                  index,
+                  id: id.clone(),
+                  error: "this project not exists",
+               });
+            }
+
+            task
+         }
+         Err(error) => {
+            errors.push(ImportItemError { index, id, error });
+            continue;
+         }
+      };
+
+      let existing = id
+         .as_deref()
+         .and_then(|task_id| find_visible_task(conn, task_id, user_id));
+
+      let result = match (existing, conflict) {
+         (Some(_), Conflict::Skip) => {
+            report.skipped.push(ImportItemError {
+               index,
+               id,
+               error: "task already exists",
+            });
+            continue;
+         }
+         (Some(existing), Conflict::Overwrite) => overwrite_task(conn, &existing.id, task, user_id)
+            .map(|updated| {
+               report.updated.push(existing.id);
+               wakeups.add(updated);
+            }),
+         (existing, _) => {
+            let mut created = TaskCreated::new(user_id, &task.name);
+
+            // Ids are kept so that restoring an export into a fresh account
+            // gives back the same tasks. Ids taken by any task, including
+            // ones the user cannot see, get a fresh id instead.
+            if let Some(task_id) = &task.id {
+               if existing.is_none() && find_any_task(conn, task_id).is_none() {
+                  created.id = task_id.clone();
+               }
+            }
+
+            created.completed = if task.completed { 1 } else { 0 };
+            created.due_date = task.due_date;
+            created.recurrence = task.recurrence;
+            created.project_id = task.project_id;
+
+            let labels = task.labels;
+
+            insert_task(conn, created, user_id)
+               .and_then(|(task_id, created)| {
+                  add_task_labels(conn, &task_id, user_id, &labels)?;
+                  report.created.push(task_id);
+                  wakeups.add(created);
+                  Ok(())
+               })
+               .map_err(|_| "not is possible create this task")
+         }
+      };
+
+      if let Err(error) = result {
+         errors.push(ImportItemError { index, id, error });
+      }
+   }
+
+   if errors.is_empty() {
+      Ok((report, wakeups))
+   } else {
+      Err(errors)
+   }
+}
+
+/// Imports the projects and tasks of an export, as JSON or, with
+/// `Content-Type: text/csv` or `?format=csv`, as CSV. Everything is written
+/// in one transaction: a single invalid task fails the whole import.
+/// `?dry_run=true` runs the import and rolls it back, and
+/// `?conflict=skip|overwrite|duplicate` picks what happens to tasks whose id
+/// already exists. Trashed tasks are skipped, tasks of projects that do not
+/// exist are imported without one, and the profile is not imported.
+#[utoipa::path(
+   post,
+   path = "/import",
+   tag = "data",
+   params(
+      ("format" = Option<String>, Query, description = "`json` (default) or `csv`"),
+      ("dry_run" = Option<bool>, Query, description = "Validate without writing"),
+      ("conflict" = Option<String>, Query, description = "`skip` (default), `overwrite` or `duplicate`")
+   ),
+   request_body(content((ExportDocument = "application/json"), (String = "text/csv"))),
+   security(("token" = [])),
+   responses(
+      (status = 200, description = "What a dry run would do", body = DataImportReport),
+      (status = 201, description = "What was imported", body = DataImportReport),
+      (status = 400, description = "Invalid tasks, nothing was imported", body = DataImportInvalid),
+      (status = 413, description = "The file is too large", body = ErrorFormated)
+   )
+)]
+pub async fn import_data(
+   req: Request<Body>,
+   conn: Arc<Mutex<Connection>>,
+) -> Result<Response<Body>, Infallible> {
+   let params = get_query_params(&req);
+   let (head, body) = req.into_parts();
+   let mut _user_id = String::from("");
+
+   let result = valid_user(&head.headers, &conn).await;
+
+   match result {
+      Ok(data) => match data {
+         ValidResponse::Id(id) => {
+            let users = get_users(conn.clone(), id.clone(), String::from("id")).await;
+
+            if users.is_empty() {
+               return create_error("this user not exists", None);
+            }
+
+            _user_id = id;
+         }
+         ValidResponse::Respo(data) => return data,
+      },
+      _ => return create_error("", None),
+   }
+
+   let content_type = head
+      .headers
+      .get("content-type")
+      .and_then(|content_type| content_type.to_str().ok())
+      .map(|content_type| content_type.to_lowercase())
+      .unwrap_or_default();
+
+   let format = match params.get("format").map(String::as_str) {
+      Some("json") => Format::Json,
+      Some("csv") => Format::Csv,
+      Some(_) => return create_error("format invalid", None),
+      None if content_type.starts_with("text/csv") => Format::Csv,
+      None => Format::Json,
+   };
+
+   let conflict = match params.get("conflict") {
+      Some(conflict) => match Conflict::parse(conflict) {
+         Some(conflict) => conflict,
+         None => return create_error("conflict invalid", None),
+      },
+      None => Conflict::Skip,
+   };
+
+   let dry_run = matches!(
+      params.get("dry_run").map(String::as_str),
+      Some("true") | Some("1")
+   );
+
+   let data = match read_body(body, IMPORT_MAX_BYTES).await {
+      Ok(data) => data,
+      Err((status, error)) => return create_error(error, Option::from(status)),
+   };
+
+   let document = match parse_import(format, &data) {
+      Ok(document) => document,
+      Err(error) => return create_error(error, None),
+   };
+
+   if document.tasks.len() > IMPORT_MAX_TASKS {
+      return create_error("too many tasks to import", Option::from(413));
+   }
+
+   let mut conn = conn.lock().await;
+
+   let transaction = match conn.transaction() {
+      Ok(transaction) => transaction,
+      _ => return create_error("", None),
+   };
+
+   let (report, wakeups) =
+      match import_document(&transaction, document, &_user_id, conflict, dry_run) {
+         Ok(imported) => imported,
+         Err(errors) => {
+            let json = serde_json::to_string(&DataImportInvalid {
+               error: "import invalid",
+               errors,
+            });
+
+            return match json {
+               Ok(json) => Ok(Response::builder()
+                  .status(400)
+                  .body(Body::from(json))
+                  .unwrap()),
+               _ => create_error("", None),
+            };
+         }
+      };
+
+   // Dropping the transaction rolls the dry run back.
+   if !dry_run {
+      if transaction.commit().is_err() {
+         return create_error("not is possible import this data", None);
+      }
+
+      wakeups.send();
+   }
+
+   match serde_json::to_string(&report) {
+      Ok(json) => Ok(Response::builder()
+         .status(if dry_run { 200 } else { 201 })
+         .body(Body::from(json))
+         .unwrap()),
+      _ => create_error("", None),
+   }
+}
+
+#[cfg(test)]
+mod tests {
+   use super::super::super::database::create_memory_connection;
+   use super::*;
+
+   fn database() -> Connection {
+      let conn = create_memory_connection();
+
+      conn
+         .execute_batch(
+            "INSERT INTO users (id, firstname, lastname, email, password) VALUES ('alice', 'Alice', 'A', 'alice@x.io', '');
+            INSERT INTO users (id, firstname, lastname, email, password) VALUES ('bob', 'Bob', 'B', 'bob@x.io', '');",
+         )
+         .unwrap();
+
+      conn
+   }
+
+   fn export(conn: &Connection, format: Format, user_id: &str) -> Vec<u8> {
+      let profile = ExportedProfile {
+         id: String::from(user_id),
+         firstname: String::from(""),
+         lastname: String::from(""),
+         email: String::from(""),
+      };
+
+      let mut data = export_start(format, profile, export_projects(conn, user_id));
+      data.extend(export_tasks(format, export_page(conn, user_id, ""), true));
+
+      if format == Format::Json {
+         data.extend(b"]}");
+      }
+
+      data
+   }
+
+   fn create_task(conn: &Connection, user_id: &str, name: &str, project_id: Option<&str>) {
+      let mut task = TaskCreated::new(user_id, name);
+      task.project_id = project_id.map(String::from);
+
+      let (task_id, _) = insert_task(conn, task, user_id).unwrap();
+      add_task_labels(conn, &task_id, user_id, &[String::from("Home")]).unwrap();
+   }
+
+   #[test]
+   fn round_trips_into_another_account() {
+      for format in [Format::Json, Format::Csv].iter().copied() {
+         let conn = database();
+         let project = Project {
+            id: Uuid::new_v4().to_string(),
+            name: String::from("Chores"),
+            user_id: String::from("alice"),
+            created_at: now(),
+         };
+
+         insert_project(&conn, &project, "alice").unwrap().send();
+         create_task(&conn, "alice", "Dishes", Option::from(project.id.as_str()));
+
+         let document = parse_import(format, &export(&conn, format, "alice")).unwrap();
+         let (report, _) = import_document(&conn, document, "bob", Conflict::Skip, false).unwrap();
+
+         assert_eq!(report.created_projects.len(), 1, "{:?}", format);
+         assert_eq!(report.created.len(), 1);
+         assert!(report.unlinked.is_empty());
+
+         let task = find_any_task(&conn, &report.created[0]).unwrap();
+         assert_ne!(report.created_projects[0], project.id);
+         assert_eq!(task.user_id, "bob");
+         assert_eq!(task.name, "Dishes");
+         assert_eq!(
+            task.project_id,
+            Option::from(report.created_projects[0].clone())
+         );
+         assert_eq!(
+            project_role(&conn, &report.created_projects[0], "bob"),
+            Option::from(Role::Owner)
+         );
+         assert_eq!(task_labels(&conn, &task.id), vec!["Home"]);
+      }
+   }
+
+   #[test]
+   fn reuses_projects_of_the_account() {
+      let conn = database();
+      let project = Project {
+         id: Uuid::new_v4().to_string(),
+         name: String::from("Chores"),
+         user_id: String::from("alice"),
+         created_at: now(),
+      };
+
+      insert_project(&conn, &project, "alice").unwrap().send();
+      create_task(&conn, "alice", "Dishes", Option::from(project.id.as_str()));
+
+      let data = export(&conn, Format::Json, "alice");
+      let document = parse_import(Format::Json, &data).unwrap();
+      let (report, _) =
+         import_document(&conn, document, "alice", Conflict::Duplicate, false).unwrap();
+
+      assert!(report.created_projects.is_empty());
+      assert_eq!(
+         find_any_task(&conn, &report.created[0]).unwrap().project_id,
+         Option::from(project.id)
+      );
+   }
+
+   #[test]
+   fn unlinks_tasks_of_unknown_projects() {
+      let conn = database();
+      create_task(&conn, "alice", "Dishes", None);
+
+      let task_id = export_page(&conn, "alice", "")[0].id.clone();
+      let data = format!(
+         "{{\"version\":2,\"tasks\":[{{\"id\":\"{}\",\"name\":\"Dishes\",\"project_id\":\"gone\"}},{{\"name\":\"Laundry\",\"project_id\":\"gone\"}}]}}",
+         task_id
+      );
+      let document = parse_import(Format::Json, data.as_bytes()).unwrap();
+      let (report, _) =
+         import_document(&conn, document, "alice", Conflict::Overwrite, false).unwrap();
+
+      assert_eq!(report.updated, vec![task_id.clone()]);
+      assert_eq!(report.created.len(), 1);
+      assert_eq!(
+         report
+            .unlinked
+            .iter()
+            .map(|item| item.index)
+            .collect::<Vec<_>>(),
+         vec![0, 1]
+      );
+      assert_eq!(find_any_task(&conn, &task_id).unwrap().project_id, None);
+      assert_eq!(
+         find_any_task(&conn, &report.created[0]).unwrap().project_id,
+         None
+      );
+   }
+}
@@ -0,0 +1,125 @@
+use super::super::graphql::types::Viewer;
+use super::super::graphql::SCHEMA;
+use super::super::middlewares::users::{valid_user, ValidResponse};
+use super::super::utils::{
+   body_error, create_error, get_query_params, get_users, parse_body, valid_json, ErrorFormated,
+};
+
+use std::convert::{From, Infallible};
+use std::sync::Arc;
+
+use futures::lock::Mutex;
+
+use rusqlite::Connection;
+
+use async_graphql::parser::types::OperationType;
+
+use validator::{Validate, ValidationErrors};
+
+use hyper::{Body, Method, Request, Response};
+
+// The schema checks the query and its variables itself.
+#[derive(Deserialize)]
+#[serde(transparent)]
+struct RequestBodyGraphql(async_graphql::Request);
+
+impl Validate for RequestBodyGraphql {
+   fn validate(&self) -> Result<(), ValidationErrors> {
+      Ok(())
+   }
+}
+
+/// Runs a GraphQL query as the authenticated user. `POST` takes the usual
+/// `{query, variables, operationName}` body; `GET` takes the same fields as
+/// query parameters, with `variables` as JSON, and only runs queries.
+#[utoipa::path(
+   method(get, post),
+   path = "/graphql",
+   tag = "graphql",
+   params(
+      ("query" = Option<String>, Query, description = "The query of a `GET` request"),
+      ("variables" = Option<String>, Query, description = "Variables of a `GET` request, as JSON"),
+      ("operationName" = Option<String>, Query, description = "Operation of a `GET` request")
+   ),
+   request_body(content = Object, description = "`{query, variables, operationName}`"),
+   security(("token" = [])),
+   responses(
+      (status = 200, description = "The GraphQL response", body = Object),
+      (status = 405, description = "A mutation sent with `GET`", body = ErrorFormated),
+      (status = 400, description = "Invalid request", body = ErrorFormated),
+      (status = 413, description = "Body larger than `BODY_MAX_BYTES`", body = ErrorFormated),
+      (status = 415, description = "Body not sent as JSON", body = ErrorFormated)
+   )
+)]
+pub async fn execute_graphql(
+   req: Request<Body>,
+   conn: Arc<Mutex<Connection>>,
+) -> Result<Response<Body>, Infallible> {
+   let params = get_query_params(&req);
+   let (head, body) = req.into_parts();
+   let mut _user_id = String::from("");
+
+   let result = valid_user(&head.headers, &conn).await;
+
+   match result {
+      Ok(data) => match data {
+         ValidResponse::Id(id) => {
+            let users = get_users(conn.clone(), id.clone(), String::from("id")).await;
+
+            if users.is_empty() {
+               return create_error("this user not exists", None);
+            }
+
+            _user_id = id;
+         }
+         ValidResponse::Respo(data) => return data,
+      },
+      _ => return create_error("", None),
+   }
+
+   let request = if head.method == Method::GET {
+      let query = match params.get("query") {
+         Some(query) => query.clone(),
+         None => return create_error("query is required", None),
+      };
+
+      let variables = match params.get("variables") {
+         Some(variables) => match serde_json::from_str(variables) {
+            Ok(variables) => async_graphql::Variables::from_json(variables),
+            Err(_) => return create_error("variables invalid", None),
+         },
+         None => async_graphql::Variables::default(),
+      };
+
+      let mut request = async_graphql::Request::new(query).variables(variables);
+
+      if let Some(operation_name) = params.get("operationName") {
+         request = request.operation_name(operation_name.clone());
+      }
+
+      // A link must not be able to change data. Syntax errors are left for
+      // the schema to report.
+      let mutation = request.parsed_query().is_ok_and(|document| {
+         document
+            .operations
+            .iter()
+            .any(|(_, operation)| operation.node.ty == OperationType::Mutation)
+      });
+
+      if mutation {
+         return create_error("mutations need a POST request", Option::from(405));
+      }
+
+      request
+   } else {
+      match parse_body::<RequestBodyGraphql>(&head.headers, body).await {
+         Ok(RequestBodyGraphql(request)) => request,
+         Err(error) => return body_error(error),
+      }
+   };
+
+   let request = request.data(Viewer { id: _user_id }).data(conn);
+   let response = SCHEMA.execute(request).await;
+
+   valid_json(serde_json::to_string(&response))
+}
@@ -0,0 +1,179 @@
+use super::super::middlewares::users::{valid_user, ValidResponse};
+use super::super::utils::history::TASK_EVENT_COLUMNS;
+use super::super::utils::permissions::{find_any_task, task_role};
+use super::super::utils::{create_error, get_query_params, get_users, valid_json, ErrorFormated};
+use super::super::views::history::{TaskEvent, TaskEvents};
+
+use std::convert::{From, Infallible};
+use std::sync::Arc;
+
+use futures::lock::Mutex;
+
+use rusqlite::{params, Connection};
+
+use hyper::{Body, Request, Response};
+
+const ACTIVITY_DEFAULT_LIMIT: i64 = 50;
+const ACTIVITY_MAX_LIMIT: i64 = 200;
+
+#[utoipa::path(
+   get,
+   path = "/tasks/{id}/history",
+   tag = "history",
+   params(
+      ("id" = String, Path, description = "Task id")
+   ),
+   security(("token" = [])),
+   responses(
+      (status = 200, description = "The changes of the task, oldest first", body = [TaskEvent]),
+      (status = 404, description = "The task does not exist or is not shared with the user", body = ErrorFormated),
+      (status = 400, description = "Invalid request", body = ErrorFormated)
+   )
+)]
+pub async fn task_history(
+   req: Request<Body>,
+   conn: Arc<Mutex<Connection>>,
+   task_id: String,
+) -> Result<Response<Body>, Infallible> {
+   let headers = req.headers();
+   let mut _user_id = String::from("");
+
+   let result = valid_user(headers, &conn).await;
+
+   match result {
+      Ok(data) => match data {
+         ValidResponse::Id(id) => {
+            let users = get_users(conn.clone(), id.clone(), String::from("id")).await;
+
+            if users.is_empty() {
+               return create_error("this user not exists", None);
+            }
+
+            _user_id = id;
+         }
+         ValidResponse::Respo(data) => return data,
+      },
+      _ => return create_error("", None),
+   }
+
+   let conn = conn.lock().await;
+
+   // The history stays readable while the task is in the trash.
+   let role = find_any_task(&conn, &task_id).and_then(|task| task_role(&conn, &task, &_user_id));
+
+   if role.is_none() {
+      return create_error("this task not exists", Option::from(404));
+   }
+
+   let mut query = conn
+      .prepare(&format!(
+         "SELECT {} FROM task_events WHERE entity = 'task' AND entity_id = ? ORDER BY id ASC",
+         TASK_EVENT_COLUMNS
+      ))
+      .unwrap();
+   let data = query
+      .query_map([&task_id], |row| TaskEvent::from_row(row, 0))
+      .unwrap();
+
+   let mut events: Vec<TaskEvent> = vec![];
+   for event in data {
+      events.push(event.unwrap());
+   }
+
+   let json = serde_json::to_string(&events);
+
+   valid_json(json)
+}
+
+#[utoipa::path(
+   get,
+   path = "/activity",
+   tag = "history",
+   params(
+      ("limit" = Option<i64>, Query, description = "Page size"),
+      ("before" = Option<i64>, Query, description = "`next_before` of the previous page")
+   ),
+   security(("token" = [])),
+   responses(
+      (status = 200, description = "The activity of the user, newest first", body = TaskEvents),
+      (status = 400, description = "Invalid request", body = ErrorFormated)
+   )
+)]
+pub async fn list_activity(
+   req: Request<Body>,
+   conn: Arc<Mutex<Connection>>,
+) -> Result<Response<Body>, Infallible> {
+   let headers = req.headers();
+   let mut _user_id = String::from("");
+
+   let result = valid_user(headers, &conn).await;
+
+   match result {
+      Ok(data) => match data {
+         ValidResponse::Id(id) => {
+            let users = get_users(conn.clone(), id.clone(), String::from("id")).await;
+
+            if users.is_empty() {
+               return create_error("this user not exists", None);
+            }
+
+            _user_id = id;
+         }
+         ValidResponse::Respo(data) => return data,
+      },
+      _ => return create_error("", None),
+   }
+
+   let params = get_query_params(&req);
+
+   let limit = match params.get("limit") {
+      Some(limit) => match limit.parse::<i64>() {
+         Ok(limit) if (1..=ACTIVITY_MAX_LIMIT).contains(&limit) => limit,
+         _ => return create_error("limit invalid", None),
+      },
+      None => ACTIVITY_DEFAULT_LIMIT,
+   };
+
+   let before = match params.get("before") {
+      Some(before) => match before.parse::<i64>() {
+         Ok(before) => before,
+         _ => return create_error("before invalid", None),
+      },
+      None => i64::MAX,
+   };
+
+   let conn = conn.lock().await;
+
+   // Fetches one more event than asked for to know if there is a next page.
+   let mut query = conn
+      .prepare(&format!(
+         "SELECT {} FROM task_events WHERE (user_id = ?1 OR actor_id = ?1) AND id < ?2 ORDER BY id DESC LIMIT ?3",
+         TASK_EVENT_COLUMNS
+      ))
+      .unwrap();
+   let data = query
+      .query_map(params![_user_id, before, limit + 1], |row| {
+         TaskEvent::from_row(row, 0)
+      })
+      .unwrap();
+
+   let mut events: Vec<TaskEvent> = vec![];
+   for event in data {
+      events.push(event.unwrap());
+   }
+
+   let next_before = if events.len() as i64 > limit {
+      events.pop();
+
+      events.last().map(|event| event.id)
+   } else {
+      None
+   };
+
+   let json = serde_json::to_string(&TaskEvents {
+      events,
+      next_before,
+   });
+
+   valid_json(json)
+}
@@ -0,0 +1,446 @@
+use super::super::middlewares::users::{valid_user, ValidResponse};
+use super::super::utils::events::Wakeups;
+use super::super::utils::importers::{parse_export, ImportPlan, PlannedTask, SkippedItem, Source};
+use super::super::utils::labels::add_task_labels;
+use super::super::utils::{
+   create_error, get_query_params, get_users, now, read_body, valid_json, ErrorFormated,
+};
+use super::super::views::imports::{ImportJob, ImportJobFormated, IMPORT_JOB_COLUMNS};
+use super::super::views::projects::Project;
+use super::super::views::tasks::TaskCreated;
+use super::projects::insert_project;
+use super::tasks::{insert_task, validate_new_task};
+
+use std::convert::{From, Infallible};
+use std::sync::Arc;
+
+use futures::lock::Mutex;
+
+use rusqlite::{params, Connection, OptionalExtension};
+
+use uuid::Uuid;
+
+use hyper::{Body, Request, Response};
+
+const IMPORT_JOB_MAX_BYTES: usize = 10 * 1024 * 1024;
+const IMPORT_JOB_MAX_TASKS: usize = 10_000;
+const IMPORT_JOBS_LIST_LIMIT: i64 = 50;
+
+// Tasks written per transaction. The connection is released between
+// batches so a large import does not stall every other request.
+const IMPORT_BATCH_SIZE: usize = 100;
+
+fn find_user_import_job(conn: &Connection, job_id: &str, user_id: &str) -> Option<ImportJob> {
+   conn
+      .query_row(
+         &format!(
+            "SELECT {} FROM import_jobs WHERE import_jobs.id = ? AND import_jobs.user_id = ?",
+            IMPORT_JOB_COLUMNS
+         ),
+         [job_id, user_id],
+         |row| ImportJob::from_row(row, 0),
+      )
+      .optional()
+      .unwrap()
+}
+
+fn finish_job(conn: &Connection, job_id: &str, skipped: &[SkippedItem], error: Option<&str>) {
+   let status = if error.is_some() {
+      "failed"
+   } else {
+      "completed"
+   };
+
+   let query = conn.execute(
+      "UPDATE import_jobs SET status = ?, skipped = ?, error = ?, finished_at = ? WHERE id = ?",
+      params![
+         status,
+         serde_json::to_string(skipped).unwrap_or_else(|_| String::from("[]")),
+         error,
+         now(),
+         job_id
+      ],
+   );
+
+   if let Err(e) = query {
+      eprintln!("Import Error: {}", e);
+   }
+}
+
+fn import_projects(
+   conn: &mut Connection,
+   job_id: &str,
+   user_id: &str,
+   names: &[String],
+) -> Result<Vec<String>, rusqlite::Error> {
+   let transaction = conn.transaction()?;
+   let mut ids = vec![];
+   let mut wakeups = Wakeups::default();
+
+   for name in names {
+      let project = Project {
+         id: Uuid::new_v4().to_string(),
+         name: name.clone(),
+         user_id: String::from(user_id),
+         created_at: now(),
+      };
+
+      wakeups.add(insert_project(&transaction, &project, user_id)?);
+      ids.push(project.id);
+   }
+
+   transaction.execute(
+      "UPDATE import_jobs SET status = 'running', created_projects = ? WHERE id = ?",
+      params![ids.len() as i64, job_id],
+   )?;
+   transaction.commit()?;
+   wakeups.send();
+
+   Ok(ids)
+}
+
+// Writes one batch of tasks and moves the job's progress forward. Tasks the
+// rules of `POST /tasks` reject are reported instead of failing the job.
+fn import_batch(
+   conn: &mut Connection,
+   job_id: &str,
+   user_id: &str,
+   projects: &[String],
+   batch: Vec<PlannedTask>,
+   skipped: &mut Vec<SkippedItem>,
+) -> Result<(), rusqlite::Error> {
+   let transaction = conn.transaction()?;
+   let processed = batch.len() as i64;
+   let mut created = 0;
+   let mut wakeups = Wakeups::default();
+
+   for planned in batch {
+      let (name, due_date, recurrence) =
+         match validate_new_task(&planned.name, planned.due_date, planned.recurrence) {
+            Ok(task) => task,
+            Err(error) => {
+               skipped.push(SkippedItem::new(&planned.name, error));
+               continue;
+            }
+         };
+
+      let mut task = TaskCreated::new(user_id, &name);
+      task.completed = if planned.completed { 1 } else { 0 };
+      task.due_date = due_date;
+      task.recurrence = recurrence;
+      task.project_id = planned
+         .project
+         .and_then(|index| projects.get(index).cloned());
+
+      let (task_id, task_wakeups) = insert_task(&transaction, task, user_id)?;
+      add_task_labels(&transaction, &task_id, user_id, &planned.labels)?;
+      wakeups.add(task_wakeups);
+
+      created += 1;
+   }
+
+   transaction.execute(
+      "UPDATE import_jobs SET processed = processed + ?, created_tasks = created_tasks + ? WHERE id = ?",
+      params![processed, created, job_id],
+   )?;
+   transaction.commit()?;
+   wakeups.send();
+
+   Ok(())
+}
+
+// Runs the job in the background. Batches already written stay in place if
+// a later one fails.
+fn spawn_import(conn: Arc<Mutex<Connection>>, job_id: String, user_id: String, plan: ImportPlan) {
+   tokio::spawn(async move {
+      let ImportPlan {
+         projects,
+         tasks,
+         mut skipped,
+      } = plan;
+
+      let projects = {
+         let mut conn = conn.lock().await;
+
+         match import_projects(&mut conn, &job_id, &user_id, &projects) {
+            Ok(projects) => projects,
+            Err(e) => {
+               eprintln!("Import Error: {}", e);
+
+               finish_job(
+                  &conn,
+                  &job_id,
+                  &skipped,
+                  Option::from("error on import projects"),
+               );
+               return;
+            }
+         }
+      };
+
+      let mut tasks = tasks.into_iter().peekable();
+
+      while tasks.peek().is_some() {
+         let batch: Vec<PlannedTask> = tasks.by_ref().take(IMPORT_BATCH_SIZE).collect();
+         let mut conn = conn.lock().await;
+
+         if let Err(e) = import_batch(&mut conn, &job_id, &user_id, &projects, batch, &mut skipped)
+         {
+            eprintln!("Import Error: {}", e);
+
+            finish_job(
+               &conn,
+               &job_id,
+               &skipped,
+               Option::from("error on import tasks"),
+            );
+            return;
+         }
+
+         // Lets the requests waiting on the connection in before the next
+         // batch takes it again.
+         drop(conn);
+         let _ = tokio::task::yield_now().await;
+      }
+
+      let conn = conn.lock().await;
+
+      finish_job(&conn, &job_id, &skipped, None);
+   });
+}
+
+/// Starts importing an export file from another app. The file is the
+/// request body and `?source=todoist|trello|microsoft-todo` names its
+/// format; `?project=` names the project of a Todoist import. The file is
+/// checked right away, and the import itself runs in the background: the
+/// response is the job, to follow at `GET /imports/{id}`.
+#[utoipa::path(
+   post,
+   path = "/imports",
+   tag = "imports",
+   params(
+      ("source" = String, Query, description = "`todoist`, `trello` or `microsoft-todo`"),
+      ("project" = Option<String>, Query, description = "Project of a Todoist import")
+   ),
+   request_body(content = Vec<u8>, content_type = "application/octet-stream", description = "The export file"),
+   security(("token" = [])),
+   responses(
+      (status = 202, description = "The queued job", body = ImportJobFormated),
+      (status = 409, description = "An import is already running", body = ErrorFormated),
+      (status = 413, description = "The file is too large", body = ErrorFormated),
+      (status = 400, description = "Invalid request", body = ErrorFormated)
+   )
+)]
+pub async fn create_import_job(
+   req: Request<Body>,
+   conn: Arc<Mutex<Connection>>,
+) -> Result<Response<Body>, Infallible> {
+   let params = get_query_params(&req);
+   let (head, body) = req.into_parts();
+   let mut _user_id = String::from("");
+
+   let result = valid_user(&head.headers, &conn).await;
+
+   match result {
+      Ok(data) => match data {
+         ValidResponse::Id(id) => {
+            let users = get_users(conn.clone(), id.clone(), String::from("id")).await;
+
+            if users.is_empty() {
+               return create_error("this user not exists", None);
+            }
+
+            _user_id = id;
+         }
+         ValidResponse::Respo(data) => return data,
+      },
+      _ => return create_error("", None),
+   }
+
+   let source = match params
+      .get("source")
+      .and_then(|source| Source::parse(source))
+   {
+      Some(source) => source,
+      None => return create_error("source invalid", None),
+   };
+
+   let project = params
+      .get("project")
+      .map(|project| project.trim())
+      .filter(|project| !project.is_empty());
+
+   let data = match read_body(body, IMPORT_JOB_MAX_BYTES).await {
+      Ok(data) => data,
+      Err((status, error)) => return create_error(error, Option::from(status)),
+   };
+
+   let plan = match parse_export(source, &data, project) {
+      Ok(plan) => plan,
+      Err(error) => return create_error(error, None),
+   };
+
+   if plan.tasks.len() > IMPORT_JOB_MAX_TASKS {
+      return create_error("too many tasks to import", Option::from(413));
+   }
+
+   let conn_job = conn.clone();
+   let conn = conn.lock().await;
+
+   // One import at a time per user keeps the progress of a job meaningful.
+   let running: i64 = conn
+      .query_row(
+         "SELECT COUNT(*) FROM import_jobs WHERE user_id = ? AND status IN ('queued', 'running')",
+         [&_user_id],
+         |row| row.get(0),
+      )
+      .unwrap_or(0);
+
+   if running > 0 {
+      return create_error("an import is already running", Option::from(409));
+   }
+
+   let job = ImportJob {
+      id: Uuid::new_v4().to_string(),
+      user_id: _user_id.clone(),
+      source: String::from(source.as_str()),
+      status: String::from("queued"),
+      total: plan.tasks.len() as i64,
+      processed: 0,
+      created_tasks: 0,
+      created_projects: 0,
+      skipped: String::from("[]"),
+      error: None,
+      created_at: now(),
+      finished_at: None,
+   };
+
+   let query = conn.execute(
+      "INSERT INTO import_jobs (id, user_id, source, status, total, created_at) VALUES (?, ?, ?, ?, ?, ?)",
+      params![
+         job.id,
+         job.user_id,
+         job.source,
+         job.status,
+         job.total,
+         job.created_at
+      ],
+   );
+
+   if query.is_err() {
+      return create_error("not is possible create this import", None);
+   }
+
+   drop(conn);
+   spawn_import(conn_job, job.id.clone(), _user_id, plan);
+
+   match serde_json::to_string(&job.format()) {
+      Ok(json) => Ok(Response::builder()
+         .status(202)
+         .body(Body::from(json))
+         .unwrap()),
+      _ => create_error("", None),
+   }
+}
+
+#[utoipa::path(
+   get,
+   path = "/imports",
+   tag = "imports",
+   security(("token" = [])),
+   responses(
+      (status = 200, description = "The latest import jobs", body = [ImportJobFormated]),
+      (status = 400, description = "Invalid request", body = ErrorFormated)
+   )
+)]
+pub async fn list_import_jobs(
+   req: Request<Body>,
+   conn: Arc<Mutex<Connection>>,
+) -> Result<Response<Body>, Infallible> {
+   let headers = req.headers();
+   let mut _user_id = String::from("");
+
+   let result = valid_user(headers, &conn).await;
+
+   match result {
+      Ok(data) => match data {
+         ValidResponse::Id(id) => {
+            let users = get_users(conn.clone(), id.clone(), String::from("id")).await;
+
+            if users.is_empty() {
+               return create_error("this user not exists", None);
+            }
+
+            _user_id = id;
+         }
+         ValidResponse::Respo(data) => return data,
+      },
+      _ => return create_error("", None),
+   }
+
+   let conn = conn.lock().await;
+
+   let mut query = conn
+      .prepare(&format!(
+         "SELECT {} FROM import_jobs WHERE import_jobs.user_id = ? ORDER BY import_jobs.created_at DESC LIMIT {}",
+         IMPORT_JOB_COLUMNS, IMPORT_JOBS_LIST_LIMIT
+      ))
+      .unwrap();
+
+   let jobs: Vec<ImportJobFormated> = query
+      .query_map([&_user_id], |row| ImportJob::from_row(row, 0))
+      .unwrap()
+      .filter_map(|job| job.ok())
+      .map(|job| job.format())
+      .collect();
+
+   valid_json(serde_json::to_string(&jobs))
+}
+
+#[utoipa::path(
+   get,
+   path = "/imports/{id}",
+   tag = "imports",
+   params(
+      ("id" = String, Path, description = "Import job id")
+   ),
+   security(("token" = [])),
+   responses(
+      (status = 200, description = "The job and its progress", body = ImportJobFormated),
+      (status = 404, description = "Unknown job", body = ErrorFormated),
+      (status = 400, description = "Invalid request", body = ErrorFormated)
+   )
+)]
+pub async fn get_import_job(
+   req: Request<Body>,
+   conn: Arc<Mutex<Connection>>,
+   job_id: String,
+) -> Result<Response<Body>, Infallible> {
+   let headers = req.headers();
+   let mut _user_id = String::from("");
+
+   let result = valid_user(headers, &conn).await;
+
+   match result {
+      Ok(data) => match data {
+         ValidResponse::Id(id) => {
+            let users = get_users(conn.clone(), id.clone(), String::from("id")).await;
+
+            if users.is_empty() {
+               return create_error("this user not exists", None);
+            }
+
+            _user_id = id;
+         }
+         ValidResponse::Respo(data) => return data,
+      },
+      _ => return create_error("", None),
+   }
+
+   let conn = conn.lock().await;
+
+   match find_user_import_job(&conn, &job_id, &_user_id) {
+      Some(job) => valid_json(serde_json::to_string(&job.format())),
+      None => create_error("this import not exists", Option::from(404)),
+   }
+}
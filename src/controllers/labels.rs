@@ -0,0 +1,69 @@
+use super::super::middlewares::users::{valid_user, ValidResponse};
+use super::super::utils::{create_error, get_users, valid_json, ErrorFormated};
+use super::super::views::labels::LabelFormated;
+
+use std::convert::{From, Infallible};
+use std::sync::Arc;
+
+use futures::lock::Mutex;
+
+use rusqlite::Connection;
+
+use hyper::{Body, Request, Response};
+
+#[utoipa::path(
+   get,
+   path = "/labels",
+   tag = "labels",
+   security(("token" = [])),
+   responses(
+      (status = 200, description = "The labels of the user", body = [LabelFormated]),
+      (status = 400, description = "Invalid request", body = ErrorFormated)
+   )
+)]
+pub async fn list_labels(
+   req: Request<Body>,
+   conn: Arc<Mutex<Connection>>,
+) -> Result<Response<Body>, Infallible> {
+   let headers = req.headers();
+   let mut _user_id = String::from("");
+
+   let result = valid_user(headers, &conn).await;
+
+   match result {
+      Ok(data) => match data {
+         ValidResponse::Id(id) => {
+            let users = get_users(conn.clone(), id.clone(), String::from("id")).await;
+
+            if users.is_empty() {
+               return create_error("this user not exists", None);
+            }
+
+            _user_id = id;
+         }
+         ValidResponse::Respo(data) => return data,
+      },
+      _ => return create_error("", None),
+   }
+
+   let conn = conn.lock().await;
+
+   let mut query = conn
+      .prepare("SELECT labels.id, labels.name, labels.created_at, (SELECT COUNT(*) FROM task_labels INNER JOIN tasks ON tasks.id = task_labels.task_id WHERE task_labels.label_id = labels.id AND tasks.deleted_at IS NULL) FROM labels WHERE labels.user_id = ? ORDER BY labels.name")
+      .unwrap();
+
+   let labels: Vec<LabelFormated> = query
+      .query_map([&_user_id], |row| {
+         Ok(LabelFormated {
+            id: row.get(0)?,
+            name: row.get(1)?,
+            created_at: row.get(2)?,
+            tasks: row.get(3)?,
+         })
+      })
+      .unwrap()
+      .filter_map(|label| label.ok())
+      .collect();
+
+   valid_json(serde_json::to_string(&labels))
+}
@@ -0,0 +1,489 @@
+use super::super::middlewares::users::{valid_user, ValidResponse};
+use super::super::utils::history::record_event;
+use super::super::utils::permissions::{find_permitted_task, project_role, Role};
+use super::super::utils::validation::{trim, ValidationErrorFormated};
+use super::super::utils::{
+   body_error, create_error, get_users, now, parse_body, valid_json, ErrorFormated,
+};
+use super::super::views::projects::MemberFormated;
+use super::super::views::users::CreatedUserFormated;
+
+use std::convert::{From, Infallible};
+use std::sync::Arc;
+
+use futures::lock::Mutex;
+
+use rusqlite::{params, Connection, OptionalExtension};
+
+use hyper::{Body, Request, Response};
+
+use utoipa::ToSchema;
+
+use validator::Validate;
+
+use serde_json::json;
+
+#[derive(Serialize, Deserialize, Debug, ToSchema, Validate)]
+#[schema(as = MemberInvite)]
+struct RequestBodyInvite {
+   #[serde(deserialize_with = "trim")]
+   #[validate(email)]
+   email: String,
+   role: String,
+}
+
+// The creator of the task or project, once `user_id` is known to hold at
+// least `minimum` on it.
+fn find_resource_owner(
+   conn: &Connection,
+   resource_type: &str,
+   resource_id: &str,
+   user_id: &str,
+   minimum: Role,
+) -> Result<String, (u16, &'static str)> {
+   if resource_type == "task" {
+      return find_permitted_task(conn, resource_id, user_id, minimum, false)
+         .map(|(task, _)| task.user_id);
+   }
+
+   let owner: Option<String> = conn
+      .query_row(
+         "SELECT user_id FROM projects WHERE id = ?",
+         [resource_id],
+         |row| row.get(0),
+      )
+      .optional()
+      .unwrap();
+
+   match (owner, project_role(conn, resource_id, user_id)) {
+      (Some(owner), Some(role)) if role >= minimum => Ok(owner),
+      (Some(_), Some(_)) => Err((403, "you not have permission for to follow")),
+      _ => Err((404, "this project not exists")),
+   }
+}
+
+async fn list_members(
+   req: Request<Body>,
+   conn: Arc<Mutex<Connection>>,
+   resource_type: &'static str,
+   resource_id: String,
+) -> Result<Response<Body>, Infallible> {
+   let headers = req.headers();
+   let mut _user_id = String::from("");
+
+   let result = valid_user(headers, &conn).await;
+
+   match result {
+      Ok(data) => match data {
+         ValidResponse::Id(id) => {
+            let users = get_users(conn.clone(), id.clone(), String::from("id")).await;
+
+            if users.is_empty() {
+               return create_error("this user not exists", None);
+            }
+
+            _user_id = id;
+         }
+         ValidResponse::Respo(data) => return data,
+      },
+      _ => return create_error("", None),
+   }
+
+   let conn = conn.lock().await;
+
+   if let Err((status, error)) =
+      find_resource_owner(&conn, resource_type, &resource_id, &_user_id, Role::Viewer)
+   {
+      return create_error(error, Option::from(status));
+   }
+
+   let mut query = conn.prepare("SELECT users.id, users.firstname, users.lastname, users.email, memberships.role, memberships.invited_by, memberships.created_at FROM memberships INNER JOIN users ON users.id = memberships.user_id WHERE memberships.resource_type = ? AND memberships.resource_id = ? AND users.deleted_at IS NULL ORDER BY memberships.created_at ASC").unwrap();
+   let data = query
+      .query_map([resource_type, resource_id.as_str()], |row| {
+         Ok(MemberFormated {
+            user: CreatedUserFormated {
+               id: row.get(0)?,
+               firstname: row.get(1)?,
+               lastname: row.get(2)?,
+               email: row.get(3)?,
+            },
+            role: row.get(4)?,
+            invited_by: row.get(5)?,
+            created_at: row.get(6)?,
+         })
+      })
+      .unwrap();
+
+   let mut members: Vec<MemberFormated> = vec![];
+   for member in data {
+      members.push(member.unwrap());
+   }
+
+   let json = serde_json::to_string(&members);
+
+   valid_json(json)
+}
+
+/// Invites a user by email, or changes the role of someone already invited.
+async fn add_member(
+   req: Request<Body>,
+   conn: Arc<Mutex<Connection>>,
+   resource_type: &'static str,
+   resource_id: String,
+) -> Result<Response<Body>, Infallible> {
+   let (head, body) = req.into_parts();
+   let mut _user_id = String::from("");
+
+   let result = valid_user(&head.headers, &conn).await;
+
+   match result {
+      Ok(data) => match data {
+         ValidResponse::Id(id) => {
+            let users = get_users(conn.clone(), id.clone(), String::from("id")).await;
+
+            if users.is_empty() {
+               return create_error("this user not exists", None);
+            }
+
+            _user_id = id;
+         }
+         ValidResponse::Respo(data) => return data,
+      },
+      _ => return create_error("", None),
+   }
+
+   let body = parse_body::<RequestBodyInvite>(&head.headers, body).await;
+   let (email, role) = match body {
+      Ok(RequestBodyInvite { email, role }) => match Role::parse(&role) {
+         Some(role) => (email, role),
+         None => return create_error("role invalid", None),
+      },
+      Err(error) => return body_error(error),
+   };
+
+   let member = match get_users(conn.clone(), email, String::from("email"))
+      .await
+      .into_iter()
+      .next()
+   {
+      Some(member) => member,
+      None => return create_error("this user not exists", Option::from(404)),
+   };
+
+   let mut conn = conn.lock().await;
+
+   let transaction = match conn.transaction() {
+      Ok(transaction) => transaction,
+      _ => return create_error("", None),
+   };
+
+   let owner_id = match find_resource_owner(
+      &transaction,
+      resource_type,
+      &resource_id,
+      &_user_id,
+      Role::Owner,
+   ) {
+      Ok(owner_id) => owner_id,
+      Err((status, error)) => return create_error(error, Option::from(status)),
+   };
+
+   if owner_id == member.id {
+      return create_error("this user already owns it", None);
+   }
+
+   let previous: Option<String> = transaction
+      .query_row(
+         "SELECT role FROM memberships WHERE resource_type = ? AND resource_id = ? AND user_id = ?",
+         [resource_type, resource_id.as_str(), member.id.as_str()],
+         |row| row.get(0),
+      )
+      .optional()
+      .unwrap();
+
+   let created_at = now();
+
+   let query = transaction
+      .execute(
+         "INSERT INTO memberships (resource_type, resource_id, user_id, role, invited_by, created_at) VALUES (?, ?, ?, ?, ?, ?) ON CONFLICT (resource_type, resource_id, user_id) DO UPDATE SET role = excluded.role",
+         params![
+            resource_type,
+            resource_id,
+            member.id,
+            role.as_str(),
+            _user_id,
+            created_at
+         ],
+      )
+      .and_then(|_| {
+         record_event(
+            &transaction,
+            resource_type,
+            &resource_id,
+            &owner_id,
+            &_user_id,
+            "shared",
+            json!({ "user_id": member.id, "role": { "from": previous, "to": role.as_str() } }),
+         )
+      })
+      .and_then(|wakeups| transaction.commit().map(|_| wakeups));
+
+   let status = if previous.is_some() { 200 } else { 201 };
+
+   let json = serde_json::to_string(&MemberFormated {
+      user: member.format(),
+      role: String::from(role.as_str()),
+      invited_by: Option::from(_user_id),
+      created_at,
+   });
+
+   match (query, json) {
+      (Ok(wakeups), Ok(json)) => {
+         wakeups.send();
+
+         Ok(Response::builder()
+            .status(status)
+            .body(Body::from(json))
+            .unwrap())
+      }
+      _ => create_error("error on share", None),
+   }
+}
+
+/// Owners may remove anyone; every member may remove themselves.
+async fn remove_member(
+   req: Request<Body>,
+   conn: Arc<Mutex<Connection>>,
+   resource_type: &'static str,
+   resource_id: String,
+   member_id: String,
+) -> Result<Response<Body>, Infallible> {
+   let headers = req.headers();
+   let mut _user_id = String::from("");
+
+   let result = valid_user(headers, &conn).await;
+
+   match result {
+      Ok(data) => match data {
+         ValidResponse::Id(id) => {
+            let users = get_users(conn.clone(), id.clone(), String::from("id")).await;
+
+            if users.is_empty() {
+               return create_error("this user not exists", None);
+            }
+
+            _user_id = id;
+         }
+         ValidResponse::Respo(data) => return data,
+      },
+      _ => return create_error("", None),
+   }
+
+   let minimum = if member_id == _user_id {
+      Role::Viewer
+   } else {
+      Role::Owner
+   };
+
+   let mut conn = conn.lock().await;
+
+   let transaction = match conn.transaction() {
+      Ok(transaction) => transaction,
+      _ => return create_error("", None),
+   };
+
+   let owner_id = match find_resource_owner(
+      &transaction,
+      resource_type,
+      &resource_id,
+      &_user_id,
+      minimum,
+   ) {
+      Ok(owner_id) => owner_id,
+      Err((status, error)) => return create_error(error, Option::from(status)),
+   };
+
+   let removed = transaction.execute(
+      "DELETE FROM memberships WHERE resource_type = ? AND resource_id = ? AND user_id = ?",
+      [resource_type, resource_id.as_str(), member_id.as_str()],
+   );
+
+   match removed {
+      Ok(0) => return create_error("this member not exists", Option::from(404)),
+      Ok(_) => {}
+      _ => return create_error("error on unshare", None),
+   }
+
+   let query = record_event(
+      &transaction,
+      resource_type,
+      &resource_id,
+      &owner_id,
+      &_user_id,
+      "unshared",
+      json!({ "user_id": member_id }),
+   )
+   .and_then(|wakeups| transaction.commit().map(|_| wakeups));
+
+   match query {
+      Ok(wakeups) => {
+         wakeups.send();
+
+         Ok(Response::builder()
+            .status(200)
+            .body(Body::from(""))
+            .unwrap())
+      }
+      _ => create_error("error on unshare", None),
+   }
+}
+
+#[utoipa::path(
+   get,
+   path = "/tasks/{id}/members",
+   tag = "members",
+   params(
+      ("id" = String, Path, description = "Task id")
+   ),
+   responses(
+      (status = 200, description = "The people the task is shared with", body = [MemberFormated]),
+      (status = 404, description = "The task does not exist or is not shared with the user", body = ErrorFormated),
+      (status = 400, description = "Invalid request", body = ErrorFormated)
+   ),
+   security(("token" = []))
+)]
+pub async fn list_task_members(
+   req: Request<Body>,
+   conn: Arc<Mutex<Connection>>,
+   task_id: String,
+) -> Result<Response<Body>, Infallible> {
+   list_members(req, conn, "task", task_id).await
+}
+
+#[utoipa::path(
+   post,
+   path = "/tasks/{id}/members",
+   tag = "members",
+   params(
+      ("id" = String, Path, description = "Task id")
+   ),
+   request_body = RequestBodyInvite,
+   responses(
+      (status = 201, description = "The new member and their role", body = MemberFormated),
+      (status = 200, description = "The member was already there, with their new role", body = MemberFormated),
+      (status = 403, description = "Only the owner may share it", body = ErrorFormated),
+      (status = 404, description = "The task does not exist or is not shared with the user", body = ErrorFormated),
+      (status = 400, description = "Invalid request", body = ErrorFormated),
+      (status = 422, description = "Fields that break their rules", body = ValidationErrorFormated),
+      (status = 413, description = "Body larger than `BODY_MAX_BYTES`", body = ErrorFormated),
+      (status = 415, description = "Body not sent as JSON", body = ErrorFormated)
+   ),
+   security(("token" = []))
+)]
+pub async fn add_task_member(
+   req: Request<Body>,
+   conn: Arc<Mutex<Connection>>,
+   task_id: String,
+) -> Result<Response<Body>, Infallible> {
+   add_member(req, conn, "task", task_id).await
+}
+
+#[utoipa::path(
+   delete,
+   path = "/tasks/{id}/members/{member_id}",
+   tag = "members",
+   params(
+      ("id" = String, Path, description = "Task id"),
+      ("member_id" = String, Path, description = "User id of the member")
+   ),
+   responses(
+      (status = 200, description = "The member was removed"),
+      (status = 403, description = "Only the owner may remove others", body = ErrorFormated),
+      (status = 404, description = "The task does not exist or is not shared with the user", body = ErrorFormated),
+      (status = 400, description = "Invalid request", body = ErrorFormated)
+   ),
+   security(("token" = []))
+)]
+pub async fn remove_task_member(
+   req: Request<Body>,
+   conn: Arc<Mutex<Connection>>,
+   task_id: String,
+   member_id: String,
+) -> Result<Response<Body>, Infallible> {
+   remove_member(req, conn, "task", task_id, member_id).await
+}
+
+#[utoipa::path(
+   get,
+   path = "/projects/{id}/members",
+   tag = "members",
+   params(
+      ("id" = String, Path, description = "Project id")
+   ),
+   responses(
+      (status = 200, description = "The people the project is shared with", body = [MemberFormated]),
+      (status = 404, description = "The project does not exist or is not shared with the user", body = ErrorFormated),
+      (status = 400, description = "Invalid request", body = ErrorFormated)
+   ),
+   security(("token" = []))
+)]
+pub async fn list_project_members(
+   req: Request<Body>,
+   conn: Arc<Mutex<Connection>>,
+   project_id: String,
+) -> Result<Response<Body>, Infallible> {
+   list_members(req, conn, "project", project_id).await
+}
+
+#[utoipa::path(
+   post,
+   path = "/projects/{id}/members",
+   tag = "members",
+   params(
+      ("id" = String, Path, description = "Project id")
+   ),
+   request_body = RequestBodyInvite,
+   responses(
+      (status = 201, description = "The new member and their role", body = MemberFormated),
+      (status = 200, description = "The member was already there, with their new role", body = MemberFormated),
+      (status = 403, description = "Only the owner may share it", body = ErrorFormated),
+      (status = 404, description = "The project does not exist or is not shared with the user", body = ErrorFormated),
+      (status = 400, description = "Invalid request", body = ErrorFormated),
+      (status = 422, description = "Fields that break their rules", body = ValidationErrorFormated),
+      (status = 413, description = "Body larger than `BODY_MAX_BYTES`", body = ErrorFormated),
+      (status = 415, description = "Body not sent as JSON", body = ErrorFormated)
+   ),
+   security(("token" = []))
+)]
+pub async fn add_project_member(
+   req: Request<Body>,
+   conn: Arc<Mutex<Connection>>,
+   project_id: String,
+) -> Result<Response<Body>, Infallible> {
+   add_member(req, conn, "project", project_id).await
+}
+
+#[utoipa::path(
+   delete,
+   path = "/projects/{id}/members/{member_id}",
+   tag = "members",
+   params(
+      ("id" = String, Path, description = "Project id"),
+      ("member_id" = String, Path, description = "User id of the member")
+   ),
+   responses(
+      (status = 200, description = "The member was removed"),
+      (status = 403, description = "Only the owner may remove others", body = ErrorFormated),
+      (status = 404, description = "The project does not exist or is not shared with the user", body = ErrorFormated),
+      (status = 400, description = "Invalid request", body = ErrorFormated)
+   ),
+   security(("token" = []))
+)]
+pub async fn remove_project_member(
+   req: Request<Body>,
+   conn: Arc<Mutex<Connection>>,
+   project_id: String,
+   member_id: String,
+) -> Result<Response<Body>, Infallible> {
+   remove_member(req, conn, "project", project_id, member_id).await
+}
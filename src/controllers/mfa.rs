@@ -0,0 +1,309 @@
+use super::super::middlewares::users::{mfa_pending_claims, valid_user, ValidResponse};
+use super::super::utils::history::record_event;
+use super::super::utils::login_attempts::{self, LoginKey};
+use super::super::utils::totp::{
+   create_recovery_codes, generate_secret, otpauth_uri, use_code, use_recovery_code,
+};
+use super::super::utils::validation::{trim, ValidationErrorFormated};
+use super::super::utils::{
+   body_error, client_ip, create_error, parse_body, valid_json, ErrorFormated,
+};
+use super::users::{login_response, too_many_attempts, LoginFormated};
+
+use std::convert::{From, Infallible};
+use std::sync::Arc;
+
+use futures::lock::Mutex;
+
+use rusqlite::{Connection, OptionalExtension};
+
+use hyper::{Body, Request, Response};
+
+use utoipa::ToSchema;
+
+use validator::Validate;
+
+use serde_json::json;
+
+const TOKEN_MAX_LENGTH: u64 = 2048;
+const CODE_MAX_LENGTH: u64 = 64;
+
+#[derive(Serialize, Deserialize, Debug, ToSchema, Validate)]
+#[schema(as = TotpConfirm)]
+struct RequestBodyConfirm {
+   #[serde(deserialize_with = "trim")]
+   #[validate(length(min = 1, max = CODE_MAX_LENGTH))]
+   code: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, ToSchema, Validate)]
+#[schema(as = LoginMfa)]
+struct RequestBodyMfaLogin {
+   #[serde(deserialize_with = "trim")]
+   #[validate(length(min = 1, max = TOKEN_MAX_LENGTH))]
+   mfa_token: String,
+   /// A code of the authenticator app, or one of the recovery codes.
+   #[serde(deserialize_with = "trim")]
+   #[validate(length(min = 1, max = CODE_MAX_LENGTH))]
+   code: String,
+}
+
+#[derive(Serialize, ToSchema)]
+struct TotpEnrollmentFormated {
+   secret: String,
+   /// For the QR code that authenticator apps scan.
+   uri: String,
+}
+
+#[derive(Serialize, ToSchema)]
+struct RecoveryCodesFormated {
+   /// Each works once in place of a code. They are not shown again.
+   recovery_codes: Vec<String>,
+}
+
+struct TotpState {
+   email: String,
+   secret: Option<String>,
+   enabled: bool,
+   session: i64,
+}
+
+fn totp_state(conn: &Connection, user_id: &str) -> Option<TotpState> {
+   conn
+      .query_row(
+         "SELECT email, totp_secret, totp_enabled, session_version FROM users WHERE id = ? AND deleted_at IS NULL",
+         [user_id],
+         |row| {
+            Ok(TotpState {
+               email: row.get(0)?,
+               secret: row.get(1)?,
+               enabled: row.get(2)?,
+               session: row.get(3)?,
+            })
+         },
+      )
+      .optional()
+      .unwrap_or(None)
+}
+
+/// Starts two-factor authentication with a new secret, which only takes
+/// effect once a code of it is sent to `POST /mfa/totp/confirm`. Enrolling
+/// again before that replaces the secret.
+#[utoipa::path(
+   post,
+   path = "/mfa/totp/enroll",
+   tag = "users",
+   security(("token" = [])),
+   responses(
+      (status = 200, description = "The secret to add to an authenticator app", body = TotpEnrollmentFormated),
+      (status = 409, description = "Two-factor authentication is already enabled", body = ErrorFormated)
+   )
+)]
+pub async fn enroll_totp(
+   req: Request<Body>,
+   conn: Arc<Mutex<Connection>>,
+) -> Result<Response<Body>, Infallible> {
+   let user_id = match valid_user(req.headers(), &conn).await {
+      Ok(data) => match data {
+         ValidResponse::Id(id) => id,
+         ValidResponse::Respo(data) => return data,
+      },
+      _ => return create_error("", None),
+   };
+
+   let conn = conn.lock().await;
+
+   let state = match totp_state(&conn, &user_id) {
+      Some(state) => state,
+      None => return create_error("this user not exists", Option::from(404)),
+   };
+
+   if state.enabled {
+      return create_error(
+         "two-factor authentication is already enabled",
+         Option::from(409),
+      );
+   }
+
+   let secret = generate_secret();
+
+   let query = conn.execute(
+      "UPDATE users SET totp_secret = ?, totp_last_step = 0 WHERE id = ?",
+      [&secret, &user_id],
+   );
+
+   match query {
+      Ok(_) => {
+         let json = serde_json::to_string(&TotpEnrollmentFormated {
+            uri: otpauth_uri(&secret, &state.email),
+            secret,
+         });
+
+         valid_json(json)
+      }
+      _ => create_error("", None),
+   }
+}
+
+/// Enables two-factor authentication with a first code of the secret of
+/// `POST /mfa/totp/enroll`, and hands out the recovery codes.
+#[utoipa::path(
+   post,
+   path = "/mfa/totp/confirm",
+   tag = "users",
+   request_body = RequestBodyConfirm,
+   security(("token" = [])),
+   responses(
+      (status = 200, description = "Two-factor authentication is enabled", body = RecoveryCodesFormated),
+      (status = 400, description = "The code is not valid, or there was no enrollment", body = ErrorFormated),
+      (status = 409, description = "Two-factor authentication is already enabled", body = ErrorFormated),
+      (status = 422, description = "Fields that break their rules", body = ValidationErrorFormated),
+      (status = 413, description = "Body larger than `BODY_MAX_BYTES`", body = ErrorFormated),
+      (status = 415, description = "Body not sent as JSON", body = ErrorFormated)
+   )
+)]
+pub async fn confirm_totp(
+   req: Request<Body>,
+   conn: Arc<Mutex<Connection>>,
+) -> Result<Response<Body>, Infallible> {
+   let user_id = match valid_user(req.headers(), &conn).await {
+      Ok(data) => match data {
+         ValidResponse::Id(id) => id,
+         ValidResponse::Respo(data) => return data,
+      },
+      _ => return create_error("", None),
+   };
+
+   let (head, body) = req.into_parts();
+
+   let code = match parse_body::<RequestBodyConfirm>(&head.headers, body).await {
+      Ok(RequestBodyConfirm { code }) => code,
+      Err(error) => return body_error(error),
+   };
+
+   let mut conn = conn.lock().await;
+
+   let state = match totp_state(&conn, &user_id) {
+      Some(state) => state,
+      None => return create_error("this user not exists", Option::from(404)),
+   };
+
+   if state.enabled {
+      return create_error(
+         "two-factor authentication is already enabled",
+         Option::from(409),
+      );
+   }
+
+   let secret = match state.secret {
+      Some(secret) => secret,
+      None => return create_error("enroll before confirming a code", None),
+   };
+
+   let transaction = match conn.transaction() {
+      Ok(transaction) => transaction,
+      _ => return create_error("", None),
+   };
+
+   if !use_code(&transaction, &user_id, &secret, &code) {
+      return create_error("this code is not valid", None);
+   }
+
+   let codes = transaction
+      .execute("UPDATE users SET totp_enabled = 1 WHERE id = ?", [&user_id])
+      .and_then(|_| create_recovery_codes(&transaction, &user_id))
+      .and_then(|codes| {
+         record_event(
+            &transaction,
+            "user",
+            &user_id,
+            &user_id,
+            &user_id,
+            "updated",
+            json!({ "totp_enabled": { "from": false, "to": true } }),
+         )
+         .map(|wakeups| (codes, wakeups))
+      })
+      .and_then(|data| transaction.commit().map(|_| data));
+
+   match codes {
+      Ok((recovery_codes, wakeups)) => {
+         wakeups.send();
+
+         valid_json(serde_json::to_string(&RecoveryCodesFormated {
+            recovery_codes,
+         }))
+      }
+      _ => create_error("", None),
+   }
+}
+
+/// Finishes a login of a user with two-factor authentication: the token of
+/// `POST /login` and a code give the token for the Authorization header.
+/// Wrong codes count as failed logins.
+#[utoipa::path(
+   post,
+   path = "/login/mfa",
+   tag = "users",
+   request_body = RequestBodyMfaLogin,
+   responses(
+      (status = 200, description = "A token for the Authorization header", body = LoginFormated),
+      (status = 401, description = "The token is invalid or expired, or the code is not valid", body = ErrorFormated),
+      (status = 429, description = "Too many failed logins for this email or address, retry after `Retry-After` seconds", body = ErrorFormated),
+      (status = 422, description = "Fields that break their rules", body = ValidationErrorFormated),
+      (status = 413, description = "Body larger than `BODY_MAX_BYTES`", body = ErrorFormated),
+      (status = 415, description = "Body not sent as JSON", body = ErrorFormated)
+   )
+)]
+pub async fn login_mfa(
+   req: Request<Body>,
+   conn: Arc<Mutex<Connection>>,
+) -> Result<Response<Body>, Infallible> {
+   let ip = client_ip(&req);
+   let (head, body) = req.into_parts();
+
+   let (mfa_token, code) = match parse_body::<RequestBodyMfaLogin>(&head.headers, body).await {
+      Ok(RequestBodyMfaLogin { mfa_token, code }) => (mfa_token, code),
+      Err(error) => return body_error(error),
+   };
+
+   let invalid = || create_error("this token is invalid or expired", Option::from(401));
+
+   let claims = match mfa_pending_claims(&mfa_token) {
+      Some(claims) => claims,
+      None => return invalid(),
+   };
+
+   let conn = conn.lock().await;
+
+   // A password reset since the login revokes its token too.
+   let state = match totp_state(&conn, &claims.id) {
+      Some(state) if state.enabled && state.session == claims.session => state,
+      _ => return invalid(),
+   };
+
+   let mut keys = vec![LoginKey::Email(state.email.clone())];
+   if let Some(ip) = ip {
+      keys.push(LoginKey::Ip(ip));
+   }
+
+   if let Err(wait) = login_attempts::begin_attempt(&conn, &keys) {
+      return too_many_attempts(wait);
+   }
+
+   let secret = state.secret.unwrap_or_default();
+   let valid =
+      use_code(&conn, &claims.id, &secret, &code) || use_recovery_code(&conn, &claims.id, &code);
+
+   if !valid {
+      return create_error("this code is not valid", Option::from(401));
+   }
+
+   for key in &keys {
+      login_attempts::undo_failure(&conn, key);
+   }
+
+   let _ = login_attempts::clear(&conn, &keys[0]);
+
+   login_response(&claims.id, state.session)
+}
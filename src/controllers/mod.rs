@@ -1,2 +1,19 @@
+pub mod attachments;
+pub mod calendar;
+pub mod comments;
+pub mod docs;
+pub mod events;
+pub mod exports;
+pub mod graphql;
+pub mod history;
+pub mod imports;
+pub mod labels;
+pub mod members;
+pub mod mfa;
+pub mod passwords;
+pub mod projects;
 pub mod tasks;
+pub mod trash;
 pub mod users;
+pub mod verification;
+pub mod webhooks;
@@ -0,0 +1,243 @@
+use super::super::config::CONFIG;
+use super::super::utils::login_attempts::{self, LoginKey};
+use super::super::utils::password_reset::{
+   create_reset, seconds_since_sent, send_reset, use_reset,
+};
+use super::super::utils::passwords::{hash_password, password_policy};
+use super::super::utils::validation::{
+   trim, ValidationErrorFormated, EMAIL_MAX_LENGTH, PASSWORD_MAX_LENGTH,
+};
+use super::super::utils::{
+   body_error, create_error, find_user, get_users, parse_body, ErrorFormated,
+};
+use super::users::save_user;
+
+use std::convert::{From, Infallible};
+use std::sync::Arc;
+
+use futures::lock::Mutex;
+
+use rusqlite::Connection;
+
+use hyper::{Body, Request, Response};
+
+use utoipa::ToSchema;
+
+use validator::Validate;
+
+const TOKEN_MAX_LENGTH: u64 = 512;
+
+#[derive(Serialize, Deserialize, Debug, ToSchema, Validate)]
+#[schema(as = PasswordForgot)]
+struct RequestBodyForgot {
+   #[serde(deserialize_with = "trim")]
+   #[validate(email, length(max = EMAIL_MAX_LENGTH))]
+   email: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, ToSchema, Validate)]
+#[schema(as = PasswordReset)]
+struct RequestBodyReset {
+   #[serde(deserialize_with = "trim")]
+   #[validate(length(min = 1, max = TOKEN_MAX_LENGTH))]
+   token: String,
+   #[validate(length(max = PASSWORD_MAX_LENGTH), custom(function = "password_policy"))]
+   password: String,
+}
+
+/// Emails a reset token to the user of an email, at most once every
+/// `PASSWORD_RESET_RESEND_SECONDS`. The answer is the same whether or not
+/// the email belongs to a user.
+#[utoipa::path(
+   post,
+   path = "/password/forgot",
+   tag = "users",
+   request_body = RequestBodyForgot,
+   responses(
+      (status = 202, description = "A token is on its way if the email belongs to a user"),
+      (status = 422, description = "Fields that break their rules", body = ValidationErrorFormated),
+      (status = 413, description = "Body larger than `BODY_MAX_BYTES`", body = ErrorFormated),
+      (status = 415, description = "Body not sent as JSON", body = ErrorFormated)
+   )
+)]
+pub async fn forgot_password(
+   req: Request<Body>,
+   conn: Arc<Mutex<Connection>>,
+) -> Result<Response<Body>, Infallible> {
+   let (head, body) = req.into_parts();
+
+   let email = match parse_body::<RequestBodyForgot>(&head.headers, body).await {
+      Ok(RequestBodyForgot { email }) => email,
+      Err(error) => return body_error(error),
+   };
+
+   let accepted = Ok(Response::builder()
+      .status(202)
+      .body(Body::from(""))
+      .unwrap());
+
+   let users = get_users(conn.clone(), email, String::from("email")).await;
+
+   let user = match users.first() {
+      Some(user) => user,
+      None => return accepted,
+   };
+
+   let conn = conn.lock().await;
+
+   let throttled = seconds_since_sent(&conn, &user.id)
+      .is_some_and(|seconds| seconds < CONFIG.password_reset_resend_seconds);
+
+   if throttled {
+      return accepted;
+   }
+
+   match create_reset(&conn, &user.id) {
+      Ok(token) => {
+         send_reset(&user.email, &user.firstname, &token);
+
+         accepted
+      }
+      _ => create_error("", None),
+   }
+}
+
+/// Sets a new password with a token of `POST /password/forgot`. Every
+/// session of the user ends: tokens from before stop working.
+#[utoipa::path(
+   post,
+   path = "/password/reset",
+   tag = "users",
+   request_body = RequestBodyReset,
+   responses(
+      (status = 200, description = "The password was changed"),
+      (status = 400, description = "The token is invalid, used or expired", body = ErrorFormated),
+      (status = 422, description = "Fields that break their rules", body = ValidationErrorFormated),
+      (status = 413, description = "Body larger than `BODY_MAX_BYTES`", body = ErrorFormated),
+      (status = 415, description = "Body not sent as JSON", body = ErrorFormated)
+   )
+)]
+pub async fn reset_password(
+   req: Request<Body>,
+   conn: Arc<Mutex<Connection>>,
+) -> Result<Response<Body>, Infallible> {
+   let (head, body) = req.into_parts();
+
+   let (token, password) = match parse_body::<RequestBodyReset>(&head.headers, body).await {
+      Ok(RequestBodyReset { token, password }) => (token, password),
+      Err(error) => return body_error(error),
+   };
+
+   // Hashing takes a while, so it is done before taking the connection.
+   let password = match hash_password(&password).await {
+      Ok(password) => password,
+      _ => return create_error("", None),
+   };
+
+   let mut conn = conn.lock().await;
+
+   let transaction = match conn.transaction() {
+      Ok(transaction) => transaction,
+      _ => return create_error("", None),
+   };
+
+   let user_id = match use_reset(&transaction, &token) {
+      Ok(user_id) => user_id,
+      Err((status, reason)) => return create_error(reason, Option::from(status)),
+   };
+
+   let before = match find_user(&transaction, &user_id) {
+      Some(user) => user,
+      None => return create_error("this token is invalid or expired", None),
+   };
+
+   let mut user = before.clone();
+   user.password = password;
+
+   let result = save_user(&transaction, &before, &user).and_then(|wakeups| {
+      transaction.execute(
+         "UPDATE users SET session_version = session_version + 1 WHERE id = ?",
+         [&user.id],
+      )?;
+      login_attempts::clear(&transaction, &LoginKey::Email(user.email.clone()))?;
+      transaction.commit().map(|_| wakeups)
+   });
+
+   match result {
+      Ok(wakeups) => {
+         wakeups.send();
+
+         Ok(Response::builder()
+            .status(200)
+            .body(Body::from(""))
+            .unwrap())
+      }
+      _ => create_error("", None),
+   }
+}
+
+#[cfg(test)]
+mod tests {
+   use super::super::super::database::create_memory_connection;
+   use super::super::super::middlewares::users::{valid_user, ValidResponse};
+   use super::super::users::login_response;
+   use super::*;
+
+   use hyper::body::to_bytes;
+   use hyper::header::{HeaderValue, AUTHORIZATION, CONTENT_TYPE};
+   use hyper::HeaderMap;
+
+   use serde_json::{json, Value};
+
+   async fn authenticated(conn: &Arc<Mutex<Connection>>, token: &str) -> bool {
+      let mut headers = HeaderMap::new();
+      headers.insert(
+         AUTHORIZATION,
+         HeaderValue::from_str(&format!("Bearer {}", token)).unwrap(),
+      );
+
+      matches!(valid_user(&headers, conn).await, Ok(ValidResponse::Id(_)))
+   }
+
+   #[tokio::test]
+   async fn reset_revokes_sessions() {
+      let conn = create_memory_connection();
+      conn
+         .execute(
+            "INSERT INTO users (id, firstname, lastname, email, password) VALUES ('alice', 'Alice', 'A', 'alice@x.io', '')",
+            [],
+         )
+         .unwrap();
+      let reset = create_reset(&conn, "alice").unwrap();
+      let conn = Arc::new(Mutex::new(conn));
+
+      let login = to_bytes(login_response("alice", 0).unwrap().into_body())
+         .await
+         .unwrap();
+      let session: Value = serde_json::from_slice(&login).unwrap();
+      let session = session["token"].as_str().unwrap();
+
+      assert!(authenticated(&conn, session).await);
+
+      let request = Request::post("/password/reset")
+         .header(CONTENT_TYPE, "application/json")
+         .body(Body::from(
+            json!({ "token": reset, "password": "correct horse battery staple" }).to_string(),
+         ))
+         .unwrap();
+      let response = reset_password(request, conn.clone()).await.unwrap();
+
+      assert_eq!(response.status(), 200);
+      assert!(!authenticated(&conn, session).await);
+
+      let request = Request::post("/password/reset")
+         .header(CONTENT_TYPE, "application/json")
+         .body(Body::from(
+            json!({ "token": reset, "password": "another horse battery staple" }).to_string(),
+         ))
+         .unwrap();
+      let response = reset_password(request, conn.clone()).await.unwrap();
+
+      assert_eq!(response.status(), 400);
+   }
+}
@@ -0,0 +1,199 @@
+use super::super::middlewares::users::{valid_user, ValidResponse};
+use super::super::utils::events::Wakeups;
+use super::super::utils::history::record_event;
+use super::super::utils::permissions::{project_role, Role};
+use super::super::utils::validation::{trim, ValidationErrorFormated, NAME_MAX_LENGTH};
+use super::super::utils::{
+   body_error, create_error, get_users, now, parse_body, valid_json, ErrorFormated,
+};
+use super::super::views::projects::{Project, ProjectFormated, PROJECT_COLUMNS};
+
+use std::convert::{From, Infallible};
+use std::sync::Arc;
+
+use futures::lock::Mutex;
+
+use rusqlite::{params, Connection};
+
+use uuid::Uuid;
+
+use hyper::{Body, Request, Response};
+
+use utoipa::ToSchema;
+
+use validator::Validate;
+
+use serde_json::json;
+
+#[derive(Serialize, Deserialize, Debug, ToSchema, Validate)]
+#[schema(as = ProjectCreate)]
+struct RequestBodyCreate {
+   #[serde(deserialize_with = "trim")]
+   #[validate(length(min = 1, max = NAME_MAX_LENGTH))]
+   name: String,
+}
+
+pub fn insert_project(
+   conn: &Connection,
+   project: &Project,
+   actor_id: &str,
+) -> Result<Wakeups, rusqlite::Error> {
+   conn.execute(
+      "INSERT INTO projects (id, name, user_id, created_at) VALUES (?, ?, ?, ?)",
+      params![
+         project.id,
+         project.name,
+         project.user_id,
+         project.created_at
+      ],
+   )?;
+
+   record_event(
+      conn,
+      "project",
+      &project.id,
+      &project.user_id,
+      actor_id,
+      "created",
+      json!({ "name": { "from": null, "to": project.name } }),
+   )
+}
+
+#[utoipa::path(
+   get,
+   path = "/projects",
+   tag = "projects",
+   security(("token" = [])),
+   responses(
+      (status = 200, description = "The projects the user owns or was invited to", body = [ProjectFormated]),
+      (status = 400, description = "Invalid request", body = ErrorFormated)
+   )
+)]
+pub async fn list_projects(
+   req: Request<Body>,
+   conn: Arc<Mutex<Connection>>,
+) -> Result<Response<Body>, Infallible> {
+   let headers = req.headers();
+   let mut _user_id = String::from("");
+
+   let result = valid_user(headers, &conn).await;
+
+   match result {
+      Ok(data) => match data {
+         ValidResponse::Id(id) => {
+            let users = get_users(conn.clone(), id.clone(), String::from("id")).await;
+
+            if users.is_empty() {
+               return create_error("this user not exists", None);
+            }
+
+            _user_id = id;
+         }
+         ValidResponse::Respo(data) => return data,
+      },
+      _ => return create_error("", None),
+   }
+
+   let conn = conn.lock().await;
+
+   let mut query = conn
+      .prepare(&format!(
+         "SELECT {} FROM projects WHERE projects.user_id = ?1 OR EXISTS (SELECT 1 FROM memberships WHERE resource_type = 'project' AND resource_id = projects.id AND user_id = ?1) ORDER BY projects.created_at ASC",
+         PROJECT_COLUMNS
+      ))
+      .unwrap();
+   let data = query
+      .query_map([&_user_id], |row| Project::from_row(row, 0))
+      .unwrap();
+
+   let mut projects: Vec<ProjectFormated> = vec![];
+
+   for project in data {
+      let project = project.unwrap();
+      let role = project_role(&conn, &project.id, &_user_id).unwrap_or(Role::Viewer);
+
+      projects.push(project.format(&_user_id, role.as_str()));
+   }
+
+   let json = serde_json::to_string(&projects);
+
+   valid_json(json)
+}
+
+#[utoipa::path(
+   post,
+   path = "/projects",
+   tag = "projects",
+   request_body = RequestBodyCreate,
+   security(("token" = [])),
+   responses(
+      (status = 201, description = "The project", body = ProjectFormated),
+      (status = 400, description = "Invalid request", body = ErrorFormated),
+      (status = 422, description = "Fields that break their rules", body = ValidationErrorFormated),
+      (status = 413, description = "Body larger than `BODY_MAX_BYTES`", body = ErrorFormated),
+      (status = 415, description = "Body not sent as JSON", body = ErrorFormated)
+   )
+)]
+pub async fn create_project(
+   req: Request<Body>,
+   conn: Arc<Mutex<Connection>>,
+) -> Result<Response<Body>, Infallible> {
+   let (head, body) = req.into_parts();
+   let mut _user_id = String::from("");
+
+   let result = valid_user(&head.headers, &conn).await;
+
+   match result {
+      Ok(data) => match data {
+         ValidResponse::Id(id) => {
+            let users = get_users(conn.clone(), id.clone(), String::from("id")).await;
+
+            if users.is_empty() {
+               return create_error("this user not exists", None);
+            }
+
+            _user_id = id;
+         }
+         ValidResponse::Respo(data) => return data,
+      },
+      _ => return create_error("", None),
+   }
+
+   let body = parse_body::<RequestBodyCreate>(&head.headers, body).await;
+   let name = match body {
+      Ok(RequestBodyCreate { name }) => name,
+      Err(error) => return body_error(error),
+   };
+
+   let project = Project {
+      id: Uuid::new_v4().to_string(),
+      name,
+      user_id: _user_id.clone(),
+      created_at: now(),
+   };
+
+   let mut conn = conn.lock().await;
+
+   let transaction = match conn.transaction() {
+      Ok(transaction) => transaction,
+      _ => return create_error("", None),
+   };
+
+   let query = insert_project(&transaction, &project, &_user_id)
+      .and_then(|wakeups| transaction.commit().map(|_| wakeups));
+
+   match query {
+      Ok(wakeups) => {
+         wakeups.send();
+
+         match serde_json::to_string(&project.format(&_user_id, Role::Owner.as_str())) {
+            Ok(json) => Ok(Response::builder()
+               .status(201)
+               .body(Body::from(json))
+               .unwrap()),
+            _ => create_error("", None),
+         }
+      }
+      _ => create_error("not is possible create this project", None),
+   }
+}
@@ -1,6 +1,24 @@
+use super::super::config::CONFIG;
 use super::super::middlewares::users::{valid_user, ValidResponse};
-use super::super::utils::{create_error, get_users, parse_body, valid_json};
-use super::super::views::tasks::{TaskCreated, TaskCreatedFormated};
+use super::super::utils::etag::{body_etag, etag_matches, task_etag};
+use super::super::utils::events::Wakeups;
+use super::super::utils::history::{diff, record_task_event, task_fields};
+use super::super::utils::labels::{task_labels, visible_task_labels};
+use super::super::utils::permissions::{
+   find_permitted_task, project_role, task_role, Role, VISIBLE_TASKS,
+};
+use super::super::utils::recurrence::Recurrence;
+use super::super::utils::validation::{
+   trim, trim_option, ValidationErrorFormated, TASK_NAME_MAX_LENGTH,
+};
+use super::super::utils::{
+   body_error, create_error, find_user, get_query_params, get_users, now, parse_body,
+   parse_datetime, valid_json, ErrorFormated, DATETIME_FORMAT,
+};
+use super::super::views::tasks::{
+   BulkOperationResult, BulkResult, TaskCreated, TaskCreatedFormated, TaskOccurrences,
+   TASK_COLUMNS, TASK_COLUMNS_COUNT,
+};
 use super::super::views::users::CreatedUser;
 
 use std::convert::{From, Infallible};
@@ -8,23 +26,506 @@ use std::sync::Arc;
 
 use futures::lock::Mutex;
 
-use rusqlite::Connection;
+use rusqlite::{params, Connection};
 
-use uuid::Uuid;
+use hyper::{Body, HeaderMap, Request, Response};
 
-use hyper::{Body, Request, Response};
+use utoipa::ToSchema;
 
-#[derive(Serialize, Deserialize, Debug)]
-struct RequestBodyCreate {
-   name: String,
+use validator::{Validate, ValidationErrors};
+
+use serde_json::json;
+
+#[derive(Serialize, Deserialize, Debug, ToSchema, Validate)]
+#[schema(as = TaskCreate)]
+pub struct RequestBodyCreate {
+   #[serde(deserialize_with = "trim")]
+   #[validate(length(min = 1, max = TASK_NAME_MAX_LENGTH))]
+   pub name: String,
+   pub due_date: Option<String>,
+   pub recurrence: Option<String>,
+   pub project_id: Option<String>,
+   pub assignee_id: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, ToSchema, Validate)]
+#[schema(as = TaskUpdate)]
+pub struct RequestBodyUpdate {
+   #[serde(default, deserialize_with = "trim_option")]
+   #[validate(length(min = 1, max = TASK_NAME_MAX_LENGTH))]
+   pub name: Option<String>,
+   pub completed: Option<bool>,
+   pub due_date: Option<String>,
+   pub recurrence: Option<String>,
+   pub project_id: Option<String>,
+   pub assignee_id: Option<String>,
+}
+
+impl RequestBodyUpdate {
+   // Assignees may complete a task they cannot otherwise edit.
+   fn allowed_for(&self, role: Role) -> bool {
+      role >= Role::Editor
+         || (role == Role::Assignee
+            && self.name.is_none()
+            && self.due_date.is_none()
+            && self.recurrence.is_none()
+            && self.project_id.is_none()
+            && self.assignee_id.is_none())
+   }
+}
+
+#[derive(Serialize, Deserialize, Debug, ToSchema, Validate)]
+#[schema(as = TaskBulk)]
+struct RequestBodyBulk {
+   #[serde(default)]
+   atomic: bool,
+   #[validate(nested)]
+   operations: Vec<BulkOperation>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-struct RequestBodyUpdate {
-   name: Option<String>,
-   completed: Option<bool>,
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
+#[serde(tag = "op", rename_all = "lowercase")]
+pub enum BulkOperation {
+   Create {
+      #[serde(flatten)]
+      task: RequestBodyCreate,
+   },
+   Update {
+      id: String,
+      version: Option<i64>,
+      #[serde(flatten)]
+      changes: RequestBodyUpdate,
+   },
+   Delete {
+      id: String,
+      version: Option<i64>,
+   },
+   Complete {
+      id: String,
+      version: Option<i64>,
+   },
+}
+
+// The fields of a create or update operation follow the rules of
+// `POST /tasks` and `PUT /task/{id}`.
+impl Validate for BulkOperation {
+   fn validate(&self) -> Result<(), ValidationErrors> {
+      match self {
+         BulkOperation::Create { task } => task.validate(),
+         BulkOperation::Update { changes, .. } => changes.validate(),
+         _ => Ok(()),
+      }
+   }
+}
+
+const BULK_MAX_OPERATIONS: usize = 500;
+const OCCURRENCES_DEFAULT: usize = 5;
+const OCCURRENCES_MAX: usize = 100;
+
+// The assignee has to be an active user.
+fn check_assignee(conn: &Connection, assignee_id: Option<&str>) -> Result<(), (u16, &'static str)> {
+   match assignee_id.map(|assignee_id| find_user(conn, assignee_id)) {
+      Some(None) => Err((404, "this assignee not exists")),
+      _ => Ok(()),
+   }
+}
+
+// Moving a task into a project needs editor rights on the project too.
+pub fn check_project(
+   conn: &Connection,
+   project_id: Option<&str>,
+   user_id: &str,
+) -> Result<(), (u16, &'static str)> {
+   match project_id.map(|project_id| project_role(conn, project_id, user_id)) {
+      None => Ok(()),
+      Some(Some(role)) if role >= Role::Editor => Ok(()),
+      Some(Some(_)) => Err((403, "you not have permission for to follow")),
+      Some(None) => Err((404, "this project not exists")),
+   }
+}
+
+// Checks a task's due date and recurrence rule, returning them in the form
+// they are stored: the due date as `DATETIME_FORMAT` and the rule as RRULE.
+pub fn normalize_schedule(
+   due_date: Option<String>,
+   recurrence: Option<String>,
+) -> Result<(Option<String>, Option<String>), &'static str> {
+   let due_date = match due_date {
+      Some(due_date) => match parse_datetime(&due_date) {
+         Some(due_date) => Option::from(due_date),
+         None => return Err("due date invalid"),
+      },
+      None => None,
+   };
+
+   let recurrence = match recurrence {
+      Some(rule) => match (Recurrence::parse(&rule), due_date) {
+         (Ok(recurrence), Some(due_date)) => Option::from(recurrence.anchored(due_date).to_rrule()),
+         (Ok(_), None) => return Err("recurrence needs a due date"),
+         (Err(error), _) => return Err(error),
+      },
+      None => None,
+   };
+
+   let due_date = due_date.map(|due_date| format!("{}", due_date.format(DATETIME_FORMAT)));
+
+   Ok((due_date, recurrence))
+}
+
+// Checks an imported task against the rules of `POST /tasks`, returning its
+// trimmed name and its schedule in the form they are stored.
+pub fn validate_new_task(
+   name: &str,
+   due_date: Option<String>,
+   recurrence: Option<String>,
+) -> Result<(String, Option<String>, Option<String>), &'static str> {
+   let task = RequestBodyCreate {
+      name: String::from(name.trim()),
+      due_date,
+      recurrence,
+      project_id: None,
+      assignee_id: None,
+   };
+
+   if task.name.is_empty() {
+      return Err("name is necessary");
+   }
+
+   if task.validate().is_err() {
+      return Err("name invalid");
+   }
+
+   let (due_date, recurrence) = normalize_schedule(task.due_date, task.recurrence)?;
+
+   Ok((task.name, due_date, recurrence))
+}
+
+pub fn insert_task(
+   conn: &Connection,
+   task: TaskCreated,
+   actor_id: &str,
+) -> Result<(String, Wakeups), rusqlite::Error> {
+   conn.execute(
+      "INSERT INTO tasks (id, name, completed, user_id, due_date, recurrence, project_id, assignee_id) VALUES(?, ?, ?, ?, ?, ?, ?, ?)",
+      params![
+         task.id,
+         task.name,
+         task.completed,
+         task.user_id,
+         task.due_date,
+         task.recurrence,
+         task.project_id,
+         task.assignee_id
+      ],
+   )?;
+
+   let changes = diff(None, Option::from(&task_fields(&task)));
+   let wakeups = record_task_event(conn, &task, actor_id, "created", changes)?;
+
+   Ok((task.id, wakeups))
+}
+
+fn soft_delete_task(
+   conn: &Connection,
+   task: &TaskCreated,
+   actor_id: &str,
+) -> Result<Wakeups, rusqlite::Error> {
+   conn.execute(
+      "UPDATE tasks SET deleted_at = ?, version = version + 1 WHERE id = ?",
+      [now(), task.id.clone()],
+   )?;
+
+   record_task_event(conn, task, actor_id, "deleted", json!({}))
+}
+
+// Creates the task that follows `task` in its series, if the rule has any
+// occurrence left after the task's due date.
+fn create_next_occurrence(
+   conn: &Connection,
+   task: &TaskCreated,
+   actor_id: &str,
+) -> Result<Wakeups, rusqlite::Error> {
+   let (rule, due_date) = match (&task.recurrence, &task.due_date) {
+      (Some(rule), Some(due_date)) => (rule, due_date),
+      _ => return Ok(Wakeups::default()),
+   };
+
+   let (recurrence, due_date) = match (Recurrence::parse(rule), parse_datetime(due_date)) {
+      (Ok(recurrence), Some(due_date)) => (recurrence, due_date),
+      _ => return Ok(Wakeups::default()),
+   };
+
+   let next_recurrence = match recurrence.advance() {
+      Some(next_recurrence) => next_recurrence,
+      None => return Ok(Wakeups::default()),
+   };
+
+   match next_recurrence.next_after(due_date) {
+      Some(next_due_date) => {
+         let mut next = TaskCreated::new(&task.user_id, &task.name);
+         next.due_date = Option::from(format!("{}", next_due_date.format(DATETIME_FORMAT)));
+         next.recurrence = Option::from(next_recurrence.to_rrule());
+         next.project_id = task.project_id.clone();
+         next.assignee_id = task.assignee_id.clone();
+
+         insert_task(conn, next, actor_id).map(|(_, wakeups)| wakeups)
+      }
+      None => Ok(Wakeups::default()),
+   }
+}
+
+// Applies `changes` to `task`, and rolls a recurring task forward when it
+// goes from pending to completed.
+pub fn apply_task_changes(
+   conn: &Connection,
+   mut task: TaskCreated,
+   changes: RequestBodyUpdate,
+   actor_id: &str,
+) -> Result<(TaskCreated, Wakeups), (u16, &'static str)> {
+   let RequestBodyUpdate {
+      name,
+      completed,
+      due_date,
+      recurrence,
+      project_id,
+      assignee_id,
+   } = changes;
+
+   if name.is_none()
+      && completed.is_none()
+      && due_date.is_none()
+      && recurrence.is_none()
+      && project_id.is_none()
+      && assignee_id.is_none()
+   {
+      return Err((400, "data invalid"));
+   }
+
+   let was_completed = task.completed != 0;
+   let before = task_fields(&task);
+
+   if let Some(name) = name {
+      task.name = name;
+   }
+
+   if let Some(completed) = completed {
+      task.completed = if completed { 1 } else { 0 };
+   }
+
+   // An empty string clears the due date or the recurrence rule.
+   let due_date = match due_date {
+      Some(due_date) if due_date.is_empty() => None,
+      Some(due_date) => Option::from(due_date),
+      None => task.due_date.clone(),
+   };
+
+   let recurrence = match recurrence {
+      Some(recurrence) if recurrence.is_empty() => None,
+      Some(recurrence) => Option::from(recurrence),
+      None => task.recurrence.clone(),
+   };
+
+   let (due_date, recurrence) = match normalize_schedule(due_date, recurrence) {
+      Ok(schedule) => schedule,
+      Err(error) => return Err((400, error)),
+   };
+
+   task.due_date = due_date;
+   task.recurrence = recurrence;
+
+   // An empty string takes the task out of its project.
+   match project_id {
+      Some(project_id) if project_id.is_empty() => task.project_id = None,
+      Some(project_id) => {
+         check_project(conn, Option::from(project_id.as_str()), actor_id)?;
+         task.project_id = Option::from(project_id);
+      }
+      None => {}
+   }
+
+   // An empty string unassigns the task.
+   match assignee_id {
+      Some(assignee_id) if assignee_id.is_empty() => task.assignee_id = None,
+      Some(assignee_id) => {
+         check_assignee(conn, Option::from(assignee_id.as_str()))?;
+         task.assignee_id = Option::from(assignee_id);
+      }
+      None => {}
+   }
+
+   // Matching on the version read earlier keeps a concurrent write from
+   // being silently overwritten.
+   let query = conn.execute(
+      "UPDATE tasks SET name = ?, completed = ?, due_date = ?, recurrence = ?, project_id = ?, assignee_id = ?, version = version + 1 WHERE tasks.id = ? AND version = ?",
+      params![
+         task.name,
+         task.completed,
+         task.due_date,
+         task.recurrence,
+         task.project_id,
+         task.assignee_id,
+         task.id,
+         task.version
+      ],
+   );
+
+   match query {
+      Ok(0) => return Err((412, "this task was modified by another request")),
+      Ok(_) => task.version += 1,
+      _ => return Err((400, "error on update this task")),
+   }
+
+   let changes = diff(Option::from(&before), Option::from(&task_fields(&task)));
+   let mut wakeups = Wakeups::default();
+
+   if changes
+      .as_object()
+      .is_some_and(|changes| !changes.is_empty())
+   {
+      match record_task_event(conn, &task, actor_id, "updated", changes) {
+         Ok(updated) => wakeups = updated,
+         _ => return Err((400, "error on update this task")),
+      }
+   }
+
+   if !was_completed && task.completed != 0 {
+      match create_next_occurrence(conn, &task, actor_id) {
+         Ok(next) => wakeups.add(next),
+         _ => return Err((400, "error on create the next occurrence")),
+      }
+   }
+
+   Ok((task, wakeups))
+}
+
+// Checks the `If-Match` precondition of a write against the task's current
+// version: 412 when it does not match, 428 when it is required but missing.
+fn check_if_match(headers: &HeaderMap, task: &TaskCreated) -> Result<(), (u16, &'static str)> {
+   match etag_matches(headers, "if-match", &task_etag(task.version), false) {
+      Some(true) => Ok(()),
+      Some(false) => Err((412, "this task was modified by another request")),
+      None if CONFIG.require_if_match => Err((428, "if-match header is necessary")),
+      None => Ok(()),
+   }
+}
+
+// Bulk operations carry the expected version in the body instead of an
+// `If-Match` header.
+fn find_bulk_task(
+   conn: &Connection,
+   task_id: &str,
+   user_id: &str,
+   minimum: Role,
+   version: Option<i64>,
+) -> Result<(TaskCreated, Role), (u16, &'static str)> {
+   let (task, role) = find_permitted_task(conn, task_id, user_id, minimum, false)?;
+
+   match version {
+      Some(version) if version != task.version => {
+         Err((412, "this task was modified by another request"))
+      }
+      None if CONFIG.require_if_match => Err((428, "version is necessary")),
+      _ => Ok((task, role)),
+   }
 }
 
+/// Runs one task operation as `user_id`, returning the status, the task id
+/// and the wake-ups to send once the operation is committed. Shared by
+/// `POST /tasks/bulk` and the GraphQL mutations.
+pub fn apply_bulk_operation(
+   conn: &Connection,
+   user_id: &str,
+   operation: BulkOperation,
+) -> Result<(u16, String, Wakeups), (u16, &'static str)> {
+   match operation {
+      BulkOperation::Create {
+         task:
+            RequestBodyCreate {
+               name,
+               due_date,
+               recurrence,
+               project_id,
+               assignee_id,
+            },
+      } => {
+         let (due_date, recurrence) = match normalize_schedule(due_date, recurrence) {
+            Ok(schedule) => schedule,
+            Err(error) => return Err((400, error)),
+         };
+
+         check_project(conn, project_id.as_deref(), user_id)?;
+         check_assignee(conn, assignee_id.as_deref())?;
+
+         let mut task = TaskCreated::new(user_id, &name);
+         task.due_date = due_date;
+         task.recurrence = recurrence;
+         task.project_id = project_id;
+         task.assignee_id = assignee_id;
+
+         let query = insert_task(conn, task, user_id);
+
+         match query {
+            Ok((task_id, wakeups)) => Ok((201, task_id, wakeups)),
+            _ => Err((400, "not is possible create this task")),
+         }
+      }
+      BulkOperation::Update {
+         id,
+         version,
+         changes,
+      } => {
+         let (task, role) = find_bulk_task(conn, &id, user_id, Role::Assignee, version)?;
+
+         if !changes.allowed_for(role) {
+            return Err((403, "you not have permission for to follow"));
+         }
+
+         let (_, wakeups) = apply_task_changes(conn, task, changes, user_id)?;
+
+         Ok((200, id, wakeups))
+      }
+      BulkOperation::Complete { id, version } => {
+         let (task, _) = find_bulk_task(conn, &id, user_id, Role::Assignee, version)?;
+
+         let changes = RequestBodyUpdate {
+            name: None,
+            completed: Option::from(true),
+            due_date: None,
+            recurrence: None,
+            project_id: None,
+            assignee_id: None,
+         };
+
+         let (_, wakeups) = apply_task_changes(conn, task, changes, user_id)?;
+
+         Ok((200, id, wakeups))
+      }
+      BulkOperation::Delete { id, version } => {
+         let (task, _) = find_bulk_task(conn, &id, user_id, Role::Owner, version)?;
+
+         match soft_delete_task(conn, &task, user_id) {
+            Ok(wakeups) => Ok((200, id, wakeups)),
+            _ => Err((400, "error on delete this task")),
+         }
+      }
+   }
+}
+
+#[utoipa::path(
+   get,
+   path = "/tasks",
+   tag = "tasks",
+   params(
+      ("assigned_to" = Option<String>, Query, description = "`me` or a user id"),
+      ("label" = Option<String>, Query, description = "Only tasks carrying this label")
+   ),
+   security(("token" = [])),
+   responses(
+      (status = 200, description = "The tasks the user can see", body = [TaskCreatedFormated]),
+      (status = 304, description = "Matches `If-None-Match`"),
+      (status = 400, description = "Invalid request", body = ErrorFormated)
+   )
+)]
 pub async fn list_tasks(
    req: Request<Body>,
    conn: Arc<Mutex<Connection>>,
@@ -32,14 +533,14 @@ pub async fn list_tasks(
    let headers = req.headers();
    let mut _user_id = String::from("");
 
-   let result = valid_user(headers).await;
+   let result = valid_user(headers, &conn).await;
 
    match result {
       Ok(data) => match data {
          ValidResponse::Id(id) => {
             let users = get_users(conn.clone(), id.clone(), String::from("id")).await;
 
-            if users.len() < 1 {
+            if users.is_empty() {
                return create_error("this user not exists", None);
             }
 
@@ -50,37 +551,192 @@ pub async fn list_tasks(
       _ => return create_error("", None),
    }
 
+   // `?assigned_to=me` or `?assigned_to=<user id>` narrows the list down to
+   // the tasks assigned to that user.
+   let params = get_query_params(&req);
+
+   let assigned_to = match params.get("assigned_to") {
+      Some(assignee) if assignee == "me" => Option::from(_user_id.clone()),
+      Some(assignee) => Option::from(assignee.clone()),
+      None => None,
+   };
+
+   // `?label=<name>` keeps the tasks carrying that label.
+   let label = params.get("label").cloned();
+
    let conn = conn.lock().await;
 
-   let mut query = conn.prepare("SELECT *, users.id as userId FROM tasks LEFT OUTER JOIN users ON users.id = tasks.user_id WHERE users.id = ?").unwrap();
+   // Owned tasks, tasks assigned or shared directly and tasks in a project
+   // the user owns or was invited to.
+   let mut query = conn.prepare(&format!("SELECT {}, users.id, users.firstname, users.lastname, users.email, users.password, assignees.id, assignees.firstname, assignees.lastname, assignees.email, assignees.password, (SELECT COUNT(*) FROM comments WHERE comments.task_id = tasks.id) FROM tasks LEFT OUTER JOIN users ON users.id = tasks.user_id LEFT OUTER JOIN users AS assignees ON assignees.id = tasks.assignee_id AND assignees.deleted_at IS NULL WHERE tasks.deleted_at IS NULL AND {} AND (?2 IS NULL OR tasks.assignee_id = ?2) AND (?3 IS NULL OR EXISTS (SELECT 1 FROM task_labels INNER JOIN labels ON labels.id = task_labels.label_id WHERE task_labels.task_id = tasks.id AND labels.name = ?3))", TASK_COLUMNS, VISIBLE_TASKS)).unwrap();
    let data = query
-      .query_map([_user_id], |row| {
-         Ok(TaskCreated {
-            id: row.get(0)?,
-            name: row.get(1)?,
-            completed: row.get(2)?,
-            user: Option::from(CreatedUser {
-               id: row.get(4)?,
-               firstname: row.get(5)?,
-               lastname: row.get(6)?,
-               email: row.get(7)?,
-               password: row.get(8)?,
-            }),
-         })
+      .query_map(params![&_user_id, assigned_to, label], |row| {
+         let mut task = TaskCreated::from_row(row, 0)?;
+
+         task.user = Option::from(CreatedUser {
+            id: row.get(TASK_COLUMNS_COUNT)?,
+            firstname: row.get(TASK_COLUMNS_COUNT + 1)?,
+            lastname: row.get(TASK_COLUMNS_COUNT + 2)?,
+            email: row.get(TASK_COLUMNS_COUNT + 3)?,
+            password: row.get(TASK_COLUMNS_COUNT + 4)?,
+         });
+
+         let assignee_id: Option<String> = row.get(TASK_COLUMNS_COUNT + 5)?;
+
+         if let Some(assignee_id) = assignee_id {
+            task.assignee = Option::from(CreatedUser {
+               id: assignee_id,
+               firstname: row.get(TASK_COLUMNS_COUNT + 6)?,
+               lastname: row.get(TASK_COLUMNS_COUNT + 7)?,
+               email: row.get(TASK_COLUMNS_COUNT + 8)?,
+               password: row.get(TASK_COLUMNS_COUNT + 9)?,
+            });
+         }
+
+         task.comments = row.get(TASK_COLUMNS_COUNT + 10)?;
+
+         Ok(task)
       })
       .unwrap();
 
+   let mut labels = visible_task_labels(&conn, &_user_id);
    let mut tasks: Vec<TaskCreatedFormated> = vec![];
 
    for task in data {
-      tasks.push(task.unwrap().format_user());
+      let mut task = task.unwrap();
+      task.labels = labels.remove(&task.id).unwrap_or_default();
+
+      let role = task_role(&conn, &task, &_user_id).unwrap_or(Role::Viewer);
+
+      tasks.push(task.format_user(&_user_id, role.as_str()));
    }
 
-   let json = serde_json::to_string(&tasks);
+   let json = match serde_json::to_string(&tasks) {
+      Ok(json) => json,
+      _ => return create_error("", None),
+   };
 
-   valid_json(json)
+   let etag = body_etag(&json);
+
+   if etag_matches(req.headers(), "if-none-match", &etag, true) == Some(true) {
+      return Ok(Response::builder()
+         .status(304)
+         .header("ETag", etag)
+         .body(Body::empty())
+         .unwrap());
+   }
+
+   Ok(Response::builder()
+      .status(200)
+      .header("ETag", etag)
+      .body(Body::from(json))
+      .unwrap())
+}
+
+#[utoipa::path(
+   get,
+   path = "/task/{id}",
+   tag = "tasks",
+   params(
+      ("id" = String, Path, description = "Task id")
+   ),
+   security(("token" = [])),
+   responses(
+      (status = 200, description = "The task", body = TaskCreatedFormated),
+      (status = 304, description = "Matches `If-None-Match`"),
+      (status = 404, description = "The task does not exist or is not shared with the user", body = ErrorFormated),
+      (status = 400, description = "Invalid request", body = ErrorFormated)
+   )
+)]
+pub async fn get_task(
+   req: Request<Body>,
+   conn: Arc<Mutex<Connection>>,
+   task_id: String,
+) -> Result<Response<Body>, Infallible> {
+   let headers = req.headers();
+   let mut _user = None;
+
+   let result = valid_user(headers, &conn).await;
+
+   match result {
+      Ok(data) => match data {
+         ValidResponse::Id(id) => {
+            let users = get_users(conn.clone(), id.clone(), String::from("id")).await;
+
+            if users.is_empty() {
+               return create_error("this user not exists", None);
+            }
+
+            _user = users.into_iter().next();
+         }
+         ValidResponse::Respo(data) => return data,
+      },
+      _ => return create_error("", None),
+   }
+
+   let user = _user.unwrap();
+
+   let conn = conn.lock().await;
+
+   let (mut task, role) = match find_permitted_task(&conn, &task_id, &user.id, Role::Viewer, false)
+   {
+      Ok(found) => found,
+      Err((status, error)) => return create_error(error, Option::from(status)),
+   };
+
+   let etag = task_etag(task.version);
+
+   if etag_matches(headers, "if-none-match", &etag, true) == Some(true) {
+      return Ok(Response::builder()
+         .status(304)
+         .header("ETag", etag)
+         .body(Body::empty())
+         .unwrap());
+   }
+
+   task.user = if task.user_id == user.id {
+      Option::from(user.clone())
+   } else {
+      find_user(&conn, &task.user_id)
+   };
+   task.assignee = task
+      .assignee_id
+      .as_deref()
+      .and_then(|assignee_id| find_user(&conn, assignee_id));
+   task.comments = conn
+      .query_row(
+         "SELECT COUNT(*) FROM comments WHERE task_id = ?",
+         [&task.id],
+         |row| row.get(0),
+      )
+      .unwrap_or(0);
+   task.labels = task_labels(&conn, &task.id);
+
+   match serde_json::to_string(&task.format_user(&user.id, role.as_str())) {
+      Ok(json) => Ok(Response::builder()
+         .status(200)
+         .header("ETag", etag)
+         .body(Body::from(json))
+         .unwrap()),
+      _ => create_error("", None),
+   }
 }
 
+#[utoipa::path(
+   post,
+   path = "/tasks",
+   tag = "tasks",
+   request_body = RequestBodyCreate,
+   security(("token" = [])),
+   responses(
+      (status = 201, description = "The task was created"),
+      (status = 403, description = "The user's role does not allow it", body = ErrorFormated),
+      (status = 400, description = "Invalid request", body = ErrorFormated),
+      (status = 422, description = "Fields that break their rules", body = ValidationErrorFormated),
+      (status = 413, description = "Body larger than `BODY_MAX_BYTES`", body = ErrorFormated),
+      (status = 415, description = "Body not sent as JSON", body = ErrorFormated)
+   )
+)]
 pub async fn create_task(
    req: Request<Body>,
    conn: Arc<Mutex<Connection>>,
@@ -88,15 +744,14 @@ pub async fn create_task(
    let (head, body) = req.into_parts();
    let mut _user_id = String::from("");
 
-   let body = parse_body::<RequestBodyCreate>(body).await;
-   let result = valid_user(&head.headers).await;
+   let result = valid_user(&head.headers, &conn).await;
 
    match result {
       Ok(data) => match data {
          ValidResponse::Id(id) => {
             let users = get_users(conn.clone(), id.clone(), String::from("id")).await;
 
-            if users.len() < 1 {
+            if users.is_empty() {
                return create_error("this user not exists", None);
             }
 
@@ -107,171 +762,658 @@ pub async fn create_task(
       _ => return create_error("", None),
    }
 
+   let body = parse_body::<RequestBodyCreate>(&head.headers, body).await;
    match body {
-      Ok(RequestBodyCreate { name }) => {
-         let conn = conn.lock().await;
+      Ok(RequestBodyCreate {
+         name,
+         due_date,
+         recurrence,
+         project_id,
+         assignee_id,
+      }) => {
+         let (due_date, recurrence) = match normalize_schedule(due_date, recurrence) {
+            Ok(schedule) => schedule,
+            Err(error) => return create_error(error, None),
+         };
 
-         let query = conn.execute(
-            "INSERT INTO tasks VALUES(?, ?, ?, ?)",
-            [
-               Uuid::new_v4().to_string(),
-               name,
-               String::from("0"),
-               _user_id,
-            ],
-         );
+         let mut conn = conn.lock().await;
+
+         let transaction = match conn.transaction() {
+            Ok(transaction) => transaction,
+            _ => return create_error("", None),
+         };
+
+         let checks = check_project(&transaction, project_id.as_deref(), &_user_id)
+            .and_then(|_| check_assignee(&transaction, assignee_id.as_deref()));
+
+         if let Err((status, error)) = checks {
+            return create_error(error, Option::from(status));
+         }
+
+         let mut task = TaskCreated::new(&_user_id, &name);
+         task.due_date = due_date;
+         task.recurrence = recurrence;
+         task.project_id = project_id;
+         task.assignee_id = assignee_id;
+
+         let query = insert_task(&transaction, task, &_user_id)
+            .and_then(|(_, wakeups)| transaction.commit().map(|_| wakeups));
 
          match query {
-            Ok(_) => Ok(Response::builder()
-               .status(201)
-               .body(Body::from(""))
-               .unwrap()),
+            Ok(wakeups) => {
+               wakeups.send();
+
+               Ok(Response::builder()
+                  .status(201)
+                  .body(Body::from(""))
+                  .unwrap())
+            }
             _ => create_error("not is possible create this task", None),
          }
       }
-      _ => create_error("data invalid", None),
+      Err(error) => body_error(error),
    }
 }
 
+#[utoipa::path(
+   put,
+   path = "/task/{id}",
+   tag = "tasks",
+   params(
+      ("id" = String, Path, description = "Task id"),
+      ("If-Match" = Option<String>, Header, description = "ETag of the version being changed")
+   ),
+   request_body = RequestBodyUpdate,
+   security(("token" = [])),
+   responses(
+      (status = 200, description = "The task was updated"),
+      (status = 403, description = "The user's role does not allow it", body = ErrorFormated),
+      (status = 404, description = "The task does not exist or is not shared with the user", body = ErrorFormated),
+      (status = 412, description = "The task was changed since `If-Match`", body = ErrorFormated),
+      (status = 428, description = "`If-Match` is required and missing", body = ErrorFormated),
+      (status = 400, description = "Invalid request", body = ErrorFormated),
+      (status = 422, description = "Fields that break their rules", body = ValidationErrorFormated),
+      (status = 413, description = "Body larger than `BODY_MAX_BYTES`", body = ErrorFormated),
+      (status = 415, description = "Body not sent as JSON", body = ErrorFormated)
+   )
+)]
 pub async fn update_task(
    req: Request<Body>,
    conn: Arc<Mutex<Connection>>,
    task_id: String,
 ) -> Result<Response<Body>, Infallible> {
    let (head, body) = req.into_parts();
+   let mut _user_id = String::from("");
 
-   let result = valid_user(&head.headers).await;
-   let body = parse_body::<RequestBodyUpdate>(body).await;
+   let result = valid_user(&head.headers, &conn).await;
 
    match result {
       Ok(data) => match data {
          ValidResponse::Id(id) => {
             let users = get_users(conn.clone(), id.clone(), String::from("id")).await;
 
-            if users.len() < 1 {
+            if users.is_empty() {
                return create_error("this user not exists", None);
             }
+
+            _user_id = id;
          }
          ValidResponse::Respo(data) => return data,
       },
       _ => return create_error("", None),
    }
 
+   let body = parse_body::<RequestBodyUpdate>(&head.headers, body).await;
    match body {
-      Ok(RequestBodyUpdate { name, completed }) => {
-         let conn = conn.lock().await;
-
-         let mut query = conn
-            .prepare("SELECT * FROM tasks WHERE tasks.id = ?")
-            .unwrap();
-         let data = query
-            .query_map([task_id], |row| {
-               Ok(TaskCreated {
-                  id: row.get(0)?,
-                  name: row.get(1)?,
-                  completed: row.get(2)?,
-                  user: None,
-               })
-            })
-            .unwrap();
-
-         let mut tasks: Vec<TaskCreated> = vec![];
-         for task in data {
-            tasks.push(task.unwrap());
+      Ok(changes) => {
+         let mut conn = conn.lock().await;
+
+         let transaction = match conn.transaction() {
+            Ok(transaction) => transaction,
+            _ => return create_error("", None),
+         };
+
+         let task =
+            match find_permitted_task(&transaction, &task_id, &_user_id, Role::Assignee, false) {
+               Ok((task, role)) if changes.allowed_for(role) => task,
+               Ok(_) => {
+                  return create_error("you not have permission for to follow", Option::from(403))
+               }
+               Err((status, error)) => return create_error(error, Option::from(status)),
+            };
+
+         if let Err((status, error)) = check_if_match(&head.headers, &task) {
+            return create_error(error, Option::from(status));
          }
 
-         if tasks.len() < 1 {
-            return create_error("this task not exists", None);
+         let (task, wakeups) = match apply_task_changes(&transaction, task, changes, &_user_id) {
+            Ok(changed) => changed,
+            Err((status, error)) => return create_error(error, Option::from(status)),
+         };
+
+         match transaction.commit() {
+            Ok(_) => {
+               wakeups.send();
+
+               Ok(Response::builder()
+                  .status(200)
+                  .header("ETag", task_etag(task.version))
+                  .body(Body::from(""))
+                  .unwrap())
+            }
+            _ => create_error("error on update this task", None),
          }
+      }
+      Err(error) => body_error(error),
+   }
+}
+
+#[utoipa::path(
+   delete,
+   path = "/task/{id}",
+   tag = "tasks",
+   params(
+      ("id" = String, Path, description = "Task id"),
+      ("If-Match" = Option<String>, Header, description = "ETag of the version being deleted")
+   ),
+   security(("token" = [])),
+   responses(
+      (status = 200, description = "The task was moved to the trash"),
+      (status = 403, description = "The user's role does not allow it", body = ErrorFormated),
+      (status = 404, description = "The task does not exist or is not shared with the user", body = ErrorFormated),
+      (status = 412, description = "The task was changed since `If-Match`", body = ErrorFormated),
+      (status = 428, description = "`If-Match` is required and missing", body = ErrorFormated),
+      (status = 400, description = "Invalid request", body = ErrorFormated)
+   )
+)]
+pub async fn delete_task(
+   req: Request<Body>,
+   conn: Arc<Mutex<Connection>>,
+   task_id: String,
+) -> Result<Response<Body>, Infallible> {
+   let headers = req.headers();
+   let mut _user_id = String::from("");
+
+   let result = valid_user(headers, &conn).await;
 
-         let mut task = tasks[0].clone();
+   match result {
+      Ok(data) => match data {
+         ValidResponse::Id(id) => {
+            let users = get_users(conn.clone(), id.clone(), String::from("id")).await;
+
+            if users.is_empty() {
+               return create_error("this user not exists", None);
+            }
 
-         if name == None && completed == None {
-            return create_error("data invalid", None);
+            _user_id = id;
          }
+         ValidResponse::Respo(data) => return data,
+      },
+      _ => return create_error("", None),
+   }
+
+   let mut conn = conn.lock().await;
+
+   let transaction = match conn.transaction() {
+      Ok(transaction) => transaction,
+      _ => return create_error("", None),
+   };
+
+   let task = match find_permitted_task(&transaction, &task_id, &_user_id, Role::Owner, false) {
+      Ok((task, _)) => task,
+      Err((status, error)) => return create_error(error, Option::from(status)),
+   };
+
+   if let Err((status, error)) = check_if_match(headers, &task) {
+      return create_error(error, Option::from(status));
+   }
+
+   let query = soft_delete_task(&transaction, &task, &_user_id)
+      .and_then(|wakeups| transaction.commit().map(|_| wakeups));
+
+   match query {
+      Ok(wakeups) => {
+         wakeups.send();
+
+         Ok(Response::builder()
+            .status(200)
+            .body(Body::from(""))
+            .unwrap())
+      }
+      _ => create_error("error on delete this task", None),
+   }
+}
+
+#[utoipa::path(
+   post,
+   path = "/tasks/bulk",
+   tag = "tasks",
+   request_body = RequestBodyBulk,
+   security(("token" = [])),
+   responses(
+      (status = 200, description = "The result of every operation", body = BulkResult),
+      (status = 400, description = "Invalid request", body = ErrorFormated),
+      (status = 422, description = "Fields that break their rules", body = ValidationErrorFormated),
+      (status = 413, description = "More than `BULK_MAX_OPERATIONS` operations, or body larger than `BODY_MAX_BYTES`", body = ErrorFormated),
+      (status = 415, description = "Body not sent as JSON", body = ErrorFormated)
+   )
+)]
+pub async fn bulk_tasks(
+   req: Request<Body>,
+   conn: Arc<Mutex<Connection>>,
+) -> Result<Response<Body>, Infallible> {
+   let (head, body) = req.into_parts();
+   let mut _user_id = String::from("");
+
+   let result = valid_user(&head.headers, &conn).await;
+
+   match result {
+      Ok(data) => match data {
+         ValidResponse::Id(id) => {
+            let users = get_users(conn.clone(), id.clone(), String::from("id")).await;
+
+            if users.is_empty() {
+               return create_error("this user not exists", None);
+            }
 
-         if name != None {
-            task.name = name.unwrap();
+            _user_id = id;
          }
+         ValidResponse::Respo(data) => return data,
+      },
+      _ => return create_error("", None),
+   }
+
+   let body = parse_body::<RequestBodyBulk>(&head.headers, body).await;
+   let RequestBodyBulk { atomic, operations } = match body {
+      Ok(data) => data,
+      Err(error) => return body_error(error),
+   };
+
+   if operations.is_empty() {
+      return create_error("data invalid", None);
+   }
+
+   if operations.len() > BULK_MAX_OPERATIONS {
+      return create_error("too many operations", Option::from(413));
+   }
+
+   let mut conn = conn.lock().await;
+
+   let mut transaction = match conn.transaction() {
+      Ok(transaction) => transaction,
+      _ => return create_error("", None),
+   };
+
+   let mut results: Vec<BulkOperationResult> = vec![];
+   let mut wakeups = Wakeups::default();
+   let mut failed = false;
+
+   for (index, operation) in operations.into_iter().enumerate() {
+      let op = match operation {
+         BulkOperation::Create { .. } => "create",
+         BulkOperation::Update { .. } => "update",
+         BulkOperation::Delete { .. } => "delete",
+         BulkOperation::Complete { .. } => "complete",
+      };
 
-         if completed != None {
-            let completed_formated = if completed.unwrap() { 1 } else { 0 };
+      let savepoint = match transaction.savepoint() {
+         Ok(savepoint) => savepoint,
+         _ => return create_error("", None),
+      };
 
-            task.completed = completed_formated;
+      let result = apply_bulk_operation(&savepoint, &_user_id, operation);
+
+      match result {
+         Ok((status, id, operation_wakeups)) => {
+            if savepoint.commit().is_err() {
+               return create_error("", None);
+            }
+
+            wakeups.add(operation_wakeups);
+
+            results.push(BulkOperationResult {
+               index,
+               op,
+               id: Option::from(id),
+               status,
+               error: None,
+            });
          }
+         Err((status, error)) => {
+            drop(savepoint);
 
-         let query = conn.execute(
-            "UPDATE tasks SET name = ?, completed = ? WHERE tasks.id = ?",
-            [task.name, task.completed.to_string(), task.id],
-         );
+            results.push(BulkOperationResult {
+               index,
+               op,
+               id: None,
+               status,
+               error: Option::from(error),
+            });
 
-         match query {
-            Ok(_) => Ok(Response::builder()
-               .status(200)
-               .body(Body::from(""))
-               .unwrap()),
-            _ => create_error("error on update this task", None),
+            failed = true;
+
+            if atomic {
+               break;
+            }
          }
       }
-      _ => create_error("data invalid", None),
+   }
+
+   let committed = !(atomic && failed);
+
+   let transaction_result = if committed {
+      transaction.commit()
+   } else {
+      transaction.rollback()
+   };
+
+   if transaction_result.is_err() {
+      return create_error("", None);
+   }
+
+   if committed {
+      wakeups.send();
+   }
+
+   let json = serde_json::to_string(&BulkResult { committed, results });
+
+   match json {
+      Ok(data) => Ok(Response::builder()
+         .status(if committed { 200 } else { 409 })
+         .body(Body::from(data))
+         .unwrap()),
+      _ => create_error("", None),
    }
 }
 
-pub async fn delete_task(
+#[utoipa::path(
+   get,
+   path = "/tasks/{id}/occurrences",
+   tag = "tasks",
+   params(
+      ("id" = String, Path, description = "Task id"),
+      ("count" = Option<usize>, Query, description = "How many occurrences, 5 by default")
+   ),
+   security(("token" = [])),
+   responses(
+      (status = 200, description = "The next due dates of a recurring task", body = TaskOccurrences),
+      (status = 404, description = "The task does not exist or is not shared with the user", body = ErrorFormated),
+      (status = 400, description = "Invalid request", body = ErrorFormated)
+   )
+)]
+pub async fn list_occurrences(
    req: Request<Body>,
    conn: Arc<Mutex<Connection>>,
    task_id: String,
 ) -> Result<Response<Body>, Infallible> {
    let headers = req.headers();
+   let mut _user_id = String::from("");
 
-   let result = valid_user(headers).await;
+   let result = valid_user(headers, &conn).await;
 
    match result {
       Ok(data) => match data {
          ValidResponse::Id(id) => {
             let users = get_users(conn.clone(), id.clone(), String::from("id")).await;
 
-            if users.len() < 1 {
+            if users.is_empty() {
                return create_error("this user not exists", None);
             }
+
+            _user_id = id;
          }
          ValidResponse::Respo(data) => return data,
       },
       _ => return create_error("", None),
    }
 
+   let amount = match get_query_params(&req).get("count") {
+      Some(count) => match count.parse::<usize>() {
+         Ok(count) if (1..=OCCURRENCES_MAX).contains(&count) => count,
+         _ => return create_error("count invalid", None),
+      },
+      None => OCCURRENCES_DEFAULT,
+   };
+
    let conn = conn.lock().await;
 
-   let mut query = conn
-      .prepare("SELECT * FROM tasks WHERE tasks.id = ?")
-      .unwrap();
-   let data = query
-      .query_map([task_id.clone()], |row| {
-         Ok(TaskCreated {
-            id: row.get(0)?,
-            name: row.get(1)?,
-            completed: row.get(2)?,
-            user: None,
-         })
-      })
-      .unwrap();
+   let task = match find_permitted_task(&conn, &task_id, &_user_id, Role::Viewer, false) {
+      Ok((task, _)) => task,
+      Err((status, error)) => return create_error(error, Option::from(status)),
+   };
 
-   let mut tasks: Vec<TaskCreated> = vec![];
-   for task in data {
-      tasks.push(task.unwrap());
-   }
+   let (rule, due_date) = match (task.recurrence, task.due_date) {
+      (Some(rule), Some(due_date)) => (rule, due_date),
+      _ => return create_error("this task is not recurring", None),
+   };
+
+   let occurrences = match (Recurrence::parse(&rule), parse_datetime(&due_date)) {
+      (Ok(recurrence), Some(due_date)) => recurrence.occurrences(due_date, amount),
+      _ => return create_error("", None),
+   };
+
+   let json = serde_json::to_string(&TaskOccurrences {
+      id: task.id,
+      recurrence: rule,
+      occurrences,
+   });
+
+   valid_json(json)
+}
+
+#[utoipa::path(
+   post,
+   path = "/tasks/{id}/restore",
+   tag = "tasks",
+   params(
+      ("id" = String, Path, description = "Task id")
+   ),
+   security(("token" = [])),
+   responses(
+      (status = 200, description = "The task was restored"),
+      (status = 404, description = "The task is not in the trash", body = ErrorFormated),
+      (status = 400, description = "Invalid request", body = ErrorFormated)
+   )
+)]
+pub async fn restore_task(
+   req: Request<Body>,
+   conn: Arc<Mutex<Connection>>,
+   task_id: String,
+) -> Result<Response<Body>, Infallible> {
+   let headers = req.headers();
+   let mut _user_id = String::from("");
+
+   let result = valid_user(headers, &conn).await;
+
+   match result {
+      Ok(data) => match data {
+         ValidResponse::Id(id) => {
+            let users = get_users(conn.clone(), id.clone(), String::from("id")).await;
 
-   if tasks.len() < 1 {
-      return create_error("this task not exists", None);
+            if users.is_empty() {
+               return create_error("this user not exists", None);
+            }
+
+            _user_id = id;
+         }
+         ValidResponse::Respo(data) => return data,
+      },
+      _ => return create_error("", None),
    }
 
-   let query = conn.execute("DELETE FROM tasks WHERE id = ?", [task_id]);
+   let mut conn = conn.lock().await;
+
+   let transaction = match conn.transaction() {
+      Ok(transaction) => transaction,
+      _ => return create_error("", None),
+   };
+
+   let task = match find_permitted_task(&transaction, &task_id, &_user_id, Role::Owner, true) {
+      Ok((task, _)) => task,
+      Err((403, error)) => return create_error(error, Option::from(403)),
+      _ => return create_error("this task is not in the trash", Option::from(404)),
+   };
+
+   let query = transaction
+      .execute(
+         "UPDATE tasks SET deleted_at = NULL, version = version + 1 WHERE id = ?",
+         [&task.id],
+      )
+      .and_then(|_| record_task_event(&transaction, &task, &_user_id, "restored", json!({})))
+      .and_then(|wakeups| transaction.commit().map(|_| wakeups));
 
    match query {
-      Ok(_) => Ok(Response::builder()
-         .status(200)
-         .body(Body::from(""))
-         .unwrap()),
-      _ => create_error("error on delete this task", None),
+      Ok(wakeups) => {
+         wakeups.send();
+
+         Ok(Response::builder()
+            .status(200)
+            .body(Body::from(""))
+            .unwrap())
+      }
+      _ => create_error("error on restore this task", None),
+   }
+}
+
+#[cfg(test)]
+mod tests {
+   use super::super::super::database::create_memory_connection;
+   use super::super::super::utils::permissions::find_any_task;
+   use super::super::users::login_response;
+   use super::*;
+
+   use hyper::body::to_bytes;
+   use hyper::header::{HeaderValue, AUTHORIZATION, IF_MATCH};
+
+   fn database() -> Connection {
+      let conn = create_memory_connection();
+
+      conn
+         .execute(
+            "INSERT INTO users (id, firstname, lastname, email, password) VALUES ('alice', 'Alice', 'A', 'alice@x.io', '')",
+            [],
+         )
+         .unwrap();
+
+      conn
+   }
+
+   async fn request(method: &str, path: &str, user_id: &str) -> Request<Body> {
+      let login = to_bytes(login_response(user_id, 0).unwrap().into_body())
+         .await
+         .unwrap();
+      let login: serde_json::Value = serde_json::from_slice(&login).unwrap();
+
+      Request::builder()
+         .method(method)
+         .uri(path)
+         .header(
+            AUTHORIZATION,
+            format!("Bearer {}", login["token"].as_str().unwrap()),
+         )
+         .body(Body::empty())
+         .unwrap()
+   }
+
+   fn rename(name: &str) -> RequestBodyUpdate {
+      serde_json::from_value(json!({ "name": name })).unwrap()
+   }
+
+   #[test]
+   fn checks_if_match_against_the_version() {
+      let mut task = TaskCreated::new("alice", "Milk");
+      task.version = 2;
+
+      let if_match = |value: &str| {
+         let mut headers = HeaderMap::new();
+         headers.insert(IF_MATCH, HeaderValue::from_str(value).unwrap());
+
+         check_if_match(&headers, &task)
+      };
+
+      assert_eq!(if_match("\"v2\""), Ok(()));
+      assert_eq!(if_match("\"v1\"").unwrap_err().0, 412);
+      assert_eq!(if_match("W/\"v2\"").unwrap_err().0, 412);
+
+      let missing = check_if_match(&HeaderMap::new(), &task);
+
+      if CONFIG.require_if_match {
+         assert_eq!(missing.unwrap_err().0, 428);
+      } else {
+         assert_eq!(missing, Ok(()));
+      }
+   }
+
+   #[test]
+   fn refuses_changes_to_a_stale_version() {
+      let conn = database();
+      let (task_id, _) = insert_task(&conn, TaskCreated::new("alice", "Milk"), "alice").unwrap();
+      let stale = find_any_task(&conn, &task_id).unwrap();
+
+      let (task, _) = apply_task_changes(&conn, stale.clone(), rename("Eggs"), "alice").unwrap();
+
+      assert_eq!(task.version, stale.version + 1);
+      assert_eq!(
+         apply_task_changes(&conn, stale, rename("Bread"), "alice")
+            .err()
+            .map(|error| error.0),
+         Some(412)
+      );
+      assert_eq!(find_any_task(&conn, &task_id).unwrap().name, "Eggs");
+   }
+
+   #[tokio::test]
+   async fn moves_tasks_to_the_trash_and_back() {
+      let conn = database();
+      let (task_id, _) = insert_task(&conn, TaskCreated::new("alice", "Milk"), "alice").unwrap();
+      let conn = Arc::new(Mutex::new(conn));
+      let path = format!("/tasks/{}", task_id);
+
+      let response = delete_task(
+         request("DELETE", &path, "alice").await,
+         conn.clone(),
+         task_id.clone(),
+      )
+      .await
+      .unwrap();
+      let deleted = find_any_task(&*conn.lock().await, &task_id).unwrap();
+
+      assert_eq!(response.status(), 200);
+      assert!(deleted.deleted_at.is_some());
+      assert_eq!(deleted.version, 2);
+
+      let response = get_task(
+         request("GET", &path, "alice").await,
+         conn.clone(),
+         task_id.clone(),
+      )
+      .await
+      .unwrap();
+
+      assert_eq!(response.status(), 404);
+
+      let restore = format!("{}/restore", path);
+      let response = restore_task(
+         request("POST", &restore, "alice").await,
+         conn.clone(),
+         task_id.clone(),
+      )
+      .await
+      .unwrap();
+      let restored = find_any_task(&*conn.lock().await, &task_id).unwrap();
+
+      assert_eq!(response.status(), 200);
+      assert_eq!(restored.deleted_at, None);
+      assert_eq!(restored.version, 3);
+
+      let response = restore_task(
+         request("POST", &restore, "alice").await,
+         conn.clone(),
+         task_id,
+      )
+      .await
+      .unwrap();
+
+      assert_eq!(response.status(), 404);
    }
 }
@@ -0,0 +1,86 @@
+use super::super::config::CONFIG;
+use super::super::middlewares::users::{valid_user, ValidResponse};
+use super::super::utils::permissions::OWNED_TASKS;
+use super::super::utils::{
+   create_error, get_users, parse_datetime, valid_json, ErrorFormated, DATETIME_FORMAT,
+};
+use super::super::views::tasks::{TaskCreated, TaskDeletedFormated, TASK_COLUMNS};
+
+use std::convert::Infallible;
+use std::sync::Arc;
+
+use futures::lock::Mutex;
+
+use chrono::Duration;
+
+use rusqlite::Connection;
+
+use hyper::{Body, Request, Response};
+
+#[utoipa::path(
+   get,
+   path = "/trash",
+   tag = "tasks",
+   security(("token" = [])),
+   responses(
+      (status = 200, description = "The tasks in the trash the user may restore", body = [TaskDeletedFormated]),
+      (status = 400, description = "Invalid request", body = ErrorFormated)
+   )
+)]
+pub async fn list_trash(
+   req: Request<Body>,
+   conn: Arc<Mutex<Connection>>,
+) -> Result<Response<Body>, Infallible> {
+   let headers = req.headers();
+   let mut _user_id = String::from("");
+
+   let result = valid_user(headers, &conn).await;
+
+   match result {
+      Ok(data) => match data {
+         ValidResponse::Id(id) => {
+            let users = get_users(conn.clone(), id.clone(), String::from("id")).await;
+
+            if users.is_empty() {
+               return create_error("this user not exists", None);
+            }
+
+            _user_id = id;
+         }
+         ValidResponse::Respo(data) => return data,
+      },
+      _ => return create_error("", None),
+   }
+
+   let conn = conn.lock().await;
+
+   let mut query = conn
+      .prepare(&format!(
+         "SELECT {} FROM tasks WHERE {} AND tasks.deleted_at IS NOT NULL ORDER BY tasks.deleted_at DESC",
+         TASK_COLUMNS, OWNED_TASKS
+      ))
+      .unwrap();
+   let data = query
+      .query_map([_user_id], |row| TaskCreated::from_row(row, 0))
+      .unwrap();
+
+   let mut tasks: Vec<TaskDeletedFormated> = vec![];
+
+   for task in data {
+      let task = task.unwrap();
+
+      let purge_at = task
+         .deleted_at
+         .as_deref()
+         .and_then(parse_datetime)
+         .map(|deleted_at| deleted_at + Duration::days(CONFIG.trash_retention_days))
+         .map(|purge_at| format!("{}", purge_at.format(DATETIME_FORMAT)))
+         .unwrap_or_default();
+
+      tasks.push(task.format_deleted(purge_at));
+   }
+
+   let json = serde_json::to_string(&tasks);
+
+   valid_json(json)
+}
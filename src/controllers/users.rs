@@ -1,6 +1,22 @@
-use super::super::middlewares::users::{valid_user, ValidResponse};
-use super::super::utils::{create_error, get_users, parse_body, valid_json};
-use super::super::views::tasks::TaskCreated;
+use super::super::config::CONFIG;
+use super::super::middlewares::users::{
+   jwt_algorithm, valid_trashed_user, valid_user, ValidResponse, EXPIRES_FORMAT,
+};
+use super::super::utils::events::Wakeups;
+use super::super::utils::history::{diff, record_event, user_fields};
+use super::super::utils::login_attempts::{self, LoginKey};
+use super::super::utils::passwords::{
+   hash_password, needs_rehash, password_policy, verify_password, UNKNOWN_USER_HASH,
+};
+use super::super::utils::validation::{
+   trim, trim_option, ValidationErrorFormated, EMAIL_MAX_LENGTH, NAME_MAX_LENGTH,
+   PASSWORD_MAX_LENGTH,
+};
+use super::super::utils::verification::{create_verification, send_verification};
+use super::super::utils::{
+   body_error, client_ip, create_error, get_users, now, parse_body, valid_json, ErrorFormated,
+};
+use super::super::views::tasks::{TaskCreated, TASK_COLUMNS};
 use super::super::views::users::{CreatedUser, CreatedUserComplete};
 
 use std::convert::{From, Infallible};
@@ -10,70 +26,98 @@ use std::sync::Arc;
 use futures::lock::Mutex;
 
 use chrono::{DateTime, Duration, Local, Utc};
-use jsonwebtokens::{encode, Algorithm, AlgorithmID};
+use jsonwebtokens::encode;
+use jsonwebtokens::error::Error as JWTError;
 
 use rusqlite::Connection;
 
-use bcrypt::{hash, verify};
 use serde_json::json;
 
 use uuid::Uuid;
 
+use hyper::header::HeaderValue;
 use hyper::{Body, Request, Response};
 
-#[derive(Serialize)]
+use utoipa::ToSchema;
+
+use validator::Validate;
+
+#[derive(Serialize, ToSchema)]
+#[schema(as = UserList)]
 struct Users {
    users: Vec<CreatedUserComplete>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, ToSchema, Validate)]
+#[schema(as = UserCreate)]
 struct RequestBodyUser {
+   #[serde(deserialize_with = "trim")]
+   #[validate(length(min = 1, max = NAME_MAX_LENGTH))]
    firstname: String,
+   #[serde(deserialize_with = "trim")]
+   #[validate(length(min = 1, max = NAME_MAX_LENGTH))]
    lastname: String,
+   #[serde(deserialize_with = "trim")]
+   #[validate(email, length(max = EMAIL_MAX_LENGTH))]
    email: String,
+   #[validate(length(max = PASSWORD_MAX_LENGTH), custom(function = "password_policy"))]
    password: String,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, ToSchema, Validate)]
+#[schema(as = UserUpdate)]
 struct RequestBodyUpdate {
+   #[serde(default, deserialize_with = "trim_option")]
+   #[validate(length(min = 1, max = NAME_MAX_LENGTH))]
    firstname: Option<String>,
+   #[serde(default, deserialize_with = "trim_option")]
+   #[validate(length(min = 1, max = NAME_MAX_LENGTH))]
    lastname: Option<String>,
+   #[serde(default, deserialize_with = "trim_option")]
+   #[validate(email, length(max = EMAIL_MAX_LENGTH))]
    email: Option<String>,
+   #[validate(length(max = PASSWORD_MAX_LENGTH), custom(function = "password_policy"))]
    password: Option<String>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, ToSchema, Validate)]
+#[schema(as = Login)]
 struct RequestBodyLogin {
+   #[serde(deserialize_with = "trim")]
    email: String,
    password: String,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-struct Claims {
-   exp: usize,
+#[derive(Serialize, ToSchema)]
+pub struct LoginFormated {
+   id: String,
+   token: String,
+}
+
+#[derive(Serialize, ToSchema)]
+struct MfaPendingFormated {
+   id: String,
+   /// Exchanged with a code at `POST /login/mfa` within `MFA_TOKEN_MINUTES`.
+   mfa_token: String,
 }
 
+#[utoipa::path(
+   get,
+   path = "/users",
+   tag = "users",
+   responses(
+      (status = 200, description = "Every active user with their tasks", body = Users)
+   )
+)]
 pub async fn list_all_users(conn: Arc<Mutex<Connection>>) -> Result<Response<Body>, Infallible> {
    let conn = conn.lock().await;
 
-   let mut query = conn.prepare("SELECT *, tasks.id as task_id FROM users LEFT OUTER JOIN tasks ON tasks.user_id = users.id").unwrap();
+   let mut query = conn.prepare(&format!("SELECT users.id, users.firstname, users.lastname, users.email, {} FROM users LEFT OUTER JOIN tasks ON tasks.user_id = users.id AND tasks.deleted_at IS NULL WHERE users.deleted_at IS NULL", TASK_COLUMNS)).unwrap();
    let data = query
       .query_map([], |row| {
-         let id = row.get(5);
-         let name = row.get(6);
-         let completed = row.get(7);
-
-         let tasks = if id.is_ok() && name.is_ok() && completed.is_ok() {
-            let task = TaskCreated {
-               id: id.unwrap(),
-               name: name.unwrap(),
-               completed: completed.unwrap(),
-               user: None,
-            };
-
-            vec![task.format()]
-         } else {
-            vec![]
+         let tasks = match TaskCreated::from_row(row, 4) {
+            Ok(task) => vec![task.format()],
+            _ => vec![],
          };
 
          Ok(CreatedUserComplete {
@@ -93,14 +137,12 @@ pub async fn list_all_users(conn: Arc<Mutex<Connection>>) -> Result<Response<Bod
 
       let position = users.iter_mut().position(|item| item.id == user.id);
 
-      if position.is_none() {
-         users.push(user);
-      } else {
-         let position = position.unwrap();
-
+      if let Some(position) = position {
          for task in user.tasks {
             users[position].tasks.push(task);
          }
+      } else {
+         users.push(user);
       }
    }
 
@@ -109,12 +151,26 @@ pub async fn list_all_users(conn: Arc<Mutex<Connection>>) -> Result<Response<Bod
    valid_json(json)
 }
 
+#[utoipa::path(
+   get,
+   path = "/user/{id}",
+   tag = "users",
+   params(
+      ("id" = String, Path, description = "User id")
+   ),
+   security(("token" = [])),
+   responses(
+      (status = 200, description = "The user with their tasks", body = CreatedUserComplete),
+      (status = 401, description = "The token belongs to another user", body = ErrorFormated),
+      (status = 400, description = "Invalid request", body = ErrorFormated)
+   )
+)]
 pub async fn list_by_id(
    req: Request<Body>,
    conn: Arc<Mutex<Connection>>,
    user_id: String,
 ) -> Result<Response<Body>, Infallible> {
-   let result = valid_user(req.headers()).await;
+   let result = valid_user(req.headers(), &conn).await;
 
    match result {
       Ok(data) => match data {
@@ -126,25 +182,13 @@ pub async fn list_by_id(
             let conn = conn.lock().await;
 
             let mut query = conn
-               .prepare("SELECT * FROM users LEFT OUTER JOIN tasks ON users.id = tasks.user_id WHERE users.id = ?")
+               .prepare(&format!("SELECT users.id, users.firstname, users.lastname, users.email, {} FROM users LEFT OUTER JOIN tasks ON users.id = tasks.user_id AND tasks.deleted_at IS NULL WHERE users.id = ? AND users.deleted_at IS NULL", TASK_COLUMNS))
                .unwrap();
             let data = query
                .query_map([user_id.clone()], |row| {
-                  let id = row.get(5);
-                  let name = row.get(6);
-                  let completed = row.get(7);
-
-                  let tasks = if id.is_ok() && name.is_ok() && completed.is_ok() {
-                     let task = TaskCreated {
-                        id: id.unwrap(),
-                        name: name.unwrap(),
-                        completed: completed.unwrap(),
-                        user: None,
-                     };
-
-                     vec![task.format()]
-                  } else {
-                     vec![]
+                  let tasks = match TaskCreated::from_row(row, 4) {
+                     Ok(task) => vec![task.format()],
+                     _ => vec![],
                   };
 
                   Ok(CreatedUserComplete {
@@ -164,18 +208,16 @@ pub async fn list_by_id(
 
                let position = users.iter_mut().position(|item| item.id == user.id);
 
-               if position.is_none() {
-                  users.push(user);
-               } else {
-                  let position = position.unwrap();
-
+               if let Some(position) = position {
                   for task in user.tasks {
                      users[position].tasks.push(task);
                   }
+               } else {
+                  users.push(user);
                }
             }
 
-            if users.len() < 1 {
+            if users.is_empty() {
                return create_error("this user is not exists", None);
             }
 
@@ -183,18 +225,31 @@ pub async fn list_by_id(
 
             valid_json(json)
          }
-         ValidResponse::Respo(data) => return data,
+         ValidResponse::Respo(data) => data,
       },
-      _ => return create_error("", None),
+      _ => create_error("", None),
    }
 }
 
+#[utoipa::path(
+   post,
+   path = "/register",
+   tag = "users",
+   request_body = RequestBodyUser,
+   responses(
+      (status = 201, description = "The user was created"),
+      (status = 400, description = "Invalid body or email already in use", body = ErrorFormated),
+      (status = 422, description = "Fields that break their rules", body = ValidationErrorFormated),
+      (status = 413, description = "Body larger than `BODY_MAX_BYTES`", body = ErrorFormated),
+      (status = 415, description = "Body not sent as JSON", body = ErrorFormated)
+   )
+)]
 pub async fn create_user(
    req: Request<Body>,
    conn: Arc<Mutex<Connection>>,
 ) -> Result<Response<Body>, Infallible> {
-   let body = parse_body::<RequestBodyUser>(req.into_body()).await;
-
+   let (head, body) = req.into_parts();
+   let body = parse_body::<RequestBodyUser>(&head.headers, body).await;
    match body {
       Ok(RequestBodyUser {
          firstname,
@@ -202,88 +257,302 @@ pub async fn create_user(
          email,
          password,
       }) => {
-         let users = get_users(conn.clone(), email.clone(), String::from("email")).await;
-         if users.len() >= 1 {
+         // Hashing takes a while, so it is done before taking the connection.
+         let password = match hash_password(&password).await {
+            Ok(password) => password,
+            _ => return create_error("", None),
+         };
+
+         let mut conn = conn.lock().await;
+
+         if email_in_use(&conn, &email) {
             return create_error("this email already in use", None);
          }
 
-         let conn = conn.lock().await;
+         let user = CreatedUser {
+            id: Uuid::new_v4().to_string(),
+            firstname,
+            lastname,
+            email,
+            password,
+         };
+
+         let transaction = match conn.transaction() {
+            Ok(transaction) => transaction,
+            _ => return create_error("", None),
+         };
 
-         let user_id = Uuid::new_v4();
-         let result = conn.execute(
-            "INSERT INTO users VALUES (?, ?, ?, ?, ?)",
-            [
-               user_id.to_string(),
-               firstname,
-               lastname,
-               email,
-               hash(password, 7).unwrap(),
-            ],
-         );
+         let result = transaction
+            .execute(
+               "INSERT INTO users (id, firstname, lastname, email, password) VALUES (?, ?, ?, ?, ?)",
+               [
+                  &user.id,
+                  &user.firstname,
+                  &user.lastname,
+                  &user.email,
+                  &user.password,
+               ],
+            )
+            .and_then(|_| {
+               record_event(
+                  &transaction,
+                  "user",
+                  &user.id,
+                  &user.id,
+                  &user.id,
+                  "created",
+                  diff(None, Option::from(&user_fields(&user))),
+               )
+            })
+            .and_then(|wakeups| {
+               create_verification(&transaction, &user.id, &user.email)
+                  .map(|token| (wakeups, token))
+            })
+            .and_then(|data| transaction.commit().map(|_| data));
 
          match result {
-            Ok(_) => Ok(Response::builder()
-               .status(201)
-               .body(Body::from(""))
-               .unwrap()),
-            _ => return create_error("", None),
+            Ok((wakeups, token)) => {
+               wakeups.send();
+               send_verification(&user.email, &user.firstname, &token);
+
+               Ok(Response::builder()
+                  .status(201)
+                  .body(Body::from(""))
+                  .unwrap())
+            }
+            _ => create_error("", None),
          }
       }
-      Err(_) => create_error("data invalid", None),
+      Err(error) => body_error(error),
+   }
+}
+
+pub fn too_many_attempts(wait: i64) -> Result<Response<Body>, Infallible> {
+   let mut response = create_error("too many failed logins, try again later", Option::from(429));
+
+   if let Ok(response) = &mut response {
+      response
+         .headers_mut()
+         .insert("Retry-After", HeaderValue::from(wait.max(1)));
    }
+
+   response
 }
 
+#[utoipa::path(
+   post,
+   path = "/login",
+   tag = "users",
+   request_body = RequestBodyLogin,
+   responses(
+      (status = 200, description = "A token for the Authorization header", body = LoginFormated),
+      (status = 202, description = "The password is right, and a code of the user's second factor is due at `POST /login/mfa`", body = MfaPendingFormated),
+      (status = 401, description = "Unknown email or wrong password", body = ErrorFormated),
+      (status = 403, description = "The email is not verified yet, with `REQUIRE_VERIFIED_EMAIL`", body = ErrorFormated),
+      (status = 429, description = "Too many failed logins for this email or address, retry after `Retry-After` seconds", body = ErrorFormated),
+      (status = 422, description = "Fields that break their rules", body = ValidationErrorFormated),
+      (status = 413, description = "Body larger than `BODY_MAX_BYTES`", body = ErrorFormated),
+      (status = 415, description = "Body not sent as JSON", body = ErrorFormated)
+   )
+)]
 pub async fn login(
    req: Request<Body>,
    conn: Arc<Mutex<Connection>>,
 ) -> Result<Response<Body>, Infallible> {
-   let body = parse_body::<RequestBodyLogin>(req.into_body()).await;
-
+   let ip = client_ip(&req);
+   let (head, body) = req.into_parts();
+   let body = parse_body::<RequestBodyLogin>(&head.headers, body).await;
    match body {
       Ok(RequestBodyLogin { email, password }) => {
+         let mut keys = vec![LoginKey::Email(email.clone())];
+         if let Some(ip) = ip {
+            keys.push(LoginKey::Ip(ip));
+         }
+
+         if let Err(wait) = login_attempts::begin_attempt(&*conn.lock().await, &keys) {
+            return too_many_attempts(wait);
+         }
+
          let users = get_users(conn.clone(), email.clone(), String::from("email")).await;
 
-         if users.len() < 1 {
-            return create_error("this user not exists", None);
+         // Unknown emails are checked against a hash too, so that they take
+         // as long as wrong passwords and get the same answer.
+         let verified = match users.first() {
+            Some(user) => verify_password(&password, &user.password).await,
+            None => {
+               verify_password(&password, &UNKNOWN_USER_HASH).await;
+
+               false
+            }
+         };
+
+         if !verified {
+            return create_error("email or password is not valid", Option::from(401));
          }
 
-         let password_verified = verify(password, &users[0].password);
+         let (session, totp_enabled): (i64, bool) = {
+            let conn = conn.lock().await;
+
+            for key in &keys {
+               login_attempts::undo_failure(&conn, key);
+            }
 
-         match password_verified {
-            Ok(result) => {
-               if !result {
-                  return create_error("password is not valid", None);
-               }
+            let row: Result<(bool, i64, bool), _> = conn.query_row(
+               "SELECT verified, session_version, totp_enabled FROM users WHERE id = ?",
+               [&users[0].id],
+               |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+            );
 
-               let now = Utc::now();
-               let converted: DateTime<Local> = DateTime::from(now);
-               let expires = converted.add(Duration::hours(1));
+            let (verified, session, totp_enabled) = match row {
+               Ok(row) => row,
+               _ => return create_error("", None),
+            };
 
-               let data = json!({ "id": &users[0].id, "expires": format!("{}", expires.format("%Y-%m-%d %H:%M:%S")) });
-               let header = json!({});
-               let alg = Algorithm::new_hmac(AlgorithmID::HS256, "random123").unwrap();
+            if CONFIG.require_verified_email && !verified {
+               return create_error("this email is not verified", Option::from(403));
+            }
 
-               let token = encode(&header, &data, &alg);
+            // With a second factor, failures are forgotten once its code is
+            // right too, so that the password alone cannot reset them.
+            if !totp_enabled {
+               let _ = login_attempts::clear(&conn, &keys[0]);
+            }
 
-               match token {
-                  Ok(jwt) => {
-                     let json = json!({ "id": &users[0].id, "token": jwt });
+            (session, totp_enabled)
+         };
 
-                     Ok(Response::builder()
-                        .status(200)
-                        .body(Body::from(json.to_string()))
-                        .unwrap())
-                  }
-                  _ => create_error("", None),
-               }
+         // The password is only known here, so this is where bcrypt hashes
+         // and Argon2 hashes made with older params are replaced.
+         if needs_rehash(&users[0].password) {
+            if let Ok(password) = hash_password(&password).await {
+               let conn = conn.lock().await;
+
+               let _ = conn.execute(
+                  "UPDATE users SET password = ? WHERE id = ?",
+                  [&password, &users[0].id],
+               );
             }
-            _ => create_error("", None),
          }
+
+         if totp_enabled {
+            let data = json!({ "id": &users[0].id, "expires": expires_in(Duration::minutes(CONFIG.mfa_token_minutes)), "session": session, "mfa_pending": true });
+
+            let json = sign_token(&data).map(|jwt| {
+               serde_json::to_string(&MfaPendingFormated {
+                  id: users[0].id.clone(),
+                  mfa_token: jwt,
+               })
+            });
+
+            return match json {
+               Ok(Ok(json)) => Ok(Response::builder()
+                  .status(202)
+                  .body(Body::from(json))
+                  .unwrap()),
+               _ => create_error("", None),
+            };
+         }
+
+         login_response(&users[0].id, session)
+      }
+      Err(error) => body_error(error),
+   }
+}
+
+fn expires_in(duration: Duration) -> String {
+   let converted: DateTime<Local> = DateTime::from(Utc::now());
+
+   format!("{}", converted.add(duration).format(EXPIRES_FORMAT))
+}
+
+fn sign_token(data: &serde_json::Value) -> Result<String, JWTError> {
+   encode(&json!({}), data, &jwt_algorithm()?)
+}
+
+/// Answers a finished login with a token for the Authorization header,
+/// valid for an hour.
+pub fn login_response(id: &str, session: i64) -> Result<Response<Body>, Infallible> {
+   let data = json!({ "id": id, "expires": expires_in(Duration::hours(1)), "session": session });
+
+   match sign_token(&data) {
+      Ok(jwt) => {
+         let json = serde_json::to_string(&LoginFormated {
+            id: id.to_string(),
+            token: jwt,
+         });
+
+         valid_json(json)
       }
       _ => create_error("", None),
    }
 }
 
+/// Writes the fields of a user that changed from `before`, with their
+/// history event, and returns its wake-ups. The password reset saves new
+/// passwords through here too.
+pub fn save_user(
+   conn: &Connection,
+   before: &CreatedUser,
+   user: &CreatedUser,
+) -> Result<Wakeups, rusqlite::Error> {
+   let mut changes = diff(
+      Option::from(&user_fields(before)),
+      Option::from(&user_fields(user)),
+   );
+
+   if user.password != before.password {
+      changes["password"] = json!({ "changed": true });
+   }
+
+   conn.execute(
+      "UPDATE users SET firstname = ?, lastname = ?, email = ?, password = ? WHERE id = ?",
+      [
+         &user.firstname,
+         &user.lastname,
+         &user.email,
+         &user.password,
+         &user.id,
+      ],
+   )?;
+
+   record_event(
+      conn, "user", &user.id, &user.id, &user.id, "updated", changes,
+   )
+}
+
+// Users in the trash keep their email until they are purged, so that they
+// can still be restored.
+fn email_in_use(conn: &Connection, email: &str) -> bool {
+   let in_use: i64 = conn
+      .query_row(
+         "SELECT COUNT(*) FROM users WHERE email = ?",
+         [email],
+         |row| row.get(0),
+      )
+      .unwrap();
+
+   in_use >= 1
+}
+
+#[utoipa::path(
+   put,
+   path = "/user/{id}",
+   tag = "users",
+   params(
+      ("id" = String, Path, description = "User id")
+   ),
+   request_body = RequestBodyUpdate,
+   security(("token" = [])),
+   responses(
+      (status = 200, description = "The user was updated"),
+      (status = 401, description = "The token belongs to another user", body = ErrorFormated),
+      (status = 409, description = "The email is used by another user", body = ErrorFormated),
+      (status = 400, description = "Invalid request", body = ErrorFormated),
+      (status = 422, description = "Fields that break their rules", body = ValidationErrorFormated),
+      (status = 413, description = "Body larger than `BODY_MAX_BYTES`", body = ErrorFormated),
+      (status = 415, description = "Body not sent as JSON", body = ErrorFormated)
+   )
+)]
 pub async fn update_user(
    req: Request<Body>,
    conn: Arc<Mutex<Connection>>,
@@ -291,8 +560,7 @@ pub async fn update_user(
 ) -> Result<Response<Body>, Infallible> {
    let (head, body) = req.into_parts();
 
-   let body = parse_body::<RequestBodyUpdate>(body).await;
-   let result = valid_user(&head.headers).await;
+   let result = valid_user(&head.headers, &conn).await;
 
    match result {
       Ok(data) => match data {
@@ -306,6 +574,7 @@ pub async fn update_user(
       _ => return create_error("", None),
    }
 
+   let body = parse_body::<RequestBodyUpdate>(&head.headers, body).await;
    match body {
       Ok(RequestBodyUpdate {
          firstname,
@@ -314,63 +583,103 @@ pub async fn update_user(
          password,
       }) => {
          let users = get_users(conn.clone(), user_id, String::from("id")).await;
-         if users.len() < 1 {
+         if users.is_empty() {
             return create_error("this user not exists", None);
          }
 
-         if firstname == None && lastname == None && email == None && password == None {
+         if firstname.is_none() && lastname.is_none() && email.is_none() && password.is_none() {
             return create_error("data invalid", None);
          }
 
          let mut user: CreatedUser = users[0].clone();
 
-         if firstname != None {
-            user.firstname = firstname.unwrap();
+         if let Some(firstname) = firstname {
+            user.firstname = firstname;
          }
 
-         if lastname != None {
-            user.lastname = lastname.unwrap();
+         if let Some(lastname) = lastname {
+            user.lastname = lastname;
          }
 
-         if email != None {
-            user.email = email.unwrap();
+         if let Some(email) = email {
+            user.email = email;
          }
 
-         if password != None {
-            user.password = hash(password.unwrap(), 8).unwrap();
+         if let Some(password) = password {
+            user.password = match hash_password(&password).await {
+               Ok(password) => password,
+               _ => return create_error("", None),
+            };
          }
 
-         let conn = conn.lock().await;
-         let data = conn.execute(
-            "UPDATE users SET firstname = ?, lastname = ?, email = ?, password = ? WHERE id = ?",
-            [
-               user.firstname,
-               user.lastname,
-               user.email,
-               user.password,
-               user.id,
-            ],
-         );
+         let email_changed = user.email != users[0].email;
+
+         let mut conn = conn.lock().await;
+
+         if email_changed && email_in_use(&conn, &user.email) {
+            return create_error("this email already in use", Option::from(409));
+         }
+
+         let transaction = match conn.transaction() {
+            Ok(transaction) => transaction,
+            _ => return create_error("", None),
+         };
+
+         let data = save_user(&transaction, &users[0], &user)
+            .and_then(|wakeups| {
+               // A new email has to be verified again.
+               if !email_changed {
+                  return Ok((wakeups, None));
+               }
+
+               transaction.execute("UPDATE users SET verified = 0 WHERE id = ?", [&user.id])?;
+               create_verification(&transaction, &user.id, &user.email)
+                  .map(|token| (wakeups, Some(token)))
+            })
+            .and_then(|data| transaction.commit().map(|_| data));
 
          match data {
-            Ok(_) => Ok(Response::builder()
-               .status(200)
-               .body(Body::from(""))
-               .unwrap()),
+            Ok((wakeups, token)) => {
+               wakeups.send();
+
+               if let Some(token) = token {
+                  send_verification(&user.email, &user.firstname, &token);
+               }
+
+               Ok(Response::builder()
+                  .status(200)
+                  .body(Body::from(""))
+                  .unwrap())
+            }
             _ => create_error("error on update user", None),
          }
       }
-      _ => create_error("data invalid", None),
+      Err(error) => body_error(error),
    }
 }
 
+#[utoipa::path(
+   delete,
+   path = "/user/{id}",
+   tag = "users",
+   params(
+      ("id" = String, Path, description = "User id")
+   ),
+   security(("token" = [])),
+   responses(
+      (status = 200, description = "The user was moved to the trash"),
+      (status = 401, description = "The token belongs to another user", body = ErrorFormated),
+      (status = 404, description = "The user is already in the trash", body = ErrorFormated),
+      (status = 400, description = "Invalid request", body = ErrorFormated)
+   )
+)]
 pub async fn delete_user(
    req: Request<Body>,
    conn: Arc<Mutex<Connection>>,
    user_id: String,
 ) -> Result<Response<Body>, Infallible> {
    let headers = req.headers();
-   let response = valid_user(headers).await;
+   let response = valid_user(headers, &conn).await;
 
    match response {
       Ok(data) => match data {
@@ -384,14 +693,293 @@ pub async fn delete_user(
       _ => return create_error("", None),
    }
 
-   let conn = conn.lock().await;
-   let result = conn.execute("DELETE FROM users WHERE id = ?", [user_id]);
+   let mut conn = conn.lock().await;
+
+   let transaction = match conn.transaction() {
+      Ok(transaction) => transaction,
+      _ => return create_error("", None),
+   };
+
+   // The user's tasks share its deleted_at, which is how restore_user tells
+   // them apart from tasks that were already in the trash.
+   let deleted_at = now();
+
+   let deleted = transaction.execute(
+      "UPDATE users SET deleted_at = ? WHERE id = ? AND deleted_at IS NULL",
+      [&deleted_at, &user_id],
+   );
+
+   match deleted {
+      Ok(0) => return create_error("this user not exists", Option::from(404)),
+      Ok(_) => {}
+      _ => return create_error("error on delete this user", None),
+   }
+
+   let result = transaction
+      .execute(
+         "UPDATE tasks SET deleted_at = ? WHERE user_id = ? AND deleted_at IS NULL",
+         [&deleted_at, &user_id],
+      )
+      .and_then(|_| {
+         record_event(
+            &transaction,
+            "user",
+            &user_id,
+            &user_id,
+            &user_id,
+            "deleted",
+            json!({}),
+         )
+      })
+      .and_then(|wakeups| transaction.commit().map(|_| wakeups));
+
+   match result {
+      Ok(wakeups) => {
+         wakeups.send();
+
+         Ok(Response::builder()
+            .status(200)
+            .body(Body::from(""))
+            .unwrap())
+      }
+      _ => create_error("error on delete this user", None),
+   }
+}
+
+#[utoipa::path(
+   post,
+   path = "/user/{id}/restore",
+   tag = "users",
+   params(
+      ("id" = String, Path, description = "User id")
+   ),
+   security(("token" = [])),
+   responses(
+      (status = 200, description = "The user was restored"),
+      (status = 401, description = "The token belongs to another user", body = ErrorFormated),
+      (status = 404, description = "The user is not in the trash", body = ErrorFormated)
+   )
+)]
+pub async fn restore_user(
+   req: Request<Body>,
+   conn: Arc<Mutex<Connection>>,
+   user_id: String,
+) -> Result<Response<Body>, Infallible> {
+   let headers = req.headers();
+   let response = valid_trashed_user(headers, &conn).await;
+
+   match response {
+      Ok(data) => match data {
+         ValidResponse::Id(id) => {
+            if id != user_id {
+               return create_error("you not have permission for to follow", Option::from(401));
+            }
+         }
+         ValidResponse::Respo(data) => return data,
+      },
+      _ => return create_error("", None),
+   }
+
+   let mut conn = conn.lock().await;
+
+   let deleted_at: Option<String> = conn
+      .query_row(
+         "SELECT deleted_at FROM users WHERE id = ? AND deleted_at IS NOT NULL",
+         [&user_id],
+         |row| row.get(0),
+      )
+      .ok();
+
+   let deleted_at = match deleted_at {
+      Some(deleted_at) => deleted_at,
+      None => return create_error("this user is not in the trash", Option::from(404)),
+   };
+
+   let transaction = match conn.transaction() {
+      Ok(transaction) => transaction,
+      _ => return create_error("", None),
+   };
+
+   let result = transaction
+      .execute(
+         "UPDATE tasks SET deleted_at = NULL WHERE user_id = ? AND deleted_at = ?",
+         [&user_id, &deleted_at],
+      )
+      .and_then(|_| {
+         transaction.execute(
+            "UPDATE users SET deleted_at = NULL WHERE id = ?",
+            [&user_id],
+         )
+      })
+      .and_then(|_| {
+         record_event(
+            &transaction,
+            "user",
+            &user_id,
+            &user_id,
+            &user_id,
+            "restored",
+            json!({}),
+         )
+      })
+      .and_then(|wakeups| transaction.commit().map(|_| wakeups));
 
    match result {
+      Ok(wakeups) => {
+         wakeups.send();
+
+         Ok(Response::builder()
+            .status(200)
+            .body(Body::from(""))
+            .unwrap())
+      }
+      _ => create_error("error on restore this user", None),
+   }
+}
+
+/// Lets an admin of `ADMIN_IDS` end the lockout of a user's email before
+/// `LOGIN_LOCKOUT_SECONDS`.
+#[utoipa::path(
+   post,
+   path = "/user/{id}/unlock",
+   tag = "users",
+   params(
+      ("id" = String, Path, description = "User id")
+   ),
+   security(("token" = [])),
+   responses(
+      (status = 200, description = "The failed logins of the user's email were forgotten"),
+      (status = 403, description = "The token does not belong to an admin", body = ErrorFormated),
+      (status = 404, description = "The user does not exist", body = ErrorFormated)
+   )
+)]
+pub async fn unlock_user(
+   req: Request<Body>,
+   conn: Arc<Mutex<Connection>>,
+   user_id: String,
+) -> Result<Response<Body>, Infallible> {
+   let headers = req.headers();
+   let response = valid_user(headers, &conn).await;
+
+   match response {
+      Ok(data) => match data {
+         ValidResponse::Id(id) => {
+            if !CONFIG.admin_ids.contains(&id) {
+               return create_error("you not have permission for to follow", Option::from(403));
+            }
+         }
+         ValidResponse::Respo(data) => return data,
+      },
+      _ => return create_error("", None),
+   }
+
+   let users = get_users(conn.clone(), user_id, String::from("id")).await;
+
+   if users.is_empty() {
+      return create_error("this user not exists", Option::from(404));
+   }
+
+   let conn = conn.lock().await;
+
+   match login_attempts::clear(&conn, &LoginKey::Email(users[0].email.clone())) {
       Ok(_) => Ok(Response::builder()
          .status(200)
          .body(Body::from(""))
          .unwrap()),
-      _ => create_error("error on delete this user", None),
+      _ => create_error("", None),
+   }
+}
+
+#[cfg(test)]
+mod tests {
+   use super::super::super::database::create_memory_connection;
+   use super::*;
+
+   use hyper::body::to_bytes;
+   use hyper::header::AUTHORIZATION;
+
+   async fn request(method: &str, path: &str, user_id: &str) -> Request<Body> {
+      let login = to_bytes(login_response(user_id, 0).unwrap().into_body())
+         .await
+         .unwrap();
+      let login: serde_json::Value = serde_json::from_slice(&login).unwrap();
+
+      Request::builder()
+         .method(method)
+         .uri(path)
+         .header(
+            AUTHORIZATION,
+            format!("Bearer {}", login["token"].as_str().unwrap()),
+         )
+         .body(Body::empty())
+         .unwrap()
+   }
+
+   fn deleted_at(conn: &Connection, table: &str, id: &str) -> Option<String> {
+      conn
+         .query_row(
+            &format!("SELECT deleted_at FROM {} WHERE id = ?", table),
+            [id],
+            |row| row.get(0),
+         )
+         .unwrap()
+   }
+
+   #[tokio::test]
+   async fn moves_users_to_the_trash_and_back() {
+      let conn = create_memory_connection();
+      conn
+         .execute_batch(
+            "INSERT INTO users (id, firstname, lastname, email, password) VALUES ('alice', 'Alice', 'A', 'alice@x.io', '');
+            INSERT INTO tasks (id, name, completed, user_id) VALUES ('milk', 'Milk', 0, 'alice');
+            INSERT INTO tasks (id, name, completed, user_id, deleted_at) VALUES ('eggs', 'Eggs', 0, 'alice', '2000-01-01 00:00:00');",
+         )
+         .unwrap();
+      let conn = Arc::new(Mutex::new(conn));
+
+      let response = delete_user(
+         request("DELETE", "/user/alice", "alice").await,
+         conn.clone(),
+         String::from("alice"),
+      )
+      .await
+      .unwrap();
+
+      assert_eq!(response.status(), 200);
+      {
+         let conn = conn.lock().await;
+         let deleted = deleted_at(&conn, "users", "alice");
+
+         assert!(deleted.is_some());
+         assert_eq!(deleted_at(&conn, "tasks", "milk"), deleted);
+      }
+
+      // The token of a user in the trash only works to restore it.
+      let response = delete_user(
+         request("DELETE", "/user/alice", "alice").await,
+         conn.clone(),
+         String::from("alice"),
+      )
+      .await
+      .unwrap();
+
+      assert_eq!(response.status(), 401);
+
+      let response = restore_user(
+         request("POST", "/user/alice/restore", "alice").await,
+         conn.clone(),
+         String::from("alice"),
+      )
+      .await
+      .unwrap();
+
+      assert_eq!(response.status(), 200);
+
+      let conn = conn.lock().await;
+
+      assert_eq!(deleted_at(&conn, "users", "alice"), None);
+      assert_eq!(deleted_at(&conn, "tasks", "milk"), None);
+      // Tasks that were in the trash before stay there.
+      assert!(deleted_at(&conn, "tasks", "eggs").is_some());
    }
 }
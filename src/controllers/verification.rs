@@ -0,0 +1,167 @@
+use super::super::config::CONFIG;
+use super::super::utils::history::record_event;
+use super::super::utils::validation::{trim, ValidationErrorFormated, EMAIL_MAX_LENGTH};
+use super::super::utils::verification::{
+   create_verification, seconds_since_sent, send_verification, use_verification,
+};
+use super::super::utils::{body_error, create_error, get_users, parse_body, ErrorFormated};
+
+use std::convert::{From, Infallible};
+use std::sync::Arc;
+
+use futures::lock::Mutex;
+
+use rusqlite::Connection;
+
+use hyper::{Body, Request, Response};
+
+use utoipa::ToSchema;
+
+use validator::Validate;
+
+use serde_json::json;
+
+const TOKEN_MAX_LENGTH: u64 = 512;
+
+#[derive(Serialize, Deserialize, Debug, ToSchema, Validate)]
+#[schema(as = EmailVerification)]
+struct RequestBodyVerify {
+   #[serde(deserialize_with = "trim")]
+   #[validate(length(min = 1, max = TOKEN_MAX_LENGTH))]
+   token: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, ToSchema, Validate)]
+#[schema(as = EmailVerificationResend)]
+struct RequestBodyResend {
+   #[serde(deserialize_with = "trim")]
+   #[validate(email, length(max = EMAIL_MAX_LENGTH))]
+   email: String,
+}
+
+/// Marks the email a token was sent to as verified. Each token works once,
+/// and only while the email is still the user's.
+#[utoipa::path(
+   post,
+   path = "/verify-email",
+   tag = "users",
+   request_body = RequestBodyVerify,
+   responses(
+      (status = 200, description = "The email was verified"),
+      (status = 400, description = "The token is invalid, used or expired", body = ErrorFormated),
+      (status = 422, description = "Fields that break their rules", body = ValidationErrorFormated),
+      (status = 413, description = "Body larger than `BODY_MAX_BYTES`", body = ErrorFormated),
+      (status = 415, description = "Body not sent as JSON", body = ErrorFormated)
+   )
+)]
+pub async fn verify_email(
+   req: Request<Body>,
+   conn: Arc<Mutex<Connection>>,
+) -> Result<Response<Body>, Infallible> {
+   let (head, body) = req.into_parts();
+
+   let token = match parse_body::<RequestBodyVerify>(&head.headers, body).await {
+      Ok(RequestBodyVerify { token }) => token,
+      Err(error) => return body_error(error),
+   };
+
+   let mut conn = conn.lock().await;
+
+   let transaction = match conn.transaction() {
+      Ok(transaction) => transaction,
+      _ => return create_error("", None),
+   };
+
+   let user_id = match use_verification(&transaction, &token) {
+      Ok(user_id) => user_id,
+      Err((status, reason)) => return create_error(reason, Option::from(status)),
+   };
+
+   let result = record_event(
+      &transaction,
+      "user",
+      &user_id,
+      &user_id,
+      &user_id,
+      "verified",
+      json!({}),
+   )
+   .and_then(|wakeups| transaction.commit().map(|_| wakeups));
+
+   match result {
+      Ok(wakeups) => {
+         wakeups.send();
+
+         Ok(Response::builder()
+            .status(200)
+            .body(Body::from(""))
+            .unwrap())
+      }
+      _ => create_error("", None),
+   }
+}
+
+/// Sends a new token to an unverified email, at most once every
+/// `VERIFICATION_RESEND_SECONDS`. The answer is the same whether or not the
+/// email belongs to a user.
+#[utoipa::path(
+   post,
+   path = "/verify-email/resend",
+   tag = "users",
+   request_body = RequestBodyResend,
+   responses(
+      (status = 202, description = "A token is on its way if the email awaits verification"),
+      (status = 422, description = "Fields that break their rules", body = ValidationErrorFormated),
+      (status = 413, description = "Body larger than `BODY_MAX_BYTES`", body = ErrorFormated),
+      (status = 415, description = "Body not sent as JSON", body = ErrorFormated)
+   )
+)]
+pub async fn resend_verification(
+   req: Request<Body>,
+   conn: Arc<Mutex<Connection>>,
+) -> Result<Response<Body>, Infallible> {
+   let (head, body) = req.into_parts();
+
+   let email = match parse_body::<RequestBodyResend>(&head.headers, body).await {
+      Ok(RequestBodyResend { email }) => email,
+      Err(error) => return body_error(error),
+   };
+
+   let accepted = Ok(Response::builder()
+      .status(202)
+      .body(Body::from(""))
+      .unwrap());
+
+   let users = get_users(conn.clone(), email, String::from("email")).await;
+
+   let user = match users.first() {
+      Some(user) => user,
+      None => return accepted,
+   };
+
+   let conn = conn.lock().await;
+
+   let verified: bool = conn
+      .query_row(
+         "SELECT verified FROM users WHERE id = ?",
+         [&user.id],
+         |row| row.get(0),
+      )
+      .unwrap_or(true);
+
+   let throttled = seconds_since_sent(&conn, &user.id)
+      .is_some_and(|seconds| seconds < CONFIG.verification_resend_seconds);
+
+   if verified || throttled {
+      return accepted;
+   }
+
+   match create_verification(&conn, &user.id, &user.email) {
+      Ok(token) => {
+         send_verification(&user.email, &user.firstname, &token);
+
+         accepted
+      }
+      _ => create_error("", None),
+   }
+}
@@ -0,0 +1,471 @@
+use super::super::middlewares::users::{valid_user, ValidResponse};
+use super::super::utils::validation::{trim, ValidationErrorFormated, URL_MAX_LENGTH};
+use super::super::utils::webhooks::{refused_host, valid_event_pattern};
+use super::super::utils::{
+   body_error, create_error, get_query_params, get_users, now, parse_body, random_token,
+   valid_json, ErrorFormated,
+};
+use super::super::views::webhooks::{
+   Webhook, WebhookDeliveries, WebhookDelivery, WebhookFormated, DELIVERY_COLUMNS, WEBHOOK_COLUMNS,
+};
+
+use std::convert::{From, Infallible};
+use std::sync::Arc;
+
+use futures::lock::Mutex;
+
+use rusqlite::{params, Connection, OptionalExtension};
+
+use uuid::Uuid;
+
+use hyper::{Body, Request, Response, Uri};
+
+use utoipa::ToSchema;
+
+use validator::Validate;
+
+#[derive(Serialize, Deserialize, Debug, ToSchema, Validate)]
+#[schema(as = WebhookCreate)]
+struct RequestBodyCreate {
+   #[serde(deserialize_with = "trim")]
+   #[validate(length(max = URL_MAX_LENGTH))]
+   url: String,
+   #[validate(length(min = 1, max = WEBHOOK_EVENTS_MAX, message = "must have 1 to 50 events"))]
+   events: Vec<String>,
+   #[validate(length(min = WEBHOOK_SECRET_MIN_LENGTH))]
+   secret: Option<String>,
+}
+
+const WEBHOOKS_MAX_PER_USER: i64 = 20;
+const WEBHOOK_EVENTS_MAX: u64 = 50;
+const WEBHOOK_SECRET_MIN_LENGTH: u64 = 16;
+const DELIVERIES_DEFAULT_LIMIT: i64 = 50;
+const DELIVERIES_MAX_LIMIT: i64 = 200;
+const DELIVERY_STATUSES: [&str; 3] = ["pending", "delivered", "dead"];
+
+fn valid_url(url: &str) -> bool {
+   match url.parse::<Uri>() {
+      Ok(uri) => {
+         matches!(uri.scheme_str(), Some("http") | Some("https"))
+            && uri.host().is_some()
+            && !refused_host(&uri)
+      }
+      _ => false,
+   }
+}
+
+fn find_user_webhook(conn: &Connection, webhook_id: &str, user_id: &str) -> Option<Webhook> {
+   conn
+      .query_row(
+         &format!(
+            "SELECT {} FROM webhooks WHERE webhooks.id = ? AND webhooks.user_id = ?",
+            WEBHOOK_COLUMNS
+         ),
+         [webhook_id, user_id],
+         |row| Webhook::from_row(row, 0),
+      )
+      .optional()
+      .unwrap()
+}
+
+#[utoipa::path(
+   get,
+   path = "/webhooks",
+   tag = "webhooks",
+   security(("token" = [])),
+   responses(
+      (status = 200, description = "The webhooks of the user", body = [WebhookFormated]),
+      (status = 400, description = "Invalid request", body = ErrorFormated)
+   )
+)]
+pub async fn list_webhooks(
+   req: Request<Body>,
+   conn: Arc<Mutex<Connection>>,
+) -> Result<Response<Body>, Infallible> {
+   let headers = req.headers();
+   let mut _user_id = String::from("");
+
+   let result = valid_user(headers, &conn).await;
+
+   match result {
+      Ok(data) => match data {
+         ValidResponse::Id(id) => {
+            let users = get_users(conn.clone(), id.clone(), String::from("id")).await;
+
+            if users.is_empty() {
+               return create_error("this user not exists", None);
+            }
+
+            _user_id = id;
+         }
+         ValidResponse::Respo(data) => return data,
+      },
+      _ => return create_error("", None),
+   }
+
+   let conn = conn.lock().await;
+
+   let mut query = conn
+      .prepare(&format!(
+         "SELECT {} FROM webhooks WHERE webhooks.user_id = ? ORDER BY webhooks.created_at ASC",
+         WEBHOOK_COLUMNS
+      ))
+      .unwrap();
+   let data = query
+      .query_map([&_user_id], |row| Webhook::from_row(row, 0))
+      .unwrap();
+
+   let mut webhooks: Vec<WebhookFormated> = vec![];
+   for webhook in data {
+      webhooks.push(webhook.unwrap().format(false));
+   }
+
+   let json = serde_json::to_string(&webhooks);
+
+   valid_json(json)
+}
+
+#[utoipa::path(
+   post,
+   path = "/webhooks",
+   tag = "webhooks",
+   request_body = RequestBodyCreate,
+   security(("token" = [])),
+   responses(
+      (status = 201, description = "The webhook, with its secret", body = WebhookFormated),
+      (status = 400, description = "Invalid request", body = ErrorFormated),
+      (status = 422, description = "Fields that break their rules", body = ValidationErrorFormated),
+      (status = 413, description = "Body larger than `BODY_MAX_BYTES`", body = ErrorFormated),
+      (status = 415, description = "Body not sent as JSON", body = ErrorFormated)
+   )
+)]
+pub async fn create_webhook(
+   req: Request<Body>,
+   conn: Arc<Mutex<Connection>>,
+) -> Result<Response<Body>, Infallible> {
+   let (head, body) = req.into_parts();
+   let mut _user_id = String::from("");
+
+   let result = valid_user(&head.headers, &conn).await;
+
+   match result {
+      Ok(data) => match data {
+         ValidResponse::Id(id) => {
+            let users = get_users(conn.clone(), id.clone(), String::from("id")).await;
+
+            if users.is_empty() {
+               return create_error("this user not exists", None);
+            }
+
+            _user_id = id;
+         }
+         ValidResponse::Respo(data) => return data,
+      },
+      _ => return create_error("", None),
+   }
+
+   let body = parse_body::<RequestBodyCreate>(&head.headers, body).await;
+
+   let RequestBodyCreate {
+      url,
+      events,
+      secret,
+   } = match body {
+      Ok(data) => data,
+      Err(error) => return body_error(error),
+   };
+
+   if !valid_url(&url) {
+      return create_error("url invalid", None);
+   }
+
+   if !events.iter().all(|event| valid_event_pattern(event)) {
+      return create_error("events invalid", None);
+   }
+
+   let secret = secret.unwrap_or_else(random_token);
+
+   let mut events = events;
+   events.sort();
+   events.dedup();
+
+   let webhook = Webhook {
+      id: Uuid::new_v4().to_string(),
+      user_id: _user_id.clone(),
+      url,
+      events: events.join(","),
+      secret,
+      created_at: now(),
+   };
+
+   let conn = conn.lock().await;
+
+   let count: i64 = conn
+      .query_row(
+         "SELECT COUNT(*) FROM webhooks WHERE user_id = ?",
+         [&_user_id],
+         |row| row.get(0),
+      )
+      .unwrap_or(0);
+
+   if count >= WEBHOOKS_MAX_PER_USER {
+      return create_error("too many webhooks", None);
+   }
+
+   let query = conn.execute(
+      "INSERT INTO webhooks (id, user_id, url, events, secret, created_at) VALUES (?, ?, ?, ?, ?, ?)",
+      params![
+         webhook.id,
+         webhook.user_id,
+         webhook.url,
+         webhook.events,
+         webhook.secret,
+         webhook.created_at
+      ],
+   );
+
+   match query {
+      Ok(_) => match serde_json::to_string(&webhook.format(true)) {
+         Ok(json) => Ok(Response::builder()
+            .status(201)
+            .body(Body::from(json))
+            .unwrap()),
+         _ => create_error("", None),
+      },
+      _ => create_error("not is possible create this webhook", None),
+   }
+}
+
+#[utoipa::path(
+   delete,
+   path = "/webhooks/{id}",
+   tag = "webhooks",
+   params(
+      ("id" = String, Path, description = "Webhook id")
+   ),
+   security(("token" = [])),
+   responses(
+      (status = 200, description = "The webhook was deleted"),
+      (status = 404, description = "The webhook does not exist or is not shared with the user", body = ErrorFormated),
+      (status = 400, description = "Invalid request", body = ErrorFormated)
+   )
+)]
+pub async fn delete_webhook(
+   req: Request<Body>,
+   conn: Arc<Mutex<Connection>>,
+   webhook_id: String,
+) -> Result<Response<Body>, Infallible> {
+   let headers = req.headers();
+   let mut _user_id = String::from("");
+
+   let result = valid_user(headers, &conn).await;
+
+   match result {
+      Ok(data) => match data {
+         ValidResponse::Id(id) => {
+            let users = get_users(conn.clone(), id.clone(), String::from("id")).await;
+
+            if users.is_empty() {
+               return create_error("this user not exists", None);
+            }
+
+            _user_id = id;
+         }
+         ValidResponse::Respo(data) => return data,
+      },
+      _ => return create_error("", None),
+   }
+
+   let conn = conn.lock().await;
+
+   let query = conn.execute(
+      "DELETE FROM webhooks WHERE id = ? AND user_id = ?",
+      [&webhook_id, &_user_id],
+   );
+
+   match query {
+      Ok(0) => create_error("this webhook not exists", Option::from(404)),
+      Ok(_) => Ok(Response::builder()
+         .status(200)
+         .body(Body::from(""))
+         .unwrap()),
+      _ => create_error("error on delete this webhook", None),
+   }
+}
+
+/// The delivery log of a webhook, newest first, filtered by `?status` and
+/// paginated with `?limit` and `?before`.
+#[utoipa::path(
+   get,
+   path = "/webhooks/{id}/deliveries",
+   tag = "webhooks",
+   params(
+      ("id" = String, Path, description = "Webhook id"),
+      ("limit" = Option<i64>, Query, description = "Page size"),
+      ("before" = Option<i64>, Query, description = "`next_before` of the previous page"),
+      ("status" = Option<String>, Query, description = "`pending`, `delivered` or `dead`")
+   ),
+   security(("token" = [])),
+   responses(
+      (status = 200, description = "The deliveries, newest first", body = WebhookDeliveries),
+      (status = 404, description = "The webhook does not exist or is not shared with the user", body = ErrorFormated),
+      (status = 400, description = "Invalid request", body = ErrorFormated)
+   )
+)]
+pub async fn list_deliveries(
+   req: Request<Body>,
+   conn: Arc<Mutex<Connection>>,
+   webhook_id: String,
+) -> Result<Response<Body>, Infallible> {
+   let headers = req.headers();
+   let mut _user_id = String::from("");
+
+   let result = valid_user(headers, &conn).await;
+
+   match result {
+      Ok(data) => match data {
+         ValidResponse::Id(id) => {
+            let users = get_users(conn.clone(), id.clone(), String::from("id")).await;
+
+            if users.is_empty() {
+               return create_error("this user not exists", None);
+            }
+
+            _user_id = id;
+         }
+         ValidResponse::Respo(data) => return data,
+      },
+      _ => return create_error("", None),
+   }
+
+   let params = get_query_params(&req);
+
+   let limit = match params.get("limit") {
+      Some(limit) => match limit.parse::<i64>() {
+         Ok(limit) if (1..=DELIVERIES_MAX_LIMIT).contains(&limit) => limit,
+         _ => return create_error("limit invalid", None),
+      },
+      None => DELIVERIES_DEFAULT_LIMIT,
+   };
+
+   let before = match params.get("before") {
+      Some(before) => match before.parse::<i64>() {
+         Ok(before) => before,
+         _ => return create_error("before invalid", None),
+      },
+      None => i64::MAX,
+   };
+
+   let status = match params.get("status") {
+      Some(status) if DELIVERY_STATUSES.contains(&status.as_str()) => Option::from(status.clone()),
+      Some(_) => return create_error("status invalid", None),
+      None => None,
+   };
+
+   let conn = conn.lock().await;
+
+   if find_user_webhook(&conn, &webhook_id, &_user_id).is_none() {
+      return create_error("this webhook not exists", Option::from(404));
+   }
+
+   // Fetches one more delivery than asked for to know if there is a next page.
+   let mut query = conn
+      .prepare(&format!(
+         "SELECT {}, webhook_deliveries.rowid FROM webhook_deliveries WHERE webhook_id = ?1 AND (?2 IS NULL OR status = ?2) AND rowid < ?3 ORDER BY rowid DESC LIMIT ?4",
+         DELIVERY_COLUMNS
+      ))
+      .unwrap();
+   let data = query
+      .query_map(params![webhook_id, status, before, limit + 1], |row| {
+         Ok((WebhookDelivery::from_row(row, 0)?, row.get::<_, i64>(12)?))
+      })
+      .unwrap();
+
+   let mut deliveries: Vec<(WebhookDelivery, i64)> = vec![];
+   for delivery in data {
+      deliveries.push(delivery.unwrap());
+   }
+
+   let next_before = if deliveries.len() as i64 > limit {
+      deliveries.pop();
+
+      deliveries.last().map(|(_, rowid)| *rowid)
+   } else {
+      None
+   };
+
+   let json = serde_json::to_string(&WebhookDeliveries {
+      deliveries: deliveries
+         .into_iter()
+         .map(|(delivery, _)| delivery)
+         .collect(),
+      next_before,
+   });
+
+   valid_json(json)
+}
+
+/// Puts a dead or failing delivery back in the queue with a fresh set of
+/// attempts.
+#[utoipa::path(
+   post,
+   path = "/webhooks/{id}/deliveries/{delivery_id}/retry",
+   tag = "webhooks",
+   params(
+      ("id" = String, Path, description = "Webhook id"),
+      ("delivery_id" = String, Path, description = "Delivery id")
+   ),
+   security(("token" = [])),
+   responses(
+      (status = 202, description = "The delivery was queued again"),
+      (status = 404, description = "The delivery does not exist or has not failed", body = ErrorFormated),
+      (status = 400, description = "Invalid request", body = ErrorFormated)
+   )
+)]
+pub async fn retry_delivery(
+   req: Request<Body>,
+   conn: Arc<Mutex<Connection>>,
+   webhook_id: String,
+   delivery_id: String,
+) -> Result<Response<Body>, Infallible> {
+   let headers = req.headers();
+   let mut _user_id = String::from("");
+
+   let result = valid_user(headers, &conn).await;
+
+   match result {
+      Ok(data) => match data {
+         ValidResponse::Id(id) => {
+            let users = get_users(conn.clone(), id.clone(), String::from("id")).await;
+
+            if users.is_empty() {
+               return create_error("this user not exists", None);
+            }
+
+            _user_id = id;
+         }
+         ValidResponse::Respo(data) => return data,
+      },
+      _ => return create_error("", None),
+   }
+
+   let conn = conn.lock().await;
+
+   if find_user_webhook(&conn, &webhook_id, &_user_id).is_none() {
+      return create_error("this webhook not exists", Option::from(404));
+   }
+
+   let query = conn.execute(
+      "UPDATE webhook_deliveries SET status = 'pending', attempts = 0, next_attempt_at = ? WHERE id = ? AND webhook_id = ? AND status != 'delivered'",
+      params![now(), delivery_id, webhook_id],
+   );
+
+   match query {
+      Ok(0) => create_error("this delivery cannot be retried", Option::from(404)),
+      Ok(_) => Ok(Response::builder()
+         .status(202)
+         .body(Body::from(""))
+         .unwrap()),
+      _ => create_error("error on retry this delivery", None),
+   }
+}
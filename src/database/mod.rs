@@ -1,15 +1,44 @@
+use std::sync::Arc;
+
+use futures::lock::Mutex;
+
+use lazy_static::lazy_static;
+
 use rusqlite::Connection;
 
+lazy_static! {
+   /// The connection every request and job shares.
+   pub static ref CONNECTION: Arc<Mutex<Connection>> = {
+      let conn = create_connection();
+
+      Arc::new(Mutex::new(conn))
+   };
+}
+
 pub fn create_connection() -> Connection {
    let conn = Connection::open("./src/database/database.db").unwrap();
 
+   prepare_connection(conn)
+}
+
+/// A private database with the whole schema, for tests.
+#[cfg(test)]
+pub fn create_memory_connection() -> Connection {
+   prepare_connection(Connection::open_in_memory().unwrap())
+}
+
+fn prepare_connection(conn: Connection) -> Connection {
    let users_sql = get_sql("users");
    let tasks_sql = get_sql("tasks");
 
    let _result = conn.execute(users_sql, []);
    let _result = conn.execute(tasks_sql, []);
 
-   return conn;
+   run_migrations(&conn);
+
+   conn.execute_batch("PRAGMA foreign_keys = ON;").unwrap();
+
+   conn
 }
 
 fn get_sql(table_name: &'static str) -> &'static str {
@@ -39,3 +68,232 @@ fn get_sql(table_name: &'static str) -> &'static str {
 
    _query
 }
+
+// Schema changes made after the tables above were first created. Each entry
+// runs once, in order, and `PRAGMA user_version` records how many have been
+// applied, so append new migrations and never edit old ones.
+fn get_migrations() -> Vec<&'static str> {
+   vec![
+      "ALTER TABLE tasks ADD COLUMN due_date VARCHAR;
+      ALTER TABLE tasks ADD COLUMN recurrence VARCHAR;",
+      // Rebuilds tasks so that hard deleting a user cascades to its tasks,
+      // dropping the tasks already orphaned by earlier user deletions.
+      "CREATE TABLE tasks_new (
+         id VARCHAR PRIMARY KEY,
+         name TEXT NOT NULL,
+         completed INT NOT NULL,
+         user_id VARCHAR NOT NULL,
+         due_date VARCHAR,
+         recurrence VARCHAR,
+         deleted_at VARCHAR,
+         FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+      );
+      INSERT INTO tasks_new (id, name, completed, user_id, due_date, recurrence)
+         SELECT id, name, completed, user_id, due_date, recurrence FROM tasks
+         WHERE user_id IN (SELECT id FROM users);
+      DROP TABLE tasks;
+      ALTER TABLE tasks_new RENAME TO tasks;
+      ALTER TABLE users ADD COLUMN deleted_at VARCHAR;
+      CREATE INDEX tasks_user_id ON tasks (user_id);",
+      "CREATE TABLE task_events (
+         id INTEGER PRIMARY KEY AUTOINCREMENT,
+         entity VARCHAR NOT NULL,
+         entity_id VARCHAR NOT NULL,
+         user_id VARCHAR NOT NULL,
+         actor_id VARCHAR NOT NULL,
+         action VARCHAR NOT NULL,
+         changes TEXT NOT NULL,
+         created_at VARCHAR NOT NULL
+      );
+      CREATE INDEX task_events_entity ON task_events (entity, entity_id);
+      CREATE INDEX task_events_user_id ON task_events (user_id);
+      CREATE INDEX task_events_actor_id ON task_events (actor_id);
+      CREATE TRIGGER task_events_no_update BEFORE UPDATE ON task_events
+      BEGIN
+         SELECT RAISE(ABORT, 'task_events is append-only');
+      END;
+      CREATE TRIGGER task_events_no_delete BEFORE DELETE ON task_events
+      BEGIN
+         SELECT RAISE(ABORT, 'task_events is append-only');
+      END;",
+      "ALTER TABLE tasks ADD COLUMN version INT NOT NULL DEFAULT 1;",
+      "CREATE TABLE projects (
+         id VARCHAR PRIMARY KEY,
+         name TEXT NOT NULL,
+         user_id VARCHAR NOT NULL,
+         created_at VARCHAR NOT NULL,
+         FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+      );
+      ALTER TABLE tasks ADD COLUMN project_id VARCHAR REFERENCES projects(id) ON DELETE SET NULL;
+      CREATE TABLE memberships (
+         resource_type VARCHAR NOT NULL,
+         resource_id VARCHAR NOT NULL,
+         user_id VARCHAR NOT NULL,
+         role VARCHAR NOT NULL,
+         invited_by VARCHAR NOT NULL,
+         created_at VARCHAR NOT NULL,
+         PRIMARY KEY (resource_type, resource_id, user_id),
+         FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+      );
+      CREATE INDEX memberships_user_id ON memberships (user_id);
+      CREATE INDEX tasks_project_id ON tasks (project_id);
+      CREATE TRIGGER tasks_memberships_cleanup AFTER DELETE ON tasks BEGIN
+         DELETE FROM memberships WHERE resource_type = 'task' AND resource_id = OLD.id;
+      END;
+      CREATE TRIGGER projects_memberships_cleanup AFTER DELETE ON projects BEGIN
+         DELETE FROM memberships WHERE resource_type = 'project' AND resource_id = OLD.id;
+      END;",
+      "ALTER TABLE tasks ADD COLUMN assignee_id VARCHAR REFERENCES users(id) ON DELETE SET NULL;
+      CREATE INDEX tasks_assignee_id ON tasks (assignee_id);",
+      "CREATE TABLE comments (
+         id VARCHAR PRIMARY KEY,
+         task_id VARCHAR NOT NULL,
+         user_id VARCHAR NOT NULL,
+         body TEXT NOT NULL,
+         created_at VARCHAR NOT NULL,
+         updated_at VARCHAR,
+         FOREIGN KEY (task_id) REFERENCES tasks(id) ON DELETE CASCADE,
+         FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+      );
+      CREATE INDEX comments_task_id ON comments (task_id, created_at);",
+      "CREATE TABLE attachments (
+         id VARCHAR PRIMARY KEY,
+         task_id VARCHAR NOT NULL,
+         user_id VARCHAR NOT NULL,
+         filename TEXT NOT NULL,
+         content_type VARCHAR NOT NULL,
+         size INT NOT NULL,
+         sha256 VARCHAR NOT NULL,
+         created_at VARCHAR NOT NULL,
+         FOREIGN KEY (task_id) REFERENCES tasks(id) ON DELETE CASCADE,
+         FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+      );
+      CREATE INDEX attachments_task_id ON attachments (task_id);
+      CREATE INDEX attachments_sha256 ON attachments (sha256);",
+      "CREATE TABLE webhooks (
+         id VARCHAR PRIMARY KEY,
+         user_id VARCHAR NOT NULL,
+         url TEXT NOT NULL,
+         events TEXT NOT NULL,
+         secret VARCHAR NOT NULL,
+         created_at VARCHAR NOT NULL,
+         FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+      );
+      CREATE TABLE webhook_deliveries (
+         id VARCHAR PRIMARY KEY,
+         webhook_id VARCHAR NOT NULL,
+         event_id INTEGER NOT NULL,
+         event VARCHAR NOT NULL,
+         payload TEXT NOT NULL,
+         status VARCHAR NOT NULL,
+         attempts INT NOT NULL DEFAULT 0,
+         next_attempt_at VARCHAR,
+         last_status_code INT,
+         last_error TEXT,
+         created_at VARCHAR NOT NULL,
+         delivered_at VARCHAR,
+         FOREIGN KEY (webhook_id) REFERENCES webhooks(id) ON DELETE CASCADE
+      );
+      CREATE INDEX webhooks_user_id ON webhooks (user_id);
+      CREATE INDEX webhook_deliveries_webhook_id ON webhook_deliveries (webhook_id, created_at);
+      CREATE INDEX webhook_deliveries_due ON webhook_deliveries (status, next_attempt_at);",
+      "ALTER TABLE users ADD COLUMN calendar_token VARCHAR;
+      CREATE UNIQUE INDEX users_calendar_token ON users (calendar_token);",
+      "CREATE TABLE labels (
+         id VARCHAR PRIMARY KEY,
+         user_id VARCHAR NOT NULL,
+         name TEXT NOT NULL COLLATE NOCASE,
+         created_at VARCHAR NOT NULL,
+         UNIQUE (user_id, name),
+         FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+      );
+      CREATE TABLE task_labels (
+         task_id VARCHAR NOT NULL,
+         label_id VARCHAR NOT NULL,
+         PRIMARY KEY (task_id, label_id),
+         FOREIGN KEY (task_id) REFERENCES tasks(id) ON DELETE CASCADE,
+         FOREIGN KEY (label_id) REFERENCES labels(id) ON DELETE CASCADE
+      );
+      CREATE TABLE import_jobs (
+         id VARCHAR PRIMARY KEY,
+         user_id VARCHAR NOT NULL,
+         source VARCHAR NOT NULL,
+         status VARCHAR NOT NULL,
+         total INT NOT NULL DEFAULT 0,
+         processed INT NOT NULL DEFAULT 0,
+         created_tasks INT NOT NULL DEFAULT 0,
+         created_projects INT NOT NULL DEFAULT 0,
+         skipped TEXT NOT NULL DEFAULT '[]',
+         error TEXT,
+         created_at VARCHAR NOT NULL,
+         finished_at VARCHAR,
+         FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+      );
+      CREATE INDEX task_labels_label_id ON task_labels (label_id);
+      CREATE INDEX import_jobs_user_id ON import_jobs (user_id, created_at);",
+      "CREATE TABLE login_attempts (
+         key VARCHAR PRIMARY KEY,
+         failures INT NOT NULL DEFAULT 0,
+         last_failure_at VARCHAR NOT NULL,
+         locked_until VARCHAR
+      );",
+      "ALTER TABLE users ADD COLUMN verified INT NOT NULL DEFAULT 0;
+      UPDATE users SET verified = 1;
+      CREATE TABLE email_verifications (
+         id VARCHAR PRIMARY KEY,
+         user_id VARCHAR NOT NULL,
+         email VARCHAR NOT NULL,
+         expires_at VARCHAR NOT NULL,
+         created_at VARCHAR NOT NULL,
+         FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+      );
+      CREATE INDEX email_verifications_user_id ON email_verifications (user_id, created_at);",
+      "ALTER TABLE users ADD COLUMN session_version INT NOT NULL DEFAULT 0;
+      CREATE TABLE password_resets (
+         token_hash VARCHAR PRIMARY KEY,
+         user_id VARCHAR NOT NULL,
+         expires_at VARCHAR NOT NULL,
+         created_at VARCHAR NOT NULL,
+         FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+      );
+      CREATE INDEX password_resets_user_id ON password_resets (user_id, created_at);",
+      "ALTER TABLE users ADD COLUMN totp_secret VARCHAR;
+      ALTER TABLE users ADD COLUMN totp_enabled INT NOT NULL DEFAULT 0;
+      ALTER TABLE users ADD COLUMN totp_last_step INT NOT NULL DEFAULT 0;
+      CREATE TABLE recovery_codes (
+         code_hash VARCHAR PRIMARY KEY,
+         user_id VARCHAR NOT NULL,
+         created_at VARCHAR NOT NULL,
+         FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+      );
+      CREATE INDEX recovery_codes_user_id ON recovery_codes (user_id);",
+      "CREATE TABLE task_event_audience (
+         event_id INTEGER NOT NULL,
+         user_id VARCHAR NOT NULL,
+         PRIMARY KEY (user_id, event_id),
+         FOREIGN KEY (event_id) REFERENCES task_events(id) ON DELETE CASCADE
+      );
+      INSERT OR IGNORE INTO task_event_audience (event_id, user_id)
+      SELECT task_events.id, tasks.user_id FROM task_events INNER JOIN tasks ON tasks.id = task_events.entity_id WHERE task_events.entity = 'task'
+      UNION SELECT task_events.id, tasks.assignee_id FROM task_events INNER JOIN tasks ON tasks.id = task_events.entity_id WHERE task_events.entity = 'task' AND tasks.assignee_id IS NOT NULL
+      UNION SELECT task_events.id, memberships.user_id FROM task_events INNER JOIN memberships ON memberships.resource_type = 'task' AND memberships.resource_id = task_events.entity_id WHERE task_events.entity = 'task'
+      UNION SELECT task_events.id, projects.user_id FROM task_events INNER JOIN tasks ON tasks.id = task_events.entity_id INNER JOIN projects ON projects.id = tasks.project_id WHERE task_events.entity = 'task'
+      UNION SELECT task_events.id, memberships.user_id FROM task_events INNER JOIN tasks ON tasks.id = task_events.entity_id INNER JOIN memberships ON memberships.resource_type = 'project' AND memberships.resource_id = tasks.project_id WHERE task_events.entity = 'task';",
+   ]
+}
+
+fn run_migrations(conn: &Connection) {
+   let version: usize = conn
+      .query_row("PRAGMA user_version", [], |row| row.get::<_, i64>(0))
+      .unwrap() as usize;
+
+   for (index, migration) in get_migrations().iter().enumerate().skip(version) {
+      conn
+         .execute_batch(&format!(
+            "BEGIN; {} PRAGMA user_version = {}; COMMIT;",
+            migration,
+            index + 1
+         ))
+         .unwrap();
+   }
+}
@@ -0,0 +1,267 @@
+pub mod types;
+
+use super::controllers::tasks::{
+   apply_bulk_operation, BulkOperation, RequestBodyCreate, RequestBodyUpdate,
+};
+use super::utils::permissions::{find_permitted_task, task_role, Role, VISIBLE_TASKS};
+use super::utils::validation::field_errors;
+use super::utils::{find_user, parse_datetime, DATETIME_FORMAT};
+use super::views::tasks::{TaskCreated, TASK_COLUMNS};
+
+use types::{
+   CreateTaskInput, PageInfo, Task, TaskConnection, TaskFilter, UpdateTaskInput, User, Viewer,
+};
+
+use std::sync::Arc;
+
+use futures::lock::Mutex;
+
+use lazy_static::lazy_static;
+
+use rusqlite::{params, Connection};
+
+use validator::Validate;
+
+use async_graphql::{Context, EmptySubscription, Error, ErrorExtensions, Object, Schema, ID};
+
+pub type TodoSchema = Schema<Query, Mutation, EmptySubscription>;
+pub type GraphqlResult<T> = Result<T, Error>;
+
+// Deep or wide queries are refused before they run: every field costs one
+// and a list multiplies the cost of its fields by its `first`.
+const GRAPHQL_MAX_DEPTH: usize = 8;
+const GRAPHQL_MAX_COMPLEXITY: usize = 2000;
+
+pub const TASKS_DEFAULT_FIRST: usize = 20;
+pub const TASKS_MAX_FIRST: usize = 100;
+pub const COMMENTS_DEFAULT_FIRST: usize = 20;
+pub const COMMENTS_MAX_FIRST: usize = 100;
+
+lazy_static! {
+   pub static ref SCHEMA: TodoSchema = Schema::build(Query, Mutation, EmptySubscription)
+      .limit_depth(GRAPHQL_MAX_DEPTH)
+      .limit_complexity(GRAPHQL_MAX_COMPLEXITY)
+      .finish();
+}
+
+/// The REST status and message of a failure, kept as `extensions.code`.
+pub fn graphql_error((status, error): (u16, &'static str)) -> Error {
+   Error::new(error).extend_with(|_, extensions| extensions.set("code", status))
+}
+
+pub fn database<'a>(ctx: &Context<'a>) -> GraphqlResult<&'a Arc<Mutex<Connection>>> {
+   ctx.data::<Arc<Mutex<Connection>>>()
+}
+
+fn viewer<'a>(ctx: &Context<'a>) -> GraphqlResult<&'a Viewer> {
+   ctx.data::<Viewer>()
+}
+
+fn load_task(conn: &Connection, task_id: &str, viewer_id: &str) -> GraphqlResult<Task> {
+   let (task, role) =
+      find_permitted_task(conn, task_id, viewer_id, Role::Viewer, false).map_err(graphql_error)?;
+
+   Ok(Task {
+      task,
+      role,
+      viewer_id: String::from(viewer_id),
+   })
+}
+
+fn normalize_filter_date(value: Option<String>) -> GraphqlResult<Option<String>> {
+   match value {
+      Some(value) => match parse_datetime(&value) {
+         Some(date) => Ok(Option::from(format!("{}", date.format(DATETIME_FORMAT)))),
+         None => Err(graphql_error((400, "due date invalid"))),
+      },
+      None => Ok(None),
+   }
+}
+
+pub struct Query;
+
+#[Object]
+impl Query {
+   async fn me(&self, ctx: &Context<'_>) -> GraphqlResult<User> {
+      let viewer = viewer(ctx)?;
+      let conn = database(ctx)?.lock().await;
+
+      match find_user(&conn, &viewer.id) {
+         Some(user) => Ok(User(user)),
+         None => Err(graphql_error((400, "this user not exists"))),
+      }
+   }
+
+   /// The tasks the user can see, like `GET /tasks`, ordered by id. `after`
+   /// is the `endCursor` of the previous page.
+   #[graphql(
+      complexity = "first.unwrap_or(TASKS_DEFAULT_FIRST).min(TASKS_MAX_FIRST) * child_complexity"
+   )]
+   async fn tasks(
+      &self,
+      ctx: &Context<'_>,
+      filter: Option<TaskFilter>,
+      first: Option<usize>,
+      after: Option<String>,
+   ) -> GraphqlResult<TaskConnection> {
+      let viewer = viewer(ctx)?;
+      let first = first.unwrap_or(TASKS_DEFAULT_FIRST).min(TASKS_MAX_FIRST);
+      let filter = filter.unwrap_or_default();
+
+      let assigned_to = match filter.assigned_to {
+         Some(assignee) if assignee == "me" => Option::from(viewer.id.clone()),
+         assignee => assignee,
+      };
+      let due_before = normalize_filter_date(filter.due_before)?;
+      let due_after = normalize_filter_date(filter.due_after)?;
+
+      let conn = database(ctx)?.lock().await;
+
+      let mut query = conn.prepare(&format!(
+         "SELECT {} FROM tasks WHERE tasks.deleted_at IS NULL AND {} AND (?2 IS NULL OR tasks.assignee_id = ?2) AND (?3 IS NULL OR EXISTS (SELECT 1 FROM task_labels INNER JOIN labels ON labels.id = task_labels.label_id WHERE task_labels.task_id = tasks.id AND labels.name = ?3)) AND (?4 IS NULL OR tasks.completed = ?4) AND (?5 IS NULL OR tasks.project_id = ?5) AND (?6 IS NULL OR tasks.due_date < ?6) AND (?7 IS NULL OR tasks.due_date >= ?7) AND (?8 IS NULL OR tasks.id > ?8) ORDER BY tasks.id LIMIT {}",
+         TASK_COLUMNS,
+         VISIBLE_TASKS,
+         first + 1
+      ))?;
+
+      let mut tasks: Vec<TaskCreated> = query
+         .query_map(
+            params![
+               &viewer.id,
+               assigned_to,
+               filter.label,
+               filter.completed.map(|completed| completed as i32),
+               filter.project_id.map(|project_id| project_id.0),
+               due_before,
+               due_after,
+               after
+            ],
+            |row| TaskCreated::from_row(row, 0),
+         )?
+         .filter_map(|task| task.ok())
+         .collect();
+
+      let has_next_page = tasks.len() > first;
+      tasks.truncate(first);
+
+      let end_cursor = tasks.last().map(|task| task.id.clone());
+
+      let nodes = tasks
+         .into_iter()
+         .map(|task| {
+            let role = task_role(&conn, &task, &viewer.id).unwrap_or(Role::Viewer);
+
+            Task {
+               task,
+               role,
+               viewer_id: viewer.id.clone(),
+            }
+         })
+         .collect();
+
+      Ok(TaskConnection {
+         nodes,
+         page_info: PageInfo {
+            has_next_page,
+            end_cursor,
+         },
+      })
+   }
+
+   async fn task(&self, ctx: &Context<'_>, id: ID) -> GraphqlResult<Task> {
+      let viewer = viewer(ctx)?;
+      let conn = database(ctx)?.lock().await;
+
+      load_task(&conn, &id, &viewer.id)
+   }
+}
+
+// Runs a task operation in its own transaction and returns the task id.
+// Inputs follow the same rules as the REST bodies, failing with code 422
+// and the failing fields in `extensions.fields`.
+async fn run_operation(ctx: &Context<'_>, operation: BulkOperation) -> GraphqlResult<String> {
+   if let Err(errors) = operation.validate() {
+      let fields = field_errors(&errors);
+
+      return Err(
+         graphql_error((422, "data invalid")).extend_with(|_, extensions| {
+            extensions.set(
+               "fields",
+               async_graphql::Value::from_json(serde_json::json!(fields)).unwrap_or_default(),
+            )
+         }),
+      );
+   }
+
+   let viewer = viewer(ctx)?;
+   let mut conn = database(ctx)?.lock().await;
+
+   let transaction = conn.transaction()?;
+   let (_, task_id, wakeups) =
+      apply_bulk_operation(&transaction, &viewer.id, operation).map_err(graphql_error)?;
+   transaction.commit()?;
+   wakeups.send();
+
+   Ok(task_id)
+}
+
+pub struct Mutation;
+
+#[Object]
+impl Mutation {
+   async fn create_task(&self, ctx: &Context<'_>, input: CreateTaskInput) -> GraphqlResult<Task> {
+      let operation = BulkOperation::Create {
+         task: RequestBodyCreate {
+            name: input.name.trim().to_string(),
+            due_date: input.due_date,
+            recurrence: input.recurrence,
+            project_id: input.project_id.map(|project_id| project_id.0),
+            assignee_id: input.assignee_id.map(|assignee_id| assignee_id.0),
+         },
+      };
+
+      let task_id = run_operation(ctx, operation).await?;
+      let conn = database(ctx)?.lock().await;
+
+      load_task(&conn, &task_id, &viewer(ctx)?.id)
+   }
+
+   /// `version` works like `If-Match` on `PUT /task/{id}`.
+   async fn update_task(
+      &self,
+      ctx: &Context<'_>,
+      id: ID,
+      input: UpdateTaskInput,
+      version: Option<i64>,
+   ) -> GraphqlResult<Task> {
+      let operation = BulkOperation::Update {
+         id: id.0,
+         version,
+         changes: RequestBodyUpdate {
+            name: input.name.map(|name| name.trim().to_string()),
+            completed: input.completed,
+            due_date: input.due_date,
+            recurrence: input.recurrence,
+            project_id: input.project_id,
+            assignee_id: input.assignee_id,
+         },
+      };
+
+      let task_id = run_operation(ctx, operation).await?;
+      let conn = database(ctx)?.lock().await;
+
+      load_task(&conn, &task_id, &viewer(ctx)?.id)
+   }
+
+   /// Moves the task to the trash and returns its id.
+   async fn delete_task(
+      &self,
+      ctx: &Context<'_>,
+      id: ID,
+      version: Option<i64>,
+   ) -> GraphqlResult<ID> {
+      let operation = BulkOperation::Delete { id: id.0, version };
+
+      run_operation(ctx, operation).await.map(ID)
+   }
+}
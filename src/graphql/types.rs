@@ -0,0 +1,257 @@
+use super::super::utils::find_user;
+use super::super::utils::labels::task_labels;
+use super::super::utils::markdown::render;
+use super::super::utils::permissions::Role;
+use super::super::views::attachments::{Attachment, ATTACHMENT_COLUMNS};
+use super::super::views::comments::{Comment, COMMENT_COLUMNS};
+use super::super::views::tasks::TaskCreated;
+use super::super::views::users::CreatedUser;
+use super::{database, GraphqlResult, COMMENTS_DEFAULT_FIRST, COMMENTS_MAX_FIRST};
+
+use async_graphql::{Context, InputObject, Object, SimpleObject, ID};
+
+/// The user the request was authenticated as.
+pub struct Viewer {
+   pub id: String,
+}
+
+pub struct User(pub CreatedUser);
+
+#[Object]
+impl User {
+   async fn id(&self) -> ID {
+      ID(self.0.id.clone())
+   }
+
+   async fn firstname(&self) -> &str {
+      &self.0.firstname
+   }
+
+   async fn lastname(&self) -> &str {
+      &self.0.lastname
+   }
+
+   async fn email(&self) -> &str {
+      &self.0.email
+   }
+}
+
+pub struct Task {
+   pub task: TaskCreated,
+   pub role: Role,
+   pub viewer_id: String,
+}
+
+#[Object]
+impl Task {
+   async fn id(&self) -> ID {
+      ID(self.task.id.clone())
+   }
+
+   async fn name(&self) -> &str {
+      &self.task.name
+   }
+
+   async fn completed(&self) -> bool {
+      self.task.completed != 0
+   }
+
+   async fn due_date(&self) -> Option<&str> {
+      self.task.due_date.as_deref()
+   }
+
+   async fn recurrence(&self) -> Option<&str> {
+      self.task.recurrence.as_deref()
+   }
+
+   async fn version(&self) -> i64 {
+      self.task.version
+   }
+
+   async fn project_id(&self) -> Option<ID> {
+      self.task.project_id.clone().map(ID)
+   }
+
+   async fn owned(&self) -> bool {
+      self.task.user_id == self.viewer_id
+   }
+
+   async fn role(&self) -> &str {
+      self.role.as_str()
+   }
+
+   async fn owner(&self, ctx: &Context<'_>) -> GraphqlResult<Option<User>> {
+      let conn = database(ctx)?.lock().await;
+
+      Ok(find_user(&conn, &self.task.user_id).map(User))
+   }
+
+   async fn assignee(&self, ctx: &Context<'_>) -> GraphqlResult<Option<User>> {
+      let assignee_id = match &self.task.assignee_id {
+         Some(assignee_id) => assignee_id,
+         None => return Ok(None),
+      };
+
+      let conn = database(ctx)?.lock().await;
+
+      Ok(find_user(&conn, assignee_id).map(User))
+   }
+
+   async fn labels(&self, ctx: &Context<'_>) -> GraphqlResult<Vec<String>> {
+      let conn = database(ctx)?.lock().await;
+
+      Ok(task_labels(&conn, &self.task.id))
+   }
+
+   /// The oldest comments first.
+   #[graphql(
+      complexity = "first.unwrap_or(COMMENTS_DEFAULT_FIRST).min(COMMENTS_MAX_FIRST) * child_complexity"
+   )]
+   async fn comments(
+      &self,
+      ctx: &Context<'_>,
+      first: Option<usize>,
+   ) -> GraphqlResult<Vec<TaskComment>> {
+      let first = first
+         .unwrap_or(COMMENTS_DEFAULT_FIRST)
+         .min(COMMENTS_MAX_FIRST);
+      let conn = database(ctx)?.lock().await;
+
+      let mut query = conn.prepare(&format!(
+         "SELECT {} FROM comments WHERE comments.task_id = ? ORDER BY comments.created_at, comments.rowid LIMIT {}",
+         COMMENT_COLUMNS, first
+      ))?;
+
+      let comments = query
+         .query_map([&self.task.id], |row| Comment::from_row(row, 0))?
+         .filter_map(|comment| comment.ok())
+         .map(|mut comment| {
+            comment.user = find_user(&conn, &comment.user_id);
+
+            TaskComment(comment)
+         })
+         .collect();
+
+      Ok(comments)
+   }
+
+   async fn attachments(&self, ctx: &Context<'_>) -> GraphqlResult<Vec<TaskAttachment>> {
+      let conn = database(ctx)?.lock().await;
+
+      let mut query = conn.prepare(&format!(
+         "SELECT {} FROM attachments WHERE attachments.task_id = ? ORDER BY attachments.created_at",
+         ATTACHMENT_COLUMNS
+      ))?;
+
+      let attachments = query
+         .query_map([&self.task.id], |row| Attachment::from_row(row, 0))?
+         .filter_map(|attachment| attachment.ok())
+         .map(TaskAttachment)
+         .collect();
+
+      Ok(attachments)
+   }
+}
+
+pub struct TaskComment(pub Comment);
+
+#[Object]
+impl TaskComment {
+   async fn id(&self) -> ID {
+      ID(self.0.id.clone())
+   }
+
+   async fn body(&self) -> &str {
+      &self.0.body
+   }
+
+   async fn body_html(&self) -> String {
+      render(&self.0.body)
+   }
+
+   async fn author(&self) -> Option<User> {
+      self.0.user.clone().map(User)
+   }
+
+   async fn created_at(&self) -> &str {
+      &self.0.created_at
+   }
+
+   async fn updated_at(&self) -> Option<&str> {
+      self.0.updated_at.as_deref()
+   }
+}
+
+pub struct TaskAttachment(pub Attachment);
+
+#[Object]
+impl TaskAttachment {
+   async fn id(&self) -> ID {
+      ID(self.0.id.clone())
+   }
+
+   async fn filename(&self) -> &str {
+      &self.0.filename
+   }
+
+   async fn content_type(&self) -> &str {
+      &self.0.content_type
+   }
+
+   async fn size(&self) -> i64 {
+      self.0.size
+   }
+
+   /// Where to download the file from.
+   async fn url(&self) -> String {
+      format!("/attachments/{}", self.0.id)
+   }
+
+   async fn created_at(&self) -> &str {
+      &self.0.created_at
+   }
+}
+
+#[derive(SimpleObject)]
+pub struct PageInfo {
+   pub has_next_page: bool,
+   pub end_cursor: Option<String>,
+}
+
+#[derive(SimpleObject)]
+pub struct TaskConnection {
+   pub nodes: Vec<Task>,
+   pub page_info: PageInfo,
+}
+
+#[derive(InputObject, Default)]
+pub struct TaskFilter {
+   pub completed: Option<bool>,
+   /// A user id, or `me`.
+   pub assigned_to: Option<String>,
+   pub label: Option<String>,
+   pub project_id: Option<ID>,
+   pub due_before: Option<String>,
+   pub due_after: Option<String>,
+}
+
+#[derive(InputObject)]
+pub struct CreateTaskInput {
+   pub name: String,
+   pub due_date: Option<String>,
+   pub recurrence: Option<String>,
+   pub project_id: Option<ID>,
+   pub assignee_id: Option<ID>,
+}
+
+/// Fields left out are kept; an empty string clears `dueDate`,
+/// `recurrence`, `projectId` and `assigneeId`, as in `PUT /task/{id}`.
+#[derive(InputObject)]
+pub struct UpdateTaskInput {
+   pub name: Option<String>,
+   pub completed: Option<bool>,
+   pub due_date: Option<String>,
+   pub recurrence: Option<String>,
+   pub project_id: Option<String>,
+   pub assignee_id: Option<String>,
+}
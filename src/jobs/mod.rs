@@ -0,0 +1,153 @@
+use super::config::CONFIG;
+use super::utils::attachments::remove_unreferenced_blobs;
+use super::utils::webhooks::{due_deliveries, record_attempt, send};
+use super::utils::{now, DATETIME_FORMAT};
+
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::time::Duration as StdDuration;
+
+use futures::lock::Mutex;
+
+use chrono::{Duration, Utc};
+
+use rusqlite::Connection;
+
+const WEBHOOK_BATCH_SIZE: i64 = 20;
+
+// The blobs of the attachments that the next purge is going to delete.
+fn purged_blobs(conn: &Connection, cutoff: &str) -> HashSet<String> {
+   let mut query = conn.prepare("SELECT DISTINCT attachments.sha256 FROM attachments INNER JOIN tasks ON tasks.id = attachments.task_id INNER JOIN users ON users.id = tasks.user_id WHERE tasks.deleted_at <= ?1 OR users.deleted_at <= ?1").unwrap();
+   let data = query.query_map([cutoff], |row| row.get(0)).unwrap();
+
+   let mut blobs = HashSet::new();
+   for blob in data {
+      blobs.insert(blob.unwrap());
+   }
+
+   blobs
+}
+
+// Hard deletes the tasks and users that went to the trash before `cutoff`.
+// Deleting a user cascades to its tasks, and deleting a task to its
+// attachments, whose files are removed afterwards.
+fn purge_trash(conn: &Connection, cutoff: &str) -> Result<(), rusqlite::Error> {
+   let blobs = purged_blobs(conn, cutoff);
+
+   let tasks = conn.execute(
+      "DELETE FROM tasks WHERE deleted_at IS NOT NULL AND deleted_at <= ?",
+      [cutoff],
+   );
+   let users = conn.execute(
+      "DELETE FROM users WHERE deleted_at IS NOT NULL AND deleted_at <= ?",
+      [cutoff],
+   );
+
+   remove_unreferenced_blobs(conn, &blobs);
+
+   tasks.and(users).map(|_| ())
+}
+
+// Purges the trash of what is older than the retention period.
+pub fn spawn_trash_purge(conn: Arc<Mutex<Connection>>) {
+   tokio::spawn(async move {
+      let mut interval =
+         tokio::time::interval(StdDuration::from_secs(CONFIG.trash_purge_interval_seconds));
+
+      loop {
+         interval.tick().await;
+
+         let cutoff = Utc::now().naive_utc() - Duration::days(CONFIG.trash_retention_days);
+         let cutoff = format!("{}", cutoff.format(DATETIME_FORMAT));
+
+         if let Err(e) = purge_trash(&*conn.lock().await, &cutoff) {
+            eprintln!("Purge Error: {}", e);
+         }
+      }
+   });
+}
+
+// Sends the webhook deliveries that are due. The connection is released
+// while the requests are in flight.
+pub fn spawn_webhook_deliveries(conn: Arc<Mutex<Connection>>) {
+   tokio::spawn(async move {
+      let mut interval =
+         tokio::time::interval(StdDuration::from_secs(CONFIG.webhook_poll_interval_seconds));
+
+      loop {
+         interval.tick().await;
+
+         let deliveries = {
+            let conn = conn.lock().await;
+
+            due_deliveries(&conn, WEBHOOK_BATCH_SIZE)
+         };
+
+         for delivery in deliveries {
+            let result = send(&delivery).await;
+
+            let conn = conn.lock().await;
+
+            if let Err(e) = record_attempt(&conn, &delivery, result) {
+               eprintln!("Webhook Error: {}", e);
+            }
+         }
+      }
+   });
+}
+
+// Import jobs run inside the server process, so the ones a restart cut
+// short can never finish.
+pub async fn fail_interrupted_imports(conn: Arc<Mutex<Connection>>) {
+   let conn = conn.lock().await;
+
+   let query = conn.execute(
+      "UPDATE import_jobs SET status = 'failed', error = 'interrupted by a restart', finished_at = ? WHERE status IN ('queued', 'running')",
+      [now()],
+   );
+
+   if let Err(e) = query {
+      eprintln!("Import Error: {}", e);
+   }
+}
+
+#[cfg(test)]
+mod tests {
+   use super::super::database::create_memory_connection;
+   use super::*;
+
+   fn exists(conn: &Connection, table: &str, id: &str) -> bool {
+      conn
+         .query_row(
+            &format!("SELECT COUNT(*) FROM {} WHERE id = ?", table),
+            [id],
+            |row| row.get::<_, i64>(0),
+         )
+         .unwrap()
+         > 0
+   }
+
+   #[test]
+   fn purges_what_went_to_the_trash_before_the_cutoff() {
+      let conn = create_memory_connection();
+      conn
+         .execute_batch(
+            "INSERT INTO users (id, firstname, lastname, email, password) VALUES ('alice', 'Alice', 'A', 'alice@x.io', '');
+            INSERT INTO users (id, firstname, lastname, email, password, deleted_at) VALUES ('bob', 'Bob', 'B', 'bob@x.io', '', '2000-01-01 00:00:00');
+            INSERT INTO tasks (id, name, completed, user_id) VALUES ('milk', 'Milk', 0, 'alice');
+            INSERT INTO tasks (id, name, completed, user_id, deleted_at) VALUES ('eggs', 'Eggs', 0, 'alice', '2000-01-01 00:00:00');
+            INSERT INTO tasks (id, name, completed, user_id, deleted_at) VALUES ('bread', 'Bread', 0, 'alice', '2000-03-01 00:00:00');
+            INSERT INTO tasks (id, name, completed, user_id) VALUES ('tea', 'Tea', 0, 'bob');",
+         )
+         .unwrap();
+
+      purge_trash(&conn, "2000-02-01 00:00:00").unwrap();
+
+      assert!(exists(&conn, "users", "alice"));
+      assert!(exists(&conn, "tasks", "milk"));
+      assert!(exists(&conn, "tasks", "bread"));
+      assert!(!exists(&conn, "tasks", "eggs"));
+      assert!(!exists(&conn, "users", "bob"));
+      assert!(!exists(&conn, "tasks", "tea"));
+   }
+}
@@ -1,43 +1,73 @@
 #[macro_use]
 extern crate serde_derive;
 
+mod config;
 mod controllers;
 mod database;
+mod graphql;
+mod jobs;
 mod middlewares;
+mod openapi;
 mod utils;
 mod views;
 
 use std::convert::Infallible;
 use std::io::Error;
-use std::sync::Arc;
-
-use futures::lock::Mutex;
-
-use lazy_static::lazy_static;
-
-use rusqlite::Connection;
 
+use hyper::server::conn::AddrStream;
 use hyper::service::{make_service_fn, service_fn};
 use hyper::{Body, Method, Request, Response, Server};
 
-use utils::{create_error, get_request_info, RequestInfo};
+use config::CONFIG;
+use database::CONNECTION;
+use middlewares::rate_limit::rate_limit;
+use utils::passwords::UNKNOWN_USER_HASH;
+use utils::{create_error, get_request_info, RemoteAddr, RequestInfo};
 
+use controllers::attachments;
+use controllers::calendar;
+use controllers::comments;
+use controllers::docs;
+use controllers::events;
+use controllers::exports;
+use controllers::graphql::execute_graphql;
+use controllers::history;
+use controllers::imports;
+use controllers::labels;
+use controllers::members;
+use controllers::mfa;
+use controllers::passwords;
+use controllers::projects;
 use controllers::tasks;
+use controllers::trash;
 use controllers::users;
-
-lazy_static! {
-   static ref CONNECTION: Arc<Mutex<Connection>> = {
-      let conn = database::create_connection();
-
-      Arc::new(Mutex::new(conn))
-   };
-}
+use controllers::verification;
+use controllers::webhooks;
 
 #[tokio::main]
 async fn main() -> Result<(), Error> {
    let addr = ([127, 0, 0, 1], 3333).into();
 
-   let make_svc = make_service_fn(|_| async { Ok::<_, Infallible>(service_fn(routes)) });
+   // A missing JWT_SECRET or Argon2 params that cannot hash stop the server
+   // here rather than on a request.
+   lazy_static::initialize(&CONFIG);
+   lazy_static::initialize(&UNKNOWN_USER_HASH);
+
+   jobs::fail_interrupted_imports(CONNECTION.clone()).await;
+   jobs::spawn_trash_purge(CONNECTION.clone());
+   jobs::spawn_webhook_deliveries(CONNECTION.clone());
+
+   let make_svc = make_service_fn(|conn: &AddrStream| {
+      let remote_addr = conn.remote_addr();
+
+      async move {
+         Ok::<_, Infallible>(service_fn(move |mut req: Request<Body>| {
+            req.extensions_mut().insert(RemoteAddr(remote_addr));
+
+            rate_limit(req, routes)
+         }))
+      }
+   });
    let server = Server::bind(&addr).serve(make_svc);
 
    if let Err(e) = server.await {
@@ -62,22 +92,169 @@ async fn routes(req: Request<Body>) -> Result<Response<Body>, Infallible> {
          users::list_by_id(req, conn, String::from(path_splited[1].as_str())).await
       }
       (Method::POST, "/register") => users::create_user(req, conn).await,
+      (Method::POST, "/login") if path_splited.len() == 2 && path_splited[1] == "mfa" => {
+         mfa::login_mfa(req, conn).await
+      }
       (Method::POST, "/login") => users::login(req, conn).await,
+      (Method::POST, "/mfa")
+         if path_splited.len() == 3 && path_splited[1] == "totp" && path_splited[2] == "enroll" =>
+      {
+         mfa::enroll_totp(req, conn).await
+      }
+      (Method::POST, "/mfa")
+         if path_splited.len() == 3
+            && path_splited[1] == "totp"
+            && path_splited[2] == "confirm" =>
+      {
+         mfa::confirm_totp(req, conn).await
+      }
+      (Method::POST, "/password") if path_splited.len() == 2 && path_splited[1] == "forgot" => {
+         passwords::forgot_password(req, conn).await
+      }
+      (Method::POST, "/password") if path_splited.len() == 2 && path_splited[1] == "reset" => {
+         passwords::reset_password(req, conn).await
+      }
+      (Method::POST, "/verify-email") if path_splited.len() == 2 && path_splited[1] == "resend" => {
+         verification::resend_verification(req, conn).await
+      }
+      (Method::POST, "/verify-email") => verification::verify_email(req, conn).await,
       (Method::PUT, "/user") if path_splited.len() > 1 => {
          users::update_user(req, conn, path_splited[1].clone()).await
       }
+      (Method::POST, "/user") if path_splited.len() > 2 && path_splited[2] == "unlock" => {
+         users::unlock_user(req, conn, path_splited[1].clone()).await
+      }
+      (Method::POST, "/user") if path_splited.len() > 2 && path_splited[2] == "restore" => {
+         users::restore_user(req, conn, path_splited[1].clone()).await
+      }
       (Method::DELETE, "/user") if path_splited.len() > 1 => {
          users::delete_user(req, conn, path_splited[1].clone()).await
       }
 
+      (Method::GET, "/tasks") if path_splited.len() > 2 && path_splited[2] == "occurrences" => {
+         tasks::list_occurrences(req, conn, path_splited[1].clone()).await
+      }
+      (Method::GET, "/tasks") if path_splited.len() > 2 && path_splited[2] == "history" => {
+         history::task_history(req, conn, path_splited[1].clone()).await
+      }
+      (Method::GET, "/tasks") if path_splited.len() > 2 && path_splited[2] == "attachments" => {
+         attachments::list_attachments(req, conn, path_splited[1].clone()).await
+      }
+      (Method::GET, "/tasks") if path_splited.len() > 2 && path_splited[2] == "comments" => {
+         comments::list_comments(req, conn, path_splited[1].clone()).await
+      }
+      (Method::GET, "/tasks") if path_splited.len() > 2 && path_splited[2] == "members" => {
+         members::list_task_members(req, conn, path_splited[1].clone()).await
+      }
       (Method::GET, "/tasks") => tasks::list_tasks(req, conn).await,
+      (Method::POST, "/tasks") if path_splited.len() > 1 && path_splited[1] == "bulk" => {
+         tasks::bulk_tasks(req, conn).await
+      }
+      (Method::POST, "/tasks") if path_splited.len() > 2 && path_splited[2] == "restore" => {
+         tasks::restore_task(req, conn, path_splited[1].clone()).await
+      }
+      (Method::POST, "/tasks") if path_splited.len() > 2 && path_splited[2] == "attachments" => {
+         attachments::upload_attachments(req, conn, path_splited[1].clone()).await
+      }
+      (Method::POST, "/tasks") if path_splited.len() > 2 && path_splited[2] == "comments" => {
+         comments::create_comment(req, conn, path_splited[1].clone()).await
+      }
+      (Method::POST, "/tasks") if path_splited.len() > 2 && path_splited[2] == "members" => {
+         members::add_task_member(req, conn, path_splited[1].clone()).await
+      }
       (Method::POST, "/tasks") => tasks::create_task(req, conn).await,
+      (Method::DELETE, "/tasks") if path_splited.len() > 3 && path_splited[2] == "members" => {
+         let (task_id, member_id) = (path_splited[1].clone(), path_splited[3].clone());
+
+         members::remove_task_member(req, conn, task_id, member_id).await
+      }
+      (Method::GET, "/task") if path_splited.len() > 1 => {
+         tasks::get_task(req, conn, path_splited[1].clone()).await
+      }
       (Method::PUT, "/task") if path_splited.len() > 1 => {
          tasks::update_task(req, conn, path_splited[1].clone()).await
       }
       (Method::DELETE, "/task") if path_splited.len() > 1 => {
          tasks::delete_task(req, conn, path_splited[1].clone()).await
       }
+
+      (Method::GET, "/attachments") if path_splited.len() > 1 => {
+         attachments::download_attachment(req, conn, path_splited[1].clone()).await
+      }
+      (Method::DELETE, "/attachments") if path_splited.len() > 1 => {
+         attachments::delete_attachment(req, conn, path_splited[1].clone()).await
+      }
+
+      (Method::PUT, "/comments") if path_splited.len() > 1 => {
+         comments::update_comment(req, conn, path_splited[1].clone()).await
+      }
+      (Method::DELETE, "/comments") if path_splited.len() > 1 => {
+         comments::delete_comment(req, conn, path_splited[1].clone()).await
+      }
+
+      (Method::GET, "/projects") if path_splited.len() > 2 && path_splited[2] == "members" => {
+         members::list_project_members(req, conn, path_splited[1].clone()).await
+      }
+      (Method::GET, "/projects") => projects::list_projects(req, conn).await,
+      (Method::POST, "/projects") if path_splited.len() > 2 && path_splited[2] == "members" => {
+         members::add_project_member(req, conn, path_splited[1].clone()).await
+      }
+      (Method::POST, "/projects") => projects::create_project(req, conn).await,
+      (Method::DELETE, "/projects") if path_splited.len() > 3 && path_splited[2] == "members" => {
+         let (project_id, member_id) = (path_splited[1].clone(), path_splited[3].clone());
+
+         members::remove_project_member(req, conn, project_id, member_id).await
+      }
+
+      (Method::GET, "/webhooks") if path_splited.len() > 2 && path_splited[2] == "deliveries" => {
+         webhooks::list_deliveries(req, conn, path_splited[1].clone()).await
+      }
+      (Method::GET, "/webhooks") => webhooks::list_webhooks(req, conn).await,
+      (Method::POST, "/webhooks")
+         if path_splited.len() > 4
+            && path_splited[2] == "deliveries"
+            && path_splited[4] == "retry" =>
+      {
+         let (webhook_id, delivery_id) = (path_splited[1].clone(), path_splited[3].clone());
+
+         webhooks::retry_delivery(req, conn, webhook_id, delivery_id).await
+      }
+      (Method::POST, "/webhooks") => webhooks::create_webhook(req, conn).await,
+      (Method::DELETE, "/webhooks") if path_splited.len() > 1 => {
+         webhooks::delete_webhook(req, conn, path_splited[1].clone()).await
+      }
+
+      (Method::POST, "/calendar") if path_splited.len() == 2 && path_splited[1] == "token" => {
+         calendar::create_calendar_token(req, conn).await
+      }
+      (Method::DELETE, "/calendar") if path_splited.len() == 2 && path_splited[1] == "token" => {
+         calendar::delete_calendar_token(req, conn).await
+      }
+      (Method::GET, "/calendar") if path_splited.len() == 2 => {
+         calendar::calendar_feed(req, conn, path_splited[1].clone()).await
+      }
+      (Method::POST, "/import") if path_splited.len() == 2 && path_splited[1] == "ics" => {
+         calendar::import_calendar(req, conn).await
+      }
+      (Method::POST, "/import") => exports::import_data(req, conn).await,
+      (Method::GET, "/export") => exports::export_data(req, conn).await,
+
+      (Method::GET, "/imports") if path_splited.len() > 1 => {
+         imports::get_import_job(req, conn, path_splited[1].clone()).await
+      }
+      (Method::GET, "/imports") => imports::list_import_jobs(req, conn).await,
+      (Method::POST, "/imports") => imports::create_import_job(req, conn).await,
+
+      (Method::GET, "/labels") => labels::list_labels(req, conn).await,
+
+      (Method::GET, "/graphql") | (Method::POST, "/graphql") => execute_graphql(req, conn).await,
+
+      (Method::GET, "/openapi.json") => docs::openapi_json(req).await,
+      (Method::GET, "/docs") => docs::swagger_ui(req).await,
+
+      (Method::GET, "/trash") => trash::list_trash(req, conn).await,
+      (Method::GET, "/activity") => history::list_activity(req, conn).await,
+      (Method::GET, "/events") => events::stream_events(req, conn).await,
       _ => create_error("this router is not exists", None),
    }
 }
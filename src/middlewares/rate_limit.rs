@@ -0,0 +1,461 @@
+use super::super::config::CONFIG;
+use super::super::utils::{client_ip, create_error};
+use super::users::token_user_id;
+
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::future::Future;
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::Instant;
+
+use lazy_static::lazy_static;
+
+use hyper::header::HeaderValue;
+use hyper::{Body, HeaderMap, Method, Request, Response};
+
+lazy_static! {
+   static ref RATE_LIMITER: RateLimiter<SystemClock> = RateLimiter::new(SystemClock);
+}
+
+// Buckets that have refilled are dropped once there are more than this.
+const BUCKETS_MAX: usize = 10_000;
+
+/// `requests` per `seconds`, written `60/60`. The bucket holds `requests`
+/// tokens, so that many can come at once; `0/…` turns the limit off.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RateLimit {
+   pub requests: u32,
+   pub seconds: u32,
+}
+
+impl RateLimit {
+   fn is_off(&self) -> bool {
+      self.requests == 0 || self.seconds == 0
+   }
+
+   fn per_second(&self) -> f64 {
+      self.requests as f64 / self.seconds as f64
+   }
+
+   fn times(&self, factor: u32) -> RateLimit {
+      RateLimit {
+         requests: self.requests.saturating_mul(factor),
+         seconds: self.seconds,
+      }
+   }
+}
+
+impl FromStr for RateLimit {
+   type Err = ();
+
+   fn from_str(value: &str) -> Result<RateLimit, ()> {
+      let (requests, seconds) = value.split_once('/').ok_or(())?;
+
+      Ok(RateLimit {
+         requests: requests.trim().parse().map_err(|_| ())?,
+         seconds: seconds.trim().parse().map_err(|_| ())?,
+      })
+   }
+}
+
+/// The limits of `CONFIG` a request counts against.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RouteGroup {
+   /// Everything under `/login`, `/register`, `/password` and
+   /// `/verify-email`, whatever follows.
+   Auth,
+   /// Every other `GET` and `HEAD`.
+   Read,
+   /// Every other method.
+   Write,
+}
+
+impl RouteGroup {
+   fn of(method: &Method, path: &str) -> Option<RouteGroup> {
+      let first = path.trim_start_matches('/').split('/').next().unwrap_or("");
+
+      match first {
+         "login" | "register" | "password" | "verify-email" => Some(RouteGroup::Auth),
+         "docs" | "openapi.json" => None,
+         _ if method == Method::GET || method == Method::HEAD => Some(RouteGroup::Read),
+         _ => Some(RouteGroup::Write),
+      }
+   }
+
+   fn name(&self) -> &'static str {
+      match self {
+         RouteGroup::Auth => "auth",
+         RouteGroup::Read => "read",
+         RouteGroup::Write => "write",
+      }
+   }
+
+   fn limit(&self) -> RateLimit {
+      match self {
+         RouteGroup::Auth => CONFIG.rate_limit_auth,
+         RouteGroup::Read => CONFIG.rate_limit_read,
+         RouteGroup::Write => CONFIG.rate_limit_write,
+      }
+   }
+}
+
+/// Where the limiter reads the time, so that tests can move it.
+pub trait Clock: Send + Sync {
+   fn now(&self) -> Instant;
+}
+
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+   fn now(&self) -> Instant {
+      Instant::now()
+   }
+}
+
+struct Bucket {
+   limit: RateLimit,
+   tokens: f64,
+   updated: Instant,
+}
+
+impl Bucket {
+   fn refill(&mut self, now: Instant) {
+      let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+
+      self.tokens =
+         (self.tokens + elapsed * self.limit.per_second()).min(self.limit.requests as f64);
+      self.updated = now;
+   }
+}
+
+/// The answer for one request: whether it may go on, and the state of the
+/// bucket closest to empty, for the `RateLimit-*` headers.
+#[derive(Debug, PartialEq)]
+pub struct Decision {
+   pub allowed: bool,
+   pub limit: u32,
+   pub remaining: u32,
+   /// Seconds until that bucket is full again.
+   pub reset: u64,
+   /// Seconds until a refused request would be let through.
+   pub retry_after: u64,
+}
+
+/// Token buckets kept in memory, one for each route group and user or
+/// address.
+pub struct RateLimiter<C: Clock> {
+   clock: C,
+   buckets: Mutex<HashMap<String, Bucket>>,
+}
+
+impl<C: Clock> RateLimiter<C> {
+   pub fn new(clock: C) -> RateLimiter<C> {
+      RateLimiter {
+         clock,
+         buckets: Mutex::new(HashMap::new()),
+      }
+   }
+
+   /// Takes a token from every bucket of `keys`, or from none of them when
+   /// one is empty. `None` when every limit is off.
+   pub fn check(&self, keys: &[(String, RateLimit)]) -> Option<Decision> {
+      let now = self.clock.now();
+      let mut buckets = self.buckets.lock().unwrap();
+
+      let keys: Vec<&(String, RateLimit)> =
+         keys.iter().filter(|(_, limit)| !limit.is_off()).collect();
+
+      if keys.is_empty() {
+         return None;
+      }
+
+      // A full bucket is the same as none at all.
+      if buckets.len() > BUCKETS_MAX {
+         buckets.retain(|_, bucket| {
+            bucket.refill(now);
+
+            bucket.tokens < bucket.limit.requests as f64
+         });
+      }
+
+      for (key, limit) in &keys {
+         let bucket = buckets.entry(key.clone()).or_insert(Bucket {
+            limit: *limit,
+            tokens: limit.requests as f64,
+            updated: now,
+         });
+
+         // A changed limit applies from now on.
+         bucket.refill(now);
+         bucket.limit = *limit;
+         bucket.tokens = bucket.tokens.min(limit.requests as f64);
+      }
+
+      let allowed = keys.iter().all(|(key, _)| buckets[key].tokens >= 1.0);
+
+      let mut decision: Option<Decision> = None;
+
+      for (key, limit) in &keys {
+         let bucket = buckets.get_mut(key).unwrap();
+
+         if allowed {
+            bucket.tokens -= 1.0;
+         }
+
+         let missing = limit.requests as f64 - bucket.tokens;
+         let candidate = Decision {
+            allowed,
+            limit: limit.requests,
+            remaining: bucket.tokens.max(0.0).floor() as u32,
+            reset: (missing / limit.per_second()).ceil().max(0.0) as u64,
+            retry_after: ((1.0 - bucket.tokens) / limit.per_second()).ceil().max(0.0) as u64,
+         };
+
+         let closer = match &decision {
+            None => true,
+            Some(decision) if allowed => candidate.remaining < decision.remaining,
+            Some(decision) => candidate.retry_after > decision.retry_after,
+         };
+
+         if closer {
+            decision = Some(candidate);
+         }
+      }
+
+      decision
+   }
+}
+
+fn set_headers(headers: &mut HeaderMap, decision: &Decision) {
+   headers.insert("RateLimit-Limit", HeaderValue::from(decision.limit));
+   headers.insert("RateLimit-Remaining", HeaderValue::from(decision.remaining));
+   headers.insert("RateLimit-Reset", HeaderValue::from(decision.reset));
+
+   if !decision.allowed {
+      headers.insert(
+         "Retry-After",
+         HeaderValue::from(decision.retry_after.max(1)),
+      );
+   }
+}
+
+/// Runs `next` when the request is within the limits of its route group,
+/// counted for the user of its token and for its address, which gets
+/// `RATE_LIMIT_IP_FACTOR` times as many requests. Answers 429 otherwise.
+pub async fn rate_limit<F, R>(req: Request<Body>, next: F) -> Result<Response<Body>, Infallible>
+where
+   F: FnOnce(Request<Body>) -> R,
+   R: Future<Output = Result<Response<Body>, Infallible>>,
+{
+   let group = match RouteGroup::of(req.method(), req.uri().path()) {
+      Some(group) => group,
+      None => return next(req).await,
+   };
+
+   let limit = group.limit();
+   let mut keys = vec![];
+
+   if let Some(user_id) = token_user_id(req.headers()) {
+      keys.push((format!("{}:user:{}", group.name(), user_id), limit));
+   }
+
+   if let Some(ip) = client_ip(&req) {
+      keys.push((
+         format!("{}:ip:{}", group.name(), ip),
+         limit.times(CONFIG.rate_limit_ip_factor),
+      ));
+   }
+
+   let decision = match RATE_LIMITER.check(&keys) {
+      Some(decision) => decision,
+      None => return next(req).await,
+   };
+
+   let mut response = if decision.allowed {
+      next(req).await
+   } else {
+      create_error("too many requests", Option::from(429))
+   };
+
+   if let Ok(response) = &mut response {
+      set_headers(response.headers_mut(), &decision);
+   }
+
+   response
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   use std::time::Duration;
+
+   struct MockClock {
+      now: Mutex<Instant>,
+   }
+
+   impl MockClock {
+      fn advance(&self, seconds: u64) {
+         *self.now.lock().unwrap() += Duration::from_secs(seconds);
+      }
+   }
+
+   impl Clock for MockClock {
+      fn now(&self) -> Instant {
+         *self.now.lock().unwrap()
+      }
+   }
+
+   fn limiter() -> RateLimiter<MockClock> {
+      RateLimiter::new(MockClock {
+         now: Mutex::new(Instant::now()),
+      })
+   }
+
+   fn limit(requests: u32, seconds: u32) -> RateLimit {
+      RateLimit { requests, seconds }
+   }
+
+   fn key(name: &str, limit: RateLimit) -> (String, RateLimit) {
+      (String::from(name), limit)
+   }
+
+   #[test]
+   fn parses_limits() {
+      assert_eq!("60/60".parse(), Ok(limit(60, 60)));
+      assert_eq!(" 5 / 1 ".parse(), Ok(limit(5, 1)));
+      assert!("60".parse::<RateLimit>().is_err());
+      assert!("a/60".parse::<RateLimit>().is_err());
+   }
+
+   #[test]
+   fn refuses_once_the_bucket_is_empty() {
+      let limiter = limiter();
+      let keys = [key("user:a", limit(3, 60))];
+
+      for remaining in (0..3).rev() {
+         let decision = limiter.check(&keys).unwrap();
+
+         assert!(decision.allowed);
+         assert_eq!(decision.remaining, remaining);
+      }
+
+      let decision = limiter.check(&keys).unwrap();
+
+      assert!(!decision.allowed);
+      assert_eq!(decision.remaining, 0);
+      assert_eq!(decision.retry_after, 20);
+      assert_eq!(decision.reset, 60);
+   }
+
+   #[test]
+   fn refills_with_time() {
+      let limiter = limiter();
+      let keys = [key("user:a", limit(2, 10))];
+
+      limiter.check(&keys);
+      limiter.check(&keys);
+      assert!(!limiter.check(&keys).unwrap().allowed);
+
+      limiter.clock.advance(4);
+      assert!(!limiter.check(&keys).unwrap().allowed);
+
+      limiter.clock.advance(1);
+      assert!(limiter.check(&keys).unwrap().allowed);
+      assert!(!limiter.check(&keys).unwrap().allowed);
+
+      // Never more than the bucket holds.
+      limiter.clock.advance(3600);
+      assert_eq!(limiter.check(&keys).unwrap().remaining, 1);
+   }
+
+   #[test]
+   fn counts_users_apart() {
+      let limiter = limiter();
+
+      assert!(
+         limiter
+            .check(&[key("user:a", limit(1, 60))])
+            .unwrap()
+            .allowed
+      );
+      assert!(
+         !limiter
+            .check(&[key("user:a", limit(1, 60))])
+            .unwrap()
+            .allowed
+      );
+      assert!(
+         limiter
+            .check(&[key("user:b", limit(1, 60))])
+            .unwrap()
+            .allowed
+      );
+   }
+
+   #[test]
+   fn takes_nothing_when_one_bucket_is_empty() {
+      let limiter = limiter();
+      let user = key("user:a", limit(1, 60));
+      let ip = key("ip:127.0.0.1", limit(4, 60));
+
+      assert!(limiter.check(&[user.clone(), ip.clone()]).unwrap().allowed);
+
+      // The address is not charged for the requests the user is refused.
+      for _ in 0..5 {
+         let decision = limiter.check(&[user.clone(), ip.clone()]).unwrap();
+
+         assert!(!decision.allowed);
+         assert_eq!(decision.limit, 1);
+      }
+
+      let decision = limiter.check(&[ip]).unwrap();
+
+      assert!(decision.allowed);
+      assert_eq!(decision.remaining, 2);
+   }
+
+   #[test]
+   fn ignores_limits_that_are_off() {
+      let limiter = limiter();
+
+      assert_eq!(limiter.check(&[key("user:a", limit(0, 60))]), None);
+   }
+
+   #[test]
+   fn groups_routes() {
+      assert_eq!(
+         RouteGroup::of(&Method::POST, "/login"),
+         Some(RouteGroup::Auth)
+      );
+      for path in [
+         "/login/mfa",
+         "/register",
+         "/password/forgot",
+         "/password/reset",
+         "/verify-email",
+         "/verify-email/resend",
+      ] {
+         assert_eq!(
+            RouteGroup::of(&Method::POST, path),
+            Some(RouteGroup::Auth),
+            "{}",
+            path
+         );
+      }
+      assert_eq!(
+         RouteGroup::of(&Method::GET, "/tasks"),
+         Some(RouteGroup::Read)
+      );
+      assert_eq!(
+         RouteGroup::of(&Method::POST, "/logins"),
+         Some(RouteGroup::Write)
+      );
+      assert_eq!(
+         RouteGroup::of(&Method::PATCH, "/tasks/1"),
+         Some(RouteGroup::Write)
+      );
+      assert_eq!(RouteGroup::of(&Method::GET, "/docs"), None);
+   }
+}
@@ -1,9 +1,16 @@
+use super::super::config::CONFIG;
 use super::super::utils::create_error;
 
 use std::convert::Infallible;
+use std::sync::Arc;
 
-use chrono::{DateTime, Local, TimeZone, Utc};
-use jsonwebtokens::{error::Error as JWTError, raw, raw::TokenSlices};
+use futures::lock::Mutex;
+
+use chrono::{DateTime, Local, NaiveDateTime, TimeZone, Utc};
+use jsonwebtokens::error::{Error as JWTError, ErrorDetails};
+use jsonwebtokens::{raw, raw::TokenSlices, Algorithm, AlgorithmID};
+
+use rusqlite::{Connection, OptionalExtension};
 
 use hyper::{Body, HeaderMap, Response};
 
@@ -13,6 +20,38 @@ use serde_json::from_value;
 pub struct JWTData {
    pub id: String,
    pub expires: String,
+   /// The `session_version` of the user when the token was made. Tokens
+   /// from before sessions could be revoked have none, which counts as 0.
+   #[serde(default)]
+   pub session: i64,
+   /// Set on the tokens of `POST /login` for users with two-factor
+   /// authentication, which only `POST /login/mfa` accepts.
+   #[serde(default)]
+   pub mfa_pending: bool,
+}
+
+pub const EXPIRES_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+
+pub fn jwt_algorithm() -> Result<Algorithm, JWTError> {
+   Algorithm::new_hmac(AlgorithmID::HS256, CONFIG.jwt_secret.as_str())
+}
+
+// The claims of a token signed with `JWT_SECRET`. The header is not read:
+// tokens were made with an empty one.
+fn decode_token(token: &str) -> Result<JWTData, JWTError> {
+   let TokenSlices {
+      message,
+      signature,
+      claims,
+      ..
+   } = raw::split_token(token)?;
+
+   jwt_algorithm()?.verify(None, message, signature)?;
+
+   let claims = raw::decode_json_token_slice(claims)?;
+
+   from_value::<JWTData>(claims)
+      .map_err(|e| JWTError::MalformedToken(ErrorDetails::new(e.to_string())))
 }
 
 pub enum ValidResponse {
@@ -20,7 +59,27 @@ pub enum ValidResponse {
    Respo(Result<Response<Body>, Infallible>),
 }
 
-pub async fn valid_user(headers: &HeaderMap) -> Result<ValidResponse, JWTError> {
+pub async fn valid_user(
+   headers: &HeaderMap,
+   conn: &Arc<Mutex<Connection>>,
+) -> Result<ValidResponse, JWTError> {
+   authenticate(headers, conn, false).await
+}
+
+/// Like `valid_user`, but also accepts the tokens of users in the trash, for
+/// the route that takes them out of it.
+pub async fn valid_trashed_user(
+   headers: &HeaderMap,
+   conn: &Arc<Mutex<Connection>>,
+) -> Result<ValidResponse, JWTError> {
+   authenticate(headers, conn, true).await
+}
+
+async fn authenticate(
+   headers: &HeaderMap,
+   conn: &Arc<Mutex<Connection>>,
+   trashed: bool,
+) -> Result<ValidResponse, JWTError> {
    let authorization = headers.get("authorization");
 
    match authorization {
@@ -41,35 +100,94 @@ pub async fn valid_user(headers: &HeaderMap) -> Result<ValidResponse, JWTError>
                )));
             }
 
-            let TokenSlices { claims, .. } = raw::split_token(splited[1])?;
-            let claims = raw::decode_json_token_slice(claims)?;
+            let jwtdata = match decode_token(splited[1]) {
+               Ok(jwtdata) => jwtdata,
+               Err(JWTError::InvalidSignature()) => {
+                  return Ok(ValidResponse::Respo(create_error(
+                     "token is not valid",
+                     Option::from(401),
+                  )))
+               }
+               Err(e) => return Err(e),
+            };
 
-            let jwtdata = from_value::<JWTData>(claims).unwrap();
+            if jwtdata.mfa_pending {
+               return Ok(ValidResponse::Respo(create_error(
+                  "two-factor authentication is not complete",
+                  Option::from(401),
+               )));
+            }
 
             let utc = Utc::now();
             let timezone = utc.timezone();
             let converted: DateTime<Local> = DateTime::from(utc);
             let expires = jwtdata.expires.as_str();
 
-            let diff = converted.signed_duration_since(
-               timezone
-                  .datetime_from_str(expires, "%Y-%m-%d %H:%M:%S")
-                  .unwrap(),
-            );
+            let diff = converted
+               .signed_duration_since(timezone.datetime_from_str(expires, EXPIRES_FORMAT).unwrap());
 
             if diff.num_days() >= 1 {
                return Err(JWTError::TokenExpiredAt(13));
             }
 
+            // A password reset bumps the version, so that older tokens
+            // stop working.
+            let user: Option<(i64, bool)> = conn
+               .lock()
+               .await
+               .query_row(
+                  "SELECT session_version, deleted_at IS NOT NULL FROM users WHERE id = ?",
+                  [&jwtdata.id],
+                  |row| Ok((row.get(0)?, row.get(1)?)),
+               )
+               .optional()
+               .unwrap_or(None);
+
+            match user {
+               Some((session, _)) if session != jwtdata.session => {
+                  return Ok(ValidResponse::Respo(create_error(
+                     "this session was revoked",
+                     Option::from(401),
+                  )))
+               }
+               Some((_, deleted)) if !deleted || trashed => {}
+               _ => {
+                  return Ok(ValidResponse::Respo(create_error(
+                     "token is not valid",
+                     Option::from(401),
+                  )))
+               }
+            }
+
             Ok(ValidResponse::Id(jwtdata.id))
          }
          _ => Ok(ValidResponse::Respo(create_error("", None))),
       },
-      _ => {
-         return Ok(ValidResponse::Respo(create_error(
-            "token is necessary",
-            None,
-         )))
-      }
+      _ => Ok(ValidResponse::Respo(create_error(
+         "token is necessary",
+         None,
+      ))),
+   }
+}
+
+/// The user id of a validly signed bearer token, without the other checks of
+/// `valid_user`, for the middlewares that only need to tell callers apart.
+pub fn token_user_id(headers: &HeaderMap) -> Option<String> {
+   let bearer = headers.get("authorization")?.to_str().ok()?;
+   let token = bearer.split(' ').nth(1)?;
+
+   decode_token(token).ok().map(|jwtdata| jwtdata.id)
+}
+
+/// The claims of an unexpired token of `POST /login` that awaits its second
+/// factor.
+pub fn mfa_pending_claims(token: &str) -> Option<JWTData> {
+   let jwtdata = decode_token(token.trim()).ok()?;
+   let expires = NaiveDateTime::parse_from_str(&jwtdata.expires, EXPIRES_FORMAT).ok()?;
+
+   if !jwtdata.mfa_pending || expires <= Local::now().naive_local() {
+      return None;
    }
+
+   Some(jwtdata)
 }
@@ -0,0 +1,327 @@
+use super::controllers::{
+   attachments, calendar, comments, docs, events, exports, graphql, history, imports, labels,
+   members, mfa, passwords, projects, tasks, trash, users, verification, webhooks,
+};
+
+use lazy_static::lazy_static;
+
+use utoipa::openapi::security::{HttpAuthScheme, HttpBuilder, SecurityScheme};
+use utoipa::{Modify, OpenApi};
+
+/// The OpenAPI document of every route, built from the `#[utoipa::path]`
+/// attribute of each handler and the request and view structs they name.
+#[derive(OpenApi)]
+#[openapi(
+   paths(
+      users::list_all_users,
+      users::list_by_id,
+      users::create_user,
+      users::login,
+      mfa::login_mfa,
+      mfa::enroll_totp,
+      mfa::confirm_totp,
+      users::update_user,
+      users::delete_user,
+      users::restore_user,
+      users::unlock_user,
+      verification::verify_email,
+      verification::resend_verification,
+      passwords::forgot_password,
+      passwords::reset_password,
+      tasks::list_tasks,
+      tasks::get_task,
+      tasks::create_task,
+      tasks::update_task,
+      tasks::delete_task,
+      tasks::bulk_tasks,
+      tasks::list_occurrences,
+      tasks::restore_task,
+      history::task_history,
+      history::list_activity,
+      attachments::list_attachments,
+      attachments::upload_attachments,
+      attachments::download_attachment,
+      attachments::delete_attachment,
+      comments::list_comments,
+      comments::create_comment,
+      comments::update_comment,
+      comments::delete_comment,
+      members::list_task_members,
+      members::add_task_member,
+      members::remove_task_member,
+      members::list_project_members,
+      members::add_project_member,
+      members::remove_project_member,
+      projects::list_projects,
+      projects::create_project,
+      webhooks::list_webhooks,
+      webhooks::create_webhook,
+      webhooks::delete_webhook,
+      webhooks::list_deliveries,
+      webhooks::retry_delivery,
+      calendar::create_calendar_token,
+      calendar::delete_calendar_token,
+      calendar::calendar_feed,
+      calendar::import_calendar,
+      exports::export_data,
+      exports::import_data,
+      imports::create_import_job,
+      imports::list_import_jobs,
+      imports::get_import_job,
+      labels::list_labels,
+      graphql::execute_graphql,
+      trash::list_trash,
+      events::stream_events,
+      docs::openapi_json,
+      docs::swagger_ui,
+   ),
+   modifiers(&BearerToken)
+)]
+pub struct ApiDoc;
+
+// The token returned by `POST /login`, sent as `Authorization: Bearer`.
+struct BearerToken;
+
+impl Modify for BearerToken {
+   fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+      let components = openapi.components.get_or_insert_with(Default::default);
+
+      components.add_security_scheme(
+         "token",
+         SecurityScheme::Http(
+            HttpBuilder::new()
+               .scheme(HttpAuthScheme::Bearer)
+               .bearer_format("JWT")
+               .build(),
+         ),
+      );
+   }
+}
+
+lazy_static! {
+   pub static ref OPENAPI_JSON: String = ApiDoc::openapi().to_json().unwrap();
+}
+
+#[cfg(test)]
+mod tests {
+   use super::ApiDoc;
+
+   use std::collections::BTreeSet;
+
+   use utoipa::OpenApi;
+
+   type Route = (String, String);
+
+   // Writes every variable segment as `{}` so the router and the spec agree
+   // on paths whatever the parameters are called.
+   fn route(method: &str, segments: &[&str]) -> Route {
+      let path: Vec<&str> = segments
+         .iter()
+         .map(|segment| if segment.contains('{') { "{}" } else { segment })
+         .collect();
+
+      (method.to_uppercase(), format!("/{}", path.join("/")))
+   }
+
+   // The routes of `routes()` in main.rs: the method and first segment of
+   // each arm, and the length and fixed segments its guard asks for.
+   fn served_routes() -> BTreeSet<Route> {
+      let source = include_str!("main.rs");
+      let start = source
+         .find("async fn routes(")
+         .expect("main.rs has no `async fn routes(`");
+      let end = source[start..]
+         .find("_ => create_error(")
+         .expect("routes() has no `_ => create_error(` fallback arm")
+         + start;
+      let source = &source[start..end];
+
+      let mut routes = BTreeSet::new();
+
+      for (index, _) in source.match_indices("(Method::") {
+         let arm = &source[index..];
+         let line = arm.lines().next().unwrap_or(arm);
+         let unreadable = |what: &str| format!("cannot read the {} of the arm `{}`", what, line);
+
+         let tuple = &arm[..arm
+            .find(')')
+            .unwrap_or_else(|| panic!("{}", unreadable("tuple")))];
+         let method = tuple["(Method::".len()..]
+            .split(',')
+            .next()
+            .unwrap_or_else(|| panic!("{}", unreadable("method")));
+         let first = tuple
+            .split('"')
+            .nth(1)
+            .unwrap_or_else(|| panic!("{}", unreadable("first segment")));
+
+         let guard = &arm[tuple.len() + 1
+            ..arm
+               .find("=>")
+               .unwrap_or_else(|| panic!("{}", unreadable("guard")))];
+         let guard = guard.split(" if ").nth(1).unwrap_or("");
+
+         let mut length = 1;
+         if let Some(rest) = guard.split("path_splited.len() ").nth(1) {
+            let mut parts = rest.split_whitespace();
+            let operator = parts.next().unwrap_or("");
+            let number: usize = parts
+               .next()
+               .map(|number| number.trim_end_matches(|c: char| !c.is_ascii_digit()))
+               .and_then(|number| number.parse().ok())
+               .unwrap_or_else(|| panic!("{}", unreadable("path length")));
+
+            length = if operator == ">" { number + 1 } else { number };
+         }
+
+         let mut segments = vec!["{}"; length];
+         segments[0] = first.trim_start_matches('/');
+
+         for condition in guard.split("path_splited[").skip(1) {
+            if let Some((position, literal)) = condition.split_once("] == \"") {
+               let position = position
+                  .parse::<usize>()
+                  .ok()
+                  .filter(|position| *position < length)
+                  .unwrap_or_else(|| panic!("{}", unreadable("segment index")));
+               let literal = literal
+                  .split('"')
+                  .next()
+                  .unwrap_or_else(|| panic!("{}", unreadable("segment")));
+
+               segments[position] = literal;
+            }
+         }
+
+         routes.insert(route(method, &segments));
+      }
+
+      routes
+   }
+
+   fn documented_routes() -> BTreeSet<Route> {
+      let mut routes = BTreeSet::new();
+
+      for (path, item) in ApiDoc::openapi().paths.paths {
+         let segments: Vec<&str> = path.trim_start_matches('/').split('/').collect();
+         let operations = [
+            ("get", &item.get),
+            ("post", &item.post),
+            ("put", &item.put),
+            ("patch", &item.patch),
+            ("delete", &item.delete),
+         ];
+
+         for (method, operation) in operations {
+            if operation.is_some() {
+               routes.insert(route(method, &segments));
+            }
+         }
+      }
+
+      routes
+   }
+
+   // The source of every controller with a documented handler.
+   const CONTROLLERS: [(&str, &str); 19] = [
+      ("attachments", include_str!("controllers/attachments.rs")),
+      ("calendar", include_str!("controllers/calendar.rs")),
+      ("comments", include_str!("controllers/comments.rs")),
+      ("docs", include_str!("controllers/docs.rs")),
+      ("events", include_str!("controllers/events.rs")),
+      ("exports", include_str!("controllers/exports.rs")),
+      ("graphql", include_str!("controllers/graphql.rs")),
+      ("history", include_str!("controllers/history.rs")),
+      ("imports", include_str!("controllers/imports.rs")),
+      ("labels", include_str!("controllers/labels.rs")),
+      ("members", include_str!("controllers/members.rs")),
+      ("mfa", include_str!("controllers/mfa.rs")),
+      ("passwords", include_str!("controllers/passwords.rs")),
+      ("projects", include_str!("controllers/projects.rs")),
+      ("tasks", include_str!("controllers/tasks.rs")),
+      ("trash", include_str!("controllers/trash.rs")),
+      ("users", include_str!("controllers/users.rs")),
+      ("verification", include_str!("controllers/verification.rs")),
+      ("webhooks", include_str!("controllers/webhooks.rs")),
+   ];
+
+   // The 2xx statuses a controller answers with, from its `.status(...)`
+   // calls and the `let status = ...` they are given.
+   fn returned_statuses(source: &str) -> BTreeSet<String> {
+      source
+         .lines()
+         .filter(|line| line.contains(".status(") || line.contains("let status ="))
+         .flat_map(|line| line.split(|c: char| !c.is_ascii_digit()))
+         .filter(|number| number.len() == 3 && number.starts_with('2'))
+         .map(String::from)
+         .collect()
+   }
+
+   // The 2xx statuses documented for the handlers of a controller.
+   fn documented_statuses(source: &str) -> BTreeSet<String> {
+      let mut statuses = BTreeSet::new();
+
+      for (_, item) in ApiDoc::openapi().paths.paths {
+         let operations = [item.get, item.post, item.put, item.patch, item.delete];
+
+         for operation in operations.iter().flatten() {
+            let handler = match &operation.operation_id {
+               Some(id) => format!("pub async fn {}(", id),
+               None => continue,
+            };
+
+            if source.contains(&handler) {
+               statuses.extend(
+                  operation
+                     .responses
+                     .responses
+                     .keys()
+                     .filter(|status| status.starts_with('2'))
+                     .cloned(),
+               );
+            }
+         }
+      }
+
+      statuses
+   }
+
+   #[test]
+   fn spec_documents_success_statuses() {
+      for (name, source) in CONTROLLERS {
+         let documented = documented_statuses(source);
+         let missing: Vec<String> = returned_statuses(source)
+            .difference(&documented)
+            .cloned()
+            .collect();
+
+         assert!(
+            missing.is_empty(),
+            "controllers/{}.rs answers with {:?}, which its handlers do not document",
+            name,
+            missing
+         );
+      }
+   }
+
+   #[test]
+   fn spec_matches_routes() {
+      let served = served_routes();
+      let documented = documented_routes();
+
+      let undocumented: Vec<&Route> = served.difference(&documented).collect();
+      let unserved: Vec<&Route> = documented.difference(&served).collect();
+
+      assert!(served.len() > 50, "main.rs could not be read: {:?}", served);
+      assert!(
+         undocumented.is_empty(),
+         "routes missing from the spec: {:?}",
+         undocumented
+      );
+      assert!(
+         unserved.is_empty(),
+         "spec paths without a route: {:?}",
+         unserved
+      );
+   }
+}
@@ -0,0 +1,172 @@
+use super::super::config::CONFIG;
+
+use std::collections::HashSet;
+use std::io::{Error as IoError, ErrorKind};
+use std::path::{Path, PathBuf};
+
+use hyper::body::Bytes;
+use hyper::Body;
+
+use futures::stream;
+
+use rusqlite::Connection;
+
+use sha2::{Digest, Sha256};
+
+use tokio::fs::{self, File};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+use uuid::Uuid;
+
+const DOWNLOAD_CHUNK_SIZE: usize = 64 * 1024;
+
+pub enum StoreError {
+   TooLarge,
+   Upload,
+   Io(IoError),
+}
+
+pub struct StoredBlob {
+   pub sha256: String,
+   pub size: u64,
+   tmp_path: PathBuf,
+}
+
+/// Blobs are named after their SHA-256 and fanned out by its first two hex
+/// digits, so identical uploads share one file on disk.
+pub fn blob_path(sha256: &str) -> PathBuf {
+   PathBuf::from(&CONFIG.attachments_dir)
+      .join(&sha256[..2])
+      .join(sha256)
+}
+
+/// Streams an uploaded file to a temporary file while hashing it, failing
+/// once it grows past `ATTACHMENT_MAX_BYTES`. `place_blob` moves it to its
+/// content address.
+pub async fn store_field(field: &mut multer::Field<'_>) -> Result<StoredBlob, StoreError> {
+   let tmp_dir = PathBuf::from(&CONFIG.attachments_dir).join("tmp");
+   fs::create_dir_all(&tmp_dir).await.map_err(StoreError::Io)?;
+
+   let tmp_path = tmp_dir.join(Uuid::new_v4().to_string());
+   let result = write_field(field, &tmp_path).await;
+
+   match result {
+      Ok((sha256, size)) => Ok(StoredBlob {
+         sha256,
+         size,
+         tmp_path,
+      }),
+      Err(error) => {
+         let _ = fs::remove_file(&tmp_path).await;
+
+         Err(error)
+      }
+   }
+}
+
+/// Moves an uploaded file to its content address, or drops it when the blob
+/// is already there. Call it with the connection locked and write the
+/// attachment rows before releasing it, so that `remove_unreferenced_blobs`
+/// cannot remove the blob in between.
+pub fn place_blob(blob: &StoredBlob) -> Result<(), IoError> {
+   let path = blob_path(&blob.sha256);
+
+   if path.exists() {
+      return std::fs::remove_file(&blob.tmp_path);
+   }
+
+   match path.parent() {
+      Some(parent) => std::fs::create_dir_all(parent)?,
+      None => return Err(IoError::from(ErrorKind::NotFound)),
+   }
+
+   std::fs::rename(&blob.tmp_path, &path)
+}
+
+/// Removes the temporary files of uploads that were not placed.
+pub async fn discard_blobs(blobs: Vec<StoredBlob>) {
+   for blob in blobs {
+      if let Err(e) = fs::remove_file(&blob.tmp_path).await {
+         if e.kind() != ErrorKind::NotFound {
+            eprintln!("Attachment Error: {}", e);
+         }
+      }
+   }
+}
+
+async fn write_field(
+   field: &mut multer::Field<'_>,
+   tmp_path: &Path,
+) -> Result<(String, u64), StoreError> {
+   let mut file = File::create(tmp_path).await.map_err(StoreError::Io)?;
+   let mut hasher = Sha256::new();
+   let mut size: u64 = 0;
+
+   loop {
+      let chunk = match field.chunk().await {
+         Ok(Some(chunk)) => chunk,
+         Ok(None) => break,
+         Err(_) => return Err(StoreError::Upload),
+      };
+
+      size += chunk.len() as u64;
+
+      if size > CONFIG.attachment_max_bytes {
+         return Err(StoreError::TooLarge);
+      }
+
+      hasher.update(&chunk);
+      file.write_all(&chunk).await.map_err(StoreError::Io)?;
+   }
+
+   file.flush().await.map_err(StoreError::Io)?;
+
+   Ok((format!("{:x}", hasher.finalize()), size))
+}
+
+/// A body that reads the blob from disk in chunks instead of loading it.
+pub async fn blob_body(sha256: &str) -> Result<Body, IoError> {
+   let file = File::open(blob_path(sha256)).await?;
+
+   let chunks = stream::unfold(file, |mut file| async move {
+      let mut buffer = vec![0; DOWNLOAD_CHUNK_SIZE];
+
+      match file.read(&mut buffer).await {
+         Ok(0) => None,
+         Ok(read) => {
+            buffer.truncate(read);
+
+            Some((Ok::<_, IoError>(Bytes::from(buffer)), file))
+         }
+         Err(error) => Some((Err(error), file)),
+      }
+   });
+
+   Ok(Body::wrap_stream(chunks))
+}
+
+/// Removes the blobs in `candidates` that no attachment points to anymore.
+/// Call it after deleting attachment rows and before releasing the
+/// connection: uploads place blobs under the same lock, so none can start
+/// using a blob between the check and the removal.
+pub fn remove_unreferenced_blobs(conn: &Connection, candidates: &HashSet<String>) {
+   for sha256 in candidates {
+      let unreferenced = conn
+         .query_row(
+            "SELECT COUNT(*) FROM attachments WHERE sha256 = ?",
+            [sha256],
+            |row| row.get::<_, i64>(0),
+         )
+         .is_ok_and(|references| references == 0);
+
+      if !unreferenced {
+         continue;
+      }
+
+      if let Err(e) = std::fs::remove_file(blob_path(sha256)) {
+         if e.kind() != ErrorKind::NotFound {
+            eprintln!("Attachment Error: {}", e);
+         }
+      }
+   }
+}
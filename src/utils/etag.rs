@@ -0,0 +1,109 @@
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+
+use hyper::HeaderMap;
+
+pub fn task_etag(version: i64) -> String {
+   format!("\"v{}\"", version)
+}
+
+// A weak validator for a whole representation, such as the task list.
+pub fn body_etag(body: &str) -> String {
+   let mut hasher = DefaultHasher::new();
+   body.hash(&mut hasher);
+
+   format!("W/\"{:016x}\"", hasher.finish())
+}
+
+/// Whether any entity tag listed in `header` matches `etag`. `If-Match`
+/// uses the strong comparison, where weak tags never match, and
+/// `If-None-Match` the weak one (RFC 7232, section 2.3.2).
+pub fn etag_matches(headers: &HeaderMap, header: &str, etag: &str, weak: bool) -> Option<bool> {
+   let values: Vec<&str> = headers
+      .get_all(header)
+      .iter()
+      .filter_map(|value| value.to_str().ok())
+      .collect();
+
+   if values.is_empty() {
+      return None;
+   }
+
+   let opaque = |tag: &str| String::from(tag.trim().trim_start_matches("W/"));
+
+   let matches = values
+      .iter()
+      .flat_map(|value| value.split(','))
+      .map(|tag| tag.trim())
+      .any(|tag| {
+         if tag == "*" {
+            return true;
+         }
+
+         if weak {
+            opaque(tag) == opaque(etag)
+         } else {
+            !tag.starts_with("W/") && !etag.starts_with("W/") && tag == etag
+         }
+      });
+
+   Option::from(matches)
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   use hyper::header::{HeaderValue, IF_MATCH, IF_NONE_MATCH};
+
+   fn headers(name: hyper::header::HeaderName, values: &[&str]) -> HeaderMap {
+      let mut headers = HeaderMap::new();
+
+      for value in values {
+         headers.append(name.clone(), HeaderValue::from_str(value).unwrap());
+      }
+
+      headers
+   }
+
+   #[test]
+   fn tags_versions_and_bodies() {
+      assert_eq!(task_etag(3), "\"v3\"");
+      assert_eq!(body_etag("[]"), body_etag("[]"));
+      assert_ne!(body_etag("[]"), body_etag("[{}]"));
+      assert!(body_etag("[]").starts_with("W/\""));
+   }
+
+   #[test]
+   fn compares_if_match_strongly() {
+      let etag = task_etag(2);
+      let matches =
+         |values: &[&str]| etag_matches(&headers(IF_MATCH, values), "if-match", &etag, false);
+
+      assert_eq!(matches(&[]), None);
+      assert_eq!(matches(&["\"v2\""]), Some(true));
+      assert_eq!(matches(&["\"v1\""]), Some(false));
+      assert_eq!(matches(&["W/\"v2\""]), Some(false));
+      assert_eq!(matches(&["\"v1\", \"v2\""]), Some(true));
+      assert_eq!(matches(&["\"v1\"", "\"v2\""]), Some(true));
+      assert_eq!(matches(&["*"]), Some(true));
+   }
+
+   #[test]
+   fn compares_if_none_match_weakly() {
+      let etag = body_etag("[]");
+      let opaque = etag.trim_start_matches("W/");
+      let matches = |values: &[&str]| {
+         etag_matches(
+            &headers(IF_NONE_MATCH, values),
+            "if-none-match",
+            &etag,
+            true,
+         )
+      };
+
+      assert_eq!(matches(&[&etag]), Some(true));
+      assert_eq!(matches(&[opaque]), Some(true));
+      assert_eq!(matches(&["W/\"0\""]), Some(false));
+   }
+}
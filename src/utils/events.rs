@@ -0,0 +1,104 @@
+use super::super::views::history::TaskEvent;
+use super::history::TASK_EVENT_COLUMNS;
+
+use std::sync::Arc;
+
+use lazy_static::lazy_static;
+
+use rusqlite::{params, Connection};
+
+use tokio::sync::broadcast;
+
+const EVENTS_CHANNEL_CAPACITY: usize = 256;
+
+lazy_static! {
+   // Only carries the ids of new events and who may see them: streams read
+   // the events themselves from `task_events`, which they can only do once
+   // the transaction that wrote them released the connection.
+   static ref EVENTS: broadcast::Sender<Published> = broadcast::channel(EVENTS_CHANNEL_CAPACITY).0;
+}
+
+#[derive(Clone)]
+pub struct Published {
+   pub id: i64,
+   pub audience: Arc<Vec<String>>,
+}
+
+/// The stream wake-ups for the events a transaction recorded, to send once
+/// it committed: streams woken earlier would not find the events yet, nor
+/// ever when the transaction rolls back. Dropping them sends nothing.
+#[derive(Default)]
+#[must_use = "send the wake-ups once the transaction committed"]
+pub struct Wakeups(Vec<Published>);
+
+impl Wakeups {
+   pub fn new(event_id: i64, audience: Vec<String>) -> Wakeups {
+      Wakeups(vec![Published {
+         id: event_id,
+         audience: Arc::new(audience),
+      }])
+   }
+
+   pub fn add(&mut self, other: Wakeups) {
+      self.0.extend(other.0);
+   }
+
+   pub fn send(self) {
+      for published in self.0 {
+         // Sending only fails when nobody is listening.
+         let _ = EVENTS.send(published);
+      }
+   }
+}
+
+pub fn subscribe() -> broadcast::Receiver<Published> {
+   EVENTS.subscribe()
+}
+
+/// Stores who may stream an event, computed when it is recorded, so that
+/// streams do not work it out again for each of their users.
+pub fn record_audience(
+   conn: &Connection,
+   event_id: i64,
+   audience: &[String],
+) -> Result<(), rusqlite::Error> {
+   for user_id in audience {
+      conn.execute(
+         "INSERT OR IGNORE INTO task_event_audience (event_id, user_id) VALUES (?, ?)",
+         params![event_id, user_id],
+      )?;
+   }
+
+   Ok(())
+}
+
+pub fn latest_event_id(conn: &Connection) -> i64 {
+   conn
+      .query_row("SELECT COALESCE(MAX(id), 0) FROM task_events", [], |row| {
+         row.get(0)
+      })
+      .unwrap_or(0)
+}
+
+/// The task events after `after` that `user_id` could see when they were
+/// recorded, oldest first.
+pub fn visible_task_events(
+   conn: &Connection,
+   user_id: &str,
+   after: i64,
+   limit: i64,
+) -> Vec<TaskEvent> {
+   let mut query = conn
+      .prepare(&format!(
+         "SELECT {} FROM task_events INNER JOIN task_event_audience ON task_event_audience.event_id = task_events.id WHERE task_event_audience.user_id = ? AND task_events.id > ? ORDER BY task_events.id ASC LIMIT ?",
+         TASK_EVENT_COLUMNS
+      ))
+      .unwrap();
+   let data = query
+      .query_map(params![user_id, after, limit], |row| {
+         TaskEvent::from_row(row, 0)
+      })
+      .unwrap();
+
+   data.map(|event| event.unwrap()).collect()
+}
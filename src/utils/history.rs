@@ -0,0 +1,126 @@
+use super::super::views::history::TaskEvent;
+use super::super::views::tasks::TaskCreated;
+use super::super::views::users::CreatedUser;
+use super::events::{record_audience, Wakeups};
+use super::now;
+use super::permissions::task_audience;
+use super::webhooks::enqueue_deliveries;
+
+use rusqlite::{params, Connection};
+
+use serde_json::{json, Map, Value};
+
+pub const TASK_EVENT_COLUMNS: &str = "task_events.id, task_events.entity, task_events.entity_id, task_events.user_id, task_events.actor_id, task_events.action, task_events.changes, task_events.created_at";
+
+pub fn task_fields(task: &TaskCreated) -> Value {
+   json!({
+      "name": task.name,
+      "completed": task.completed != 0,
+      "due_date": task.due_date,
+      "recurrence": task.recurrence,
+      "project_id": task.project_id,
+      "assignee_id": task.assignee_id,
+   })
+}
+
+// Only the profile fields are diffed; callers record a password change as
+// `{ "changed": true }` so that hashes never end up in the history.
+pub fn user_fields(user: &CreatedUser) -> Value {
+   json!({
+      "firstname": user.firstname,
+      "lastname": user.lastname,
+      "email": user.email,
+   })
+}
+
+/// `{ field: { "from": .., "to": .. } }` for every field that differs
+/// between the two snapshots, where a missing snapshot counts as all null.
+pub fn diff(before: Option<&Value>, after: Option<&Value>) -> Value {
+   let empty = Map::new();
+
+   let before = before.and_then(|value| value.as_object()).unwrap_or(&empty);
+   let after = after.and_then(|value| value.as_object()).unwrap_or(&empty);
+
+   let mut changes = Map::new();
+
+   for key in before.keys().chain(after.keys()) {
+      let from = before.get(key).unwrap_or(&Value::Null);
+      let to = after.get(key).unwrap_or(&Value::Null);
+
+      if from != to && !changes.contains_key(key) {
+         changes.insert(key.clone(), json!({ "from": from, "to": to }));
+      }
+   }
+
+   Value::Object(changes)
+}
+
+/// Appends an event to `task_events` and queues it for the subscribed
+/// webhooks, returning the wake-up of the open event streams to send after
+/// the commit. `user_id` is the owner of the entity and `actor_id` the user
+/// who made the change.
+pub fn record_event(
+   conn: &Connection,
+   entity: &str,
+   entity_id: &str,
+   user_id: &str,
+   actor_id: &str,
+   action: &str,
+   changes: Value,
+) -> Result<Wakeups, rusqlite::Error> {
+   let mut event = TaskEvent {
+      id: 0,
+      entity: String::from(entity),
+      entity_id: String::from(entity_id),
+      user_id: String::from(user_id),
+      actor_id: String::from(actor_id),
+      action: String::from(action),
+      changes,
+      created_at: now(),
+   };
+
+   conn.execute(
+      "INSERT INTO task_events (entity, entity_id, user_id, actor_id, action, changes, created_at) VALUES (?, ?, ?, ?, ?, ?, ?)",
+      params![
+         event.entity,
+         event.entity_id,
+         event.user_id,
+         event.actor_id,
+         event.action,
+         event.changes.to_string(),
+         event.created_at
+      ],
+   )?;
+
+   event.id = conn.last_insert_rowid();
+
+   // Worked out once here, for the event streams and the webhooks.
+   let audience = if event.entity == "task" {
+      task_audience(conn, &event.entity_id)
+   } else {
+      vec![]
+   };
+
+   record_audience(conn, event.id, &audience)?;
+   enqueue_deliveries(conn, &event, &audience)?;
+
+   Ok(Wakeups::new(event.id, audience))
+}
+
+pub fn record_task_event(
+   conn: &Connection,
+   task: &TaskCreated,
+   actor_id: &str,
+   action: &str,
+   changes: Value,
+) -> Result<Wakeups, rusqlite::Error> {
+   record_event(
+      conn,
+      "task",
+      &task.id,
+      &task.user_id,
+      actor_id,
+      action,
+      changes,
+   )
+}
@@ -0,0 +1,368 @@
+use super::super::views::tasks::TaskCreated;
+use super::recurrence::Recurrence;
+use super::{parse_datetime, DATETIME_FORMAT};
+
+use chrono::{NaiveDate, NaiveDateTime, NaiveTime, Utc};
+
+pub const PRODID: &str = "-//todo-api//tasks//EN";
+
+// RFC 5545 section 3.1: content lines longer than 75 octets are folded.
+const LINE_LIMIT: usize = 75;
+
+// Events have no end in our model, so timed ones are given a fixed length to
+// show up on calendars that ignore zero-length events.
+const EVENT_DURATION: &str = "PT30M";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Component {
+   Todo,
+   Event,
+}
+
+impl Component {
+   pub fn parse(component: &str) -> Option<Component> {
+      match component.to_lowercase().as_str() {
+         "vtodo" => Option::from(Component::Todo),
+         "vevent" => Option::from(Component::Event),
+         _ => None,
+      }
+   }
+
+   fn name(self) -> &'static str {
+      match self {
+         Component::Todo => "VTODO",
+         Component::Event => "VEVENT",
+      }
+   }
+}
+
+/// A task read from an uploaded calendar, before it is validated against
+/// the rules of `POST /tasks`.
+#[derive(Debug, Clone)]
+pub struct ImportedTask {
+   pub uid: Option<String>,
+   pub name: Option<String>,
+   pub due_date: Option<String>,
+   pub recurrence: Option<String>,
+   pub completed: bool,
+}
+
+#[derive(Debug, Clone)]
+struct Property {
+   name: String,
+   params: Vec<(String, String)>,
+   value: String,
+}
+
+impl Property {
+   fn param(&self, name: &str) -> Option<&str> {
+      self
+         .params
+         .iter()
+         .find(|(key, _)| key == name)
+         .map(|(_, value)| value.as_str())
+   }
+}
+
+fn escape_text(value: &str) -> String {
+   let mut escaped = String::with_capacity(value.len());
+
+   for character in value.chars() {
+      match character {
+         '\\' => escaped.push_str("\\\\"),
+         ';' => escaped.push_str("\\;"),
+         ',' => escaped.push_str("\\,"),
+         '\n' => escaped.push_str("\\n"),
+         '\r' => {}
+         _ => escaped.push(character),
+      }
+   }
+
+   escaped
+}
+
+fn unescape_text(value: &str) -> String {
+   let mut unescaped = String::with_capacity(value.len());
+   let mut characters = value.chars();
+
+   while let Some(character) = characters.next() {
+      if character != '\\' {
+         unescaped.push(character);
+         continue;
+      }
+
+      match characters.next() {
+         Some('n') | Some('N') => unescaped.push('\n'),
+         Some(other) => unescaped.push(other),
+         None => {}
+      }
+   }
+
+   unescaped
+}
+
+// Splits a content line into chunks of at most 75 octets without cutting a
+// UTF-8 sequence; continuation lines start with a single space.
+fn fold_line(output: &mut String, line: &str) {
+   let mut limit = LINE_LIMIT;
+   let mut start = 0;
+
+   while line.len() - start > limit {
+      let mut end = start + limit;
+
+      while !line.is_char_boundary(end) {
+         end -= 1;
+      }
+
+      output.push_str(&line[start..end]);
+      output.push_str("\r\n ");
+
+      start = end;
+      limit = LINE_LIMIT - 1;
+   }
+
+   output.push_str(&line[start..]);
+   output.push_str("\r\n");
+}
+
+fn format_utc(datetime: NaiveDateTime) -> String {
+   format!("{}", datetime.format("%Y%m%dT%H%M%SZ"))
+}
+
+/// Renders the tasks as an RFC 5545 calendar. Tasks without a due date are
+/// skipped; due dates at midnight become all-day events.
+pub fn write_calendar(name: &str, tasks: &[TaskCreated], component: Component) -> String {
+   let stamp = format_utc(Utc::now().naive_utc());
+   let mut output = String::new();
+
+   fold_line(&mut output, "BEGIN:VCALENDAR");
+   fold_line(&mut output, "VERSION:2.0");
+   fold_line(&mut output, &format!("PRODID:{}", PRODID));
+   fold_line(&mut output, "CALSCALE:GREGORIAN");
+   fold_line(&mut output, &format!("X-WR-CALNAME:{}", escape_text(name)));
+
+   for task in tasks {
+      let due = match task.due_date.as_deref().and_then(parse_datetime) {
+         Some(due) => due,
+         None => continue,
+      };
+
+      fold_line(&mut output, &format!("BEGIN:{}", component.name()));
+      fold_line(&mut output, &format!("UID:{}@todo-api", task.id));
+      fold_line(&mut output, &format!("DTSTAMP:{}", stamp));
+      fold_line(&mut output, &format!("SEQUENCE:{}", task.version - 1));
+      fold_line(&mut output, &format!("SUMMARY:{}", escape_text(&task.name)));
+
+      let all_day = due.time() == NaiveTime::from_hms(0, 0, 0);
+
+      match (component, all_day) {
+         (Component::Todo, true) => {
+            fold_line(
+               &mut output,
+               &format!("DUE;VALUE=DATE:{}", due.format("%Y%m%d")),
+            );
+         }
+         (Component::Todo, false) => {
+            fold_line(&mut output, &format!("DUE:{}", format_utc(due)));
+         }
+         (Component::Event, true) => {
+            fold_line(
+               &mut output,
+               &format!("DTSTART;VALUE=DATE:{}", due.format("%Y%m%d")),
+            );
+            fold_line(&mut output, "DURATION:P1D");
+         }
+         (Component::Event, false) => {
+            fold_line(&mut output, &format!("DTSTART:{}", format_utc(due)));
+            fold_line(&mut output, &format!("DURATION:{}", EVENT_DURATION));
+         }
+      }
+
+      if let Some(recurrence) = task
+         .recurrence
+         .as_deref()
+         .and_then(|rule| Recurrence::parse(rule).ok())
+      {
+         let rule = if all_day {
+            recurrence.to_rrule()
+         } else {
+            recurrence.to_utc_rrule()
+         };
+
+         fold_line(&mut output, &format!("RRULE:{}", rule));
+      }
+
+      if component == Component::Todo {
+         let status = if task.completed != 0 {
+            "COMPLETED"
+         } else {
+            "NEEDS-ACTION"
+         };
+
+         fold_line(&mut output, &format!("STATUS:{}", status));
+      }
+
+      fold_line(&mut output, &format!("END:{}", component.name()));
+   }
+
+   fold_line(&mut output, "END:VCALENDAR");
+
+   output
+}
+
+fn unfold(text: &str) -> Vec<String> {
+   let mut lines: Vec<String> = vec![];
+
+   for line in text.split('\n') {
+      let line = line.strip_suffix('\r').unwrap_or(line);
+
+      if line.starts_with(' ') || line.starts_with('\t') {
+         if let Some(last) = lines.last_mut() {
+            last.push_str(&line[1..]);
+            continue;
+         }
+      }
+
+      if !line.is_empty() {
+         lines.push(String::from(line));
+      }
+   }
+
+   lines
+}
+
+// Parameter values may be quoted, in which case `;`, `:` and `,` inside the
+// quotes are not separators.
+fn parse_property(line: &str) -> Option<Property> {
+   let mut quoted = false;
+   let mut separators = vec![];
+   let mut value_start = None;
+
+   for (index, character) in line.char_indices() {
+      match character {
+         '"' => quoted = !quoted,
+         ';' if !quoted => separators.push(index),
+         ':' if !quoted => {
+            value_start = Option::from(index);
+            break;
+         }
+         _ => {}
+      }
+   }
+
+   let value_start = value_start?;
+   let name_end = separators.first().copied().unwrap_or(value_start);
+   let name = line[..name_end].trim().to_uppercase();
+
+   if name.is_empty() {
+      return None;
+   }
+
+   let mut params = vec![];
+   let mut bounds = separators.clone();
+   bounds.push(value_start);
+
+   for pair in bounds.windows(2) {
+      let param = &line[pair[0] + 1..pair[1]];
+      let mut parts = param.splitn(2, '=');
+      let key = parts.next().unwrap_or("").trim().to_uppercase();
+      let value = parts.next().unwrap_or("").trim().trim_matches('"');
+
+      params.push((key, String::from(value)));
+   }
+
+   Option::from(Property {
+      name,
+      params,
+      value: String::from(&line[value_start + 1..]),
+   })
+}
+
+// Date-times carrying a `TZID` are read as UTC: the server has no time zone
+// database to convert them with.
+fn parse_ical_datetime(property: &Property) -> Option<String> {
+   let value = property.value.trim();
+
+   let datetime = if property.param("VALUE") == Some("DATE") || value.len() == 8 {
+      NaiveDate::parse_from_str(value, "%Y%m%d")
+         .ok()
+         .map(|date| date.and_hms(0, 0, 0))
+   } else {
+      NaiveDateTime::parse_from_str(value, "%Y%m%dT%H%M%SZ")
+         .or_else(|_| NaiveDateTime::parse_from_str(value, "%Y%m%dT%H%M%S"))
+         .ok()
+   };
+
+   datetime.map(|datetime| format!("{}", datetime.format(DATETIME_FORMAT)))
+}
+
+/// Reads the `VTODO` and `VEVENT` components of an RFC 5545 calendar. A todo
+/// uses `DUE` (or `DTSTART` when it has none) and an event uses `DTSTART`.
+pub fn parse_calendar(text: &str) -> Result<Vec<ImportedTask>, &'static str> {
+   let lines = unfold(text);
+
+   match lines.first().and_then(|line| parse_property(line)) {
+      Some(property)
+         if property.name == "BEGIN" && property.value.eq_ignore_ascii_case("VCALENDAR") => {}
+      _ => return Err("calendar invalid"),
+   }
+
+   let mut tasks = vec![];
+   let mut current: Option<(Component, ImportedTask, Option<String>)> = None;
+   // Depth of components nested in the current one, like `VALARM`.
+   let mut nested = 0;
+
+   for line in lines.iter().skip(1) {
+      let property = match parse_property(line) {
+         Some(property) => property,
+         None => return Err("calendar invalid"),
+      };
+
+      match (property.name.as_str(), current.as_mut()) {
+         ("BEGIN", None) => {
+            if let Some(component) = Component::parse(&property.value) {
+               let task = ImportedTask {
+                  uid: None,
+                  name: None,
+                  due_date: None,
+                  recurrence: None,
+                  completed: false,
+               };
+
+               current = Option::from((component, task, None));
+            }
+         }
+         ("BEGIN", Some(_)) => nested += 1,
+         ("END", Some(_)) if nested > 0 => nested -= 1,
+         ("END", Some(_)) => {
+            if let Some((_, mut task, start)) = current.take() {
+               if task.due_date.is_none() {
+                  task.due_date = start;
+               }
+
+               tasks.push(task);
+            }
+         }
+         (_, Some(_)) if nested > 0 => {}
+         (name, Some((component, task, start))) => match name {
+            "UID" => task.uid = Option::from(unescape_text(&property.value)),
+            "SUMMARY" => task.name = Option::from(unescape_text(&property.value)),
+            "DUE" if *component == Component::Todo => {
+               task.due_date = parse_ical_datetime(&property)
+            }
+            "DTSTART" => *start = parse_ical_datetime(&property),
+            "RRULE" => task.recurrence = Option::from(property.value.clone()),
+            "STATUS" => task.completed = property.value.eq_ignore_ascii_case("COMPLETED"),
+            "COMPLETED" => task.completed = true,
+            _ => {}
+         },
+         _ => {}
+      }
+   }
+
+   if current.is_some() {
+      return Err("calendar invalid");
+   }
+
+   Ok(tasks)
+}
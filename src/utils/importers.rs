@@ -0,0 +1,621 @@
+use super::{parse_datetime, DATETIME_FORMAT};
+
+use std::collections::HashMap;
+
+use chrono::{DateTime, NaiveDateTime};
+
+use serde_json::Value;
+
+use utoipa::ToSchema;
+
+/// The export formats `POST /imports` understands.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Source {
+   Todoist,
+   Trello,
+   MicrosoftTodo,
+}
+
+impl Source {
+   pub fn parse(source: &str) -> Option<Source> {
+      match source {
+         "todoist" => Option::from(Source::Todoist),
+         "trello" => Option::from(Source::Trello),
+         "microsoft-todo" => Option::from(Source::MicrosoftTodo),
+         _ => None,
+      }
+   }
+
+   pub fn as_str(self) -> &'static str {
+      match self {
+         Source::Todoist => "todoist",
+         Source::Trello => "trello",
+         Source::MicrosoftTodo => "microsoft-todo",
+      }
+   }
+}
+
+#[derive(Debug, Clone)]
+pub struct PlannedTask {
+   pub name: String,
+   /// Index in `ImportPlan::projects`.
+   pub project: Option<usize>,
+   pub due_date: Option<String>,
+   pub recurrence: Option<String>,
+   pub completed: bool,
+   pub labels: Vec<String>,
+}
+
+impl PlannedTask {
+   fn new(name: &str, project: Option<usize>) -> PlannedTask {
+      PlannedTask {
+         name: String::from(name.trim()),
+         project,
+         due_date: None,
+         recurrence: None,
+         completed: false,
+         labels: vec![],
+      }
+   }
+}
+
+/// Something from the export that was left out, or only partly imported.
+#[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
+pub struct SkippedItem {
+   pub item: String,
+   pub reason: String,
+}
+
+impl SkippedItem {
+   pub fn new(item: &str, reason: &str) -> SkippedItem {
+      SkippedItem {
+         item: String::from(item),
+         reason: String::from(reason),
+      }
+   }
+}
+
+/// What an import job is going to create: the projects first, then the
+/// tasks with their labels.
+#[derive(Debug, Clone, Default)]
+pub struct ImportPlan {
+   pub projects: Vec<String>,
+   pub tasks: Vec<PlannedTask>,
+   pub skipped: Vec<SkippedItem>,
+}
+
+pub fn parse_export(
+   source: Source,
+   data: &[u8],
+   project: Option<&str>,
+) -> Result<ImportPlan, &'static str> {
+   match source {
+      Source::Todoist => parse_todoist(data, project),
+      Source::Trello => parse_trello(data),
+      Source::MicrosoftTodo => parse_microsoft_todo(data),
+   }
+}
+
+fn format_datetime(datetime: NaiveDateTime) -> String {
+   format!("{}", datetime.format(DATETIME_FORMAT))
+}
+
+// RFC 3339 with an offset, like Trello's `2026-10-20T12:00:00.000Z`, or a
+// local date-time with up to seven fractional digits, like Microsoft To Do's
+// `2026-10-20T05:00:00.0000000`.
+fn parse_iso_datetime(value: &str) -> Option<String> {
+   if let Ok(datetime) = DateTime::parse_from_rfc3339(value) {
+      return Option::from(format_datetime(datetime.naive_utc()));
+   }
+
+   NaiveDateTime::parse_from_str(value, "%Y-%m-%dT%H:%M:%S%.f")
+      .ok()
+      .or_else(|| parse_datetime(value))
+      .map(format_datetime)
+}
+
+// Todoist keeps labels inline in the content, as `@label` words.
+fn split_todoist_labels(content: &str) -> (String, Vec<String>) {
+   let mut words = vec![];
+   let mut labels = vec![];
+
+   for word in content.split_whitespace() {
+      match word.strip_prefix('@') {
+         Some(label) if !label.is_empty() => labels.push(String::from(label)),
+         _ => words.push(word),
+      }
+   }
+
+   (words.join(" "), labels)
+}
+
+/// A Todoist project template CSV, with `TYPE`, `CONTENT` and `DATE`
+/// columns. A file holds a single project, named after `project` or
+/// "Todoist". Sections become labels on the tasks under them. Dates written
+/// in natural language, like "every monday", are not understood.
+fn parse_todoist(data: &[u8], project: Option<&str>) -> Result<ImportPlan, &'static str> {
+   let mut reader = csv::ReaderBuilder::new().flexible(true).from_reader(data);
+
+   let headers: HashMap<String, usize> = match reader.headers() {
+      Ok(headers) => headers
+         .iter()
+         .enumerate()
+         .map(|(index, header)| (header.trim().to_uppercase(), index))
+         .collect(),
+      _ => return Err("todoist csv invalid"),
+   };
+
+   let (kind, content) = match (headers.get("TYPE"), headers.get("CONTENT")) {
+      (Some(kind), Some(content)) => (*kind, *content),
+      _ => return Err("todoist csv invalid"),
+   };
+   let date = headers.get("DATE").copied();
+
+   let mut plan = ImportPlan {
+      projects: vec![String::from(project.unwrap_or("Todoist"))],
+      ..ImportPlan::default()
+   };
+   let mut section: Option<String> = None;
+
+   for record in reader.records() {
+      let record = match record {
+         Ok(record) => record,
+         _ => return Err("todoist csv invalid"),
+      };
+
+      let text = record.get(content).unwrap_or("").trim();
+
+      match record.get(kind).unwrap_or("").trim() {
+         "task" => {
+            let (name, labels) = split_todoist_labels(text);
+
+            if name.is_empty() {
+               plan
+                  .skipped
+                  .push(SkippedItem::new(text, "task has no name"));
+               continue;
+            }
+
+            let mut task = PlannedTask::new(&name, Option::from(0));
+            task.labels = labels;
+            task.labels.extend(section.clone());
+
+            let due = date.and_then(|date| record.get(date)).unwrap_or("").trim();
+
+            if !due.is_empty() {
+               task.due_date = parse_datetime(due).map(format_datetime);
+
+               if task.due_date.is_none() {
+                  plan.skipped.push(SkippedItem::new(
+                     &name,
+                     "due date not understood, imported without it",
+                  ));
+               }
+            }
+
+            plan.tasks.push(task);
+         }
+         "section" => section = Option::from(String::from(text)).filter(|text| !text.is_empty()),
+         "note" => plan
+            .skipped
+            .push(SkippedItem::new(text, "notes are not imported")),
+         _ => {}
+      }
+   }
+
+   Ok(plan)
+}
+
+/// A Trello board exported as JSON. The board becomes a project and its
+/// cards tasks, labelled with the card's labels and the name of its list.
+/// Archived cards and lists are skipped.
+fn parse_trello(data: &[u8]) -> Result<ImportPlan, &'static str> {
+   let board: Value = match serde_json::from_slice(data) {
+      Ok(board) => board,
+      _ => return Err("trello json invalid"),
+   };
+
+   let (name, cards) = match (board["name"].as_str(), board["cards"].as_array()) {
+      (Some(name), Some(cards)) => (name, cards),
+      _ => return Err("trello json invalid"),
+   };
+
+   // List id to its name, or None when the list is archived.
+   let lists: HashMap<&str, Option<&str>> = board["lists"]
+      .as_array()
+      .map(|lists| {
+         lists
+            .iter()
+            .filter_map(|list| {
+               let id = list["id"].as_str()?;
+               let closed = list["closed"].as_bool().unwrap_or(false);
+
+               Option::from((id, list["name"].as_str().filter(|_| !closed)))
+            })
+            .collect()
+      })
+      .unwrap_or_default();
+
+   let mut plan = ImportPlan {
+      projects: vec![String::from(name)],
+      ..ImportPlan::default()
+   };
+
+   for card in cards {
+      let name = card["name"].as_str().unwrap_or("").trim();
+
+      if name.is_empty() {
+         plan.skipped.push(SkippedItem::new("", "card has no name"));
+         continue;
+      }
+
+      if card["closed"].as_bool().unwrap_or(false) {
+         plan
+            .skipped
+            .push(SkippedItem::new(name, "card is archived"));
+         continue;
+      }
+
+      let list = match card["idList"].as_str().map(|id| lists.get(id)) {
+         Some(Some(None)) => {
+            plan
+               .skipped
+               .push(SkippedItem::new(name, "list is archived"));
+            continue;
+         }
+         Some(Some(Some(list))) => Option::from(String::from(*list)),
+         _ => None,
+      };
+
+      let mut task = PlannedTask::new(name, Option::from(0));
+      task.completed = card["dueComplete"].as_bool().unwrap_or(false);
+      task.due_date = card["due"].as_str().and_then(parse_iso_datetime);
+      task.labels = card["labels"]
+         .as_array()
+         .map(|labels| {
+            labels
+               .iter()
+               .filter_map(|label| {
+                  label["name"]
+                     .as_str()
+                     .filter(|name| !name.is_empty())
+                     .or_else(|| label["color"].as_str())
+               })
+               .map(String::from)
+               .collect()
+         })
+         .unwrap_or_default();
+      task.labels.extend(list);
+
+      let has_checklists = card["idChecklists"]
+         .as_array()
+         .is_some_and(|checklists| !checklists.is_empty());
+
+      if has_checklists {
+         plan
+            .skipped
+            .push(SkippedItem::new(name, "checklists are not imported"));
+      }
+
+      plan.tasks.push(task);
+   }
+
+   Ok(plan)
+}
+
+fn todo_weekday(day: &str) -> Option<&'static str> {
+   match day.to_lowercase().as_str() {
+      "monday" => Option::from("MO"),
+      "tuesday" => Option::from("TU"),
+      "wednesday" => Option::from("WE"),
+      "thursday" => Option::from("TH"),
+      "friday" => Option::from("FR"),
+      "saturday" => Option::from("SA"),
+      "sunday" => Option::from("SU"),
+      _ => None,
+   }
+}
+
+// Maps a Microsoft Graph `patternedRecurrence` to the RRULE subset tasks
+// support: daily, weekly on given days and monthly on a day of the month.
+fn todo_recurrence(recurrence: &Value) -> Option<String> {
+   let pattern = &recurrence["pattern"];
+   let interval = pattern["interval"].as_u64().unwrap_or(1).max(1);
+
+   let rule = match pattern["type"].as_str()? {
+      "daily" => String::from("FREQ=DAILY"),
+      "weekly" => {
+         let days = pattern["daysOfWeek"]
+            .as_array()?
+            .iter()
+            .map(|day| day.as_str().and_then(todo_weekday))
+            .collect::<Option<Vec<&str>>>()?;
+
+         if days.is_empty() {
+            String::from("FREQ=WEEKLY")
+         } else {
+            format!("FREQ=WEEKLY;BYDAY={}", days.join(","))
+         }
+      }
+      "absoluteMonthly" => format!(
+         "FREQ=MONTHLY;BYMONTHDAY={}",
+         pattern["dayOfMonth"].as_u64()?
+      ),
+      _ => return None,
+   };
+
+   if interval > 1 {
+      Option::from(format!("{};INTERVAL={}", rule, interval))
+   } else {
+      Option::from(rule)
+   }
+}
+
+/// Microsoft To Do lists as returned by the Graph API, either as
+/// `{"lists": [...]}` or a bare array, each list with its `tasks`. Lists
+/// become projects and categories labels. Due dates are read as UTC,
+/// whatever their `timeZone`.
+fn parse_microsoft_todo(data: &[u8]) -> Result<ImportPlan, &'static str> {
+   let document: Value = match serde_json::from_slice(data) {
+      Ok(document) => document,
+      _ => return Err("microsoft to do json invalid"),
+   };
+
+   let lists = match document["lists"].as_array().or_else(|| document.as_array()) {
+      Some(lists) => lists,
+      None => return Err("microsoft to do json invalid"),
+   };
+
+   let mut plan = ImportPlan::default();
+
+   for list in lists {
+      let list_name = list["displayName"].as_str().unwrap_or("").trim();
+
+      let project = if list_name.is_empty() {
+         None
+      } else {
+         plan.projects.push(String::from(list_name));
+
+         Option::from(plan.projects.len() - 1)
+      };
+
+      for item in list["tasks"].as_array().into_iter().flatten() {
+         let name = item["title"].as_str().unwrap_or("").trim();
+
+         if name.is_empty() {
+            plan.skipped.push(SkippedItem::new("", "task has no name"));
+            continue;
+         }
+
+         let mut task = PlannedTask::new(name, project);
+         task.completed = item["status"].as_str() == Some("completed");
+         task.due_date = item["dueDateTime"]["dateTime"]
+            .as_str()
+            .and_then(parse_iso_datetime);
+         task.labels = item["categories"]
+            .as_array()
+            .map(|categories| {
+               categories
+                  .iter()
+                  .filter_map(|category| category.as_str())
+                  .map(String::from)
+                  .collect()
+            })
+            .unwrap_or_default();
+
+         if !item["recurrence"].is_null() {
+            task.recurrence = todo_recurrence(&item["recurrence"]);
+
+            if task.recurrence.is_none() || task.due_date.is_none() {
+               task.recurrence = None;
+               plan.skipped.push(SkippedItem::new(
+                  name,
+                  "recurrence not supported, imported without it",
+               ));
+            }
+         }
+
+         let has_steps = item["checklistItems"]
+            .as_array()
+            .is_some_and(|steps| !steps.is_empty());
+
+         if has_steps {
+            plan
+               .skipped
+               .push(SkippedItem::new(name, "steps are not imported"));
+         }
+
+         plan.tasks.push(task);
+      }
+   }
+
+   Ok(plan)
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   fn reasons(plan: &ImportPlan) -> Vec<(&str, &str)> {
+      plan
+         .skipped
+         .iter()
+         .map(|skipped| (skipped.item.as_str(), skipped.reason.as_str()))
+         .collect()
+   }
+
+   #[test]
+   fn labels_todoist_tasks_with_their_section() {
+      let csv = "TYPE,CONTENT,PRIORITY,DATE\n\
+         task,Buy milk @errands,4,\n\
+         section,Work,,\n\
+         task,Write report @urgent,1,2026-10-20\n\
+         note,Remember the charts,,\n";
+
+      let plan = parse_todoist(csv.as_bytes(), Option::from("Home")).unwrap();
+
+      assert_eq!(plan.projects, vec!["Home"]);
+      assert_eq!(plan.tasks.len(), 2);
+      assert_eq!(plan.tasks[0].name, "Buy milk");
+      assert_eq!(plan.tasks[0].labels, vec!["errands"]);
+      assert_eq!(plan.tasks[1].name, "Write report");
+      assert_eq!(plan.tasks[1].labels, vec!["urgent", "Work"]);
+      assert_eq!(
+         plan.tasks[1].due_date.as_deref(),
+         Some("2026-10-20 00:00:00")
+      );
+      assert_eq!(
+         reasons(&plan),
+         vec![("Remember the charts", "notes are not imported")]
+      );
+   }
+
+   #[test]
+   fn reports_todoist_dates_it_does_not_understand() {
+      let csv = "TYPE,CONTENT,DATE\ntask,Water plants,every monday\n";
+
+      let plan = parse_todoist(csv.as_bytes(), None).unwrap();
+
+      assert_eq!(plan.projects, vec!["Todoist"]);
+      assert_eq!(plan.tasks.len(), 1);
+      assert_eq!(plan.tasks[0].due_date, None);
+      assert_eq!(
+         reasons(&plan),
+         vec![(
+            "Water plants",
+            "due date not understood, imported without it"
+         )]
+      );
+   }
+
+   #[test]
+   fn refuses_todoist_csv_without_its_columns() {
+      let csv = "NAME,WHEN\nBuy milk,today\n";
+
+      assert!(parse_todoist(csv.as_bytes(), None).is_err());
+   }
+
+   #[test]
+   fn skips_archived_trello_cards_and_lists() {
+      let board = r#"{
+         "name": "Board",
+         "lists": [
+            {"id": "l1", "name": "Doing", "closed": false},
+            {"id": "l2", "name": "Old", "closed": true}
+         ],
+         "cards": [
+            {
+               "name": "Open card",
+               "idList": "l1",
+               "due": "2026-10-20T12:00:00.000Z",
+               "dueComplete": true,
+               "labels": [{"name": "bug", "color": "red"}, {"name": "", "color": "green"}],
+               "idChecklists": ["c1"]
+            },
+            {"name": "Archived card", "idList": "l1", "closed": true},
+            {"name": "Card of an archived list", "idList": "l2"}
+         ]
+      }"#;
+
+      let plan = parse_trello(board.as_bytes()).unwrap();
+
+      assert_eq!(plan.projects, vec!["Board"]);
+      assert_eq!(plan.tasks.len(), 1);
+      assert_eq!(plan.tasks[0].name, "Open card");
+      assert!(plan.tasks[0].completed);
+      assert_eq!(
+         plan.tasks[0].due_date.as_deref(),
+         Some("2026-10-20 12:00:00")
+      );
+      assert_eq!(plan.tasks[0].labels, vec!["bug", "green", "Doing"]);
+      assert_eq!(
+         reasons(&plan),
+         vec![
+            ("Open card", "checklists are not imported"),
+            ("Archived card", "card is archived"),
+            ("Card of an archived list", "list is archived"),
+         ]
+      );
+   }
+
+   #[test]
+   fn maps_microsoft_todo_recurrences() {
+      let recurrence = |pattern: Value| todo_recurrence(&serde_json::json!({ "pattern": pattern }));
+
+      assert_eq!(
+         recurrence(serde_json::json!({"type": "daily", "interval": 1})).as_deref(),
+         Some("FREQ=DAILY")
+      );
+      assert_eq!(
+         recurrence(serde_json::json!({
+            "type": "weekly",
+            "interval": 2,
+            "daysOfWeek": ["monday", "Friday"]
+         }))
+         .as_deref(),
+         Some("FREQ=WEEKLY;BYDAY=MO,FR;INTERVAL=2")
+      );
+      assert_eq!(
+         recurrence(serde_json::json!({"type": "absoluteMonthly", "dayOfMonth": 15})).as_deref(),
+         Some("FREQ=MONTHLY;BYMONTHDAY=15")
+      );
+      assert_eq!(
+         recurrence(serde_json::json!({"type": "relativeMonthly", "index": "first"})),
+         None
+      );
+      assert_eq!(
+         recurrence(serde_json::json!({"type": "weekly", "daysOfWeek": ["someday"]})),
+         None
+      );
+   }
+
+   #[test]
+   fn imports_microsoft_todo_recurrences_only_with_a_due_date() {
+      let lists = r#"{"lists": [{
+         "displayName": "Chores",
+         "tasks": [
+            {
+               "title": "Laundry",
+               "status": "completed",
+               "categories": ["home"],
+               "dueDateTime": {"dateTime": "2026-10-20T05:00:00.0000000", "timeZone": "UTC"},
+               "recurrence": {"pattern": {"type": "weekly", "interval": 1, "daysOfWeek": ["tuesday"]}}
+            },
+            {
+               "title": "Dishes",
+               "recurrence": {"pattern": {"type": "daily", "interval": 1}}
+            },
+            {
+               "title": "Taxes",
+               "dueDateTime": {"dateTime": "2026-10-20T05:00:00.0000000", "timeZone": "UTC"},
+               "recurrence": {"pattern": {"type": "relativeYearly", "interval": 1}}
+            }
+         ]
+      }]}"#;
+
+      let plan = parse_microsoft_todo(lists.as_bytes()).unwrap();
+
+      assert_eq!(plan.projects, vec!["Chores"]);
+      assert_eq!(plan.tasks.len(), 3);
+      assert_eq!(plan.tasks[0].project, Some(0));
+      assert!(plan.tasks[0].completed);
+      assert_eq!(plan.tasks[0].labels, vec!["home"]);
+      assert_eq!(
+         plan.tasks[0].due_date.as_deref(),
+         Some("2026-10-20 05:00:00")
+      );
+      assert_eq!(
+         plan.tasks[0].recurrence.as_deref(),
+         Some("FREQ=WEEKLY;BYDAY=TU")
+      );
+      assert_eq!(plan.tasks[1].recurrence, None);
+      assert_eq!(plan.tasks[2].recurrence, None);
+      assert_eq!(
+         reasons(&plan),
+         vec![
+            ("Dishes", "recurrence not supported, imported without it"),
+            ("Taxes", "recurrence not supported, imported without it"),
+         ]
+      );
+   }
+}
@@ -0,0 +1,96 @@
+use std::collections::HashMap;
+
+use rusqlite::{params, Connection, OptionalExtension};
+
+use uuid::Uuid;
+
+use super::now;
+use super::permissions::VISIBLE_TASKS;
+
+pub const LABEL_MAX_LENGTH: usize = 64;
+
+// Labels belong to a user and are matched by name, ignoring case.
+fn find_or_create_label(conn: &Connection, user_id: &str, name: &str) -> rusqlite::Result<String> {
+   let existing: Option<String> = conn
+      .query_row(
+         "SELECT id FROM labels WHERE user_id = ? AND name = ?",
+         [user_id, name],
+         |row| row.get(0),
+      )
+      .optional()?;
+
+   if let Some(id) = existing {
+      return Ok(id);
+   }
+
+   let id = Uuid::new_v4().to_string();
+
+   conn.execute(
+      "INSERT INTO labels (id, user_id, name, created_at) VALUES (?, ?, ?, ?)",
+      params![id, user_id, name, now()],
+   )?;
+
+   Ok(id)
+}
+
+/// Tags the task with the labels of `user_id` named in `names`, creating the
+/// ones that do not exist yet. Blank names are ignored and long ones cut to
+/// `LABEL_MAX_LENGTH` characters.
+pub fn add_task_labels(
+   conn: &Connection,
+   task_id: &str,
+   user_id: &str,
+   names: &[String],
+) -> rusqlite::Result<()> {
+   for name in names.iter().map(|name| name.trim()) {
+      if name.is_empty() {
+         continue;
+      }
+
+      let name: String = name.chars().take(LABEL_MAX_LENGTH).collect();
+      let label_id = find_or_create_label(conn, user_id, name.trim_end())?;
+
+      conn.execute(
+         "INSERT OR IGNORE INTO task_labels (task_id, label_id) VALUES (?, ?)",
+         [task_id, &label_id],
+      )?;
+   }
+
+   Ok(())
+}
+
+pub fn task_labels(conn: &Connection, task_id: &str) -> Vec<String> {
+   let mut query = conn
+      .prepare("SELECT labels.name FROM task_labels INNER JOIN labels ON labels.id = task_labels.label_id WHERE task_labels.task_id = ? ORDER BY labels.name")
+      .unwrap();
+
+   query
+      .query_map([task_id], |row| row.get(0))
+      .unwrap()
+      .filter_map(|name| name.ok())
+      .collect()
+}
+
+/// The labels of every live task `user_id` can see, by task id.
+pub fn visible_task_labels(conn: &Connection, user_id: &str) -> HashMap<String, Vec<String>> {
+   let mut query = conn
+      .prepare(&format!(
+         "SELECT task_labels.task_id, labels.name FROM task_labels INNER JOIN labels ON labels.id = task_labels.label_id INNER JOIN tasks ON tasks.id = task_labels.task_id WHERE tasks.deleted_at IS NULL AND {} ORDER BY labels.name",
+         VISIBLE_TASKS
+      ))
+      .unwrap();
+
+   let data = query
+      .query_map([user_id], |row| {
+         Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+      })
+      .unwrap();
+
+   let mut labels: HashMap<String, Vec<String>> = HashMap::new();
+
+   for (task_id, name) in data.filter_map(|row| row.ok()) {
+      labels.entry(task_id).or_default().push(name);
+   }
+
+   labels
+}
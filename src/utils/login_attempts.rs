@@ -0,0 +1,150 @@
+use super::super::config::CONFIG;
+use super::{now, parse_datetime, DATETIME_FORMAT};
+
+use std::net::IpAddr;
+
+use chrono::{Duration, Utc};
+
+use rusqlite::{params, Connection, OptionalExtension};
+
+/// What failed logins are counted against: the email that was sent, whether
+/// or not it belongs to a user, and the address they came from.
+pub enum LoginKey {
+   Email(String),
+   Ip(IpAddr),
+}
+
+impl LoginKey {
+   fn key(&self) -> String {
+      match self {
+         LoginKey::Email(email) => format!("email:{}", email.to_lowercase()),
+         LoginKey::Ip(ip) => format!("ip:{}", ip),
+      }
+   }
+
+   // An address is shared by everyone behind it, so it gets more tries.
+   fn delay_after(&self) -> i64 {
+      match self {
+         LoginKey::Email(_) => CONFIG.login_delay_after,
+         LoginKey::Ip(_) => CONFIG.login_ip_delay_after,
+      }
+   }
+
+   fn lockout_after(&self) -> i64 {
+      match self {
+         LoginKey::Email(_) => CONFIG.login_lockout_after,
+         LoginKey::Ip(_) => CONFIG.login_ip_lockout_after,
+      }
+   }
+}
+
+fn seconds_until(datetime: &str) -> i64 {
+   match parse_datetime(datetime) {
+      Some(datetime) => (datetime - Utc::now().naive_utc()).num_seconds(),
+      None => 0,
+   }
+}
+
+/// How many seconds the caller has to wait before trying a key again: until
+/// its lockout ends, or, after `LOGIN_DELAY_AFTER` failures for an email
+/// and `LOGIN_IP_DELAY_AFTER` for an address, a delay that doubles with each
+/// one. Counters whose last failure is older than
+/// `LOGIN_ATTEMPTS_WINDOW_SECONDS` and ended lockouts are forgotten.
+fn retry_after(conn: &Connection, key: &LoginKey) -> Option<i64> {
+   let row: Option<(i64, String, Option<String>)> = conn
+      .query_row(
+         "SELECT failures, last_failure_at, locked_until FROM login_attempts WHERE key = ?",
+         [key.key()],
+         |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+      )
+      .optional()
+      .unwrap_or(None);
+
+   let (failures, last_failure_at, locked_until) = row?;
+   let since_failure = -seconds_until(&last_failure_at);
+
+   let expired = match &locked_until {
+      Some(locked_until) => seconds_until(locked_until) <= 0,
+      None => since_failure >= CONFIG.login_attempts_window_seconds,
+   };
+
+   if expired {
+      let _ = conn.execute("DELETE FROM login_attempts WHERE key = ?", [key.key()]);
+
+      return None;
+   }
+
+   if let Some(locked_until) = locked_until {
+      return Some(seconds_until(&locked_until));
+   }
+
+   if failures < key.delay_after() {
+      return None;
+   }
+
+   let exponent = (failures - key.delay_after()).min(30) as u32;
+   let delay = 2_i64.pow(exponent).min(CONFIG.login_delay_max_seconds);
+
+   match delay - since_failure {
+      wait if wait > 0 => Some(wait),
+      _ => None,
+   }
+}
+
+// Counts a failed login, locking the key for `LOGIN_LOCKOUT_SECONDS` once
+// it reaches its limit.
+fn record_failure(conn: &Connection, key: &LoginKey) {
+   let _ = conn.execute(
+      "INSERT INTO login_attempts (key, failures, last_failure_at) VALUES (?, 1, ?) ON CONFLICT (key) DO UPDATE SET failures = failures + 1, last_failure_at = excluded.last_failure_at",
+      [key.key(), now()],
+   );
+
+   let locked_until = Utc::now().naive_utc() + Duration::seconds(CONFIG.login_lockout_seconds);
+
+   let _ = conn.execute(
+      "UPDATE login_attempts SET locked_until = ? WHERE key = ? AND failures >= ?",
+      params![
+         format!("{}", locked_until.format(DATETIME_FORMAT)),
+         key.key(),
+         key.lockout_after()
+      ],
+   );
+}
+
+/// Counts a login as failed for every key before its password or code is
+/// checked, or, when one of the keys has to wait, returns the longest wait
+/// without counting anything. Checking and counting under the same lock of
+/// the connection keeps concurrent logins from all passing the check before
+/// any of them fails.
+pub fn begin_attempt(conn: &Connection, keys: &[LoginKey]) -> Result<(), i64> {
+   let wait = keys.iter().filter_map(|key| retry_after(conn, key)).max();
+
+   if let Some(wait) = wait {
+      return Err(wait);
+   }
+
+   for key in keys {
+      record_failure(conn, key);
+   }
+
+   Ok(())
+}
+
+/// Takes back the failure `begin_attempt` counted for a key once the login
+/// turns out to be right, along with the lockout it may have started.
+pub fn undo_failure(conn: &Connection, key: &LoginKey) {
+   let _ = conn.execute(
+      "UPDATE login_attempts SET failures = failures - 1, locked_until = CASE WHEN failures - 1 >= ? THEN locked_until END WHERE key = ?",
+      params![key.lockout_after(), key.key()],
+   );
+
+   let _ = conn.execute(
+      "DELETE FROM login_attempts WHERE key = ? AND failures <= 0",
+      [key.key()],
+   );
+}
+
+/// Forgets the failures of a key, after a successful login or an unlock.
+pub fn clear(conn: &Connection, key: &LoginKey) -> rusqlite::Result<usize> {
+   conn.execute("DELETE FROM login_attempts WHERE key = ?", [key.key()])
+}
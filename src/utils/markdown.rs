@@ -0,0 +1,105 @@
+use pulldown_cmark::{html, CowStr, Event, Options, Parser, Tag};
+
+const UNSAFE_SCHEMES: [&str; 3] = ["javascript:", "vbscript:", "data:"];
+
+fn safe_url(url: CowStr) -> CowStr {
+   let lowercase = url.trim().to_lowercase();
+
+   if UNSAFE_SCHEMES
+      .iter()
+      .any(|scheme| lowercase.starts_with(scheme))
+   {
+      CowStr::from("")
+   } else {
+      url
+   }
+}
+
+/// Renders a markdown comment to HTML. Raw HTML in the source is escaped
+/// rather than passed through and script URLs are dropped from links and
+/// images, so the result is safe to embed as is.
+pub fn render(body: &str) -> String {
+   let mut options = Options::empty();
+   options.insert(Options::ENABLE_STRIKETHROUGH);
+   options.insert(Options::ENABLE_TABLES);
+   options.insert(Options::ENABLE_TASKLISTS);
+
+   let parser = Parser::new_ext(body, options).map(|event| match event {
+      Event::Html(html) | Event::InlineHtml(html) => Event::Text(html),
+      Event::Start(Tag::Link {
+         link_type,
+         dest_url,
+         title,
+         id,
+      }) => Event::Start(Tag::Link {
+         link_type,
+         dest_url: safe_url(dest_url),
+         title,
+         id,
+      }),
+      Event::Start(Tag::Image {
+         link_type,
+         dest_url,
+         title,
+         id,
+      }) => Event::Start(Tag::Image {
+         link_type,
+         dest_url: safe_url(dest_url),
+         title,
+         id,
+      }),
+      event => event,
+   });
+
+   let mut rendered = String::new();
+   html::push_html(&mut rendered, parser);
+
+   rendered
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   #[test]
+   fn renders_markdown() {
+      assert_eq!(
+         render("**Milk** and ~~eggs~~"),
+         "<p><strong>Milk</strong> and <del>eggs</del></p>\n"
+      );
+      assert_eq!(
+         render("[shop](https://example.com)"),
+         "<p><a href=\"https://example.com\">shop</a></p>\n"
+      );
+   }
+
+   #[test]
+   fn escapes_raw_html() {
+      let rendered = render("<script>alert(1)</script>\n\nhi <img src=x onerror=alert(1)>");
+
+      assert!(!rendered.contains("<script"), "{}", rendered);
+      assert!(!rendered.contains("<img"), "{}", rendered);
+      assert!(rendered.contains("&lt;script&gt;"), "{}", rendered);
+   }
+
+   #[test]
+   fn drops_script_urls() {
+      for source in [
+         "[click](javascript:alert(1))",
+         "[click]( JavaScript:alert(1))",
+         "<vbscript:msgbox(1)>",
+         "![pic](data:text/html;base64,PHNjcmlwdD4=)",
+      ]
+      .iter()
+      {
+         let rendered = render(source);
+
+         // The text of an autolink still shows the URL, which is harmless.
+         assert!(
+            rendered.contains("href=\"\"") || rendered.contains("src=\"\""),
+            "{}",
+            rendered
+         );
+      }
+   }
+}
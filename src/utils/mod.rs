@@ -1,23 +1,68 @@
+pub mod attachments;
+pub mod etag;
+pub mod events;
+pub mod history;
+pub mod ical;
+pub mod importers;
+pub mod labels;
+pub mod login_attempts;
+pub mod mailer;
+pub mod markdown;
+pub mod password_reset;
+pub mod passwords;
+pub mod permissions;
+pub mod recurrence;
+pub mod totp;
+pub mod validation;
+pub mod verification;
+pub mod webhooks;
+
+use super::config::CONFIG;
 use super::views::users::CreatedUser;
+use validation::validation_error;
 
+use std::collections::HashMap;
 use std::convert::Infallible;
+use std::net::{IpAddr, SocketAddr};
+
+use chrono::{NaiveDate, NaiveDateTime, Utc};
 
 use futures::TryStreamExt;
 
-use hyper::{Body, Method, Request, Response};
+use hyper::header::{CONTENT_LENGTH, CONTENT_TYPE};
+use hyper::{Body, HeaderMap, Method, Request, Response};
 
 use futures::lock::Mutex;
 use rusqlite::Connection;
 use std::sync::Arc;
 
+use uuid::Uuid;
+
 use serde::de::DeserializeOwned;
 use serde_json::{from_slice, Error as SerdeError};
 
-#[derive(Debug, Serialize, Deserialize)]
-struct Error {
+use utoipa::ToSchema;
+
+use validator::{Validate, ValidationErrors};
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct ErrorFormated {
    error: &'static str,
 }
 
+/// The body of a 400 for JSON that could not be read as the expected data.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct JsonErrorFormated {
+   pub error: &'static str,
+   /// What serde found wrong, without the position.
+   pub message: String,
+   /// Where it found it, both counted from 1.
+   pub line: usize,
+   pub column: usize,
+}
+
+pub const DATETIME_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+
 pub struct RequestInfo {
    pub path: String,
    pub method: Method,
@@ -30,13 +75,13 @@ pub async fn get_request_info(req: &Request<Body>) -> RequestInfo {
 
    let path_splited: Vec<String> = path
       .split("/")
-      .filter(|data| data.len() >= 1)
+      .filter(|data| !data.is_empty())
       .map(|item| item.to_string())
       .collect();
 
    let path_formated = format!(
       "/{}",
-      if path_splited.len() < 1 {
+      if path_splited.is_empty() {
          "/"
       } else {
          path_splited[0].as_str()
@@ -50,19 +95,93 @@ pub async fn get_request_info(req: &Request<Body>) -> RequestInfo {
    }
 }
 
+/// The address of the peer, put on every request by the server.
+#[derive(Clone, Copy, Debug)]
+pub struct RemoteAddr(pub SocketAddr);
+
+// The address the outermost of `hops` trusted proxies saw: each appends the
+// peer it got the request from, so anything left of that was written by the
+// client and can be made up.
+fn forwarded_ip<'a>(values: impl Iterator<Item = &'a str>, hops: usize) -> Option<IpAddr> {
+   let addresses: Vec<&str> = values.flat_map(|value| value.split(',')).collect();
+
+   addresses
+      .len()
+      .checked_sub(hops.max(1))
+      .and_then(|index| addresses[index].trim().parse::<IpAddr>().ok())
+}
+
+/// The IP of the client: when `TRUST_PROXY` is set, the `X-Forwarded-For`
+/// address added by the outermost of the `TRUSTED_PROXY_HOPS` proxies in
+/// front of the server, the peer's otherwise.
+pub fn client_ip(req: &Request<Body>) -> Option<IpAddr> {
+   if CONFIG.trust_proxy {
+      let values = req
+         .headers()
+         .get_all("x-forwarded-for")
+         .iter()
+         .filter_map(|value| value.to_str().ok());
+
+      let forwarded = forwarded_ip(values, CONFIG.trusted_proxy_hops);
+
+      if forwarded.is_some() {
+         return forwarded;
+      }
+   }
+
+   req.extensions()
+      .get::<RemoteAddr>()
+      .map(|RemoteAddr(addr)| addr.ip())
+}
+
+pub fn get_query_params(req: &Request<Body>) -> HashMap<String, String> {
+   match req.uri().query() {
+      Some(query) => form_urlencoded::parse(query.as_bytes())
+         .into_owned()
+         .collect(),
+      None => HashMap::new(),
+   }
+}
+
+pub fn parse_datetime(value: &str) -> Option<NaiveDateTime> {
+   let value = value.trim();
+
+   NaiveDateTime::parse_from_str(value, DATETIME_FORMAT)
+      .or_else(|_| NaiveDateTime::parse_from_str(value, "%Y-%m-%dT%H:%M:%S"))
+      .ok()
+      .or_else(|| {
+         NaiveDate::parse_from_str(value, "%Y-%m-%d")
+            .ok()
+            .map(|date| date.and_hms(0, 0, 0))
+      })
+}
+
+/// A 64 character hex secret, for tokens handed out in URLs or headers.
+pub fn random_token() -> String {
+   format!(
+      "{}{}",
+      Uuid::new_v4().to_simple(),
+      Uuid::new_v4().to_simple()
+   )
+}
+
+pub fn now() -> String {
+   format!("{}", Utc::now().naive_utc().format(DATETIME_FORMAT))
+}
+
 pub fn create_error(
    reason: &'static str,
    status: Option<u16>,
 ) -> Result<Response<Body>, Infallible> {
-   let code = if status != None { status.unwrap() } else { 400 };
+   let code = status.unwrap_or(400);
 
    let mut label = reason;
 
-   if reason.len() < 1 {
+   if reason.is_empty() {
       label = "Internal Server Error";
    }
 
-   let json = serde_json::to_string(&Error { error: label });
+   let json = serde_json::to_string(&ErrorFormated { error: label });
 
    match json {
       Ok(data) => Ok(Response::builder()
@@ -89,28 +208,116 @@ pub fn valid_json(json: Result<String, SerdeError>) -> Result<Response<Body>, In
    }
 }
 
-pub async fn parse_body<'de, T>(body: Body) -> Result<T, SerdeError>
-where
-   T: DeserializeOwned,
-{
-   let body_unformated = body
+/// Reads the whole body, failing with 413 as soon as it grows past `limit`
+/// bytes instead of buffering the rest.
+pub async fn read_body(body: Body, limit: usize) -> Result<Vec<u8>, (u16, &'static str)> {
+   body
+      .map_err(|_| (400, "body invalid"))
       .try_fold(Vec::new(), |mut data, chunk| async move {
+         if data.len() + chunk.len() > limit {
+            return Err((413, "body too large"));
+         }
+
          data.extend_from_slice(&chunk);
 
          Ok(data)
       })
-      .await;
+      .await
+}
 
-   match body_unformated {
-      Ok(slice) => {
-         let data: Result<T, SerdeError> = from_slice(&slice);
+/// Why `parse_body` refused a body.
+#[derive(Debug)]
+pub enum BodyError {
+   /// The body could not be read or went past the limit: 400 or 413.
+   Unreadable((u16, &'static str)),
+   UnsupportedType,
+   Invalid(SerdeError),
+   Unprocessable(ValidationErrors),
+}
 
-         match data {
-            Ok(new_data) => Ok(new_data),
-            Err(e) => Err(e),
-         }
+/// The response for a body `parse_body` refused: 413 when it is too large,
+/// 415 when it is not sent as JSON, 400 with the position of the first
+/// error when it is not the expected JSON, 422 listing the fields that break
+/// their rules.
+pub fn body_error(error: BodyError) -> Result<Response<Body>, Infallible> {
+   match error {
+      BodyError::Unreadable((status, reason)) => create_error(reason, Option::from(status)),
+      BodyError::UnsupportedType => {
+         create_error("content type must be application/json", Option::from(415))
       }
-      Err(_) => panic!("error"),
+      BodyError::Invalid(error) => json_error(&error),
+      BodyError::Unprocessable(errors) => validation_error(&errors),
+   }
+}
+
+fn json_error(error: &SerdeError) -> Result<Response<Body>, Infallible> {
+   let message = error.to_string();
+   let position = format!(" at line {} column {}", error.line(), error.column());
+
+   let json = serde_json::to_string(&JsonErrorFormated {
+      error: "data invalid",
+      message: message.trim_end_matches(&position).to_string(),
+      line: error.line(),
+      column: error.column(),
+   });
+
+   match json {
+      Ok(data) => Ok(Response::builder()
+         .status(400)
+         .body(Body::from(data))
+         .unwrap()),
+      _ => create_error("", None),
+   }
+}
+
+// `application/json` or a `+json` type such as `application/merge-patch+json`,
+// with any params. A body without `Content-Type` is not JSON.
+fn is_json(headers: &HeaderMap) -> bool {
+   let content_type = match headers.get(CONTENT_TYPE).map(|value| value.to_str()) {
+      Some(Ok(value)) => value,
+      _ => return false,
+   };
+
+   let essence = content_type
+      .split(';')
+      .next()
+      .unwrap_or("")
+      .trim()
+      .to_lowercase();
+
+   essence == "application/json"
+      || (essence.starts_with("application/") && essence.ends_with("+json"))
+}
+
+/// Reads a JSON body of at most `BODY_MAX_BYTES` and checks it against the
+/// `#[validate]` rules of `T`.
+pub async fn parse_body<T>(headers: &HeaderMap, body: Body) -> Result<T, BodyError>
+where
+   T: DeserializeOwned + Validate,
+{
+   if !is_json(headers) {
+      return Err(BodyError::UnsupportedType);
+   }
+
+   // Refused before reading when the client announces its size.
+   let length = headers
+      .get(CONTENT_LENGTH)
+      .and_then(|value| value.to_str().ok())
+      .and_then(|value| value.parse::<usize>().ok());
+
+   if length.is_some_and(|length| length > CONFIG.body_max_bytes) {
+      return Err(BodyError::Unreadable((413, "body too large")));
+   }
+
+   let slice = read_body(body, CONFIG.body_max_bytes)
+      .await
+      .map_err(BodyError::Unreadable)?;
+
+   let data: T = from_slice(&slice).map_err(BodyError::Invalid)?;
+
+   match data.validate() {
+      Ok(_) => Ok(data),
+      Err(errors) => Err(BodyError::Unprocessable(errors)),
    }
 }
 
@@ -122,8 +329,8 @@ pub async fn get_users(
    let conn = conn.lock().await;
 
    let sql = match query_type.as_str() {
-      "email" => "SELECT * FROM users WHERE email = ?",
-      "id" => "SELECT * FROM users WHERE id = ?",
+      "email" => "SELECT id, firstname, lastname, email, password FROM users WHERE email = ? AND deleted_at IS NULL",
+      "id" => "SELECT id, firstname, lastname, email, password FROM users WHERE id = ? AND deleted_at IS NULL",
       _ => "",
    };
 
@@ -147,3 +354,62 @@ pub async fn get_users(
 
    users
 }
+
+// Same as `get_users` by id, for callers that already hold the connection.
+pub fn find_user(conn: &Connection, user_id: &str) -> Option<CreatedUser> {
+   conn
+      .query_row(
+         "SELECT id, firstname, lastname, email, password FROM users WHERE id = ? AND deleted_at IS NULL",
+         [user_id],
+         |row| {
+            Ok(CreatedUser {
+               id: row.get(0)?,
+               firstname: row.get(1)?,
+               lastname: row.get(2)?,
+               email: row.get(3)?,
+               password: row.get(4)?,
+            })
+         },
+      )
+      .ok()
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   #[test]
+   fn reads_forwarded_ips_from_the_right() {
+      let ip = |values: &[&str], hops| forwarded_ip(values.iter().copied(), hops);
+      let parsed = |ip: &str| ip.parse::<IpAddr>().ok();
+
+      assert_eq!(ip(&["1.1.1.1, 10.0.0.1"], 1), parsed("10.0.0.1"));
+      assert_eq!(ip(&["1.1.1.1, 10.0.0.1"], 2), parsed("1.1.1.1"));
+      assert_eq!(ip(&["6.6.6.6", "1.1.1.1, 10.0.0.1"], 2), parsed("1.1.1.1"));
+      assert_eq!(ip(&["1.1.1.1, 2001:db8::1"], 0), parsed("2001:db8::1"));
+      assert_eq!(ip(&["10.0.0.1"], 2), None);
+      assert_eq!(ip(&["1.1.1.1, not an ip"], 1), None);
+      assert_eq!(ip(&[], 1), None);
+   }
+
+   #[test]
+   fn requires_a_json_content_type() {
+      let is = |value: Option<&str>| {
+         let mut headers = HeaderMap::new();
+
+         if let Some(value) = value {
+            headers.insert(CONTENT_TYPE, value.parse().unwrap());
+         }
+
+         is_json(&headers)
+      };
+
+      assert!(is(Option::from("application/json")));
+      assert!(is(Option::from("Application/JSON; charset=utf-8")));
+      assert!(is(Option::from("application/merge-patch+json")));
+      assert!(!is(None));
+      assert!(!is(Option::from("text/plain")));
+      assert!(!is(Option::from("text/json")));
+      assert!(!is(Option::from("application/jsonp")));
+   }
+}
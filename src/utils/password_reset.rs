@@ -0,0 +1,161 @@
+use super::super::config::CONFIG;
+use super::mailer::{deliver, Email};
+use super::{now, parse_datetime, random_token, DATETIME_FORMAT};
+
+use chrono::{Duration, Utc};
+
+use sha2::{Digest, Sha256};
+
+use rusqlite::{Connection, OptionalExtension};
+
+// Only the hash is stored, so that the table is of no use to whoever reads
+// it. Tokens are random enough that a salt would add nothing.
+fn hash_token(token: &str) -> String {
+   format!("{:x}", Sha256::digest(token.as_bytes()))
+}
+
+/// Starts a password reset for a user, replacing the ones sent before, and
+/// returns its token, valid for `PASSWORD_RESET_MINUTES`.
+pub fn create_reset(conn: &Connection, user_id: &str) -> rusqlite::Result<String> {
+   let token = random_token();
+   let expires_at = Utc::now().naive_utc() + Duration::minutes(CONFIG.password_reset_minutes);
+
+   conn.execute("DELETE FROM password_resets WHERE user_id = ?", [user_id])?;
+   conn.execute(
+      "INSERT INTO password_resets (token_hash, user_id, expires_at, created_at) VALUES (?, ?, ?, ?)",
+      [
+         &hash_token(&token),
+         user_id,
+         &format!("{}", expires_at.format(DATETIME_FORMAT)),
+         &now(),
+      ],
+   )?;
+
+   Ok(token)
+}
+
+/// Uses a token of `create_reset`, which then stops working, and returns the
+/// id of its user.
+pub fn use_reset(conn: &Connection, token: &str) -> Result<String, (u16, &'static str)> {
+   let invalid = (400, "this token is invalid or expired");
+   let token_hash = hash_token(token.trim());
+
+   let row: Option<(String, String)> = conn
+      .query_row(
+         "SELECT user_id, expires_at FROM password_resets WHERE token_hash = ?",
+         [&token_hash],
+         |row| Ok((row.get(0)?, row.get(1)?)),
+      )
+      .optional()
+      .map_err(|_| (500, ""))?;
+
+   let (user_id, expires_at) = row.ok_or(invalid)?;
+
+   conn
+      .execute(
+         "DELETE FROM password_resets WHERE token_hash = ?",
+         [&token_hash],
+      )
+      .map_err(|_| (500, ""))?;
+
+   match parse_datetime(&expires_at) {
+      Some(expires_at) if expires_at > Utc::now().naive_utc() => Ok(user_id),
+      _ => Err(invalid),
+   }
+}
+
+/// Seconds since the last password reset of a user was sent.
+pub fn seconds_since_sent(conn: &Connection, user_id: &str) -> Option<i64> {
+   let created_at: Option<String> = conn
+      .query_row(
+         "SELECT MAX(created_at) FROM password_resets WHERE user_id = ?",
+         [user_id],
+         |row| row.get(0),
+      )
+      .unwrap_or(None);
+
+   created_at
+      .and_then(|created_at| parse_datetime(&created_at))
+      .map(|created_at| (Utc::now().naive_utc() - created_at).num_seconds())
+}
+
+/// Emails a token of `create_reset` to the user.
+pub fn send_reset(email: &str, firstname: &str, token: &str) {
+   let link = if CONFIG.app_url.is_empty() {
+      String::new()
+   } else {
+      format!(
+         "\n\nor open {}/password/reset?token={}",
+         CONFIG.app_url.trim_end_matches('/'),
+         token
+      )
+   };
+
+   deliver(Email {
+      to: email.to_string(),
+      subject: String::from("Reset your password"),
+      body: format!(
+         "Hello {},\n\nSomeone asked to reset the password of your account. Choose a new one by sending this token to POST /password/reset:\n\n{}{}\n\nIt expires in {} minutes. If it was not you, ignore this email: your password stays as it is.\n",
+         firstname, token, link, CONFIG.password_reset_minutes
+      ),
+   });
+}
+
+#[cfg(test)]
+mod tests {
+   use super::super::super::database::create_memory_connection;
+   use super::*;
+
+   fn database() -> Connection {
+      let conn = create_memory_connection();
+
+      conn
+         .execute(
+            "INSERT INTO users (id, firstname, lastname, email, password) VALUES ('alice', 'Alice', 'A', 'alice@x.io', '')",
+            [],
+         )
+         .unwrap();
+
+      conn
+   }
+
+   #[test]
+   fn tokens_work_once_and_only_the_last_one() {
+      let conn = database();
+      let first = create_reset(&conn, "alice").unwrap();
+      let second = create_reset(&conn, "alice").unwrap();
+
+      assert!(use_reset(&conn, &first).is_err());
+      assert_eq!(
+         use_reset(&conn, &format!(" {} ", second)),
+         Ok(String::from("alice"))
+      );
+      assert!(use_reset(&conn, &second).is_err());
+   }
+
+   #[test]
+   fn stores_hashes_and_refuses_expired_tokens() {
+      let conn = database();
+      let token = create_reset(&conn, "alice").unwrap();
+
+      let stored: String = conn
+         .query_row("SELECT token_hash FROM password_resets", [], |row| {
+            row.get(0)
+         })
+         .unwrap();
+
+      assert_ne!(stored, token);
+      assert!(use_reset(&conn, &stored).is_err());
+
+      let token = create_reset(&conn, "alice").unwrap();
+
+      conn
+         .execute(
+            "UPDATE password_resets SET expires_at = '2000-01-01 00:00:00'",
+            [],
+         )
+         .unwrap();
+
+      assert!(use_reset(&conn, &token).is_err());
+   }
+}
@@ -0,0 +1,387 @@
+use super::super::config::CONFIG;
+
+use std::borrow::Cow;
+use std::collections::HashSet;
+use std::convert::TryFrom;
+
+use argon2::password_hash::rand_core::OsRng;
+use argon2::password_hash::{
+   Error as PasswordHashError, PasswordHash, PasswordHasher, PasswordVerifier, SaltString,
+};
+use argon2::{Algorithm, Argon2, Params, Version};
+
+use lazy_static::lazy_static;
+
+use tokio::task;
+
+use uuid::Uuid;
+
+use validator::ValidationError;
+
+// One password a line, in lowercase.
+const COMMON_PASSWORDS_LIST: &str = include_str!("common_passwords.txt");
+
+lazy_static! {
+   static ref COMMON_PASSWORDS: HashSet<&'static str> = COMMON_PASSWORDS_LIST
+      .lines()
+      .map(|line| line.trim())
+      .filter(|line| !line.is_empty())
+      .collect();
+   // The words looked for inside longer passwords, longest first.
+   static ref COMMON_WORDS: Vec<&'static str> = {
+      let mut words: Vec<&'static str> = COMMON_PASSWORDS
+         .iter()
+         .filter(|word| word.chars().count() >= 4)
+         .copied()
+         .collect();
+
+      words.sort_by(|a, b| b.len().cmp(&a.len()).then(a.cmp(b)));
+      words
+   };
+}
+
+const KEYBOARD_ROWS: [&str; 4] = ["1234567890", "qwertyuiop", "asdfghjkl", "zxcvbnm"];
+
+/// The strongest score `strength` gives.
+pub const STRENGTH_MAX_SCORE: u8 = 4;
+
+// The password with the usual character swaps undone, once reading `1` as
+// `i` and once as `l`.
+fn unleet(password: &str) -> [Vec<char>; 2] {
+   let swap = |one: char| {
+      password
+         .chars()
+         .map(|character| match character {
+            '4' | '@' => 'a',
+            '3' => 'e',
+            '1' | '!' => one,
+            '0' => 'o',
+            '5' | '$' => 's',
+            '7' => 't',
+            character => character,
+         })
+         .collect()
+   };
+
+   [swap('i'), swap('l')]
+}
+
+fn is_common(password: &str) -> bool {
+   let lowercase = password.to_lowercase();
+   let stripped = lowercase.trim_end_matches(|character: char| !character.is_alphabetic());
+
+   COMMON_PASSWORDS.contains(lowercase.as_str())
+      || (stripped.chars().count() >= 4 && COMMON_PASSWORDS.contains(stripped))
+      || unleet(&lowercase)
+         .iter()
+         .any(|variant| COMMON_PASSWORDS.contains(variant.iter().collect::<String>().as_str()))
+}
+
+// Whether `next` continues `previous` as a repeat, an alphabet or digit
+// sequence, or a walk along a keyboard row.
+fn is_predictable(previous: char, next: char) -> bool {
+   let (previous, next) = (previous.to_ascii_lowercase(), next.to_ascii_lowercase());
+
+   if previous == next || (previous as i64 - next as i64).abs() == 1 {
+      return true;
+   }
+
+   KEYBOARD_ROWS
+      .iter()
+      .any(|row| match (row.find(previous), row.find(next)) {
+         (Some(a), Some(b)) => (a as i64 - b as i64).abs() == 1,
+         _ => false,
+      })
+}
+
+fn charset_size(password: &str) -> f64 {
+   let mut size = 0;
+
+   if password
+      .chars()
+      .any(|character| character.is_ascii_lowercase())
+   {
+      size += 26;
+   }
+   if password
+      .chars()
+      .any(|character| character.is_ascii_uppercase())
+   {
+      size += 26;
+   }
+   if password.chars().any(|character| character.is_ascii_digit()) {
+      size += 10;
+   }
+   if password
+      .chars()
+      .any(|character| character.is_ascii_punctuation() || character == ' ')
+   {
+      size += 33;
+   }
+   if !password.is_ascii() {
+      size += 100;
+   }
+
+   size as f64
+}
+
+/// A zxcvbn-style estimate of how hard the password is to guess, from 0
+/// (found in the first thousand guesses) to 4 (more than 10^10). Common
+/// passwords inside it count as one guess among the bundled list, and
+/// repeats, sequences and keyboard walks barely count at all.
+pub fn strength(password: &str) -> u8 {
+   let characters: Vec<char> = password.chars().collect();
+   let lowercase: Vec<char> = password.to_lowercase().chars().collect();
+
+   if characters.is_empty() {
+      return 0;
+   }
+
+   let word_bits = (COMMON_PASSWORDS.len() as f64).log2();
+   let mut covered = vec![false; characters.len()];
+   let mut bits = 0.0;
+
+   if lowercase.len() == characters.len() {
+      let [variant_i, variant_l] = unleet(&password.to_lowercase());
+
+      for word in COMMON_WORDS.iter() {
+         let word: Vec<char> = word.chars().collect();
+
+         for start in 0..=characters.len().saturating_sub(word.len()) {
+            let end = start + word.len();
+
+            if end > characters.len() || covered[start..end].iter().any(|covered| *covered) {
+               continue;
+            }
+
+            let found = [&lowercase, &variant_i, &variant_l]
+               .iter()
+               .any(|variant| variant[start..end] == word[..]);
+
+            if found {
+               covered[start..end]
+                  .iter_mut()
+                  .for_each(|covered| *covered = true);
+
+               // One more guess each for capitals and swapped characters.
+               bits += word_bits;
+               if characters[start..end].iter().any(|c| c.is_uppercase()) {
+                  bits += 1.0;
+               }
+               if lowercase[start..end] != word[..] {
+                  bits += 1.0;
+               }
+            }
+         }
+      }
+   }
+
+   let character_bits = charset_size(password).log2();
+
+   for (index, character) in characters.iter().enumerate() {
+      if covered[index] {
+         continue;
+      }
+
+      if index > 0 && !covered[index - 1] && is_predictable(characters[index - 1], *character) {
+         bits += 1.0;
+      } else {
+         bits += character_bits;
+      }
+   }
+
+   // 10^3, 10^6, 10^8 and 10^10 guesses, as zxcvbn scores them.
+   match bits {
+      bits if bits < 10.0 => 0,
+      bits if bits < 19.9 => 1,
+      bits if bits < 26.6 => 2,
+      bits if bits < 33.2 => 3,
+      _ => STRENGTH_MAX_SCORE,
+   }
+}
+
+/// `custom` rule for new passwords: `PASSWORD_MIN_LENGTH` characters, not a
+/// common password and a `strength` of at least `PASSWORD_MIN_SCORE`.
+pub fn password_policy(password: &str) -> Result<(), ValidationError> {
+   let length = password.chars().count();
+
+   if length < CONFIG.password_min_length {
+      let mut error = ValidationError::new("length");
+      error.add_param(Cow::from("min"), &CONFIG.password_min_length);
+
+      return Err(error);
+   }
+
+   if is_common(password) {
+      return Err(
+         ValidationError::new("common").with_message(Cow::from("is a commonly used password")),
+      );
+   }
+
+   let score = strength(password);
+
+   if score < CONFIG.password_min_score.min(STRENGTH_MAX_SCORE) {
+      let mut error = ValidationError::new("weak")
+         .with_message(Cow::from("is too easy to guess, add words or characters"));
+      error.add_param(Cow::from("score"), &score);
+
+      return Err(error);
+   }
+
+   Ok(())
+}
+
+fn argon2() -> Result<Argon2<'static>, PasswordHashError> {
+   let params = Params::new(
+      CONFIG.argon2_memory_kib,
+      CONFIG.argon2_iterations,
+      CONFIG.argon2_parallelism,
+      None,
+   )?;
+
+   Ok(Argon2::new(Algorithm::Argon2id, Version::V0x13, params))
+}
+
+fn hash(password: &str) -> Result<String, PasswordHashError> {
+   let salt = SaltString::generate(&mut OsRng);
+
+   Ok(argon2()?
+      .hash_password(password.as_bytes(), &salt)?
+      .to_string())
+}
+
+/// Hashes a password with Argon2id and the `ARGON2_*` params of the config,
+/// as a PHC string: `$argon2id$v=19$m=19456,t=2,p=1$<salt>$<hash>`. The
+/// hashing runs on the blocking pool, since it keeps a thread busy for tens
+/// of milliseconds.
+pub async fn hash_password(password: &str) -> Result<String, PasswordHashError> {
+   let password = String::from(password);
+
+   task::spawn_blocking(move || hash(&password))
+      .await
+      .unwrap_or(Err(PasswordHashError::Crypto))
+}
+
+lazy_static! {
+   /// A hash to check passwords against when the email is unknown, made with
+   /// the same params as the others so that it takes as long. Without it
+   /// unknown emails would answer faster, so failing to make it is fatal.
+   pub static ref UNKNOWN_USER_HASH: String =
+      hash(&Uuid::new_v4().to_string()).expect("hashing with the configured Argon2 params failed");
+}
+
+fn verify(password: &str, stored: &str) -> bool {
+   if stored.starts_with("$2") {
+      return bcrypt::verify(password, stored).unwrap_or(false);
+   }
+
+   match PasswordHash::new(stored) {
+      Ok(parsed) => Argon2::default()
+         .verify_password(password.as_bytes(), &parsed)
+         .is_ok(),
+      _ => false,
+   }
+}
+
+/// Checks a password against a stored hash: a PHC string, whose params are
+/// read from the string itself, or a bcrypt hash from before Argon2id. Like
+/// `hash_password`, on the blocking pool.
+pub async fn verify_password(password: &str, stored: &str) -> bool {
+   let (password, stored) = (String::from(password), String::from(stored));
+
+   task::spawn_blocking(move || verify(&password, &stored))
+      .await
+      .unwrap_or(false)
+}
+
+/// Whether a stored hash is bcrypt or Argon2 with other params than the
+/// configured ones, so that it is replaced the next time its password is
+/// known.
+pub fn needs_rehash(stored: &str) -> bool {
+   let parsed = match PasswordHash::new(stored) {
+      Ok(parsed) => parsed,
+      _ => return true,
+   };
+
+   let params = match Params::try_from(&parsed) {
+      Ok(params) => params,
+      _ => return true,
+   };
+
+   parsed.algorithm != Algorithm::Argon2id.ident()
+      || params.m_cost() != CONFIG.argon2_memory_kib
+      || params.t_cost() != CONFIG.argon2_iterations
+      || params.p_cost() != CONFIG.argon2_parallelism
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   fn refused_because(password: &str) -> Option<String> {
+      password_policy(password)
+         .err()
+         .map(|error| error.code.to_string())
+   }
+
+   #[test]
+   fn refuses_common_and_predictable_passwords() {
+      assert_eq!(refused_because("p@ssw0rd").as_deref(), Some("common"));
+      assert_eq!(refused_because("Password1!").as_deref(), Some("common"));
+      assert!(refused_because("qwertyuiop").is_some());
+      assert!(refused_because("abcdefgh").is_some());
+      assert_eq!(refused_because("Xy7!").as_deref(), Some("length"));
+   }
+
+   #[test]
+   fn accepts_long_passphrases() {
+      assert_eq!(refused_because("correct horse battery staple"), None);
+      assert_eq!(strength("correct horse battery staple"), STRENGTH_MAX_SCORE);
+   }
+
+   #[test]
+   fn verifies_argon2_and_bcrypt_hashes() {
+      let argon2 = hash("Dragon!Fly88").unwrap();
+      let bcrypt = bcrypt::hash("Dragon!Fly88", 4).unwrap();
+
+      assert!(verify("Dragon!Fly88", &argon2));
+      assert!(!verify("dragon!fly88", &argon2));
+      assert!(verify("Dragon!Fly88", &bcrypt));
+      assert!(!verify("dragon!fly88", &bcrypt));
+      assert!(!verify("Dragon!Fly88", "not a hash"));
+      assert!(!verify("", &UNKNOWN_USER_HASH));
+      assert!(!needs_rehash(&UNKNOWN_USER_HASH));
+   }
+
+   #[test]
+   fn rehashes_bcrypt_and_other_argon2_params() {
+      let current = hash("Dragon!Fly88").unwrap();
+      let bcrypt = bcrypt::hash("Dragon!Fly88", 4).unwrap();
+
+      let params = Params::new(
+         CONFIG.argon2_memory_kib / 2,
+         CONFIG.argon2_iterations + 1,
+         CONFIG.argon2_parallelism,
+         None,
+      )
+      .unwrap();
+      let older = Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+         .hash_password(b"Dragon!Fly88", &SaltString::generate(&mut OsRng))
+         .unwrap()
+         .to_string();
+      let argon2i = Argon2::new(
+         Algorithm::Argon2i,
+         Version::V0x13,
+         argon2().unwrap().params().clone(),
+      )
+      .hash_password(b"Dragon!Fly88", &SaltString::generate(&mut OsRng))
+      .unwrap()
+      .to_string();
+
+      assert!(!needs_rehash(&current));
+      assert!(needs_rehash(&bcrypt));
+      assert!(needs_rehash(&older));
+      assert!(needs_rehash(&argon2i));
+      assert!(verify("Dragon!Fly88", &older));
+   }
+}
@@ -0,0 +1,268 @@
+use super::super::views::tasks::{TaskCreated, TASK_COLUMNS};
+
+use rusqlite::{Connection, OptionalExtension};
+
+/// SQL condition matching the tasks `?1` can see: owned, assigned, shared
+/// directly or in a project the user owns or was invited to.
+pub const VISIBLE_TASKS: &str = "(tasks.user_id = ?1 OR tasks.assignee_id = ?1 OR EXISTS (SELECT 1 FROM memberships WHERE resource_type = 'task' AND resource_id = tasks.id AND user_id = ?1) OR EXISTS (SELECT 1 FROM memberships WHERE resource_type = 'project' AND resource_id = tasks.project_id AND user_id = ?1) OR EXISTS (SELECT 1 FROM projects WHERE projects.id = tasks.project_id AND projects.user_id = ?1))";
+
+/// SQL condition matching the tasks `?1` holds `Role::Owner` on, by the
+/// rules of `task_role`: created, shared as owner or in a project the user
+/// owns or was invited to as owner.
+pub const OWNED_TASKS: &str = "(tasks.user_id = ?1 OR EXISTS (SELECT 1 FROM memberships WHERE resource_type = 'task' AND resource_id = tasks.id AND user_id = ?1 AND role = 'owner') OR EXISTS (SELECT 1 FROM memberships WHERE resource_type = 'project' AND resource_id = tasks.project_id AND user_id = ?1 AND role = 'owner') OR EXISTS (SELECT 1 FROM projects WHERE projects.id = tasks.project_id AND projects.user_id = ?1))";
+
+/// What a user may do with a shared task or project. Roles are ordered, so
+/// `role >= Role::Editor` reads as "at least an editor". `Assignee` is never
+/// stored in `memberships`; it comes from `tasks.assignee_id` and allows
+/// completing the task on top of viewing it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Role {
+   Viewer,
+   Assignee,
+   Editor,
+   Owner,
+}
+
+impl Role {
+   pub fn parse(role: &str) -> Option<Role> {
+      match role {
+         "viewer" => Option::from(Role::Viewer),
+         "editor" => Option::from(Role::Editor),
+         "owner" => Option::from(Role::Owner),
+         _ => None,
+      }
+   }
+
+   pub fn as_str(self) -> &'static str {
+      match self {
+         Role::Viewer => "viewer",
+         Role::Assignee => "assignee",
+         Role::Editor => "editor",
+         Role::Owner => "owner",
+      }
+   }
+}
+
+fn membership_role(
+   conn: &Connection,
+   resource_type: &str,
+   resource_id: &str,
+   user_id: &str,
+) -> Option<Role> {
+   conn
+      .query_row(
+         "SELECT role FROM memberships WHERE resource_type = ? AND resource_id = ? AND user_id = ?",
+         [resource_type, resource_id, user_id],
+         |row| row.get::<_, String>(0),
+      )
+      .optional()
+      .unwrap()
+      .and_then(|role| Role::parse(&role))
+}
+
+pub fn project_role(conn: &Connection, project_id: &str, user_id: &str) -> Option<Role> {
+   let owner: Option<String> = conn
+      .query_row(
+         "SELECT user_id FROM projects WHERE id = ?",
+         [project_id],
+         |row| row.get(0),
+      )
+      .optional()
+      .unwrap();
+
+   match owner {
+      Some(owner) if owner == user_id => Option::from(Role::Owner),
+      Some(_) => membership_role(conn, "project", project_id, user_id),
+      None => None,
+   }
+}
+
+/// The highest role `user_id` holds on `task`, either as its creator, as its
+/// assignee, as a member of the task or as a member of the task's project.
+pub fn task_role(conn: &Connection, task: &TaskCreated, user_id: &str) -> Option<Role> {
+   if task.user_id == user_id {
+      return Option::from(Role::Owner);
+   }
+
+   let task_role = membership_role(conn, "task", &task.id, user_id);
+   let project_role = task
+      .project_id
+      .as_deref()
+      .and_then(|project_id| project_role(conn, project_id, user_id));
+
+   let assignee_role = if task.assignee_id.as_deref() == Option::from(user_id) {
+      Option::from(Role::Assignee)
+   } else {
+      None
+   };
+
+   task_role.max(project_role).max(assignee_role)
+}
+
+/// Looks a task up whether or not it is in the trash.
+pub fn find_any_task(conn: &Connection, task_id: &str) -> Option<TaskCreated> {
+   conn
+      .query_row(
+         &format!("SELECT {} FROM tasks WHERE tasks.id = ?", TASK_COLUMNS),
+         [task_id],
+         |row| TaskCreated::from_row(row, 0),
+      )
+      .optional()
+      .unwrap()
+}
+
+/// Looks a task up, whether or not it is in the trash, when `user_id` can
+/// see it. Tasks of others answer `None` like unknown ids, so that imports
+/// naming them do not tell which ids exist.
+pub fn find_visible_task(conn: &Connection, task_id: &str, user_id: &str) -> Option<TaskCreated> {
+   find_any_task(conn, task_id).filter(|task| task_role(conn, task, user_id).is_some())
+}
+
+/// Looks a task up for `user_id`, who needs at least `minimum` on it. Tasks
+/// that are not shared with the user answer 404 so their existence does not
+/// leak; a role below `minimum` answers 403.
+pub fn find_permitted_task(
+   conn: &Connection,
+   task_id: &str,
+   user_id: &str,
+   minimum: Role,
+   deleted: bool,
+) -> Result<(TaskCreated, Role), (u16, &'static str)> {
+   let task = match find_any_task(conn, task_id) {
+      Some(task) if task.deleted_at.is_some() == deleted => task,
+      _ => return Err((404, "this task not exists")),
+   };
+
+   match task_role(conn, &task, user_id) {
+      Some(role) if role >= minimum => Ok((task, role)),
+      Some(_) => Err((403, "you not have permission for to follow")),
+      None => Err((404, "this task not exists")),
+   }
+}
+
+/// Everyone who can see `task_id`: its creator, its assignee, its members and
+/// the owner and members of its project.
+pub fn task_audience(conn: &Connection, task_id: &str) -> Vec<String> {
+   let mut query = conn
+      .prepare(
+         "SELECT tasks.user_id FROM tasks WHERE tasks.id = ?1
+         UNION SELECT tasks.assignee_id FROM tasks WHERE tasks.id = ?1 AND tasks.assignee_id IS NOT NULL
+         UNION SELECT memberships.user_id FROM memberships WHERE memberships.resource_type = 'task' AND memberships.resource_id = ?1
+         UNION SELECT projects.user_id FROM projects INNER JOIN tasks ON tasks.project_id = projects.id WHERE tasks.id = ?1
+         UNION SELECT memberships.user_id FROM memberships INNER JOIN tasks ON tasks.project_id = memberships.resource_id WHERE memberships.resource_type = 'project' AND tasks.id = ?1",
+      )
+      .unwrap();
+   let data = query.query_map([task_id], |row| row.get(0)).unwrap();
+
+   let mut users: Vec<String> = vec![];
+   for user in data {
+      users.push(user.unwrap());
+   }
+
+   users
+}
+
+#[cfg(test)]
+mod tests {
+   use super::super::super::database::create_memory_connection;
+   use super::*;
+
+   fn owned_tasks(conn: &Connection, user_id: &str) -> Vec<String> {
+      let mut query = conn
+         .prepare(&format!(
+            "SELECT tasks.id FROM tasks WHERE {} ORDER BY tasks.id",
+            OWNED_TASKS
+         ))
+         .unwrap();
+
+      query
+         .query_map([user_id], |row| row.get(0))
+         .unwrap()
+         .map(|id| id.unwrap())
+         .collect()
+   }
+
+   #[test]
+   fn owned_tasks_agree_with_task_role() {
+      let conn = create_memory_connection();
+
+      conn
+         .execute_batch(
+            "INSERT INTO users (id, firstname, lastname, email, password) VALUES ('alice', 'A', 'A', 'alice@x.io', '');
+            INSERT INTO users (id, firstname, lastname, email, password) VALUES ('bob', 'B', 'B', 'bob@x.io', '');
+            INSERT INTO projects (id, name, user_id, created_at) VALUES ('home', 'Home', 'alice', '');
+            INSERT INTO tasks (id, name, completed, user_id, project_id, deleted_at) VALUES ('1', 'Created', 0, 'alice', NULL, 'x');
+            INSERT INTO tasks (id, name, completed, user_id, project_id, deleted_at) VALUES ('2', 'In a project', 0, 'bob', 'home', 'x');
+            INSERT INTO tasks (id, name, completed, user_id, project_id, deleted_at) VALUES ('3', 'Shared as owner', 0, 'bob', NULL, 'x');
+            INSERT INTO tasks (id, name, completed, user_id, project_id, deleted_at) VALUES ('4', 'Shared as editor', 0, 'bob', NULL, 'x');
+            INSERT INTO tasks (id, name, completed, user_id, assignee_id, deleted_at) VALUES ('5', 'Assigned', 0, 'bob', 'alice', 'x');
+            INSERT INTO memberships (resource_type, resource_id, user_id, role, invited_by, created_at) VALUES ('task', '3', 'alice', 'owner', 'bob', '');
+            INSERT INTO memberships (resource_type, resource_id, user_id, role, invited_by, created_at) VALUES ('task', '4', 'alice', 'editor', 'bob', '');",
+         )
+         .unwrap();
+
+      for user_id in ["alice", "bob"].iter() {
+         let expected: Vec<String> = (1..=5)
+            .map(|id| id.to_string())
+            .filter(|id| {
+               let task = find_any_task(&conn, id).unwrap();
+
+               task_role(&conn, &task, user_id) == Option::from(Role::Owner)
+            })
+            .collect();
+
+         assert_eq!(owned_tasks(&conn, user_id), expected, "for {}", user_id);
+      }
+
+      assert_eq!(owned_tasks(&conn, "alice"), vec!["1", "2", "3"]);
+   }
+
+   #[test]
+   fn takes_the_highest_role_and_hides_unshared_tasks() {
+      let conn = create_memory_connection();
+
+      conn
+         .execute_batch(
+            "INSERT INTO users (id, firstname, lastname, email, password) VALUES ('alice', 'A', 'A', 'alice@x.io', '');
+            INSERT INTO users (id, firstname, lastname, email, password) VALUES ('bob', 'B', 'B', 'bob@x.io', '');
+            INSERT INTO users (id, firstname, lastname, email, password) VALUES ('carol', 'C', 'C', 'carol@x.io', '');
+            INSERT INTO users (id, firstname, lastname, email, password) VALUES ('dave', 'D', 'D', 'dave@x.io', '');
+            INSERT INTO projects (id, name, user_id, created_at) VALUES ('home', 'Home', 'alice', '');
+            INSERT INTO tasks (id, name, completed, user_id, project_id, assignee_id) VALUES ('milk', 'Milk', 0, 'alice', 'home', 'dave');
+            INSERT INTO memberships (resource_type, resource_id, user_id, role, invited_by, created_at) VALUES ('project', 'home', 'bob', 'viewer', 'alice', '');
+            INSERT INTO memberships (resource_type, resource_id, user_id, role, invited_by, created_at) VALUES ('task', 'milk', 'bob', 'editor', 'alice', '');
+            INSERT INTO memberships (resource_type, resource_id, user_id, role, invited_by, created_at) VALUES ('project', 'home', 'carol', 'viewer', 'alice', '');",
+         )
+         .unwrap();
+
+      let role = |user_id: &str| {
+         find_permitted_task(&conn, "milk", user_id, Role::Viewer, false).map(|(_, role)| role)
+      };
+
+      assert_eq!(role("alice"), Ok(Role::Owner));
+      assert_eq!(role("bob"), Ok(Role::Editor));
+      assert_eq!(role("carol"), Ok(Role::Viewer));
+      assert_eq!(role("dave"), Ok(Role::Assignee));
+      assert_eq!(role("eve").unwrap_err().0, 404);
+
+      let edit = |user_id: &str| {
+         find_permitted_task(&conn, "milk", user_id, Role::Editor, false).map(|(_, role)| role)
+      };
+
+      assert_eq!(edit("bob"), Ok(Role::Editor));
+      assert_eq!(edit("carol").unwrap_err().0, 403);
+      assert_eq!(edit("dave").unwrap_err().0, 403);
+      assert_eq!(edit("eve").unwrap_err().0, 404);
+      assert_eq!(
+         find_permitted_task(&conn, "milk", "alice", Role::Viewer, true)
+            .unwrap_err()
+            .0,
+         404
+      );
+
+      let mut audience = task_audience(&conn, "milk");
+      audience.sort();
+
+      assert_eq!(audience, vec!["alice", "bob", "carol", "dave"]);
+   }
+}
@@ -0,0 +1,475 @@
+use std::convert::TryFrom;
+
+use chrono::{Datelike, Duration, NaiveDate, NaiveDateTime, Weekday};
+
+use super::{parse_datetime, DATETIME_FORMAT};
+
+// Larger intervals are of no use and push dates past what chrono can hold.
+const INTERVAL_MAX: u32 = 1000;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Frequency {
+   Daily,
+   Weekly,
+   Monthly,
+}
+
+/// A recurrence rule, stored in the `tasks.recurrence` column as the subset
+/// of an RFC 5545 RRULE we understand: `FREQ` (DAILY, WEEKLY or MONTHLY),
+/// `INTERVAL`, `BYDAY`, `BYMONTHDAY`, `COUNT` and `UNTIL`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Recurrence {
+   pub frequency: Frequency,
+   pub interval: u32,
+   pub by_day: Vec<Weekday>,
+   pub by_month_day: Option<u32>,
+   pub count: Option<u32>,
+   pub until: Option<NaiveDateTime>,
+}
+
+impl Recurrence {
+   fn new(frequency: Frequency) -> Recurrence {
+      Recurrence {
+         frequency,
+         interval: 1,
+         by_day: vec![],
+         by_month_day: None,
+         count: None,
+         until: None,
+      }
+   }
+
+   /// Accepts the shorthands `daily`, `weekly`, `weekly:MO,WE`, `monthly` and
+   /// `monthly:15`, or an RRULE with or without the `RRULE:` prefix.
+   pub fn parse(rule: &str) -> Result<Recurrence, &'static str> {
+      let rule = rule.trim();
+      let lowercase = rule.to_lowercase();
+
+      let mut shorthand = lowercase.splitn(2, ':');
+      let kind = shorthand.next().unwrap_or("");
+      let argument = shorthand.next();
+
+      match (kind, argument) {
+         ("daily", None) => return Ok(Recurrence::new(Frequency::Daily)),
+         ("weekly", argument) => {
+            let mut recurrence = Recurrence::new(Frequency::Weekly);
+
+            if let Some(days) = argument {
+               recurrence.by_day = parse_weekdays(days)?;
+            }
+
+            return Ok(recurrence);
+         }
+         ("monthly", argument) => {
+            let mut recurrence = Recurrence::new(Frequency::Monthly);
+
+            if let Some(day) = argument {
+               recurrence.by_month_day = Option::from(parse_month_day(day)?);
+            }
+
+            return Ok(recurrence);
+         }
+         _ => {}
+      }
+
+      let rule = if lowercase.starts_with("rrule:") {
+         &rule[6..]
+      } else {
+         rule
+      };
+
+      let mut frequency = None;
+      let mut recurrence = Recurrence::new(Frequency::Daily);
+
+      for part in rule.split(';').filter(|part| !part.is_empty()) {
+         let mut pair = part.splitn(2, '=');
+         let key = pair.next().unwrap_or("").trim().to_uppercase();
+         let value = pair.next().ok_or("recurrence rule invalid")?.trim();
+
+         match key.as_str() {
+            "FREQ" => {
+               frequency = Option::from(match value.to_uppercase().as_str() {
+                  "DAILY" => Frequency::Daily,
+                  "WEEKLY" => Frequency::Weekly,
+                  "MONTHLY" => Frequency::Monthly,
+                  _ => return Err("recurrence frequency not supported"),
+               })
+            }
+            "INTERVAL" => {
+               recurrence.interval = match value.parse::<u32>() {
+                  Ok(interval) if (1..=INTERVAL_MAX).contains(&interval) => interval,
+                  _ => return Err("recurrence interval invalid"),
+               }
+            }
+            "BYDAY" => recurrence.by_day = parse_weekdays(value)?,
+            "BYMONTHDAY" => recurrence.by_month_day = Option::from(parse_month_day(value)?),
+            "COUNT" => {
+               recurrence.count = match value.parse::<u32>() {
+                  Ok(count) if count >= 1 => Option::from(count),
+                  _ => return Err("recurrence count invalid"),
+               }
+            }
+            "UNTIL" => {
+               let until = NaiveDateTime::parse_from_str(value, "%Y%m%dT%H%M%SZ")
+                  .or_else(|_| NaiveDateTime::parse_from_str(value, "%Y%m%dT%H%M%S"))
+                  .ok()
+                  .or_else(|| {
+                     NaiveDate::parse_from_str(value, "%Y%m%d")
+                        .ok()
+                        .map(|date| date.and_hms(23, 59, 59))
+                  })
+                  .or_else(|| parse_datetime(value));
+
+               match until {
+                  Some(until) => recurrence.until = Option::from(until),
+                  None => return Err("recurrence until invalid"),
+               }
+            }
+            "WKST" => {}
+            _ => return Err("recurrence rule not supported"),
+         }
+      }
+
+      match frequency {
+         Some(frequency) => recurrence.frequency = frequency,
+         None => return Err("recurrence frequency is necessary"),
+      }
+
+      if recurrence.count.is_some() && recurrence.until.is_some() {
+         return Err("recurrence cannot have count and until");
+      }
+
+      if recurrence.frequency != Frequency::Weekly && !recurrence.by_day.is_empty() {
+         return Err("recurrence byday is only supported on weekly rules");
+      }
+
+      if recurrence.frequency != Frequency::Monthly && recurrence.by_month_day.is_some() {
+         return Err("recurrence bymonthday is only supported on monthly rules");
+      }
+
+      Ok(recurrence)
+   }
+
+   pub fn to_rrule(&self) -> String {
+      self.rrule("")
+   }
+
+   /// Same as `to_rrule` with `UNTIL` marked as UTC, which RFC 5545 requires
+   /// when the rule sits next to a UTC `DTSTART` or `DUE`.
+   pub fn to_utc_rrule(&self) -> String {
+      self.rrule("Z")
+   }
+
+   fn rrule(&self, until_suffix: &str) -> String {
+      let frequency = match self.frequency {
+         Frequency::Daily => "DAILY",
+         Frequency::Weekly => "WEEKLY",
+         Frequency::Monthly => "MONTHLY",
+      };
+
+      let mut parts = vec![format!("FREQ={}", frequency)];
+
+      if self.interval > 1 {
+         parts.push(format!("INTERVAL={}", self.interval));
+      }
+
+      if !self.by_day.is_empty() {
+         let days: Vec<&str> = self.by_day.iter().map(|day| weekday_code(*day)).collect();
+
+         parts.push(format!("BYDAY={}", days.join(",")));
+      }
+
+      if let Some(day) = self.by_month_day {
+         parts.push(format!("BYMONTHDAY={}", day));
+      }
+
+      if let Some(count) = self.count {
+         parts.push(format!("COUNT={}", count));
+      }
+
+      if let Some(until) = self.until {
+         parts.push(format!(
+            "UNTIL={}{}",
+            until.format("%Y%m%dT%H%M%S"),
+            until_suffix
+         ));
+      }
+
+      parts.join(";")
+   }
+
+   /// Pins the rule to `start` so later occurrences keep the anchor day even
+   /// after being clamped to a shorter month.
+   pub fn anchored(mut self, start: NaiveDateTime) -> Recurrence {
+      if self.frequency == Frequency::Monthly && self.by_month_day.is_none() {
+         self.by_month_day = Option::from(start.day());
+      }
+
+      self
+   }
+
+   /// The rule that the task generated from `self` should carry, or `None`
+   /// when `self` describes the last occurrence.
+   pub fn advance(&self) -> Option<Recurrence> {
+      match self.count {
+         Some(count) if count <= 1 => None,
+         Some(count) => {
+            let mut recurrence = self.clone();
+            recurrence.count = Option::from(count - 1);
+
+            Option::from(recurrence)
+         }
+         None => Option::from(self.clone()),
+      }
+   }
+
+   /// The occurrence after `from`, or `None` past `UNTIL` or past the dates
+   /// chrono can represent.
+   pub fn next_after(&self, from: NaiveDateTime) -> Option<NaiveDateTime> {
+      let interval = self.interval as i64;
+
+      let next = match self.frequency {
+         Frequency::Daily => from.checked_add_signed(Duration::days(interval))?,
+         Frequency::Weekly => {
+            let weekday = from.weekday().num_days_from_monday() as i64;
+
+            // A later day of the same week, or else the first day of the week
+            // `interval` weeks on.
+            let later = self
+               .by_day
+               .iter()
+               .map(|day| day.num_days_from_monday() as i64)
+               .find(|day| *day > weekday);
+
+            let days = match (later, self.by_day.first()) {
+               (_, None) => interval * 7,
+               (Some(day), _) => day - weekday,
+               (None, Some(first)) => interval * 7 - weekday + first.num_days_from_monday() as i64,
+            };
+
+            from.checked_add_signed(Duration::days(days))?
+         }
+         Frequency::Monthly => {
+            let day = self.by_month_day.unwrap_or_else(|| from.day());
+            let on_day = |year: i32, month: u32| {
+               NaiveDate::from_ymd_opt(year, month, day.min(days_in_month(year, month)?))
+                  .map(|date| date.and_time(from.time()))
+            };
+
+            // A later day of the same month when every month recurs, or
+            // else that day `interval` months on.
+            let current = on_day(from.year(), from.month()).filter(|date| *date > from);
+
+            match current {
+               Some(date) if interval == 1 => date,
+               _ => {
+                  let months = from.year() as i64 * 12 + from.month0() as i64 + interval;
+                  let (year, month) = (i32::try_from(months / 12).ok()?, (months % 12) as u32 + 1);
+
+                  on_day(year, month)?
+               }
+            }
+         }
+      };
+
+      match self.until {
+         Some(until) if next > until => None,
+         _ => Option::from(next),
+      }
+   }
+
+   /// The next `amount` occurrences after `start`, honouring `COUNT` (which
+   /// includes `start` itself) and `UNTIL`.
+   pub fn occurrences(&self, start: NaiveDateTime, amount: usize) -> Vec<String> {
+      let mut occurrences = vec![];
+      let mut current = start;
+      let mut recurrence = self.clone();
+
+      while occurrences.len() < amount {
+         recurrence = match recurrence.advance() {
+            Some(recurrence) => recurrence,
+            None => break,
+         };
+
+         current = match recurrence.next_after(current) {
+            Some(next) => next,
+            None => break,
+         };
+
+         occurrences.push(format!("{}", current.format(DATETIME_FORMAT)));
+      }
+
+      occurrences
+   }
+}
+
+fn parse_weekdays(value: &str) -> Result<Vec<Weekday>, &'static str> {
+   let mut days = vec![];
+
+   for day in value.split(',') {
+      let day = match day.trim().to_uppercase().as_str() {
+         "MO" => Weekday::Mon,
+         "TU" => Weekday::Tue,
+         "WE" => Weekday::Wed,
+         "TH" => Weekday::Thu,
+         "FR" => Weekday::Fri,
+         "SA" => Weekday::Sat,
+         "SU" => Weekday::Sun,
+         _ => return Err("recurrence weekday invalid"),
+      };
+
+      if !days.contains(&day) {
+         days.push(day);
+      }
+   }
+
+   days.sort_by_key(|day: &Weekday| day.num_days_from_monday());
+
+   Ok(days)
+}
+
+fn parse_month_day(value: &str) -> Result<u32, &'static str> {
+   match value.trim().parse::<u32>() {
+      Ok(day) if (1..=31).contains(&day) => Ok(day),
+      _ => Err("recurrence month day invalid"),
+   }
+}
+
+fn weekday_code(day: Weekday) -> &'static str {
+   match day {
+      Weekday::Mon => "MO",
+      Weekday::Tue => "TU",
+      Weekday::Wed => "WE",
+      Weekday::Thu => "TH",
+      Weekday::Fri => "FR",
+      Weekday::Sat => "SA",
+      Weekday::Sun => "SU",
+   }
+}
+
+fn days_in_month(year: i32, month: u32) -> Option<u32> {
+   let (next_year, next_month) = if month == 12 {
+      (year + 1, 1)
+   } else {
+      (year, month + 1)
+   };
+
+   NaiveDate::from_ymd_opt(next_year, next_month, 1)?
+      .pred_opt()
+      .map(|date| date.day())
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   fn datetime(value: &str) -> NaiveDateTime {
+      parse_datetime(value).unwrap()
+   }
+
+   fn next(rule: &str, from: &str) -> Option<NaiveDateTime> {
+      Recurrence::parse(rule).unwrap().next_after(datetime(from))
+   }
+
+   #[test]
+   fn clamps_month_days_to_short_months() {
+      let recurrence = Recurrence::parse("FREQ=MONTHLY;BYMONTHDAY=31").unwrap();
+
+      assert_eq!(
+         recurrence.occurrences(datetime("2024-01-31 09:00:00"), 4),
+         vec![
+            "2024-02-29 09:00:00",
+            "2024-03-31 09:00:00",
+            "2024-04-30 09:00:00",
+            "2024-05-31 09:00:00",
+         ]
+      );
+   }
+
+   #[test]
+   fn starts_on_a_later_month_day_of_the_same_month() {
+      assert_eq!(
+         next("FREQ=MONTHLY;BYMONTHDAY=20", "2024-01-05 09:00:00"),
+         Option::from(datetime("2024-01-20 09:00:00"))
+      );
+      assert_eq!(
+         next("FREQ=MONTHLY;BYMONTHDAY=20", "2024-01-20 09:00:00"),
+         Option::from(datetime("2024-02-20 09:00:00"))
+      );
+      assert_eq!(
+         next("FREQ=MONTHLY;BYMONTHDAY=31", "2024-02-29 09:00:00"),
+         Option::from(datetime("2024-03-31 09:00:00"))
+      );
+      assert_eq!(
+         next(
+            "FREQ=MONTHLY;INTERVAL=2;BYMONTHDAY=20",
+            "2024-01-05 09:00:00"
+         ),
+         Option::from(datetime("2024-03-20 09:00:00"))
+      );
+   }
+
+   #[test]
+   fn skips_weeks_by_interval() {
+      let rule = "FREQ=WEEKLY;INTERVAL=2;BYDAY=MO,FR";
+
+      // 2024-01-01 is a Monday.
+      assert_eq!(
+         next(rule, "2024-01-01 08:00:00"),
+         Some(datetime("2024-01-05 08:00:00"))
+      );
+      assert_eq!(
+         next(rule, "2024-01-05 08:00:00"),
+         Some(datetime("2024-01-15 08:00:00"))
+      );
+      assert_eq!(
+         next(rule, "2024-01-07 08:00:00"),
+         Some(datetime("2024-01-15 08:00:00"))
+      );
+      assert_eq!(
+         next("FREQ=WEEKLY;INTERVAL=3", "2024-01-03 08:00:00"),
+         Some(datetime("2024-01-24 08:00:00"))
+      );
+   }
+
+   #[test]
+   fn stops_at_count_and_until() {
+      let start = datetime("2024-01-01 08:00:00");
+
+      assert_eq!(
+         Recurrence::parse("FREQ=DAILY;COUNT=3")
+            .unwrap()
+            .occurrences(start, 10),
+         vec!["2024-01-02 08:00:00", "2024-01-03 08:00:00"]
+      );
+      assert_eq!(
+         Recurrence::parse("FREQ=DAILY;UNTIL=20240103T120000Z")
+            .unwrap()
+            .occurrences(start, 10),
+         vec!["2024-01-02 08:00:00", "2024-01-03 08:00:00"]
+      );
+   }
+
+   #[test]
+   fn refuses_huge_intervals() {
+      assert!(Recurrence::parse("FREQ=DAILY;INTERVAL=100000000").is_err());
+      assert!(Recurrence::parse("FREQ=WEEKLY;BYDAY=MO;INTERVAL=4000000000").is_err());
+      assert!(Recurrence::parse("FREQ=MONTHLY;INTERVAL=1000").is_ok());
+   }
+
+   #[test]
+   fn ends_instead_of_overflowing() {
+      let end = NaiveDate::from_ymd(262_000, 12, 1).and_hms(0, 0, 0);
+
+      for rule in [
+         "FREQ=DAILY;INTERVAL=1000",
+         "FREQ=WEEKLY;BYDAY=MO;INTERVAL=1000",
+         "FREQ=MONTHLY;INTERVAL=1000",
+      ] {
+         let mut recurrence = Recurrence::parse(rule).unwrap();
+         recurrence.interval = u32::MAX;
+
+         assert_eq!(recurrence.next_after(end), None, "{}", rule);
+      }
+   }
+}
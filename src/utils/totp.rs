@@ -0,0 +1,277 @@
+use super::super::config::CONFIG;
+use super::now;
+
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+
+use chrono::Utc;
+
+use hmac::{Hmac, Mac};
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+
+use rusqlite::{params, Connection};
+
+const ALPHABET: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+const STEP_SECONDS: i64 = 30;
+const DIGITS: usize = 6;
+// Codes of the step before and after the current one work too, for phones
+// whose clock is a little off.
+const SKEW_STEPS: i64 = 1;
+
+// Base32 without padding, the form authenticator apps read secrets in.
+fn base32_encode(bytes: &[u8]) -> String {
+   let mut text = String::new();
+   let mut buffer: u32 = 0;
+   let mut bits = 0;
+
+   for byte in bytes {
+      buffer = (buffer << 8) | u32::from(*byte);
+      bits += 8;
+
+      while bits >= 5 {
+         bits -= 5;
+         text.push(ALPHABET[((buffer >> bits) & 31) as usize] as char);
+      }
+   }
+
+   if bits > 0 {
+      text.push(ALPHABET[((buffer << (5 - bits)) & 31) as usize] as char);
+   }
+
+   text
+}
+
+fn base32_decode(text: &str) -> Option<Vec<u8>> {
+   let mut bytes = Vec::new();
+   let mut buffer: u32 = 0;
+   let mut bits = 0;
+
+   for character in text.bytes() {
+      let value = ALPHABET.iter().position(|a| *a == character)?;
+
+      buffer = (buffer << 5) | value as u32;
+      bits += 5;
+
+      if bits >= 8 {
+         bits -= 8;
+         bytes.push((buffer >> bits) as u8);
+      }
+   }
+
+   Some(bytes)
+}
+
+fn random_base32(length: usize) -> String {
+   let mut bytes = vec![0u8; length];
+   OsRng.fill_bytes(&mut bytes);
+
+   base32_encode(&bytes)
+}
+
+// The code of a step, as in RFC 6238 with HMAC-SHA1.
+fn code_at(key: &[u8], step: i64) -> String {
+   let mut mac = Hmac::<Sha1>::new_from_slice(key).unwrap();
+   mac.update(&step.to_be_bytes());
+   let hash = mac.finalize().into_bytes();
+
+   let offset = (hash[hash.len() - 1] & 0xf) as usize;
+   let binary = u32::from_be_bytes([
+      hash[offset] & 0x7f,
+      hash[offset + 1],
+      hash[offset + 2],
+      hash[offset + 3],
+   ]);
+
+   format!(
+      "{:0width$}",
+      binary % 10u32.pow(DIGITS as u32),
+      width = DIGITS
+   )
+}
+
+/// A new 160 bit secret, base32 encoded.
+pub fn generate_secret() -> String {
+   random_base32(20)
+}
+
+/// The `otpauth://` URI of a secret, which authenticator apps add from a QR
+/// code, labelled with `TOTP_ISSUER` and the email of the user.
+pub fn otpauth_uri(secret: &str, email: &str) -> String {
+   let encode = |text: &str| {
+      form_urlencoded::byte_serialize(text.as_bytes())
+         .collect::<String>()
+         .replace('+', "%20")
+   };
+   let issuer = encode(&CONFIG.totp_issuer);
+
+   format!(
+      "otpauth://totp/{}:{}?secret={}&issuer={}&algorithm=SHA1&digits={}&period={}",
+      issuer,
+      encode(email),
+      secret,
+      issuer,
+      DIGITS,
+      STEP_SECONDS
+   )
+}
+
+/// Checks a code of the secret of a user. A code works once: codes of its
+/// step or of the steps before are refused afterwards.
+pub fn use_code(conn: &Connection, user_id: &str, secret: &str, code: &str) -> bool {
+   let code = code.trim();
+
+   if code.len() != DIGITS || !code.bytes().all(|c| c.is_ascii_digit()) {
+      return false;
+   }
+
+   let key = match base32_decode(secret) {
+      Some(key) => key,
+      None => return false,
+   };
+
+   let current = Utc::now().timestamp() / STEP_SECONDS;
+   let step =
+      (current - SKEW_STEPS..=current + SKEW_STEPS).find(|step| code_at(&key, *step) == code);
+
+   match step {
+      Some(step) => conn
+         .execute(
+            "UPDATE users SET totp_last_step = ? WHERE id = ? AND totp_last_step < ?",
+            params![step, user_id, step],
+         )
+         .is_ok_and(|changed| changed > 0),
+      None => false,
+   }
+}
+
+// Dashes, spaces and case do not matter when a code is typed back.
+fn hash_recovery_code(code: &str) -> String {
+   let code: String = code.chars().filter(|c| c.is_ascii_alphanumeric()).collect();
+
+   format!("{:x}", Sha256::digest(code.to_uppercase().as_bytes()))
+}
+
+/// Replaces the recovery codes of a user with `RECOVERY_CODES` new ones and
+/// returns them. Only their hashes are kept, so they are shown just once.
+pub fn create_recovery_codes(conn: &Connection, user_id: &str) -> rusqlite::Result<Vec<String>> {
+   conn.execute("DELETE FROM recovery_codes WHERE user_id = ?", [user_id])?;
+
+   let created_at = now();
+   let mut codes = Vec::new();
+
+   for _ in 0..CONFIG.recovery_codes {
+      let code = random_base32(10).to_lowercase();
+      let code = format!(
+         "{}-{}-{}-{}",
+         &code[..4],
+         &code[4..8],
+         &code[8..12],
+         &code[12..]
+      );
+
+      conn.execute(
+         "INSERT INTO recovery_codes (code_hash, user_id, created_at) VALUES (?, ?, ?)",
+         [&hash_recovery_code(&code), user_id, &created_at],
+      )?;
+
+      codes.push(code);
+   }
+
+   Ok(codes)
+}
+
+/// Checks a recovery code of a user, which then stops working.
+pub fn use_recovery_code(conn: &Connection, user_id: &str, code: &str) -> bool {
+   conn
+      .execute(
+         "DELETE FROM recovery_codes WHERE code_hash = ? AND user_id = ?",
+         [&hash_recovery_code(code), user_id],
+      )
+      .is_ok_and(|deleted| deleted > 0)
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   fn database() -> Connection {
+      let conn = Connection::open_in_memory().unwrap();
+
+      conn
+         .execute_batch(
+            "CREATE TABLE users (id TEXT PRIMARY KEY, totp_last_step INTEGER NOT NULL DEFAULT 0);
+            CREATE TABLE recovery_codes (code_hash TEXT PRIMARY KEY, user_id TEXT NOT NULL, created_at TEXT NOT NULL);
+            INSERT INTO users (id) VALUES ('user');",
+         )
+         .unwrap();
+
+      conn
+   }
+
+   #[test]
+   fn matches_rfc_6238_sha1_vectors() {
+      // Appendix B, with the last six of its eight digits.
+      let key = b"12345678901234567890";
+      let vectors = [
+         (59, "287082"),
+         (1111111109, "081804"),
+         (1111111111, "050471"),
+         (1234567890, "005924"),
+         (2000000000, "279037"),
+         (20000000000, "353130"),
+      ];
+
+      for (time, code) in vectors {
+         assert_eq!(code_at(key, time / STEP_SECONDS), code, "at {}", time);
+      }
+   }
+
+   #[test]
+   fn round_trips_base32() {
+      assert_eq!(
+         base32_encode(b"12345678901234567890"),
+         "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ"
+      );
+
+      for length in 0..=20 {
+         let bytes: Vec<u8> = (0..length)
+            .map(|byte: u8| byte.wrapping_mul(37) ^ 0xa5)
+            .collect();
+
+         assert_eq!(base32_decode(&base32_encode(&bytes)), Some(bytes));
+      }
+
+      assert_eq!(base32_decode("GEZD1"), None);
+      assert_eq!(generate_secret().len(), 32);
+   }
+
+   #[test]
+   fn refuses_replayed_codes() {
+      let conn = database();
+      let secret = generate_secret();
+      let key = base32_decode(&secret).unwrap();
+      let step = Utc::now().timestamp() / STEP_SECONDS;
+
+      assert!(use_code(&conn, "user", &secret, &code_at(&key, step)));
+      assert!(!use_code(&conn, "user", &secret, &code_at(&key, step)));
+      assert!(!use_code(&conn, "user", &secret, &code_at(&key, step - 1)));
+      assert!(!use_code(&conn, "user", &secret, &code_at(&key, step + 5)));
+      assert!(!use_code(&conn, "user", &secret, "12345"));
+      assert!(!use_code(&conn, "other", &secret, &code_at(&key, step + 1)));
+   }
+
+   #[test]
+   fn normalizes_recovery_codes() {
+      let conn = database();
+      let codes = create_recovery_codes(&conn, "user").unwrap();
+
+      assert_eq!(codes.len(), CONFIG.recovery_codes);
+
+      let typed = codes[0].replace('-', " ").to_uppercase();
+
+      assert!(!use_recovery_code(&conn, "other", &typed));
+      assert!(use_recovery_code(&conn, "user", &format!(" {} ", typed)));
+      assert!(!use_recovery_code(&conn, "user", &codes[0]));
+      assert!(use_recovery_code(&conn, "user", &codes[1]));
+   }
+}
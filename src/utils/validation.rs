@@ -0,0 +1,132 @@
+use std::borrow::Cow;
+use std::convert::Infallible;
+
+use hyper::{Body, Response};
+
+use serde::{Deserialize, Deserializer};
+
+use utoipa::ToSchema;
+
+use validator::{ValidationError, ValidationErrors, ValidationErrorsKind};
+
+pub const NAME_MAX_LENGTH: u64 = 200;
+pub const TASK_NAME_MAX_LENGTH: u64 = 500;
+pub const EMAIL_MAX_LENGTH: u64 = 254;
+pub const PASSWORD_MAX_LENGTH: u64 = 128;
+pub const URL_MAX_LENGTH: u64 = 2048;
+
+#[derive(Serialize, Debug, ToSchema)]
+pub struct FieldErrorFormated {
+   /// The field, with the index of list items: `operations[2].name`.
+   pub field: String,
+   pub code: String,
+   pub message: String,
+}
+
+/// The body of a 422: every field that failed its rules.
+#[derive(Serialize, Debug, ToSchema)]
+pub struct ValidationErrorFormated {
+   pub error: &'static str,
+   pub fields: Vec<FieldErrorFormated>,
+}
+
+/// `custom` rule for text that is kept as sent but must not be only spaces.
+pub fn not_blank(value: &str) -> Result<(), ValidationError> {
+   if value.trim().is_empty() {
+      return Err(ValidationError::new("blank").with_message(Cow::from("must not be empty")));
+   }
+
+   Ok(())
+}
+
+/// `deserialize_with` for strings read without surrounding whitespace.
+pub fn trim<'de, D>(deserializer: D) -> Result<String, D::Error>
+where
+   D: Deserializer<'de>,
+{
+   String::deserialize(deserializer).map(|value| value.trim().to_string())
+}
+
+/// `trim` for optional fields, which also need `#[serde(default)]`.
+pub fn trim_option<'de, D>(deserializer: D) -> Result<Option<String>, D::Error>
+where
+   D: Deserializer<'de>,
+{
+   Option::<String>::deserialize(deserializer)
+      .map(|value| value.map(|value| value.trim().to_string()))
+}
+
+fn describe(error: &ValidationError) -> String {
+   if let Some(message) = &error.message {
+      return message.to_string();
+   }
+
+   let param = |name: &str| error.params.get(name).and_then(|value| value.as_u64());
+
+   match (error.code.as_ref(), param("min"), param("max")) {
+      ("length", Some(1), Some(max)) => format!("must have 1 to {} characters", max),
+      ("length", Some(1), None) => String::from("must not be empty"),
+      ("length", Some(min), Some(max)) => format!("must have {} to {} characters", min, max),
+      ("length", Some(min), None) => format!("must have at least {} characters", min),
+      ("length", None, Some(max)) => format!("must have at most {} characters", max),
+      ("email", _, _) => String::from("must be an email address"),
+      ("url", _, _) => String::from("must be an url"),
+      (code, _, _) => format!("{} invalid", code),
+   }
+}
+
+fn collect(errors: &ValidationErrors, prefix: &str, fields: &mut Vec<FieldErrorFormated>) {
+   for (field, kind) in errors.errors() {
+      let path = if prefix.is_empty() {
+         field.to_string()
+      } else {
+         format!("{}.{}", prefix, field)
+      };
+
+      match kind {
+         ValidationErrorsKind::Field(errors) => {
+            for error in errors {
+               fields.push(FieldErrorFormated {
+                  field: path.clone(),
+                  code: error.code.to_string(),
+                  message: describe(error),
+               });
+            }
+         }
+         ValidationErrorsKind::Struct(errors) => collect(errors, &path, fields),
+         ValidationErrorsKind::List(items) => {
+            for (index, errors) in items {
+               collect(errors, &format!("{}[{}]", path, index), fields);
+            }
+         }
+      }
+   }
+}
+
+/// Every failing field, in a stable order.
+pub fn field_errors(errors: &ValidationErrors) -> Vec<FieldErrorFormated> {
+   let mut fields = vec![];
+
+   collect(errors, "", &mut fields);
+   fields.sort_by(|a, b| a.field.cmp(&b.field).then(a.code.cmp(&b.code)));
+
+   fields
+}
+
+pub fn validation_error(errors: &ValidationErrors) -> Result<Response<Body>, Infallible> {
+   let json = serde_json::to_string(&ValidationErrorFormated {
+      error: "data invalid",
+      fields: field_errors(errors),
+   });
+
+   match json {
+      Ok(data) => Ok(Response::builder()
+         .status(422)
+         .body(Body::from(data))
+         .unwrap()),
+      _ => Ok(Response::builder()
+         .status(500)
+         .body(Body::from("Internal Server Error"))
+         .unwrap()),
+   }
+}
@@ -0,0 +1,225 @@
+use super::super::config::CONFIG;
+use super::mailer::{deliver, Email};
+use super::{now, parse_datetime, DATETIME_FORMAT};
+
+use chrono::{Duration, Utc};
+
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+use rusqlite::{Connection, OptionalExtension};
+
+use uuid::Uuid;
+
+fn signature(payload: &str) -> String {
+   let mut mac = Hmac::<Sha256>::new_from_slice(CONFIG.email_token_secret.as_bytes()).unwrap();
+   mac.update(payload.as_bytes());
+
+   format!("{:x}", mac.finalize().into_bytes())
+}
+
+// Compares every byte, so that the time taken does not tell how much of a
+// forged signature was right.
+fn same(a: &str, b: &str) -> bool {
+   a.len() == b.len()
+      && a
+         .bytes()
+         .zip(b.bytes())
+         .fold(0, |difference, (a, b)| difference | (a ^ b))
+         == 0
+}
+
+/// Starts the verification of the current email of a user, replacing the
+/// ones sent before, and returns its token: `<id>.<signature>`, signed with
+/// `EMAIL_TOKEN_SECRET`. The row of `email_verifications` makes it single
+/// use and ties it to the email it was sent to.
+pub fn create_verification(
+   conn: &Connection,
+   user_id: &str,
+   email: &str,
+) -> rusqlite::Result<String> {
+   let id = Uuid::new_v4().to_string();
+   let expires_at = Utc::now().naive_utc() + Duration::hours(CONFIG.email_verification_hours);
+
+   conn.execute(
+      "DELETE FROM email_verifications WHERE user_id = ?",
+      [user_id],
+   )?;
+   conn.execute(
+      "INSERT INTO email_verifications (id, user_id, email, expires_at, created_at) VALUES (?, ?, ?, ?, ?)",
+      [
+         &id,
+         user_id,
+         email,
+         &format!("{}", expires_at.format(DATETIME_FORMAT)),
+         &now(),
+      ],
+   )?;
+
+   Ok(format!("{}.{}", id, signature(&id)))
+}
+
+/// Uses a token of `create_verification`, marking the email it was sent to
+/// as verified if it is still the email of the user. Returns the user id.
+pub fn use_verification(conn: &Connection, token: &str) -> Result<String, (u16, &'static str)> {
+   let invalid = (400, "this token is invalid or expired");
+
+   let (id, token_signature) = token.trim().rsplit_once('.').ok_or(invalid)?;
+
+   if !same(&signature(id), token_signature) {
+      return Err(invalid);
+   }
+
+   let row: Option<(String, String, String)> = conn
+      .query_row(
+         "SELECT user_id, email, expires_at FROM email_verifications WHERE id = ?",
+         [id],
+         |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+      )
+      .optional()
+      .map_err(|_| (500, ""))?;
+
+   let (user_id, email, expires_at) = row.ok_or(invalid)?;
+
+   conn
+      .execute("DELETE FROM email_verifications WHERE id = ?", [id])
+      .map_err(|_| (500, ""))?;
+
+   let expired = match parse_datetime(&expires_at) {
+      Some(expires_at) => expires_at <= Utc::now().naive_utc(),
+      None => true,
+   };
+
+   if expired {
+      return Err(invalid);
+   }
+
+   let verified = conn
+      .execute(
+         "UPDATE users SET verified = 1 WHERE id = ? AND email = ? AND deleted_at IS NULL",
+         [&user_id, &email],
+      )
+      .map_err(|_| (500, ""))?;
+
+   if verified == 0 {
+      return Err(invalid);
+   }
+
+   Ok(user_id)
+}
+
+/// Seconds since the last verification of a user was sent.
+pub fn seconds_since_sent(conn: &Connection, user_id: &str) -> Option<i64> {
+   let created_at: Option<String> = conn
+      .query_row(
+         "SELECT MAX(created_at) FROM email_verifications WHERE user_id = ?",
+         [user_id],
+         |row| row.get(0),
+      )
+      .unwrap_or(None);
+
+   created_at
+      .and_then(|created_at| parse_datetime(&created_at))
+      .map(|created_at| (Utc::now().naive_utc() - created_at).num_seconds())
+}
+
+/// Emails a token of `create_verification` to the address it verifies.
+pub fn send_verification(email: &str, firstname: &str, token: &str) {
+   let link = if CONFIG.app_url.is_empty() {
+      String::new()
+   } else {
+      format!(
+         "\n\nor open {}/verify-email?token={}",
+         CONFIG.app_url.trim_end_matches('/'),
+         token
+      )
+   };
+
+   deliver(Email {
+      to: email.to_string(),
+      subject: String::from("Confirm your email address"),
+      body: format!(
+         "Hello {},\n\nConfirm your email address by sending this token to POST /verify-email:\n\n{}{}\n\nIt expires in {} hours.\n",
+         firstname, token, link, CONFIG.email_verification_hours
+      ),
+   });
+}
+
+#[cfg(test)]
+mod tests {
+   use super::super::super::database::create_memory_connection;
+   use super::*;
+
+   fn database() -> Connection {
+      let conn = create_memory_connection();
+
+      conn
+         .execute(
+            "INSERT INTO users (id, firstname, lastname, email, password) VALUES ('alice', 'Alice', 'A', 'alice@x.io', '')",
+            [],
+         )
+         .unwrap();
+
+      conn
+   }
+
+   fn verified(conn: &Connection) -> bool {
+      conn
+         .query_row("SELECT verified FROM users WHERE id = 'alice'", [], |row| {
+            row.get::<_, i64>(0)
+         })
+         .unwrap()
+         != 0
+   }
+
+   #[test]
+   fn tokens_work_once() {
+      let conn = database();
+      let token = create_verification(&conn, "alice", "alice@x.io").unwrap();
+
+      assert!(!verified(&conn));
+      assert_eq!(use_verification(&conn, &token), Ok(String::from("alice")));
+      assert!(verified(&conn));
+      assert_eq!(use_verification(&conn, &token).unwrap_err().0, 400);
+   }
+
+   #[test]
+   fn refuses_forged_and_replaced_tokens() {
+      let conn = database();
+      let first = create_verification(&conn, "alice", "alice@x.io").unwrap();
+      let (id, _) = first.rsplit_once('.').unwrap();
+
+      assert!(use_verification(&conn, &format!("{}.{}", id, "0".repeat(64))).is_err());
+      assert!(use_verification(&conn, id).is_err());
+
+      let second = create_verification(&conn, "alice", "alice@x.io").unwrap();
+
+      assert!(use_verification(&conn, &first).is_err());
+      assert!(use_verification(&conn, &second).is_ok());
+   }
+
+   #[test]
+   fn refuses_tokens_of_an_old_email_or_expired() {
+      let conn = database();
+      let token = create_verification(&conn, "alice", "alice@x.io").unwrap();
+
+      conn
+         .execute("UPDATE users SET email = 'new@x.io' WHERE id = 'alice'", [])
+         .unwrap();
+
+      assert!(use_verification(&conn, &token).is_err());
+      assert!(!verified(&conn));
+
+      let token = create_verification(&conn, "alice", "new@x.io").unwrap();
+
+      conn
+         .execute(
+            "UPDATE email_verifications SET expires_at = '2000-01-01 00:00:00'",
+            [],
+         )
+         .unwrap();
+
+      assert!(use_verification(&conn, &token).is_err());
+      assert!(!verified(&conn));
+   }
+}
@@ -0,0 +1,445 @@
+use super::super::config::CONFIG;
+use super::super::views::history::TaskEvent;
+use super::{now, DATETIME_FORMAT};
+
+use std::io::{Error as IoError, ErrorKind};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::task::{Context, Poll};
+use std::time::Duration as StdDuration;
+
+use chrono::{Duration, Utc};
+
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+use futures::future::BoxFuture;
+
+use hyper::client::connect::dns::Name;
+use hyper::client::HttpConnector;
+use hyper::service::Service;
+use hyper::{Body, Client, Method, Request, Uri};
+use hyper_rustls::{HttpsConnector, HttpsConnectorBuilder};
+
+use lazy_static::lazy_static;
+
+use rusqlite::{params, Connection};
+
+use serde_json::json;
+
+use uuid::Uuid;
+
+const WEBHOOK_ENTITIES: [&str; 3] = ["task", "project", "user"];
+const WEBHOOK_RETRY_MAX_SECONDS: i64 = 6 * 60 * 60;
+const WEBHOOK_ERROR_MAX_LENGTH: usize = 500;
+
+lazy_static! {
+   static ref CLIENT: Client<HttpsConnector<HttpConnector<PublicResolver>>> = {
+      let mut http = HttpConnector::new_with_resolver(PublicResolver);
+      http.enforce_http(false);
+
+      Client::builder().build(
+         HttpsConnectorBuilder::new()
+            .with_webpki_roots()
+            .https_or_http()
+            .enable_http1()
+            .wrap_connector(http),
+      )
+   };
+}
+
+fn public_ipv4(ip: Ipv4Addr) -> bool {
+   let [a, b, ..] = ip.octets();
+
+   !(ip.is_private()
+      || ip.is_loopback()
+      || ip.is_link_local()
+      || ip.is_unspecified()
+      || ip.is_broadcast()
+      || ip.is_documentation()
+      || ip.is_multicast()
+      || a == 0
+      || a >= 240
+      || (a == 100 && (64..128).contains(&b))
+      || (a == 192 && b == 0 && ip.octets()[2] == 0)
+      || (a == 198 && (18..20).contains(&b)))
+}
+
+/// Whether webhooks may reach an address: loopback, private, link-local
+/// (cloud metadata services among them) and other reserved ranges are
+/// refused unless `WEBHOOK_ALLOW_PRIVATE` is set for local testing.
+pub fn allowed_ip(ip: IpAddr) -> bool {
+   if CONFIG.webhook_allow_private {
+      return true;
+   }
+
+   match ip {
+      IpAddr::V4(ip) => public_ipv4(ip),
+      IpAddr::V6(ip) => {
+         let segments = ip.segments();
+
+         if let Some(ip) = ip.to_ipv4_mapped() {
+            return public_ipv4(ip);
+         }
+
+         // NAT64 addresses embed the IPv4 address they reach.
+         if segments[..6] == [0x64, 0xff9b, 0, 0, 0, 0] {
+            let [.., high, low] = segments;
+
+            return public_ipv4(Ipv4Addr::from((u32::from(high) << 16) | u32::from(low)));
+         }
+
+         !(ip.is_loopback()
+            || ip.is_unspecified()
+            || ip.is_multicast()
+            || (segments[0] & 0xfe00) == 0xfc00
+            || (segments[0] & 0xffc0) == 0xfe80
+            || segments[0] == 0x2001 && segments[1] == 0xdb8)
+      }
+   }
+}
+
+/// Whether the host of a webhook URL is an address `allowed_ip` refuses.
+/// Names are only checked when resolved, at delivery time.
+pub fn refused_host(uri: &Uri) -> bool {
+   uri.host()
+      .map(|host| host.trim_start_matches('[').trim_end_matches(']'))
+      .and_then(|host| host.parse::<IpAddr>().ok())
+      .is_some_and(|ip| !allowed_ip(ip))
+}
+
+// Resolves webhook hosts and drops the addresses of `allowed_ip`, so that
+// the addresses checked are the ones connected to, even when a name
+// resolves differently from one lookup to the next.
+#[derive(Clone)]
+pub struct PublicResolver;
+
+impl Service<Name> for PublicResolver {
+   type Response = std::vec::IntoIter<SocketAddr>;
+   type Error = IoError;
+   type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+   fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+      Poll::Ready(Ok(()))
+   }
+
+   fn call(&mut self, name: Name) -> Self::Future {
+      Box::pin(async move {
+         let addresses: Vec<SocketAddr> = tokio::net::lookup_host((name.as_str(), 0))
+            .await?
+            .filter(|address| allowed_ip(address.ip()))
+            .collect();
+
+         if addresses.is_empty() {
+            return Err(IoError::new(
+               ErrorKind::PermissionDenied,
+               format!("{} does not resolve to a public address", name),
+            ));
+         }
+
+         Ok(addresses.into_iter())
+      })
+   }
+}
+
+/// Subscriptions are `*`, `<entity>.*` or `<entity>.<action>`, where the
+/// entity is one of `task`, `project` or `user` and the action is one of the
+/// actions recorded in the history, e.g. `task.updated`.
+pub fn valid_event_pattern(pattern: &str) -> bool {
+   if pattern == "*" {
+      return true;
+   }
+
+   let mut parts = pattern.splitn(2, '.');
+
+   match (parts.next(), parts.next()) {
+      (Some(entity), Some(action)) => {
+         WEBHOOK_ENTITIES.contains(&entity)
+            && !action.is_empty()
+            && (action == "*" || action.chars().all(|c| c.is_ascii_lowercase() || c == '_'))
+      }
+      _ => false,
+   }
+}
+
+fn matches_event(patterns: &str, event: &str) -> bool {
+   let entity = event.split('.').next().unwrap_or("");
+
+   patterns
+      .split(',')
+      .any(|pattern| pattern == "*" || pattern == event || *pattern == format!("{}.*", entity))
+}
+
+/// Queues a delivery of `event` to every webhook subscribed to it whose
+/// owner can see the entity, given the `task_audience` of task events.
+/// Called in the same transaction that records the event, so a rolled back
+/// change never notifies anyone.
+pub fn enqueue_deliveries(
+   conn: &Connection,
+   event: &TaskEvent,
+   audience: &[String],
+) -> Result<(), rusqlite::Error> {
+   let mut audience = if event.entity == "task" {
+      audience.to_vec()
+   } else {
+      vec![event.user_id.clone()]
+   };
+
+   if !audience.contains(&event.actor_id) {
+      audience.push(event.actor_id.clone());
+   }
+
+   let name = format!("{}.{}", event.entity, event.action);
+   let created_at = now();
+
+   for user_id in audience {
+      let mut query = conn.prepare("SELECT id, events FROM webhooks WHERE user_id = ?")?;
+      let webhooks = query
+         .query_map([&user_id], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+         })?
+         .collect::<Result<Vec<(String, String)>, rusqlite::Error>>()?;
+
+      for (webhook_id, patterns) in webhooks {
+         if !matches_event(&patterns, &name) {
+            continue;
+         }
+
+         let id = Uuid::new_v4().to_string();
+         let payload = json!({
+            "id": id,
+            "event": name,
+            "created_at": created_at,
+            "data": event,
+         });
+
+         conn.execute(
+            "INSERT INTO webhook_deliveries (id, webhook_id, event_id, event, payload, status, attempts, next_attempt_at, created_at) VALUES (?, ?, ?, ?, ?, 'pending', 0, ?, ?)",
+            params![
+               id,
+               webhook_id,
+               event.id,
+               name,
+               payload.to_string(),
+               created_at,
+               created_at
+            ],
+         )?;
+      }
+   }
+
+   Ok(())
+}
+
+/// `sha256=<hex>` HMAC of `<timestamp>.<payload>`, so receivers can reject
+/// replays of an old body with a fresh timestamp.
+pub fn sign(secret: &str, timestamp: i64, payload: &str) -> String {
+   let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).unwrap();
+   mac.update(format!("{}.{}", timestamp, payload).as_bytes());
+
+   format!("sha256={:x}", mac.finalize().into_bytes())
+}
+
+pub struct PendingDelivery {
+   pub id: String,
+   pub event: String,
+   pub payload: String,
+   pub attempts: i64,
+   pub url: String,
+   pub secret: String,
+}
+
+pub fn due_deliveries(conn: &Connection, limit: i64) -> Vec<PendingDelivery> {
+   let mut query = conn.prepare("SELECT webhook_deliveries.id, webhook_deliveries.event, webhook_deliveries.payload, webhook_deliveries.attempts, webhooks.url, webhooks.secret FROM webhook_deliveries INNER JOIN webhooks ON webhooks.id = webhook_deliveries.webhook_id WHERE webhook_deliveries.status = 'pending' AND webhook_deliveries.next_attempt_at <= ? ORDER BY webhook_deliveries.next_attempt_at ASC LIMIT ?").unwrap();
+   let data = query
+      .query_map(params![now(), limit], |row| {
+         Ok(PendingDelivery {
+            id: row.get(0)?,
+            event: row.get(1)?,
+            payload: row.get(2)?,
+            attempts: row.get(3)?,
+            url: row.get(4)?,
+            secret: row.get(5)?,
+         })
+      })
+      .unwrap();
+
+   let mut deliveries: Vec<PendingDelivery> = vec![];
+   for delivery in data {
+      deliveries.push(delivery.unwrap());
+   }
+
+   deliveries
+}
+
+/// Posts the payload and returns the response status, or why no response
+/// came back. Only a 2xx status counts as delivered.
+pub async fn send(delivery: &PendingDelivery) -> Result<u16, (Option<u16>, String)> {
+   let timestamp = Utc::now().timestamp();
+
+   let request = Request::builder()
+      .method(Method::POST)
+      .uri(delivery.url.as_str())
+      .header("Content-Type", "application/json")
+      .header("User-Agent", "todo-api-webhooks")
+      .header("X-Webhook-Id", delivery.id.as_str())
+      .header("X-Webhook-Event", delivery.event.as_str())
+      .header("X-Webhook-Timestamp", timestamp)
+      .header(
+         "X-Webhook-Signature",
+         sign(&delivery.secret, timestamp, &delivery.payload),
+      )
+      .body(Body::from(delivery.payload.clone()));
+
+   let request = match request {
+      Ok(request) => request,
+      Err(e) => return Err((None, e.to_string())),
+   };
+
+   // Addresses in the URL skip the resolver, so they are checked here.
+   if refused_host(request.uri()) {
+      return Err((None, String::from("the address of this url is not allowed")));
+   }
+
+   let timeout = StdDuration::from_secs(CONFIG.webhook_timeout_seconds);
+
+   match tokio::time::timeout(timeout, CLIENT.request(request)).await {
+      Ok(Ok(response)) if response.status().is_success() => Ok(response.status().as_u16()),
+      Ok(Ok(response)) => Err((
+         Option::from(response.status().as_u16()),
+         format!("unexpected status {}", response.status()),
+      )),
+      Ok(Err(e)) => Err((None, e.to_string())),
+      Err(_) => Err((None, String::from("timed out"))),
+   }
+}
+
+/// Records the outcome of an attempt. Failures are retried after
+/// `WEBHOOK_RETRY_BASE_SECONDS * 2^(attempts - 1)`, capped at six hours,
+/// until `WEBHOOK_MAX_ATTEMPTS` is reached and the delivery is dead.
+pub fn record_attempt(
+   conn: &Connection,
+   delivery: &PendingDelivery,
+   result: Result<u16, (Option<u16>, String)>,
+) -> Result<usize, rusqlite::Error> {
+   let attempts = delivery.attempts + 1;
+
+   match result {
+      Ok(status_code) => conn.execute(
+         "UPDATE webhook_deliveries SET status = 'delivered', attempts = ?, next_attempt_at = NULL, last_status_code = ?, last_error = NULL, delivered_at = ? WHERE id = ?",
+         params![attempts, status_code, now(), delivery.id],
+      ),
+      Err((status_code, error)) => {
+         let error: String = error.chars().take(WEBHOOK_ERROR_MAX_LENGTH).collect();
+
+         if attempts >= CONFIG.webhook_max_attempts {
+            return conn.execute(
+               "UPDATE webhook_deliveries SET status = 'dead', attempts = ?, next_attempt_at = NULL, last_status_code = ?, last_error = ? WHERE id = ?",
+               params![attempts, status_code, error, delivery.id],
+            );
+         }
+
+         let delay = CONFIG
+            .webhook_retry_base_seconds
+            .saturating_mul(1_i64 << (attempts - 1).min(32))
+            .min(WEBHOOK_RETRY_MAX_SECONDS);
+         let next_attempt_at = Utc::now().naive_utc() + Duration::seconds(delay);
+
+         conn.execute(
+            "UPDATE webhook_deliveries SET attempts = ?, next_attempt_at = ?, last_status_code = ?, last_error = ? WHERE id = ?",
+            params![
+               attempts,
+               format!("{}", next_attempt_at.format(DATETIME_FORMAT)),
+               status_code,
+               error,
+               delivery.id
+            ],
+         )
+      }
+   }
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   #[test]
+   fn signs_the_timestamp_and_payload() {
+      assert_eq!(
+         sign("whsec", 1700000000, "{\"id\":1}"),
+         "sha256=e79220cb981f992adbc8b93ac6d46028b0217ea19327d27dc9d18bf334403bde"
+      );
+      assert_ne!(
+         sign("whsec", 1700000001, "{\"id\":1}"),
+         sign("whsec", 1700000000, "{\"id\":1}")
+      );
+      assert_ne!(
+         sign("other", 1700000000, "{\"id\":1}"),
+         sign("whsec", 1700000000, "{\"id\":1}")
+      );
+   }
+
+   #[test]
+   fn refuses_private_addresses() {
+      let allowed = |ip: &str| allowed_ip(ip.parse().unwrap());
+
+      for ip in [
+         "127.0.0.1",
+         "10.1.2.3",
+         "172.16.0.1",
+         "192.168.1.1",
+         "169.254.169.254",
+         "100.64.0.1",
+         "0.0.0.0",
+         "255.255.255.255",
+         "::1",
+         "::",
+         "fd00::1",
+         "fe80::1",
+         "::ffff:127.0.0.1",
+         "::ffff:169.254.169.254",
+         "64:ff9b::a00:1",
+      ]
+      .iter()
+      {
+         assert!(!allowed(ip), "{}", ip);
+      }
+
+      for ip in [
+         "1.1.1.1",
+         "93.184.216.34",
+         "2606:4700::1111",
+         "64:ff9b::101:101",
+      ]
+      .iter()
+      {
+         assert!(allowed(ip), "{}", ip);
+      }
+   }
+
+   #[test]
+   fn refuses_private_hosts_of_urls() {
+      let refused = |url: &str| refused_host(&url.parse().unwrap());
+
+      assert!(refused("http://127.0.0.1:8080/hook"));
+      assert!(refused("http://[::1]/hook"));
+      assert!(refused("http://169.254.169.254/latest/meta-data"));
+      assert!(!refused("https://1.1.1.1/hook"));
+      // Names are checked once resolved.
+      assert!(!refused("https://localhost/hook"));
+   }
+
+   #[test]
+   fn matches_event_patterns() {
+      assert!(valid_event_pattern("*"));
+      assert!(valid_event_pattern("task.*"));
+      assert!(valid_event_pattern("project.comment_edited"));
+      assert!(!valid_event_pattern("comment.created"));
+      assert!(!valid_event_pattern("task."));
+      assert!(!valid_event_pattern("task.Created"));
+
+      assert!(matches_event("task.*", "task.created"));
+      assert!(matches_event("user.updated,task.deleted", "task.deleted"));
+      assert!(!matches_event("task.*", "project.created"));
+      assert!(!matches_event("task.created", "task.deleted"));
+   }
+}
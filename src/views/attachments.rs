@@ -0,0 +1,62 @@
+use super::users::{CreatedUser, CreatedUserFormated};
+
+use rusqlite::{Result as SqlResult, Row};
+
+use utoipa::ToSchema;
+
+pub const ATTACHMENT_COLUMNS: &str = "attachments.id, attachments.task_id, attachments.user_id, attachments.filename, attachments.content_type, attachments.size, attachments.sha256, attachments.created_at";
+pub const ATTACHMENT_COLUMNS_COUNT: usize = 8;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Attachment {
+   pub id: String,
+   pub task_id: String,
+   pub user_id: String,
+   pub filename: String,
+   pub content_type: String,
+   pub size: i64,
+   pub sha256: String,
+   pub created_at: String,
+   pub user: Option<CreatedUser>,
+}
+
+impl Attachment {
+   pub fn from_row(row: &Row, offset: usize) -> SqlResult<Attachment> {
+      Ok(Attachment {
+         id: row.get(offset)?,
+         task_id: row.get(offset + 1)?,
+         user_id: row.get(offset + 2)?,
+         filename: row.get(offset + 3)?,
+         content_type: row.get(offset + 4)?,
+         size: row.get(offset + 5)?,
+         sha256: row.get(offset + 6)?,
+         created_at: row.get(offset + 7)?,
+         user: None,
+      })
+   }
+
+   pub fn format(self) -> AttachmentFormated {
+      AttachmentFormated {
+         id: self.id,
+         task_id: self.task_id,
+         filename: self.filename,
+         content_type: self.content_type,
+         size: self.size,
+         sha256: self.sha256,
+         created_at: self.created_at,
+         user: self.user.unwrap().format(),
+      }
+   }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
+pub struct AttachmentFormated {
+   pub id: String,
+   pub task_id: String,
+   pub filename: String,
+   pub content_type: String,
+   pub size: i64,
+   pub sha256: String,
+   pub created_at: String,
+   pub user: CreatedUserFormated,
+}
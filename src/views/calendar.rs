@@ -0,0 +1,20 @@
+use utoipa::ToSchema;
+
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
+pub struct CalendarTokenFormated {
+   pub token: String,
+   pub path: String,
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+pub struct ImportSkipped {
+   pub index: usize,
+   pub uid: Option<String>,
+   pub error: &'static str,
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+pub struct ImportReport {
+   pub created: Vec<String>,
+   pub skipped: Vec<ImportSkipped>,
+}
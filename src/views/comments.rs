@@ -0,0 +1,59 @@
+use super::super::utils::markdown::render;
+use super::users::{CreatedUser, CreatedUserFormated};
+
+use rusqlite::{Result as SqlResult, Row};
+
+use utoipa::ToSchema;
+
+pub const COMMENT_COLUMNS: &str = "comments.id, comments.task_id, comments.user_id, comments.body, comments.created_at, comments.updated_at";
+pub const COMMENT_COLUMNS_COUNT: usize = 6;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Comment {
+   pub id: String,
+   pub task_id: String,
+   pub user_id: String,
+   pub body: String,
+   pub created_at: String,
+   pub updated_at: Option<String>,
+   pub user: Option<CreatedUser>,
+}
+
+impl Comment {
+   pub fn from_row(row: &Row, offset: usize) -> SqlResult<Comment> {
+      Ok(Comment {
+         id: row.get(offset)?,
+         task_id: row.get(offset + 1)?,
+         user_id: row.get(offset + 2)?,
+         body: row.get(offset + 3)?,
+         created_at: row.get(offset + 4)?,
+         updated_at: row.get(offset + 5)?,
+         user: None,
+      })
+   }
+
+   pub fn format(self) -> CommentFormated {
+      let body_html = render(&self.body);
+
+      CommentFormated {
+         id: self.id,
+         task_id: self.task_id,
+         body: self.body,
+         body_html,
+         created_at: self.created_at,
+         updated_at: self.updated_at,
+         user: self.user.unwrap().format(),
+      }
+   }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
+pub struct CommentFormated {
+   pub id: String,
+   pub task_id: String,
+   pub body: String,
+   pub body_html: String,
+   pub created_at: String,
+   pub updated_at: Option<String>,
+   pub user: CreatedUserFormated,
+}
@@ -0,0 +1,196 @@
+use super::projects::Project;
+use super::tasks::TaskCreated;
+use super::users::CreatedUser;
+
+use utoipa::ToSchema;
+
+/// Bumped when the layout of exports changes in a way imports must handle.
+pub const EXPORT_VERSION: u32 = 2;
+
+#[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
+pub struct ExportedProfile {
+   pub id: String,
+   pub firstname: String,
+   pub lastname: String,
+   pub email: String,
+}
+
+impl ExportedProfile {
+   pub fn new(user: CreatedUser) -> ExportedProfile {
+      ExportedProfile {
+         id: user.id,
+         firstname: user.firstname,
+         lastname: user.lastname,
+         email: user.email,
+      }
+   }
+}
+
+/// A project the user owns. Imports create it again and move its tasks to
+/// it, unless the user can already write to a project with the same id.
+#[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
+pub struct ExportedProject {
+   pub id: String,
+   pub name: String,
+}
+
+impl ExportedProject {
+   pub fn new(project: Project) -> ExportedProject {
+      ExportedProject {
+         id: project.id,
+         name: project.name,
+      }
+   }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
+pub struct ExportedTask {
+   pub id: Option<String>,
+   pub name: String,
+   #[serde(default)]
+   pub completed: bool,
+   pub due_date: Option<String>,
+   pub recurrence: Option<String>,
+   pub project_id: Option<String>,
+   pub deleted_at: Option<String>,
+   /// Label names, which imports match against the user's labels.
+   #[serde(default)]
+   pub labels: Vec<String>,
+}
+
+impl ExportedTask {
+   pub fn new(task: TaskCreated) -> ExportedTask {
+      ExportedTask {
+         id: Option::from(task.id),
+         name: task.name,
+         completed: task.completed != 0,
+         due_date: task.due_date,
+         recurrence: task.recurrence,
+         project_id: task.project_id,
+         deleted_at: task.deleted_at,
+         labels: task.labels,
+      }
+   }
+}
+
+/// The body of `POST /import` in JSON; `GET /export?format=json` streams the
+/// same shape.
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
+pub struct ExportDocument {
+   #[serde(default)]
+   pub version: u32,
+   pub exported_at: Option<String>,
+   pub user: Option<ExportedProfile>,
+   #[serde(default)]
+   pub projects: Vec<ExportedProject>,
+   pub tasks: Vec<ExportedTask>,
+}
+
+/// One line of a CSV export. The first line is the profile
+/// (`record = "profile"`), followed by the projects (`record = "project"`)
+/// and the tasks (`record = "task"`), leaving the columns of the other kinds
+/// empty. The labels of a task are written one per line of their cell.
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct CsvRecord {
+   pub record: String,
+   pub id: Option<String>,
+   pub firstname: Option<String>,
+   pub lastname: Option<String>,
+   pub email: Option<String>,
+   pub name: Option<String>,
+   pub completed: Option<bool>,
+   pub due_date: Option<String>,
+   pub recurrence: Option<String>,
+   pub project_id: Option<String>,
+   pub deleted_at: Option<String>,
+   pub labels: Option<String>,
+}
+
+impl CsvRecord {
+   pub fn from_profile(profile: ExportedProfile) -> CsvRecord {
+      CsvRecord {
+         record: String::from("profile"),
+         id: Option::from(profile.id),
+         firstname: Option::from(profile.firstname),
+         lastname: Option::from(profile.lastname),
+         email: Option::from(profile.email),
+         ..CsvRecord::default()
+      }
+   }
+
+   pub fn from_project(project: ExportedProject) -> CsvRecord {
+      CsvRecord {
+         record: String::from("project"),
+         id: Option::from(project.id),
+         name: Option::from(project.name),
+         ..CsvRecord::default()
+      }
+   }
+
+   pub fn from_task(task: ExportedTask) -> CsvRecord {
+      CsvRecord {
+         record: String::from("task"),
+         id: task.id,
+         name: Option::from(task.name),
+         completed: Option::from(task.completed),
+         due_date: task.due_date,
+         recurrence: task.recurrence,
+         project_id: task.project_id,
+         deleted_at: task.deleted_at,
+         labels: Option::from(task.labels.join("\n")),
+         ..CsvRecord::default()
+      }
+   }
+
+   pub fn into_project(self) -> ExportedProject {
+      ExportedProject {
+         id: self.id.unwrap_or_default(),
+         name: self.name.unwrap_or_default(),
+      }
+   }
+
+   // Empty cells read as absent values.
+   pub fn into_task(self) -> ExportedTask {
+      let present = |value: Option<String>| value.filter(|value| !value.trim().is_empty());
+
+      ExportedTask {
+         id: present(self.id),
+         name: self.name.unwrap_or_default(),
+         completed: self.completed.unwrap_or(false),
+         due_date: present(self.due_date),
+         recurrence: present(self.recurrence),
+         project_id: present(self.project_id),
+         deleted_at: present(self.deleted_at),
+         labels: self
+            .labels
+            .unwrap_or_default()
+            .lines()
+            .map(String::from)
+            .collect(),
+      }
+   }
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+pub struct ImportItemError {
+   pub index: usize,
+   pub id: Option<String>,
+   pub error: &'static str,
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+pub struct DataImportReport {
+   pub dry_run: bool,
+   pub created_projects: Vec<String>,
+   pub created: Vec<String>,
+   pub updated: Vec<String>,
+   pub skipped: Vec<ImportItemError>,
+   /// Tasks imported without their project, which does not exist here.
+   pub unlinked: Vec<ImportItemError>,
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+pub struct DataImportInvalid {
+   pub error: &'static str,
+   pub errors: Vec<ImportItemError>,
+}
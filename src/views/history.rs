@@ -0,0 +1,40 @@
+use rusqlite::{Result as SqlResult, Row};
+
+use serde_json::Value;
+
+use utoipa::ToSchema;
+
+#[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
+pub struct TaskEvent {
+   pub id: i64,
+   pub entity: String,
+   pub entity_id: String,
+   pub user_id: String,
+   pub actor_id: String,
+   pub action: String,
+   pub changes: Value,
+   pub created_at: String,
+}
+
+impl TaskEvent {
+   pub fn from_row(row: &Row, offset: usize) -> SqlResult<TaskEvent> {
+      let changes: String = row.get(offset + 6)?;
+
+      Ok(TaskEvent {
+         id: row.get(offset)?,
+         entity: row.get(offset + 1)?,
+         entity_id: row.get(offset + 2)?,
+         user_id: row.get(offset + 3)?,
+         actor_id: row.get(offset + 4)?,
+         action: row.get(offset + 5)?,
+         changes: serde_json::from_str(&changes).unwrap_or(Value::Null),
+         created_at: row.get(offset + 7)?,
+      })
+   }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
+pub struct TaskEvents {
+   pub events: Vec<TaskEvent>,
+   pub next_before: Option<i64>,
+}
@@ -0,0 +1,83 @@
+use super::super::utils::importers::SkippedItem;
+
+use rusqlite::{Result as SqlResult, Row};
+
+use utoipa::ToSchema;
+
+pub const IMPORT_JOB_COLUMNS: &str = "import_jobs.id, import_jobs.user_id, import_jobs.source, import_jobs.status, import_jobs.total, import_jobs.processed, import_jobs.created_tasks, import_jobs.created_projects, import_jobs.skipped, import_jobs.error, import_jobs.created_at, import_jobs.finished_at";
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ImportJob {
+   pub id: String,
+   pub user_id: String,
+   pub source: String,
+   pub status: String,
+   pub total: i64,
+   pub processed: i64,
+   pub created_tasks: i64,
+   pub created_projects: i64,
+   pub skipped: String,
+   pub error: Option<String>,
+   pub created_at: String,
+   pub finished_at: Option<String>,
+}
+
+impl ImportJob {
+   pub fn from_row(row: &Row, offset: usize) -> SqlResult<ImportJob> {
+      Ok(ImportJob {
+         id: row.get(offset)?,
+         user_id: row.get(offset + 1)?,
+         source: row.get(offset + 2)?,
+         status: row.get(offset + 3)?,
+         total: row.get(offset + 4)?,
+         processed: row.get(offset + 5)?,
+         created_tasks: row.get(offset + 6)?,
+         created_projects: row.get(offset + 7)?,
+         skipped: row.get(offset + 8)?,
+         error: row.get(offset + 9)?,
+         created_at: row.get(offset + 10)?,
+         finished_at: row.get(offset + 11)?,
+      })
+   }
+
+   pub fn format(self) -> ImportJobFormated {
+      let progress = if self.total > 0 {
+         self.processed * 100 / self.total
+      } else if self.status == "completed" {
+         100
+      } else {
+         0
+      };
+
+      ImportJobFormated {
+         id: self.id,
+         source: self.source,
+         status: self.status,
+         total: self.total,
+         processed: self.processed,
+         progress,
+         created_tasks: self.created_tasks,
+         created_projects: self.created_projects,
+         skipped: serde_json::from_str(&self.skipped).unwrap_or_default(),
+         error: self.error,
+         created_at: self.created_at,
+         finished_at: self.finished_at,
+      }
+   }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
+pub struct ImportJobFormated {
+   pub id: String,
+   pub source: String,
+   pub status: String,
+   pub total: i64,
+   pub processed: i64,
+   pub progress: i64,
+   pub created_tasks: i64,
+   pub created_projects: i64,
+   pub skipped: Vec<SkippedItem>,
+   pub error: Option<String>,
+   pub created_at: String,
+   pub finished_at: Option<String>,
+}
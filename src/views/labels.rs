@@ -0,0 +1,9 @@
+use utoipa::ToSchema;
+
+#[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
+pub struct LabelFormated {
+   pub id: String,
+   pub name: String,
+   pub tasks: i64,
+   pub created_at: String,
+}
@@ -1 +1,11 @@
-pub mod tasks;pub mod users;
+pub mod attachments;
+pub mod calendar;
+pub mod comments;
+pub mod exports;
+pub mod history;
+pub mod imports;
+pub mod labels;
+pub mod projects;
+pub mod tasks;
+pub mod users;
+pub mod webhooks;
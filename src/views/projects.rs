@@ -0,0 +1,56 @@
+use super::users::CreatedUserFormated;
+
+use rusqlite::{Result as SqlResult, Row};
+
+use utoipa::ToSchema;
+
+pub const PROJECT_COLUMNS: &str =
+   "projects.id, projects.name, projects.user_id, projects.created_at";
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Project {
+   pub id: String,
+   pub name: String,
+   pub user_id: String,
+   pub created_at: String,
+}
+
+impl Project {
+   pub fn from_row(row: &Row, offset: usize) -> SqlResult<Project> {
+      Ok(Project {
+         id: row.get(offset)?,
+         name: row.get(offset + 1)?,
+         user_id: row.get(offset + 2)?,
+         created_at: row.get(offset + 3)?,
+      })
+   }
+
+   pub fn format(self, viewer_id: &str, role: &str) -> ProjectFormated {
+      let owned = self.user_id == viewer_id;
+
+      ProjectFormated {
+         id: self.id,
+         name: self.name,
+         owned,
+         role: String::from(role),
+         created_at: self.created_at,
+      }
+   }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
+pub struct ProjectFormated {
+   pub id: String,
+   pub name: String,
+   pub owned: bool,
+   pub role: String,
+   pub created_at: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
+pub struct MemberFormated {
+   pub user: CreatedUserFormated,
+   pub role: String,
+   pub invited_by: Option<String>,
+   pub created_at: String,
+}
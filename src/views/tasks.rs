@@ -1,47 +1,184 @@
 use super::users::{CreatedUser, CreatedUserFormated};
 
+use rusqlite::{Result as SqlResult, Row};
+
+use uuid::Uuid;
+
+use utoipa::ToSchema;
+
+pub const TASK_COLUMNS: &str = "tasks.id, tasks.name, tasks.completed, tasks.user_id, tasks.due_date, tasks.recurrence, tasks.deleted_at, tasks.version, tasks.project_id, tasks.assignee_id";
+pub const TASK_COLUMNS_COUNT: usize = 10;
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct TaskCreated {
    pub id: String,
    pub name: String,
    pub completed: i32,
+   pub user_id: String,
+   pub due_date: Option<String>,
+   pub recurrence: Option<String>,
+   pub deleted_at: Option<String>,
+   pub version: i64,
+   pub project_id: Option<String>,
+   pub assignee_id: Option<String>,
    pub user: Option<CreatedUser>,
+   pub assignee: Option<CreatedUser>,
+   pub comments: i64,
+   pub labels: Vec<String>,
 }
 
 impl TaskCreated {
+   pub fn new(user_id: &str, name: &str) -> TaskCreated {
+      TaskCreated {
+         id: Uuid::new_v4().to_string(),
+         name: String::from(name),
+         completed: 0,
+         user_id: String::from(user_id),
+         due_date: None,
+         recurrence: None,
+         deleted_at: None,
+         version: 1,
+         project_id: None,
+         assignee_id: None,
+         user: None,
+         assignee: None,
+         comments: 0,
+         labels: vec![],
+      }
+   }
+
+   pub fn from_row(row: &Row, offset: usize) -> SqlResult<TaskCreated> {
+      Ok(TaskCreated {
+         id: row.get(offset)?,
+         name: row.get(offset + 1)?,
+         completed: row.get(offset + 2)?,
+         user_id: row.get(offset + 3)?,
+         due_date: row.get(offset + 4)?,
+         recurrence: row.get(offset + 5)?,
+         deleted_at: row.get(offset + 6)?,
+         version: row.get(offset + 7)?,
+         project_id: row.get(offset + 8)?,
+         assignee_id: row.get(offset + 9)?,
+         user: None,
+         assignee: None,
+         comments: 0,
+         labels: vec![],
+      })
+   }
+
    pub fn format(self) -> TaskCreatedUserFormated {
-      let completed = if self.completed == 0 { false } else { true };
+      let completed = self.completed != 0;
 
       TaskCreatedUserFormated {
          id: self.id,
          name: self.name,
          completed,
+         due_date: self.due_date,
+         recurrence: self.recurrence,
+         version: self.version,
+         project_id: self.project_id,
+         assignee_id: self.assignee_id,
+      }
+   }
+
+   pub fn format_deleted(self, purge_at: String) -> TaskDeletedFormated {
+      let completed = self.completed != 0;
+
+      TaskDeletedFormated {
+         id: self.id,
+         name: self.name,
+         completed,
+         due_date: self.due_date,
+         recurrence: self.recurrence,
+         deleted_at: self.deleted_at.unwrap_or_default(),
+         purge_at,
       }
    }
 
-   pub fn format_user(self) -> TaskCreatedFormated {
-      let completed = if self.completed == 0 { false } else { true };
+   /// `role` is what `viewer_id`, the requesting user, may do with the
+   /// task; `owned` tells the tasks they created apart from shared ones.
+   pub fn format_user(self, viewer_id: &str, role: &str) -> TaskCreatedFormated {
+      let completed = self.completed != 0;
+      let owned = self.user_id == viewer_id;
 
       TaskCreatedFormated {
          id: self.id,
          name: self.name,
          completed,
+         due_date: self.due_date,
+         recurrence: self.recurrence,
+         version: self.version,
+         project_id: self.project_id,
+         owned,
+         role: String::from(role),
          user: self.user.unwrap().format(),
+         assignee: self.assignee.map(|assignee| assignee.format()),
+         comments: self.comments,
+         labels: self.labels,
       }
    }
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
 pub struct TaskCreatedFormated {
    pub id: String,
    pub name: String,
    pub completed: bool,
+   pub due_date: Option<String>,
+   pub recurrence: Option<String>,
+   pub version: i64,
+   pub project_id: Option<String>,
+   pub owned: bool,
+   pub role: String,
    pub user: CreatedUserFormated,
+   pub assignee: Option<CreatedUserFormated>,
+   pub comments: i64,
+   pub labels: Vec<String>,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
 pub struct TaskCreatedUserFormated {
    pub id: String,
    pub name: String,
    pub completed: bool,
+   pub due_date: Option<String>,
+   pub recurrence: Option<String>,
+   pub version: i64,
+   pub project_id: Option<String>,
+   pub assignee_id: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
+pub struct TaskDeletedFormated {
+   pub id: String,
+   pub name: String,
+   pub completed: bool,
+   pub due_date: Option<String>,
+   pub recurrence: Option<String>,
+   pub deleted_at: String,
+   pub purge_at: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
+pub struct TaskOccurrences {
+   pub id: String,
+   pub recurrence: String,
+   pub occurrences: Vec<String>,
+}
+
+#[derive(Serialize, Debug, Clone, ToSchema)]
+pub struct BulkOperationResult {
+   pub index: usize,
+   pub op: &'static str,
+   #[serde(skip_serializing_if = "Option::is_none")]
+   pub id: Option<String>,
+   pub status: u16,
+   #[serde(skip_serializing_if = "Option::is_none")]
+   pub error: Option<&'static str>,
+}
+
+#[derive(Serialize, Debug, Clone, ToSchema)]
+pub struct BulkResult {
+   pub committed: bool,
+   pub results: Vec<BulkOperationResult>,
 }
@@ -1,5 +1,7 @@
 use super::tasks::TaskCreatedUserFormated;
 
+use utoipa::ToSchema;
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct CreatedUser {
    pub id: String,
@@ -20,7 +22,7 @@ impl CreatedUser {
    }
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
 pub struct CreatedUserFormated {
    pub id: String,
    pub firstname: String,
@@ -28,7 +30,7 @@ pub struct CreatedUserFormated {
    pub email: String,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
 pub struct CreatedUserComplete {
    pub id: String,
    pub firstname: String,
@@ -0,0 +1,100 @@
+use rusqlite::{Result as SqlResult, Row};
+
+use serde_json::Value;
+
+use utoipa::ToSchema;
+
+pub const WEBHOOK_COLUMNS: &str = "webhooks.id, webhooks.user_id, webhooks.url, webhooks.events, webhooks.secret, webhooks.created_at";
+pub const DELIVERY_COLUMNS: &str = "webhook_deliveries.id, webhook_deliveries.webhook_id, webhook_deliveries.event_id, webhook_deliveries.event, webhook_deliveries.payload, webhook_deliveries.status, webhook_deliveries.attempts, webhook_deliveries.next_attempt_at, webhook_deliveries.last_status_code, webhook_deliveries.last_error, webhook_deliveries.created_at, webhook_deliveries.delivered_at";
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Webhook {
+   pub id: String,
+   pub user_id: String,
+   pub url: String,
+   pub events: String,
+   pub secret: String,
+   pub created_at: String,
+}
+
+impl Webhook {
+   pub fn from_row(row: &Row, offset: usize) -> SqlResult<Webhook> {
+      Ok(Webhook {
+         id: row.get(offset)?,
+         user_id: row.get(offset + 1)?,
+         url: row.get(offset + 2)?,
+         events: row.get(offset + 3)?,
+         secret: row.get(offset + 4)?,
+         created_at: row.get(offset + 5)?,
+      })
+   }
+
+   /// The secret is only shown once, in the response that creates the
+   /// webhook.
+   pub fn format(self, with_secret: bool) -> WebhookFormated {
+      WebhookFormated {
+         id: self.id,
+         url: self.url,
+         events: self.events.split(',').map(String::from).collect(),
+         secret: if with_secret {
+            Option::from(self.secret)
+         } else {
+            None
+         },
+         created_at: self.created_at,
+      }
+   }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
+pub struct WebhookFormated {
+   pub id: String,
+   pub url: String,
+   pub events: Vec<String>,
+   #[serde(skip_serializing_if = "Option::is_none")]
+   pub secret: Option<String>,
+   pub created_at: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
+pub struct WebhookDelivery {
+   pub id: String,
+   pub webhook_id: String,
+   pub event_id: i64,
+   pub event: String,
+   pub payload: Value,
+   pub status: String,
+   pub attempts: i64,
+   pub next_attempt_at: Option<String>,
+   pub last_status_code: Option<u16>,
+   pub last_error: Option<String>,
+   pub created_at: String,
+   pub delivered_at: Option<String>,
+}
+
+impl WebhookDelivery {
+   pub fn from_row(row: &Row, offset: usize) -> SqlResult<WebhookDelivery> {
+      let payload: String = row.get(offset + 4)?;
+
+      Ok(WebhookDelivery {
+         id: row.get(offset)?,
+         webhook_id: row.get(offset + 1)?,
+         event_id: row.get(offset + 2)?,
+         event: row.get(offset + 3)?,
+         payload: serde_json::from_str(&payload).unwrap_or(Value::Null),
+         status: row.get(offset + 5)?,
+         attempts: row.get(offset + 6)?,
+         next_attempt_at: row.get(offset + 7)?,
+         last_status_code: row.get(offset + 8)?,
+         last_error: row.get(offset + 9)?,
+         created_at: row.get(offset + 10)?,
+         delivered_at: row.get(offset + 11)?,
+      })
+   }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
+pub struct WebhookDeliveries {
+   pub deliveries: Vec<WebhookDelivery>,
+   pub next_before: Option<i64>,
+}